@@ -0,0 +1,9 @@
+CREATE TABLE customer_segments (
+    `merchant_id` String,
+    `payment_id` String,
+    `customer_segment` LowCardinality(String),
+    `updated_at` DateTime DEFAULT now()
+) ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY
+	(merchant_id, payment_id)
+;
@@ -0,0 +1,7 @@
+CREATE TABLE sdk_events_audit_archive AS sdk_events_audit
+ENGINE = MergeTree
+PARTITION BY toStartOfMonth(created_at)
+ORDER BY
+	(merchant_id, created_at, cityHash64(payment_id))
+SAMPLE BY cityHash64(payment_id)
+;
@@ -0,0 +1,56 @@
+CREATE TABLE sdk_events_hourly (
+    `created_at` DateTime,
+    `merchant_id` String,
+    `event_name` LowCardinality(String),
+    `log_type` LowCardinality(Nullable(String)),
+    `category` LowCardinality(Nullable(String)),
+    `first_event` UInt8,
+    `payment_method` LowCardinality(Nullable(String)),
+    `platform` LowCardinality(Nullable(String)),
+    `browser_name` LowCardinality(Nullable(String)),
+    `source` LowCardinality(Nullable(String)),
+    `component` LowCardinality(Nullable(String)),
+    `version` LowCardinality(Nullable(String)),
+    `country` LowCardinality(Nullable(String)),
+    `event_count` UInt64
+) ENGINE = SummingMergeTree(event_count)
+PARTITION BY toStartOfMonth(created_at)
+ORDER BY
+	(merchant_id, created_at, event_name, log_type, category, first_event, payment_method,
+	platform, browser_name, source, component, version, country)
+SETTINGS allow_nullable_key = 1
+;
+
+CREATE MATERIALIZED VIEW sdk_events_hourly_mv TO sdk_events_hourly AS
+SELECT
+    toStartOfHour(sdk_events_audit.created_at) AS created_at,
+    merchant_id,
+    event_name,
+    log_type,
+    category,
+    first_event,
+    payment_method,
+    platform,
+    browser_name,
+    source,
+    component,
+    version,
+    country,
+    count() AS event_count
+FROM
+    sdk_events_audit
+GROUP BY
+    created_at,
+    merchant_id,
+    event_name,
+    log_type,
+    category,
+    first_event,
+    payment_method,
+    platform,
+    browser_name,
+    source,
+    component,
+    version,
+    country
+;
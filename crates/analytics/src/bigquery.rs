@@ -0,0 +1,782 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use api_models::analytics::Granularity;
+use common_utils::errors::{CustomResult, ParsingError};
+use error_stack::{report, Report, ResultExt};
+use masking::{PeekInterface, Secret};
+use router_env::logger;
+use time::PrimitiveDateTime;
+
+use super::{
+    query::{format_time_bucket, Aggregate, DatePart, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, LoadRow, QueryExecutionError, TableEngine},
+};
+
+const BIGQUERY_URL: &str = "https://bigquery.googleapis.com/bigquery/v2";
+
+/// Token endpoint of the GCE metadata server, which hands out tokens of the service account the
+/// instance (or GKE workload, Cloud Run service) runs as
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Tokens are fetched again this long before they expire
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// How long a single request waits for the query to finish before polling for its results
+const QUERY_WAIT_MS: u64 = 10_000;
+
+/// Google BigQuery, queried through its REST API. The tables are expected to have the names and
+/// columns of the clickhouse tables, in the dataset `dataset_id` of the project `project_id`.
+#[derive(Clone, Debug)]
+pub struct BigQueryClient {
+    config: Arc<BigQueryConfig>,
+    client: reqwest::Client,
+    token: Arc<Mutex<Option<AccessToken>>>,
+}
+
+/// Queries are authenticated with the OAuth 2.0 `access_token` if it's set. Otherwise a token of
+/// the service account the process runs as is taken from the GCE metadata server, which is
+/// available on Compute Engine, GKE (through workload identity) and Cloud Run. The account needs
+/// the `roles/bigquery.jobUser` role on the project and `roles/bigquery.dataViewer` on the
+/// dataset.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct BigQueryConfig {
+    project_id: String,
+    dataset_id: String,
+    /// Location of the dataset, e.g. `EU`, required for datasets outside the US and EU
+    #[serde(default)]
+    location: Option<String>,
+    /// Static token, meant for local runs, e.g. the output of `gcloud auth print-access-token`
+    #[serde(default)]
+    access_token: Option<Secret<String>>,
+}
+
+#[derive(Clone, Debug)]
+struct AccessToken {
+    token: Secret<String>,
+    expires_at: Instant,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MetadataToken {
+    access_token: Secret<String>,
+    expires_in: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequest<'a> {
+    query: &'a str,
+    use_legacy_sql: bool,
+    default_dataset: DatasetReference<'a>,
+    timeout_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<&'a str>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DatasetReference<'a> {
+    project_id: &'a str,
+    dataset_id: &'a str,
+}
+
+/// Response of `jobs.query` and `jobs.getQueryResults`
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryResponse {
+    job_reference: JobReference,
+    #[serde(default)]
+    job_complete: bool,
+    #[serde(default)]
+    schema: Option<TableSchema>,
+    #[serde(default)]
+    rows: Vec<TableRow>,
+    #[serde(default)]
+    page_token: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JobReference {
+    job_id: String,
+    #[serde(default)]
+    location: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TableSchema {
+    fields: Vec<TableField>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TableField {
+    name: String,
+    #[serde(rename = "type")]
+    field_type: String,
+}
+
+/// Row of a result, the values are in the order of the schema fields
+#[derive(Debug, serde::Deserialize)]
+struct TableRow {
+    f: Vec<TableCell>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TableCell {
+    v: serde_json::Value,
+}
+
+impl BigQueryClient {
+    pub fn from_conf(conf: &BigQueryConfig) -> Self {
+        Self {
+            config: Arc::new(conf.clone()),
+            client: reqwest::Client::new(),
+            token: Arc::default(),
+        }
+    }
+
+    async fn access_token(&self) -> CustomResult<Secret<String>, QueryExecutionError> {
+        if let Some(token) = &self.config.access_token {
+            return Ok(token.clone());
+        }
+        let cached = self
+            .token
+            .lock()
+            .map_err(|_| report!(QueryExecutionError::DatabaseError))
+            .attach_printable("BigQuery token lock poisoned")?
+            .clone()
+            .filter(|token| {
+                Instant::now()
+                    .checked_add(TOKEN_EXPIRY_MARGIN)
+                    .is_some_and(|margin| token.expires_at > margin)
+            });
+        if let Some(token) = cached {
+            return Ok(token.token);
+        }
+
+        let token = self
+            .client
+            .get(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .change_context(QueryExecutionError::DatabaseError)
+            .attach_printable("Failed to fetch access token from the metadata server")?
+            .json::<MetadataToken>()
+            .await
+            .change_context(QueryExecutionError::DatabaseError)
+            .attach_printable("Failed to parse access token of the metadata server")?;
+        let token = AccessToken {
+            expires_at: Instant::now()
+                .checked_add(Duration::from_secs(token.expires_in))
+                .unwrap_or_else(Instant::now),
+            token: token.access_token,
+        };
+        *self
+            .token
+            .lock()
+            .map_err(|_| report!(QueryExecutionError::DatabaseError))
+            .attach_printable("BigQuery token lock poisoned")? = Some(token.clone());
+        Ok(token.token)
+    }
+
+    /// Runs the query and returns every row as a JSON object keyed by column name, the same
+    /// shape clickhouse returns rows in
+    async fn fetch_rows(
+        &self,
+        query: &str,
+    ) -> CustomResult<Vec<serde_json::Value>, QueryExecutionError> {
+        let url = format!("{BIGQUERY_URL}/projects/{}/queries", self.config.project_id);
+        let request = QueryRequest {
+            query,
+            use_legacy_sql: false,
+            default_dataset: DatasetReference {
+                project_id: &self.config.project_id,
+                dataset_id: &self.config.dataset_id,
+            },
+            timeout_ms: QUERY_WAIT_MS,
+            location: self.config.location.as_deref(),
+        };
+        let mut response = self
+            .send(self.client.post(&url).json(&request))
+            .await
+            .attach_printable_lazy(|| format!("Failed to run query {query}"))?;
+        let mut schema = response.schema.take();
+        let mut rows = Vec::new();
+
+        // Results which aren't ready in time or don't fit a single response are polled for
+        loop {
+            rows.append(&mut response.rows);
+            let page_token = match (response.job_complete, response.page_token.take()) {
+                (true, None) => break,
+                (_, page_token) => page_token,
+            };
+            let job_url = format!("{url}/{}", response.job_reference.job_id);
+            let params = [
+                ("pageToken", page_token),
+                ("location", response.job_reference.location.clone()),
+                ("timeoutMs", Some(QUERY_WAIT_MS.to_string())),
+            ];
+            let params = params
+                .iter()
+                .filter_map(|(key, value)| Some((*key, value.as_deref()?)))
+                .collect::<Vec<_>>();
+            response = self
+                .send(self.client.get(&job_url).query(&params))
+                .await
+                .attach_printable_lazy(|| format!("Failed to fetch results of query {query}"))?;
+            if schema.is_none() {
+                schema = response.schema.take();
+            }
+        }
+
+        let fields = schema.map(|schema| schema.fields).unwrap_or_default();
+        rows.into_iter()
+            .map(|row| to_object(&fields, row))
+            .collect()
+    }
+
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> CustomResult<QueryResponse, QueryExecutionError> {
+        let token = self.access_token().await?;
+        let response = request
+            .bearer_auth(token.peek())
+            .send()
+            .await
+            .change_context(QueryExecutionError::DatabaseError)?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(report!(QueryExecutionError::DatabaseError))
+                .attach_printable_lazy(|| format!("BigQuery responded with {status}: {body}"));
+        }
+        response
+            .json::<QueryResponse>()
+            .await
+            .change_context(QueryExecutionError::RowExtractionFailure)
+    }
+}
+
+fn to_object(
+    fields: &[TableField],
+    row: TableRow,
+) -> CustomResult<serde_json::Value, QueryExecutionError> {
+    fields
+        .iter()
+        .zip(row.f)
+        .map(|(field, cell)| {
+            to_json(&field.field_type, cell.v).map(|value| (field.name.clone(), value))
+        })
+        .collect::<CustomResult<serde_json::Map<_, _>, _>>()
+        .map(serde_json::Value::Object)
+}
+
+/// BigQuery returns every value as a string, they're converted by the type of the column
+fn to_json(
+    field_type: &str,
+    value: serde_json::Value,
+) -> CustomResult<serde_json::Value, QueryExecutionError> {
+    let serde_json::Value::String(value) = value else {
+        return Ok(value);
+    };
+    let parse_error = || {
+        report!(QueryExecutionError::RowExtractionFailure)
+            .attach_printable(format!("Invalid {field_type} value {value}"))
+    };
+    Ok(match field_type {
+        "INTEGER" | "INT64" => value.parse::<i64>().map_err(|_| parse_error())?.into(),
+        "FLOAT" | "FLOAT64" => value.parse::<f64>().map_err(|_| parse_error())?.into(),
+        "BOOLEAN" | "BOOL" => value.parse::<bool>().map_err(|_| parse_error())?.into(),
+        // Seconds since the epoch, with a fraction for sub second precision
+        "TIMESTAMP" => {
+            let seconds = value.parse::<f64>().map_err(|_| parse_error())?;
+            let date_time = time::Duration::checked_seconds_f64(seconds)
+                .and_then(|since_epoch| time::OffsetDateTime::UNIX_EPOCH.checked_add(since_epoch))
+                .ok_or_else(parse_error)?;
+            format_time_bucket(PrimitiveDateTime::new(date_time.date(), date_time.time()))
+                .change_context(QueryExecutionError::RowExtractionFailure)?
+                .into()
+        }
+        // Numerics are kept as a string so that no precision is lost, bigdecimal parses them back
+        _ => value.into(),
+    })
+}
+
+#[async_trait::async_trait]
+impl AnalyticsDataSource for BigQueryClient {
+    type Row = serde_json::Value;
+
+    fn source_name(&self) -> &'static str {
+        "BigQuery"
+    }
+
+    async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        logger::debug!("Executing query: {query}");
+        self.fetch_rows(query)
+            .await?
+            .into_iter()
+            .map(Self::load_row)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(QueryExecutionError::RowExtractionFailure)
+    }
+
+    fn get_time_bucket(
+        time_column: &str,
+        granularity: &Granularity,
+        time_zone: Option<&str>,
+    ) -> String {
+        let tz = time_zone.map(|tz| format!(", '{tz}'")).unwrap_or_default();
+        // Minute intervals are counted from the unix epoch, like clickhouse does
+        let interval = |minutes: u32| {
+            format!(
+                "TIMESTAMP_SECONDS(DIV(UNIX_SECONDS({time_column}), {seconds}) * {seconds})",
+                seconds = minutes * 60
+            )
+        };
+        let time_bucket = match granularity {
+            Granularity::OneMin => format!("TIMESTAMP_TRUNC({time_column}, MINUTE)"),
+            Granularity::FiveMin => interval(5),
+            Granularity::FifteenMin => interval(15),
+            Granularity::ThirtyMin => interval(30),
+            Granularity::OneHour => format!("TIMESTAMP_TRUNC({time_column}, HOUR{tz})"),
+            Granularity::OneDay => format!("TIMESTAMP_TRUNC({time_column}, DAY{tz})"),
+            // ISO weeks start on Monday
+            Granularity::Weekly => format!("TIMESTAMP_TRUNC({time_column}, ISOWEEK{tz})"),
+            Granularity::Monthly => format!("TIMESTAMP_TRUNC({time_column}, MONTH{tz})"),
+            Granularity::Quarterly => format!("TIMESTAMP_TRUNC({time_column}, QUARTER{tz})"),
+        };
+        // Rendered as the wall clock time of the bucket start in the requested zone
+        format!("FORMAT_TIMESTAMP('%Y-%m-%d %H:%M:%S', {time_bucket}{tz})")
+    }
+
+    fn get_date_part(time_column: &str, part: DatePart, time_zone: Option<&str>) -> String {
+        let tz = time_zone.map(|tz| format!(", '{tz}'")).unwrap_or_default();
+        match part {
+            // %u is the ISO weekday, counted from Monday as 1
+            DatePart::DayOfWeek => format!("FORMAT_TIMESTAMP('%u', {time_column}{tz})"),
+            DatePart::HourOfDay => format!(
+                "CAST(EXTRACT(HOUR FROM {time_column} AT TIME ZONE '{}') AS STRING)",
+                time_zone.unwrap_or("UTC")
+            ),
+        }
+    }
+
+    fn get_json_string(column: &str, key: &str) -> String {
+        format!("JSON_VALUE(SAFE.PARSE_JSON({column}), '$.{key}')")
+    }
+
+    fn get_json_number(column: &str, key: &str) -> String {
+        format!(
+            "SAFE_CAST({} AS FLOAT64)",
+            Self::get_json_string(column, key)
+        )
+    }
+
+    fn get_sample_clause(sample_rate: f64) -> String {
+        format!("TABLESAMPLE SYSTEM ({} PERCENT)", sample_rate * 100.0)
+    }
+
+    fn get_max_partitions_clause(_max_partitions: u32) -> Option<String> {
+        // BigQuery bounds the bytes a query reads rather than its partitions
+        None
+    }
+
+    fn get_string_cast(expression: &str) -> String {
+        format!("CAST({expression} AS STRING)")
+    }
+
+    fn get_count_if(condition: &str, count_column: Option<&str>) -> String {
+        format!(
+            "sum(CASE WHEN {condition} THEN {} ELSE 0 END)",
+            count_column.unwrap_or("1")
+        )
+    }
+}
+
+impl<T, E> LoadRow<T> for BigQueryClient
+where
+    Self::Row: TryInto<T, Error = Report<E>>,
+{
+    fn load_row(row: Self::Row) -> CustomResult<T, QueryExecutionError> {
+        row.try_into()
+            .map_err(|error| error.change_context(QueryExecutionError::RowExtractionFailure))
+    }
+}
+
+impl super::sdk_events::metrics::SdkEventMetricAnalytics for BigQueryClient {}
+
+impl ToSql<BigQueryClient> for PrimitiveDateTime {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        // Compared against timestamp columns, which coerces the string literal to a UTC timestamp
+        format_time_bucket(*self)
+    }
+}
+
+impl ToSql<BigQueryClient> for AnalyticsCollection {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        // Resolved in the default dataset of the query, quoted since names may clash with
+        // reserved words
+        match self {
+            Self::Payment => Ok("`payment_attempts`".to_string()),
+            Self::Refund => Ok("`refunds`".to_string()),
+            Self::SdkEvents => Ok("`sdk_events_audit`".to_string()),
+            Self::SdkEventsArchive => Ok("`sdk_events_audit_archive`".to_string()),
+            Self::SdkEventsHourly => Ok("`sdk_events_hourly`".to_string()),
+            Self::ApiEvents => Ok("`api_events_audit`".to_string()),
+            Self::PaymentIntent => Ok("`payment_intents`".to_string()),
+            Self::ConnectorEvents => Ok("`connector_events_audit`".to_string()),
+            Self::OutgoingWebhookEvent => Ok("`outgoing_webhook_events_audit`".to_string()),
+            Self::Dispute => Ok("`dispute`".to_string()),
+            Self::CustomerSegments => Ok("`customer_segments`".to_string()),
+        }
+    }
+}
+
+impl<T> ToSql<BigQueryClient> for Aggregate<T>
+where
+    T: ToSql<BigQueryClient>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        Ok(match self {
+            Self::Count { field: _, alias } => {
+                format!(
+                    "count(*){}",
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Sum { field, alias } => {
+                format!(
+                    "sum({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to sum aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Min { field, alias } => {
+                format!(
+                    "min({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to min aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Max { field, alias } => {
+                format!(
+                    "max({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to max aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // The quantile is picked out of the percentiles of the column
+            Self::Percentile {
+                field,
+                alias,
+                quantile,
+            } => {
+                format!(
+                    "APPROX_QUANTILES({}, 100)[OFFSET({:.0})]{}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to percentile aggregate")?,
+                    quantile * 100.0,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CountDistinct { field, alias } => {
+                format!(
+                    "count(distinct {}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CountDistinctApprox { field, alias } => {
+                format!(
+                    "APPROX_COUNT_DISTINCT({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to approximate count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // BigQuery has no t-digest, its approximate quantiles are the closest there is
+            Self::MedianTDigest { field, alias } => {
+                format!(
+                    "APPROX_QUANTILES({}, 2)[OFFSET(1)]{}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to approximate median aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // Every quantile is picked out of the same percentiles of the column, like a
+            // percentile
+            Self::Quantiles { field, quantiles } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to quantiles aggregate")?;
+                quantiles
+                    .iter()
+                    .map(|(alias, quantile)| {
+                        format!(
+                            "APPROX_QUANTILES({field}, 100)[OFFSET({:.0})] as {alias}",
+                            quantile * 100.0
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+            Self::StdDev { field, alias } => {
+                format!(
+                    "STDDEV_POP({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to standard deviation aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Variance { field, alias } => {
+                format!(
+                    "VAR_POP({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to variance aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+        })
+    }
+}
+
+impl<T> ToSql<BigQueryClient> for Window<T>
+where
+    T: ToSql<BigQueryClient>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        Ok(match self {
+            Self::Sum {
+                field,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "sum({}) over ({}{}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to sum window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::RowNumber {
+                field: _,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "row_number() over ({}{}){}",
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CumulativeSum {
+                field,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "sum({}) over ({}{} rows unbounded preceding){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to cumulative sum window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::MovingAverage {
+                field,
+                partition_by,
+                order_by,
+                preceding,
+                alias,
+            } => {
+                format!(
+                    "avg({}) over ({}{} rows between {preceding} preceding and current row){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to moving average window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::PercentOfTotal {
+                field,
+                partition_by,
+                alias,
+            } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to percent of total window")?;
+                format!(
+                    "{field} * 100 / NULLIF(sum({field}) over ({}), 0){}",
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use super::*;
+
+    #[test]
+    fn test_rows_are_converted_by_column_type() {
+        let fields = serde_json::from_value::<Vec<TableField>>(serde_json::json!([
+            { "name": "platform", "type": "STRING" },
+            { "name": "count", "type": "INTEGER" },
+            { "name": "rate", "type": "NUMERIC" },
+            { "name": "created_at", "type": "TIMESTAMP" },
+            { "name": "browser_name", "type": "STRING" },
+        ]))
+        .expect("invalid schema");
+        let row = serde_json::from_value::<TableRow>(serde_json::json!({
+            "f": [
+                { "v": "web" },
+                { "v": "42" },
+                { "v": "0.125" },
+                { "v": "1.7092872E9" },
+                { "v": null },
+            ]
+        }))
+        .expect("invalid row");
+
+        assert_eq!(
+            to_object(&fields, row).expect("failed to convert row"),
+            serde_json::json!({
+                "platform": "web",
+                "count": 42,
+                "rate": "0.125",
+                "created_at": "2024-03-01 10:00:00",
+                "browser_name": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_percentile_lowering() {
+        let percentile = Aggregate::Percentile {
+            field: "latency",
+            alias: Some("p90"),
+            quantile: 0.9,
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<BigQueryClient>>::to_sql(
+                &percentile,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "APPROX_QUANTILES(latency, 100)[OFFSET(90)] as p90"
+        );
+    }
+
+    #[test]
+    fn test_time_bucket_in_time_zone() {
+        assert_eq!(
+            BigQueryClient::get_time_bucket(
+                "created_at",
+                &Granularity::OneDay,
+                Some("Asia/Kolkata")
+            ),
+            "FORMAT_TIMESTAMP('%Y-%m-%d %H:%M:%S', TIMESTAMP_TRUNC(created_at, DAY, 'Asia/Kolkata'), 'Asia/Kolkata')"
+        );
+        assert_eq!(
+            BigQueryClient::get_time_bucket("created_at", &Granularity::FiveMin, None),
+            "FORMAT_TIMESTAMP('%Y-%m-%d %H:%M:%S', TIMESTAMP_SECONDS(DIV(UNIX_SECONDS(created_at), 300) * 300))"
+        );
+    }
+
+    #[test]
+    fn test_quantiles_lowering() {
+        let quantiles = Aggregate::Quantiles {
+            field: "latency",
+            quantiles: &[("p50", 0.5), ("p99", 0.99)],
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<BigQueryClient>>::to_sql(
+                &quantiles,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "APPROX_QUANTILES(latency, 100)[OFFSET(50)] as p50, \
+             APPROX_QUANTILES(latency, 100)[OFFSET(99)] as p99"
+        );
+    }
+}
@@ -1,20 +1,28 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use actix_web::http::StatusCode;
 use common_utils::errors::ParsingError;
 use error_stack::{report, Report, ResultExt};
+use futures::{Stream, StreamExt};
 use router_env::logger;
 use time::PrimitiveDateTime;
 
 use super::{
+    fallback::LoadRawRows,
     health_check::HealthCheck,
     payments::{
         distribution::PaymentDistributionRow, filters::FilterRow, metrics::PaymentMetricRow,
     },
     query::{Aggregate, ToSql, Window},
     refunds::{filters::RefundFilterRow, metrics::RefundMetricRow},
-    sdk_events::{filters::SdkEventFilter, metrics::SdkEventMetricRow},
-    types::{AnalyticsCollection, AnalyticsDataSource, LoadRow, QueryExecutionError},
+    sdk_events::{
+        filters::SdkEventFilter,
+        metrics::{MetricsCacheConfig, SdkEventBatchRow, SdkEventMetricRow, SdkEventMetricsCache},
+    },
+    types::{
+        AnalyticsCollection, AnalyticsDataSource, Gauge, GaugeGuard, LoadRow, MerchantQueryLimit,
+        MerchantQueryLimiter, PoolStats, QueryExecutionError, QueryReport, RetryConfig,
+    },
 };
 use crate::{
     api_event::{
@@ -26,6 +34,7 @@ use crate::{
     disputes::{filters::DisputeFilterRow, metrics::DisputeMetricRow},
     outgoing_webhook_event::events::OutgoingWebhookLogsResult,
     sdk_events::events::SdkEventsResult,
+    time_range::GranularityThresholds,
     types::TableEngine,
 };
 
@@ -34,6 +43,15 @@ pub type ClickhouseResult<T> = error_stack::Result<T, ClickhouseError>;
 #[derive(Clone, Debug)]
 pub struct ClickhouseClient {
     pub config: Arc<ClickhouseConfig>,
+    merchant_query_limiter: Option<Arc<MerchantQueryLimiter>>,
+    metrics_cache: Option<Arc<SdkEventMetricsCache>>,
+    client: reqwest::Client,
+    /// Slots of [`ClickhousePoolConfig::max_connections`], `None` if it's unbounded
+    connection_limit: Option<Arc<tokio::sync::Semaphore>>,
+    /// Queries which were sent and whose response isn't read to its end yet
+    in_use: Arc<Gauge>,
+    /// Queries waiting for a slot of `connection_limit`
+    waiters: Arc<Gauge>,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
@@ -42,6 +60,52 @@ pub struct ClickhouseConfig {
     password: Option<String>,
     host: String,
     database_name: String,
+    #[serde(default)]
+    retry: RetryConfig,
+    /// Queries estimated to read more rows are rejected before being run
+    #[serde(default)]
+    max_scanned_rows: Option<u64>,
+    /// Sdk event metric queries returning more rows are truncated to their first rows
+    #[serde(default)]
+    max_result_rows: Option<usize>,
+    /// Whether the materialized views of the sdk events have been created
+    #[serde(default)]
+    materialized_views: bool,
+    /// Metric queries over a range of more days only query its last days
+    #[serde(default)]
+    max_time_range_days: Option<u32>,
+    /// Limits the metric queries each merchant runs at once
+    #[serde(default)]
+    merchant_query_limit: Option<MerchantQueryLimit>,
+    /// Keeps the results of sdk event metric queries in memory, so that identical queries
+    /// aren't run again
+    #[serde(default)]
+    metrics_cache: Option<MetricsCacheConfig>,
+    /// Range lengths of the granularities suggested for time series without one
+    #[serde(default)]
+    granularity_thresholds: GranularityThresholds,
+    #[serde(default)]
+    pool: ClickhousePoolConfig,
+}
+
+/// Sizing of the HTTP connections to clickhouse. Every connection runs a single query at a time,
+/// settings which are left unset keep the defaults of the HTTP client.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ClickhousePoolConfig {
+    /// Queries sent at once, further ones wait for one of them to finish. Unbounded if unset.
+    pub max_connections: Option<u32>,
+    /// Idle connections kept open for later queries
+    pub max_idle_connections: Option<usize>,
+    /// Seconds an idle connection is kept open for
+    pub idle_timeout_secs: Option<u64>,
+}
+
+/// A query's slot of [`ClickhousePoolConfig::max_connections`], the query counts as in use until
+/// it's dropped
+struct Connection {
+    _permit: Option<tokio::sync::OwnedSemaphorePermit>,
+    _in_use: GaugeGuard,
 }
 
 impl Default for ClickhouseConfig {
@@ -51,30 +115,190 @@ impl Default for ClickhouseConfig {
             password: None,
             host: "http://localhost:8123".to_string(),
             database_name: "default".to_string(),
+            retry: RetryConfig::default(),
+            max_scanned_rows: None,
+            max_result_rows: None,
+            materialized_views: false,
+            max_time_range_days: None,
+            merchant_query_limit: None,
+            metrics_cache: None,
+            granularity_thresholds: GranularityThresholds::default(),
+            pool: ClickhousePoolConfig::default(),
         }
     }
 }
 
 impl ClickhouseClient {
+    pub fn from_conf(conf: &ClickhouseConfig) -> Self {
+        let mut client = reqwest::Client::builder();
+        if let Some(max_idle_connections) = conf.pool.max_idle_connections {
+            client = client.pool_max_idle_per_host(max_idle_connections);
+        }
+        if let Some(idle_timeout_secs) = conf.pool.idle_timeout_secs {
+            client = client.pool_idle_timeout(Duration::from_secs(idle_timeout_secs));
+        }
+        let client = client.build().unwrap_or_else(|error| {
+            logger::error!(
+                ?error,
+                "Failed to configure clickhouse client, using the defaults"
+            );
+            reqwest::Client::new()
+        });
+        Self {
+            config: Arc::new(conf.clone()),
+            merchant_query_limiter: conf
+                .merchant_query_limit
+                .as_ref()
+                .map(|limit| Arc::new(MerchantQueryLimiter::new(limit))),
+            metrics_cache: conf
+                .metrics_cache
+                .as_ref()
+                .map(|config| Arc::new(SdkEventMetricsCache::new(config))),
+            client,
+            connection_limit: conf.pool.max_connections.map(|max_connections| {
+                let permits = usize::try_from(max_connections).unwrap_or(usize::MAX);
+                // A limit of no connections would never run a query
+                Arc::new(tokio::sync::Semaphore::new(
+                    permits.clamp(1, tokio::sync::Semaphore::MAX_PERMITS),
+                ))
+            }),
+            in_use: Arc::default(),
+            waiters: Arc::default(),
+        }
+    }
+
+    /// Waits for a slot of the connection limit if there is one
+    async fn connection(&self) -> ClickhouseResult<Connection> {
+        let permit = match &self.connection_limit {
+            Some(limit) => {
+                let _waiting = self.waiters.track();
+                Some(
+                    Arc::clone(limit)
+                        .acquire_owned()
+                        .await
+                        .change_context(ClickhouseError::ConnectionError)
+                        .attach_printable("Clickhouse connection limit closed")?,
+                )
+            }
+            None => None,
+        };
+        Ok(Connection {
+            _permit: permit,
+            _in_use: self.in_use.track(),
+        })
+    }
+
     async fn execute_query(&self, query: &str) -> ClickhouseResult<Vec<serde_json::Value>> {
+        self.execute_query_with_max_execution_time(query, None)
+            .await
+    }
+
+    /// `max_execution_time` is in seconds and makes clickhouse abort the query server side.
+    async fn execute_query_with_max_execution_time(
+        &self,
+        query: &str,
+        max_execution_time: Option<u64>,
+    ) -> ClickhouseResult<Vec<serde_json::Value>> {
+        self.config
+            .retry
+            .retry(
+                |error: &Report<ClickhouseError>| error.current_context().is_transient(),
+                || self.send_query(query, max_execution_time),
+            )
+            .await
+    }
+
+    /// Sends the query and hands out the result rows as they arrive instead of waiting for the
+    /// whole response body.
+    async fn stream_query(
+        &self,
+        query: &str,
+    ) -> ClickhouseResult<impl Stream<Item = ClickhouseResult<serde_json::Value>> + Send + 'static>
+    {
+        let connection = self.connection().await?;
+        let response = self
+            .config
+            .retry
+            .retry(
+                |error: &Report<ClickhouseError>| error.current_context().is_transient(),
+                || self.send(query, "JSONEachRow", None, false),
+            )
+            .await?;
+        // The connection stays in use until the caller stops reading rows
+        Ok(json_each_row(response.bytes_stream()).map(move |row| {
+            let _connection = &connection;
+            row
+        }))
+    }
+
+    async fn send_query(
+        &self,
+        query: &str,
+        max_execution_time: Option<u64>,
+    ) -> ClickhouseResult<Vec<serde_json::Value>> {
+        let _connection = self.connection().await?;
+        Ok(self
+            .send(query, "JSON", max_execution_time, false)
+            .await?
+            .json::<CkhOutput<serde_json::Value>>()
+            .await
+            .change_context(ClickhouseError::ResponseError)?
+            .data)
+    }
+
+    /// Like [`Self::send_query`] but also reads how much the query read from the
+    /// `X-ClickHouse-Summary` header of the response. Clickhouse holds the response back until
+    /// the query is done so that the summary is the final one.
+    async fn send_query_with_summary(
+        &self,
+        query: &str,
+        max_execution_time: Option<u64>,
+    ) -> ClickhouseResult<(Vec<serde_json::Value>, QueryReport)> {
+        let _connection = self.connection().await?;
+        let response = self.send(query, "JSON", max_execution_time, true).await?;
+        let report = response
+            .headers()
+            .get(SUMMARY_HEADER)
+            .and_then(|summary| summary.to_str().ok())
+            .map(query_report)
+            .unwrap_or_default();
+        let rows = response
+            .json::<CkhOutput<serde_json::Value>>()
+            .await
+            .change_context(ClickhouseError::ResponseError)?
+            .data;
+        Ok((rows, report))
+    }
+
+    async fn send(
+        &self,
+        query: &str,
+        format: &str,
+        max_execution_time: Option<u64>,
+        wait_end_of_query: bool,
+    ) -> ClickhouseResult<reqwest::Response> {
         logger::debug!("Executing query: {query}");
-        let client = reqwest::Client::new();
         let params = CkhQuery {
             date_time_output_format: String::from("iso"),
             output_format_json_quote_64bit_integers: 0,
             database: self.config.database_name.clone(),
+            max_execution_time,
+            wait_end_of_query: wait_end_of_query.then_some(1),
         };
-        let response = client
+        let response = self
+            .client
             .post(&self.config.host)
             .query(&params)
             .basic_auth(self.config.username.clone(), self.config.password.clone())
-            .body(format!("{query}\nFORMAT JSON"))
+            .body(format!("{query}\nFORMAT {format}"))
             .send()
             .await
             .change_context(ClickhouseError::ConnectionError)?;
 
         logger::debug!(clickhouse_response=?response, query=?query, "Clickhouse response");
-        if response.status() != StatusCode::OK {
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            Err(report!(ClickhouseError::ServiceUnavailable))
+        } else if response.status() != StatusCode::OK {
             response.text().await.map_or_else(
                 |er| {
                     Err(ClickhouseError::ResponseError)
@@ -83,15 +307,48 @@ impl ClickhouseClient {
                 |t| Err(report!(ClickhouseError::ResponseNotOK(t))),
             )
         } else {
-            Ok(response
-                .json::<CkhOutput<serde_json::Value>>()
-                .await
-                .change_context(ClickhouseError::ResponseError)?
-                .data)
+            Ok(response)
         }
     }
 }
 
+/// Splits a `JSONEachRow` response body into rows, a row may be spread over several chunks.
+fn json_each_row<S, B>(
+    chunks: S,
+) -> impl Stream<Item = ClickhouseResult<serde_json::Value>> + Send + 'static
+where
+    S: Stream<Item = reqwest::Result<B>> + Send + 'static,
+    B: AsRef<[u8]>,
+{
+    futures::stream::try_unfold(
+        (Box::pin(chunks), Vec::new()),
+        |(mut chunks, mut buffer)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line = buffer.drain(..=end).collect::<Vec<_>>();
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    let row = serde_json::from_slice(&line)
+                        .change_context(ClickhouseError::ResponseError)
+                        .attach_printable("Failed to parse clickhouse row")?;
+                    return ClickhouseResult::Ok(Some((row, (chunks, buffer))));
+                }
+                match chunks.next().await {
+                    Some(chunk) => buffer.extend_from_slice(
+                        chunk
+                            .change_context(ClickhouseError::ResponseError)?
+                            .as_ref(),
+                    ),
+                    None if buffer.iter().all(u8::is_ascii_whitespace) => return Ok(None),
+                    // The last row isn't followed by a newline
+                    None => buffer.push(b'\n'),
+                }
+            }
+        },
+    )
+}
+
 #[async_trait::async_trait]
 impl HealthCheck for ClickhouseClient {
     async fn deep_health_check(
@@ -108,6 +365,10 @@ impl HealthCheck for ClickhouseClient {
 impl AnalyticsDataSource for ClickhouseClient {
     type Row = serde_json::Value;
 
+    fn source_name(&self) -> &'static str {
+        "Clickhouse"
+    }
+
     async fn load_results<T>(
         &self,
         query: &str,
@@ -117,13 +378,130 @@ impl AnalyticsDataSource for ClickhouseClient {
     {
         self.execute_query(query)
             .await
-            .change_context(QueryExecutionError::DatabaseError)?
+            .map_err(query_error)?
             .into_iter()
             .map(Self::load_row)
             .collect::<Result<Vec<_>, _>>()
             .change_context(QueryExecutionError::RowExtractionFailure)
     }
 
+    async fn load_results_with_timeout<T>(
+        &self,
+        query: &str,
+        timeout: Duration,
+    ) -> common_utils::errors::CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        tokio::time::timeout(
+            timeout,
+            self.execute_query_with_max_execution_time(query, Some(max_execution_time(timeout))),
+        )
+        .await
+        .map_err(|_| report!(QueryExecutionError::Timeout))
+        .attach_printable_lazy(|| format!("Query did not finish within {timeout:?}"))?
+        .map_err(query_error)?
+        .into_iter()
+        .map(Self::load_row)
+        .collect::<Result<Vec<_>, _>>()
+        .change_context(QueryExecutionError::RowExtractionFailure)
+    }
+
+    async fn load_results_with_report<T>(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> common_utils::errors::CustomResult<(Vec<T>, QueryReport), QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        let load = self.config.retry.retry(
+            |error: &Report<ClickhouseError>| error.current_context().is_transient(),
+            || self.send_query_with_summary(query, timeout.map(max_execution_time)),
+        );
+        let (rows, report) = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, load)
+                .await
+                .map_err(|_| report!(QueryExecutionError::Timeout))
+                .attach_printable_lazy(|| format!("Query did not finish within {timeout:?}"))?,
+            None => load.await,
+        }
+        .map_err(query_error)?;
+        let rows = rows
+            .into_iter()
+            .map(Self::load_row)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(QueryExecutionError::RowExtractionFailure)?;
+        Ok((rows, report))
+    }
+
+    async fn load_results_stream<T>(
+        &self,
+        query: String,
+    ) -> common_utils::errors::CustomResult<RowStream<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+        T: Send + 'static,
+    {
+        Ok(self
+            .stream_query(&query)
+            .await
+            .map_err(query_error)?
+            .map(|row| row.map_err(query_error).and_then(Self::load_row))
+            .boxed())
+    }
+
+    async fn estimate_scanned_rows(
+        &self,
+        query: &str,
+    ) -> common_utils::errors::CustomResult<Option<u64>, QueryExecutionError> {
+        // One row per table read, with the number of rows in the parts the query has to read
+        let rows = self
+            .execute_query(&format!("EXPLAIN ESTIMATE {query}"))
+            .await
+            .map_err(query_error)
+            .attach_printable_lazy(|| format!("Failed to estimate query {query}"))?
+            .iter()
+            .filter_map(|table| table.get("rows").and_then(serde_json::Value::as_u64))
+            .fold(0, u64::saturating_add);
+        Ok(Some(rows))
+    }
+
+    fn max_scanned_rows(&self) -> Option<u64> {
+        self.config.max_scanned_rows
+    }
+
+    fn max_result_rows(&self) -> Option<usize> {
+        self.config.max_result_rows
+    }
+
+    fn has_materialized_views(&self) -> bool {
+        self.config.materialized_views
+    }
+
+    fn max_time_range(&self) -> Option<time::Duration> {
+        self.config
+            .max_time_range_days
+            .map(|days| time::Duration::days(days.into()))
+    }
+
+    fn granularity_thresholds(&self) -> GranularityThresholds {
+        self.config.granularity_thresholds.clone()
+    }
+
+    fn merchant_query_limiter(&self) -> Option<&MerchantQueryLimiter> {
+        self.merchant_query_limiter.as_deref()
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        // The HTTP client doesn't tell how many of its connections are idle
+        Some(PoolStats {
+            in_use: self.in_use.get(),
+            idle: None,
+            waiters: self.waiters.get(),
+        })
+    }
+
     fn get_table_engine(table: AnalyticsCollection) -> TableEngine {
         match table {
             AnalyticsCollection::Payment
@@ -133,10 +511,33 @@ impl AnalyticsDataSource for ClickhouseClient {
                 TableEngine::CollapsingMergeTree { sign: "sign_flag" }
             }
             AnalyticsCollection::SdkEvents => TableEngine::BasicTree,
+            AnalyticsCollection::SdkEventsArchive => TableEngine::BasicTree,
+            AnalyticsCollection::SdkEventsHourly => TableEngine::BasicTree,
             AnalyticsCollection::ApiEvents => TableEngine::BasicTree,
             AnalyticsCollection::ConnectorEvents => TableEngine::BasicTree,
             AnalyticsCollection::OutgoingWebhookEvent => TableEngine::BasicTree,
+            AnalyticsCollection::CustomerSegments => TableEngine::BasicTree,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LoadRawRows for ClickhouseClient {
+    async fn load_raw_rows(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> common_utils::errors::CustomResult<Vec<serde_json::Value>, QueryExecutionError> {
+        let load =
+            self.execute_query_with_max_execution_time(query, timeout.map(max_execution_time));
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, load)
+                .await
+                .map_err(|_| report!(QueryExecutionError::Timeout))
+                .attach_printable_lazy(|| format!("Query did not finish within {timeout:?}"))?,
+            None => load.await,
         }
+        .map_err(query_error)
     }
 }
 
@@ -156,7 +557,11 @@ impl super::payments::distribution::PaymentDistributionAnalytics for ClickhouseC
 impl super::refunds::metrics::RefundMetricAnalytics for ClickhouseClient {}
 impl super::refunds::filters::RefundFilterAnalytics for ClickhouseClient {}
 impl super::sdk_events::filters::SdkEventFilterAnalytics for ClickhouseClient {}
-impl super::sdk_events::metrics::SdkEventMetricAnalytics for ClickhouseClient {}
+impl super::sdk_events::metrics::SdkEventMetricAnalytics for ClickhouseClient {
+    fn metrics_cache(&self) -> Option<&SdkEventMetricsCache> {
+        self.metrics_cache.as_deref()
+    }
+}
 impl super::sdk_events::events::SdkEventsFilterAnalytics for ClickhouseClient {}
 impl super::api_event::events::ApiLogsFilterAnalytics for ClickhouseClient {}
 impl super::api_event::filters::ApiEventFilterAnalytics for ClickhouseClient {}
@@ -174,6 +579,41 @@ struct CkhQuery {
     date_time_output_format: String,
     output_format_json_quote_64bit_integers: u8,
     database: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_execution_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_end_of_query: Option<u8>,
+}
+
+/// Header of a response clickhouse reports the progress of its query in
+const SUMMARY_HEADER: &str = "X-ClickHouse-Summary";
+
+/// Progress of a query as clickhouse reports it in its [`SUMMARY_HEADER`], with quoted numbers
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct CkhSummary {
+    read_rows: Option<String>,
+    read_bytes: Option<String>,
+}
+
+/// How much a query read according to its `summary`, nothing read if it can't be parsed
+fn query_report(summary: &str) -> QueryReport {
+    let summary = serde_json::from_str::<CkhSummary>(summary).unwrap_or_default();
+    QueryReport {
+        scanned_rows: summary.read_rows.and_then(|rows| rows.parse().ok()),
+        scanned_bytes: summary.read_bytes.and_then(|bytes| bytes.parse().ok()),
+        served_by_fallback: false,
+    }
+}
+
+/// `max_execution_time` which lets clickhouse stop a query on its end too once `timeout` has
+/// elapsed, the client side timeout only drops the connection. The setting has a resolution of
+/// seconds so it's rounded up.
+fn max_execution_time(timeout: Duration) -> u64 {
+    timeout
+        .as_secs()
+        .saturating_add(u64::from(timeout.subsec_nanos() > 0))
+        .max(1)
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -310,6 +750,16 @@ impl TryInto<SdkEventMetricRow> for serde_json::Value {
     }
 }
 
+impl TryInto<SdkEventBatchRow> for serde_json::Value {
+    type Error = Report<ParsingError>;
+
+    fn try_into(self) -> Result<SdkEventBatchRow, Self::Error> {
+        serde_json::from_value(self).change_context(ParsingError::StructParseFailure(
+            "Failed to parse SdkEventBatchRow in clickhouse results",
+        ))
+    }
+}
+
 impl TryInto<SdkEventFilter> for serde_json::Value {
     type Error = Report<ParsingError>;
 
@@ -360,11 +810,14 @@ impl ToSql<ClickhouseClient> for AnalyticsCollection {
             Self::Payment => Ok("payment_attempts".to_string()),
             Self::Refund => Ok("refunds".to_string()),
             Self::SdkEvents => Ok("sdk_events_audit".to_string()),
+            Self::SdkEventsArchive => Ok("sdk_events_audit_archive".to_string()),
+            Self::SdkEventsHourly => Ok("sdk_events_hourly".to_string()),
             Self::ApiEvents => Ok("api_events_audit".to_string()),
             Self::PaymentIntent => Ok("payment_intents".to_string()),
             Self::ConnectorEvents => Ok("connector_events_audit".to_string()),
             Self::OutgoingWebhookEvent => Ok("outgoing_webhook_events_audit".to_string()),
             Self::Dispute => Ok("dispute".to_string()),
+            Self::CustomerSegments => Ok("customer_segments".to_string()),
         }
     }
 }
@@ -423,6 +876,84 @@ where
                     alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
                 )
             }
+            Self::Percentile {
+                field,
+                alias,
+                quantile,
+            } => {
+                format!(
+                    "quantile({quantile})({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to percentile aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CountDistinct { field, alias } => {
+                format!(
+                    "uniqExact({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CountDistinctApprox { field, alias } => {
+                format!(
+                    "uniq({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to approximate count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::MedianTDigest { field, alias } => {
+                format!(
+                    "quantileTDigest(0.5)({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to approximate median aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // The columns pick their quantile out of the array of every quantile, the identical
+            // aggregates of each column are only computed once
+            Self::Quantiles { field, quantiles } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to quantiles aggregate")?;
+                let levels = quantiles
+                    .iter()
+                    .map(|(_, quantile)| quantile.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                quantiles
+                    .iter()
+                    .zip(1..)
+                    .map(|((alias, _), index)| {
+                        format!("quantiles({levels})({field})[{index}] as {alias}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+            Self::StdDev { field, alias } => {
+                format!(
+                    "stddevPop({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to standard deviation aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Variance { field, alias } => {
+                format!(
+                    "varPop({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to variance aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
         })
     }
 }
@@ -482,6 +1013,76 @@ where
                     alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
                 )
             }
+            Self::CumulativeSum {
+                field,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "sum({}) over ({}{} rows unbounded preceding){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to cumulative sum window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::MovingAverage {
+                field,
+                partition_by,
+                order_by,
+                preceding,
+                alias,
+            } => {
+                format!(
+                    "avg({}) over ({}{} rows between {preceding} preceding and current row){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to moving average window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::PercentOfTotal {
+                field,
+                partition_by,
+                alias,
+            } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to percent of total window")?;
+                format!(
+                    "{field} * 100 / nullIf(sum({field}) over ({}), 0){}",
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
         })
     }
 }
@@ -494,4 +1095,154 @@ pub enum ClickhouseError {
     ResponseNotOK(String),
     #[error("Clickhouse response error")]
     ResponseError,
+    #[error("Clickhouse service unavailable")]
+    ServiceUnavailable,
+}
+
+/// A failed query as it's reported by the data source, the ones clickhouse couldn't be reached for
+/// are told apart
+fn query_error(error: Report<ClickhouseError>) -> Report<QueryExecutionError> {
+    let context = if error.current_context().is_transient() {
+        QueryExecutionError::ConnectionFailure
+    } else {
+        QueryExecutionError::DatabaseError
+    };
+    error.change_context(context)
+}
+
+impl ClickhouseError {
+    /// Whether the query could succeed if sent again unchanged
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::ConnectionError | Self::ServiceUnavailable => true,
+            Self::ResponseNotOK(_) | Self::ResponseError => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pool_stats_count_queries_waiting_for_a_connection() {
+        let client = ClickhouseClient::from_conf(&ClickhouseConfig {
+            pool: ClickhousePoolConfig {
+                max_connections: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let connection = client.connection().await.expect("no connection");
+
+        let waiting = client.connection();
+        futures::pin_mut!(waiting);
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        assert_eq!(
+            client.pool_stats(),
+            Some(PoolStats {
+                in_use: 1,
+                idle: None,
+                waiters: 1,
+            })
+        );
+
+        drop(connection);
+        let _connection = waiting.await.expect("no connection");
+        assert_eq!(
+            client.pool_stats(),
+            Some(PoolStats {
+                in_use: 1,
+                idle: None,
+                waiters: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_sdk_event_metric_row_ignores_unknown_columns() {
+        let row = serde_json::json!({
+            "platform": "web",
+            "count": 7,
+            "checkout_variant": "b",
+            "integration": { "name": "react", "version": 2 },
+        });
+
+        let row = <ClickhouseClient as LoadRow<SdkEventMetricRow>>::load_row(row)
+            .expect("failed to load row");
+        assert_eq!(
+            row,
+            SdkEventMetricRow {
+                platform: Some("web".to_string()),
+                count: Some(7),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_sdk_event_metric_row_defaults_missing_columns() {
+        let row = <ClickhouseClient as LoadRow<SdkEventMetricRow>>::load_row(serde_json::json!({}))
+            .expect("failed to load row");
+        assert_eq!(row, SdkEventMetricRow::default());
+    }
+
+    #[test]
+    fn test_query_report_is_read_from_the_summary() {
+        let summary = r#"{"read_rows":"1200","read_bytes":"96000","written_rows":"0","written_bytes":"0","total_rows_to_read":"1200","result_rows":"3","result_bytes":"120"}"#;
+        assert_eq!(
+            query_report(summary),
+            QueryReport {
+                scanned_rows: Some(1200),
+                scanned_bytes: Some(96000),
+                ..Default::default()
+            }
+        );
+        assert_eq!(query_report("not a summary"), QueryReport::default());
+    }
+
+    #[test]
+    fn test_quantiles_lowering() {
+        let quantiles = Aggregate::Quantiles {
+            field: "latency",
+            quantiles: &[("p50", 0.5), ("p99", 0.99)],
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<ClickhouseClient>>::to_sql(
+                &quantiles,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "quantiles(0.5, 0.99)(latency)[1] as p50, quantiles(0.5, 0.99)(latency)[2] as p99"
+        );
+    }
+
+    #[test]
+    fn test_spread_lowering() {
+        let stddev = Aggregate::StdDev {
+            field: "latency",
+            alias: Some("stddev"),
+        };
+        let variance = Aggregate::Variance {
+            field: "latency",
+            alias: Some("variance"),
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<ClickhouseClient>>::to_sql(
+                &stddev,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "stddevPop(latency) as stddev"
+        );
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<ClickhouseClient>>::to_sql(
+                &variance,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "varPop(latency) as variance"
+        );
+    }
 }
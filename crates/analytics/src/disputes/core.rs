@@ -115,6 +115,9 @@ pub async fn get_metrics(
         query_data,
         meta_data: [AnalyticsMetadata {
             current_time_range: req.time_range,
+            sample_rate: None,
+            truncated: false,
+            query_stats: Vec::new(),
         }],
     })
 }
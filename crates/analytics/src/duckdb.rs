@@ -0,0 +1,783 @@
+use std::sync::{Arc, Mutex};
+
+use api_models::analytics::Granularity;
+use common_utils::errors::{CustomResult, ParsingError};
+use duckdb::{
+    types::{TimeUnit, Value},
+    Connection,
+};
+use error_stack::{report, Report, ResultExt};
+use router_env::logger;
+use time::PrimitiveDateTime;
+
+use super::{
+    query::{format_time_bucket, Aggregate, DatePart, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, LoadRow, QueryExecutionError, TableEngine},
+};
+
+/// Embedded DuckDB database, meant for running analytics locally against dumps of the
+/// clickhouse tables instead of a clickhouse server.
+#[derive(Clone, Debug)]
+pub struct DuckDbClient {
+    connection: Arc<Mutex<Connection>>,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct DuckDbConfig {
+    /// Database file, the database is kept in memory when unset
+    #[serde(default)]
+    path: Option<String>,
+    /// Statements run once the database has been opened, e.g.
+    /// `CREATE VIEW sdk_events_audit AS SELECT * FROM read_parquet('dumps/sdk_events/*.parquet')`
+    #[serde(default)]
+    init_statements: Vec<String>,
+}
+
+impl DuckDbClient {
+    pub fn from_conf(conf: &DuckDbConfig) -> CustomResult<Self, QueryExecutionError> {
+        let connection = match &conf.path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        }
+        .change_context(QueryExecutionError::DatabaseError)
+        .attach_printable("Failed to open duckdb database")?;
+        for statement in conf.init_statements.iter() {
+            connection
+                .execute_batch(statement)
+                .change_context(QueryExecutionError::DatabaseError)
+                .attach_printable_lazy(|| format!("Failed to run init statement {statement}"))?;
+        }
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// New connection to the same database, so that queries can run concurrently
+    fn connect(&self) -> CustomResult<Connection, QueryExecutionError> {
+        self.connection
+            .lock()
+            .map_err(|_| report!(QueryExecutionError::DatabaseError))
+            .attach_printable("DuckDB connection lock poisoned")?
+            .try_clone()
+            .change_context(QueryExecutionError::DatabaseError)
+            .attach_printable("Failed to open duckdb connection")
+    }
+}
+
+/// Runs the query and returns every row as a JSON object keyed by column name, the same shape
+/// clickhouse returns rows in.
+fn fetch_rows(
+    connection: &Connection,
+    query: &str,
+) -> CustomResult<Vec<serde_json::Value>, QueryExecutionError> {
+    let mut statement = connection
+        .prepare(query)
+        .change_context(QueryExecutionError::DatabaseError)?;
+    let mut rows = statement
+        .query([])
+        .change_context(QueryExecutionError::DatabaseError)?;
+    // Column names are only known once the statement has been executed
+    let columns = rows
+        .as_ref()
+        .map(|statement| statement.column_names())
+        .unwrap_or_default();
+    let mut results = Vec::new();
+    while let Some(row) = rows
+        .next()
+        .change_context(QueryExecutionError::DatabaseError)?
+    {
+        let mut object = serde_json::Map::with_capacity(columns.len());
+        for (index, column) in columns.iter().enumerate() {
+            let value = row
+                .get::<_, Value>(index)
+                .change_context(QueryExecutionError::RowExtractionFailure)?;
+            object.insert(column.clone(), to_json(value)?);
+        }
+        results.push(serde_json::Value::Object(object));
+    }
+    Ok(results)
+}
+
+fn to_json(value: Value) -> CustomResult<serde_json::Value, QueryExecutionError> {
+    Ok(match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Boolean(value) => value.into(),
+        Value::TinyInt(value) => value.into(),
+        Value::SmallInt(value) => value.into(),
+        Value::Int(value) => value.into(),
+        Value::BigInt(value) => value.into(),
+        Value::UTinyInt(value) => value.into(),
+        Value::USmallInt(value) => value.into(),
+        Value::UInt(value) => value.into(),
+        Value::UBigInt(value) => value.into(),
+        // Sums of integers are 128 bit wide, they only overflow an i64 for absurd totals
+        Value::HugeInt(value) => {
+            i64::try_from(value).map_or_else(|_| value.to_string().into(), Into::into)
+        }
+        Value::Float(value) => f64::from(value).into(),
+        Value::Double(value) => value.into(),
+        // Kept as a string so that no precision is lost, bigdecimal parses it back
+        Value::Decimal(value) => value.to_string().into(),
+        Value::Text(value) | Value::Enum(value) => value.into(),
+        Value::Timestamp(unit, value) => to_primitive_date_time(unit, value)
+            .and_then(format_time_bucket)
+            .change_context(QueryExecutionError::RowExtractionFailure)?
+            .into(),
+        value => Err(report!(QueryExecutionError::RowExtractionFailure))
+            .attach_printable_lazy(|| format!("Unsupported duckdb value {value:?}"))?,
+    })
+}
+
+/// DuckDB timestamps without a time zone count from the epoch in wall clock time
+fn to_primitive_date_time(
+    unit: TimeUnit,
+    value: i64,
+) -> error_stack::Result<PrimitiveDateTime, ParsingError> {
+    let nanos_per_unit = match unit {
+        TimeUnit::Second => 1_000_000_000,
+        TimeUnit::Millisecond => 1_000_000,
+        TimeUnit::Microsecond => 1_000,
+        TimeUnit::Nanosecond => 1,
+    };
+    let date_time = time::OffsetDateTime::from_unix_timestamp_nanos(
+        i128::from(value).saturating_mul(nanos_per_unit),
+    )
+    .change_context(ParsingError::DateTimeParsingError)
+    .attach_printable("Timestamp out of range")?;
+    Ok(PrimitiveDateTime::new(date_time.date(), date_time.time()))
+}
+
+#[async_trait::async_trait]
+impl AnalyticsDataSource for DuckDbClient {
+    type Row = serde_json::Value;
+
+    fn source_name(&self) -> &'static str {
+        "DuckDb"
+    }
+
+    async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        logger::debug!("Executing query: {query}");
+        let connection = self.connect()?;
+        let owned_query = query.to_string();
+        // DuckDB runs the query on the calling thread
+        tokio::task::spawn_blocking(move || fetch_rows(&connection, &owned_query))
+            .await
+            .change_context(QueryExecutionError::DatabaseError)?
+            .attach_printable_lazy(|| format!("Failed to run query {query}"))?
+            .into_iter()
+            .map(Self::load_row)
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(QueryExecutionError::RowExtractionFailure)
+    }
+
+    fn get_time_bucket(
+        time_column: &str,
+        granularity: &Granularity,
+        time_zone: Option<&str>,
+    ) -> String {
+        // The time is a UTC timestamp, shift it to the wall clock of the requested zone
+        let time = time_zone
+            .map(|tz| format!("timezone('{tz}', timezone('UTC', {time_column}))"))
+            .unwrap_or_else(|| time_column.to_string());
+        match granularity {
+            Granularity::OneMin => format!("time_bucket(INTERVAL 1 MINUTE, {time})"),
+            Granularity::FiveMin => format!("time_bucket(INTERVAL 5 MINUTE, {time})"),
+            Granularity::FifteenMin => format!("time_bucket(INTERVAL 15 MINUTE, {time})"),
+            Granularity::ThirtyMin => format!("time_bucket(INTERVAL 30 MINUTE, {time})"),
+            Granularity::OneHour => format!("date_trunc('hour', {time})"),
+            Granularity::OneDay => format!("date_trunc('day', {time})"),
+            // Weeks start on Monday
+            Granularity::Weekly => format!("date_trunc('week', {time})"),
+            Granularity::Monthly => format!("date_trunc('month', {time})"),
+            Granularity::Quarterly => format!("date_trunc('quarter', {time})"),
+        }
+    }
+
+    fn get_date_part(time_column: &str, part: DatePart, time_zone: Option<&str>) -> String {
+        let time = time_zone
+            .map(|tz| format!("timezone('{tz}', timezone('UTC', {time_column}))"))
+            .unwrap_or_else(|| time_column.to_string());
+        match part {
+            DatePart::DayOfWeek => format!("CAST(isodow({time}) AS VARCHAR)"),
+            DatePart::HourOfDay => format!("CAST(hour({time}) AS VARCHAR)"),
+        }
+    }
+
+    fn get_json_string(column: &str, key: &str) -> String {
+        format!("CASE WHEN json_valid({column}) THEN json_extract_string({column}, '$.{key}') END")
+    }
+
+    fn get_json_number(column: &str, key: &str) -> String {
+        format!("TRY_CAST({} AS DOUBLE)", Self::get_json_string(column, key))
+    }
+
+    fn get_sample_clause(sample_rate: f64) -> String {
+        format!("TABLESAMPLE {}% (bernoulli)", sample_rate * 100.0)
+    }
+
+    fn get_max_partitions_clause(_max_partitions: u32) -> Option<String> {
+        // DuckDB tables aren't partitioned
+        None
+    }
+
+    fn get_string_cast(expression: &str) -> String {
+        format!("CAST({expression} AS VARCHAR)")
+    }
+
+    fn get_count_if(condition: &str, count_column: Option<&str>) -> String {
+        format!(
+            "sum(CASE WHEN {condition} THEN {} ELSE 0 END)",
+            count_column.unwrap_or("1")
+        )
+    }
+}
+
+impl<T, E> LoadRow<T> for DuckDbClient
+where
+    Self::Row: TryInto<T, Error = Report<E>>,
+{
+    fn load_row(row: Self::Row) -> CustomResult<T, QueryExecutionError> {
+        row.try_into()
+            .map_err(|error| error.change_context(QueryExecutionError::RowExtractionFailure))
+    }
+}
+
+impl super::sdk_events::metrics::SdkEventMetricAnalytics for DuckDbClient {}
+
+impl ToSql<DuckDbClient> for PrimitiveDateTime {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        format_time_bucket(*self)
+    }
+}
+
+impl ToSql<DuckDbClient> for AnalyticsCollection {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        // Named after the clickhouse tables, so that dumps of them can be loaded as is
+        match self {
+            Self::Payment => Ok("payment_attempts".to_string()),
+            Self::Refund => Ok("refunds".to_string()),
+            Self::SdkEvents => Ok("sdk_events_audit".to_string()),
+            Self::SdkEventsArchive => Ok("sdk_events_audit_archive".to_string()),
+            Self::SdkEventsHourly => Ok("sdk_events_hourly".to_string()),
+            Self::ApiEvents => Ok("api_events_audit".to_string()),
+            Self::PaymentIntent => Ok("payment_intents".to_string()),
+            Self::ConnectorEvents => Ok("connector_events_audit".to_string()),
+            Self::OutgoingWebhookEvent => Ok("outgoing_webhook_events_audit".to_string()),
+            Self::Dispute => Ok("dispute".to_string()),
+            Self::CustomerSegments => Ok("customer_segments".to_string()),
+        }
+    }
+}
+
+impl<T> ToSql<DuckDbClient> for Aggregate<T>
+where
+    T: ToSql<DuckDbClient>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        Ok(match self {
+            Self::Count { field: _, alias } => {
+                format!(
+                    "count(*){}",
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Sum { field, alias } => {
+                format!(
+                    "sum({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to sum aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Min { field, alias } => {
+                format!(
+                    "min({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to min aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Max { field, alias } => {
+                format!(
+                    "max({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to max aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Percentile {
+                field,
+                alias,
+                quantile,
+            } => {
+                format!(
+                    "quantile_cont({}, {quantile}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to percentile aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CountDistinct { field, alias } => {
+                format!(
+                    "count(distinct {}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CountDistinctApprox { field, alias } => {
+                format!(
+                    "approx_count_distinct({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to approximate count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // approx_quantile is backed by a t-digest as well
+            Self::MedianTDigest { field, alias } => {
+                format!(
+                    "approx_quantile({}, 0.5){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to approximate median aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // A list of quantiles is computed in one go, its items are indexed from 1
+            Self::Quantiles { field, quantiles } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to quantiles aggregate")?;
+                let levels = quantiles
+                    .iter()
+                    .map(|(_, quantile)| quantile.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                quantiles
+                    .iter()
+                    .zip(1..)
+                    .map(|((alias, _), index)| {
+                        format!("quantile_cont({field}, [{levels}])[{index}] as {alias}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+            Self::StdDev { field, alias } => {
+                format!(
+                    "stddev_pop({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to standard deviation aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Variance { field, alias } => {
+                format!(
+                    "var_pop({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to variance aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+        })
+    }
+}
+
+impl<T> ToSql<DuckDbClient> for Window<T>
+where
+    T: ToSql<DuckDbClient>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        Ok(match self {
+            Self::Sum {
+                field,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "sum({}) over ({}{}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to sum window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::RowNumber {
+                field: _,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "row_number() over ({}{}){}",
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::CumulativeSum {
+                field,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "sum({}) over ({}{} rows unbounded preceding){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to cumulative sum window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::MovingAverage {
+                field,
+                partition_by,
+                order_by,
+                preceding,
+                alias,
+            } => {
+                format!(
+                    "avg({}) over ({}{} rows between {preceding} preceding and current row){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to moving average window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::PercentOfTotal {
+                field,
+                partition_by,
+                alias,
+            } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to percent of total window")?;
+                format!(
+                    "{field} * 100 / nullif(sum({field}) over ({}), 0){}",
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use api_models::analytics::{
+        sdk_events::{SdkEventDimensions, SdkEventFilters, SdkEventMetrics},
+        Comparison, CountThreshold, TimeRange,
+    };
+    use time::{Date, Month, Time};
+
+    use super::*;
+    use crate::sdk_events::metrics::{SdkEventMetric, SdkEventQueryOptions};
+
+    fn client() -> DuckDbClient {
+        DuckDbClient::from_conf(&DuckDbConfig {
+            path: None,
+            init_statements: vec![
+                "CREATE TABLE sdk_events_audit (
+                    merchant_id VARCHAR,
+                    event_name VARCHAR,
+                    first_event UTINYINT,
+                    platform VARCHAR,
+                    created_at TIMESTAMP
+                )"
+                .to_string(),
+                "INSERT INTO sdk_events_audit VALUES
+                    ('pk_test', 'PAYMENT_ATTEMPT', 1, 'web', '2024-03-01 10:01:00'),
+                    ('pk_test', 'PAYMENT_ATTEMPT', 1, 'web', '2024-03-01 10:14:00'),
+                    ('pk_test', 'PAYMENT_ATTEMPT', 1, 'ios', '2024-03-01 10:20:00'),
+                    ('pk_test', 'PAYMENT_ATTEMPT', 0, 'web', '2024-03-01 10:21:00'),
+                    ('pk_other', 'PAYMENT_ATTEMPT', 1, 'web', '2024-03-01 10:22:00')"
+                    .to_string(),
+            ],
+        })
+        .expect("failed to open duckdb database")
+    }
+
+    fn time_range() -> TimeRange {
+        TimeRange {
+            start_time: PrimitiveDateTime::new(
+                Date::from_calendar_date(2024, Month::March, 1).expect("invalid date"),
+                Time::MIDNIGHT,
+            ),
+            end_time: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payment_attempts_by_platform() {
+        let mut counts = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &SdkEventQueryOptions::default(),
+                &time_range(),
+                &client(),
+            )
+            .await
+            .expect("failed to load metrics")
+            .into_iter()
+            .map(|(id, row)| (id.platform, row.count))
+            .collect::<Vec<_>>();
+        counts.sort();
+
+        assert_eq!(
+            counts,
+            vec![
+                (Some("ios".to_string()), Some(1)),
+                (Some("web".to_string()), Some(2))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payment_attempts_by_fifteen_minute_bucket() {
+        let mut counts = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &Some(Granularity::FifteenMin),
+                &SdkEventQueryOptions::default(),
+                &time_range(),
+                &client(),
+            )
+            .await
+            .expect("failed to load metrics")
+            .into_iter()
+            .map(|(_, row)| (row.time_bucket, row.count))
+            .collect::<Vec<_>>();
+        counts.sort();
+
+        assert_eq!(
+            counts,
+            vec![
+                (Some("2024-03-01 10:00:00".to_string()), Some(2)),
+                (Some("2024-03-01 10:15:00".to_string()), Some(1))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_groups_under_the_count_threshold_are_dropped() {
+        let options = SdkEventQueryOptions {
+            count_threshold: Some(CountThreshold {
+                comparison: Comparison::GreaterThan,
+                value: 1,
+            }),
+            ..Default::default()
+        };
+
+        let counts = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &options,
+                &time_range(),
+                &client(),
+            )
+            .await
+            .expect("failed to load metrics")
+            .into_iter()
+            .map(|(id, row)| (id.platform, row.count))
+            .collect::<Vec<_>>();
+
+        // The single ios attempt is under the threshold
+        assert_eq!(counts, vec![(Some("web".to_string()), Some(2))]);
+    }
+
+    #[tokio::test]
+    async fn test_median_payment_time_of_skewed_latencies() {
+        let client = DuckDbClient::from_conf(&DuckDbConfig {
+            path: None,
+            init_statements: vec![
+                "CREATE TABLE sdk_events_audit (
+                    merchant_id VARCHAR,
+                    event_name VARCHAR,
+                    first_event UTINYINT,
+                    latency BIGINT,
+                    created_at TIMESTAMP
+                )"
+                .to_string(),
+                // A single slow payment drags the mean far above the typical payment
+                "INSERT INTO sdk_events_audit
+                    SELECT 'pk_test', 'PAYMENT_ATTEMPT', 1, latency, '2024-03-01 10:00:00'
+                    FROM (VALUES (100), (110), (120), (130), (140), (150), (20000)) t(latency)"
+                    .to_string(),
+            ],
+        })
+        .expect("failed to open duckdb database");
+
+        let buckets = SdkEventMetrics::AveragePaymentTime
+            .load_metrics(
+                &[],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &SdkEventQueryOptions::default(),
+                &time_range(),
+                &client,
+            )
+            .await
+            .expect("failed to load metrics");
+
+        let (_, row) = buckets.first().expect("missing bucket");
+        let to_f64 = |value: &Option<bigdecimal::BigDecimal>| {
+            value
+                .as_ref()
+                .and_then(bigdecimal::ToPrimitive::to_f64)
+                .expect("missing value")
+        };
+        let mean = to_f64(&row.total) / 7.0;
+        let median = to_f64(&row.median);
+        assert!((100.0..=150.0).contains(&median));
+        assert!(mean > 2000.0);
+    }
+
+    #[tokio::test]
+    async fn test_payment_time_spread_of_constant_latencies() {
+        let client = DuckDbClient::from_conf(&DuckDbConfig {
+            path: None,
+            init_statements: vec![
+                "CREATE TABLE sdk_events_audit (
+                    merchant_id VARCHAR,
+                    event_name VARCHAR,
+                    first_event UTINYINT,
+                    latency BIGINT,
+                    created_at TIMESTAMP
+                )"
+                .to_string(),
+                "INSERT INTO sdk_events_audit
+                    SELECT 'pk_test', 'PAYMENT_ATTEMPT', 1, 250, '2024-03-01 10:00:00'
+                    FROM range(5)"
+                    .to_string(),
+            ],
+        })
+        .expect("failed to open duckdb database");
+
+        let buckets = SdkEventMetrics::AveragePaymentTime
+            .load_metrics(
+                &[],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &SdkEventQueryOptions::default(),
+                &time_range(),
+                &client,
+            )
+            .await
+            .expect("failed to load metrics");
+
+        let (_, row) = buckets.first().expect("missing bucket");
+        let to_f64 = |value: &Option<bigdecimal::BigDecimal>| {
+            value
+                .as_ref()
+                .and_then(bigdecimal::ToPrimitive::to_f64)
+                .expect("missing value")
+        };
+        assert_eq!(to_f64(&row.stddev), 0.0);
+        assert_eq!(to_f64(&row.variance), 0.0);
+    }
+
+    #[test]
+    fn test_percentile_lowering() {
+        let percentile = Aggregate::Percentile {
+            field: "latency",
+            alias: Some("p50"),
+            quantile: 0.5,
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<DuckDbClient>>::to_sql(
+                &percentile,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "quantile_cont(latency, 0.5) as p50"
+        );
+    }
+
+    #[test]
+    fn test_quantiles_lowering() {
+        let quantiles = Aggregate::Quantiles {
+            field: "latency",
+            quantiles: &[("p50", 0.5), ("p99", 0.99)],
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<DuckDbClient>>::to_sql(
+                &quantiles,
+                &TableEngine::BasicTree
+            )
+            .expect("failed to lower aggregate"),
+            "quantile_cont(latency, [0.5, 0.99])[1] as p50, \
+             quantile_cont(latency, [0.5, 0.99])[2] as p99"
+        );
+    }
+}
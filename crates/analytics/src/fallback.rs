@@ -0,0 +1,319 @@
+use std::time::Duration;
+
+use api_models::analytics::Granularity;
+use common_utils::errors::{CustomResult, ParsingError};
+use router_env::logger;
+use time::PrimitiveDateTime;
+
+use super::{
+    clickhouse::{ClickhouseClient, ClickhouseConfig},
+    metrics::{add_attributes, CONTEXT, FALLBACK_QUERIES},
+    query::{Aggregate, DatePart, ToSql, Window},
+    sdk_events::metrics::{SdkEventMetricAnalytics, SdkEventMetricsCache},
+    types::{
+        AnalyticsCollection, AnalyticsDataSource, LoadRow, MerchantQueryLimiter, PoolStats,
+        QueryExecutionError, QueryReport, TableEngine,
+    },
+};
+use crate::time_range::GranularityThresholds;
+
+/// A data source which hands out the rows of a query before they're turned into domain rows, so
+/// that [`FallbackSource`] can load them from either of its data sources
+#[async_trait::async_trait]
+pub trait LoadRawRows: AnalyticsDataSource {
+    /// Runs the query, giving up once `timeout` has elapsed if there's one
+    async fn load_raw_rows(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> CustomResult<Vec<Self::Row>, QueryExecutionError>;
+}
+
+/// Which data source of a [`FallbackSource`] answered a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServedBy {
+    Primary,
+    Secondary,
+}
+
+/// Data source which runs its queries on `primary`, and on `secondary` when `primary` can't be
+/// reached, e.g. on a read replica. Only connection failures fall back: a query which the
+/// primary rejects or which times out would fare no better on the secondary. Queries are built
+/// for the primary, so both must be of the same kind and have the same tables.
+#[derive(Debug, Clone)]
+pub struct FallbackSource<P> {
+    primary: P,
+    secondary: P,
+}
+
+impl<P: LoadRawRows> FallbackSource<P> {
+    pub fn new(primary: P, secondary: P) -> Self {
+        Self { primary, secondary }
+    }
+
+    /// Runs the query like [`AnalyticsDataSource::load_results`], or with a timeout like
+    /// [`AnalyticsDataSource::load_results_with_timeout`], and tells which data source answered
+    /// it
+    pub async fn load_results_served<T>(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> CustomResult<(Vec<T>, ServedBy), QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        let (rows, served_by) = self
+            .run(|source| source.load_raw_rows(query, timeout))
+            .await?;
+        let rows = rows
+            .into_iter()
+            .map(Self::load_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((rows, served_by))
+    }
+
+    /// Runs `load` on the primary, and on the secondary if the primary couldn't be reached
+    async fn run<'a, R, F, Fut>(
+        &'a self,
+        load: F,
+    ) -> CustomResult<(R, ServedBy), QueryExecutionError>
+    where
+        F: Fn(&'a P) -> Fut,
+        Fut: std::future::Future<Output = CustomResult<R, QueryExecutionError>>,
+    {
+        match load(&self.primary).await {
+            Err(error) if falls_back(error.current_context()) => {
+                let source = self.primary.source_name();
+                logger::warn!(
+                    ?error,
+                    "{source} data source unreachable, using its fallback"
+                );
+                FALLBACK_QUERIES.add(&CONTEXT, 1, &[add_attributes("source", source)]);
+                Ok((load(&self.secondary).await?, ServedBy::Secondary))
+            }
+            result => Ok((result?, ServedBy::Primary)),
+        }
+    }
+}
+
+impl FallbackSource<ClickhouseClient> {
+    pub fn from_conf(primary: &ClickhouseConfig, secondary: &ClickhouseConfig) -> Self {
+        Self::new(
+            ClickhouseClient::from_conf(primary),
+            ClickhouseClient::from_conf(secondary),
+        )
+    }
+}
+
+/// Whether a query failing on the primary with `error` is run on the secondary
+fn falls_back(error: &QueryExecutionError) -> bool {
+    match error {
+        QueryExecutionError::ConnectionFailure => true,
+        QueryExecutionError::RowExtractionFailure
+        | QueryExecutionError::DatabaseError
+        | QueryExecutionError::Timeout
+        | QueryExecutionError::TooLarge => false,
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: LoadRawRows> AnalyticsDataSource for FallbackSource<P> {
+    type Row = P::Row;
+
+    fn source_name(&self) -> &'static str {
+        self.primary.source_name()
+    }
+
+    async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        Ok(self.load_results_served(query, None).await?.0)
+    }
+
+    async fn load_results_with_timeout<T>(
+        &self,
+        query: &str,
+        timeout: Duration,
+    ) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        Ok(self.load_results_served(query, Some(timeout)).await?.0)
+    }
+
+    /// The figures of how much the query read aren't loaded, only which data source answered it
+    async fn load_results_with_report<T>(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> CustomResult<(Vec<T>, QueryReport), QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        let (rows, served_by) = self.load_results_served(query, timeout).await?;
+        Ok((
+            rows,
+            QueryReport {
+                served_by_fallback: served_by == ServedBy::Secondary,
+                ..Default::default()
+            },
+        ))
+    }
+
+    async fn estimate_scanned_rows(
+        &self,
+        query: &str,
+    ) -> CustomResult<Option<u64>, QueryExecutionError> {
+        Ok(self
+            .run(|source| source.estimate_scanned_rows(query))
+            .await?
+            .0)
+    }
+
+    fn max_scanned_rows(&self) -> Option<u64> {
+        self.primary.max_scanned_rows()
+    }
+
+    fn max_result_rows(&self) -> Option<usize> {
+        self.primary.max_result_rows()
+    }
+
+    /// Queries may be answered by either data source, so the views are only read if both have
+    /// them
+    fn has_materialized_views(&self) -> bool {
+        self.primary.has_materialized_views() && self.secondary.has_materialized_views()
+    }
+
+    fn max_time_range(&self) -> Option<time::Duration> {
+        self.primary.max_time_range()
+    }
+
+    fn granularity_thresholds(&self) -> GranularityThresholds {
+        self.primary.granularity_thresholds()
+    }
+
+    fn merchant_query_limiter(&self) -> Option<&MerchantQueryLimiter> {
+        self.primary.merchant_query_limiter()
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.primary.pool_stats()
+    }
+
+    fn get_table_engine(table: AnalyticsCollection) -> TableEngine {
+        P::get_table_engine(table)
+    }
+
+    fn get_time_column(table: AnalyticsCollection) -> &'static str {
+        P::get_time_column(table)
+    }
+
+    fn get_time_bucket(
+        time_column: &str,
+        granularity: &Granularity,
+        time_zone: Option<&str>,
+    ) -> String {
+        P::get_time_bucket(time_column, granularity, time_zone)
+    }
+
+    fn get_date_part(time_column: &str, part: DatePart, time_zone: Option<&str>) -> String {
+        P::get_date_part(time_column, part, time_zone)
+    }
+
+    fn get_json_string(column: &str, key: &str) -> String {
+        P::get_json_string(column, key)
+    }
+
+    fn get_json_number(column: &str, key: &str) -> String {
+        P::get_json_number(column, key)
+    }
+
+    fn get_sample_clause(sample_rate: f64) -> String {
+        P::get_sample_clause(sample_rate)
+    }
+
+    fn get_max_partitions_clause(max_partitions: u32) -> Option<String> {
+        P::get_max_partitions_clause(max_partitions)
+    }
+
+    fn get_round(expression: &str, decimal_places: u32) -> String {
+        P::get_round(expression, decimal_places)
+    }
+
+    fn get_string_cast(expression: &str) -> String {
+        P::get_string_cast(expression)
+    }
+
+    fn get_count_if(condition: &str, count_column: Option<&str>) -> String {
+        P::get_count_if(condition, count_column)
+    }
+}
+
+impl<P, T> LoadRow<T> for FallbackSource<P>
+where
+    P: LoadRawRows + LoadRow<T>,
+{
+    fn load_row(row: Self::Row) -> CustomResult<T, QueryExecutionError> {
+        P::load_row(row)
+    }
+}
+
+impl<P> SdkEventMetricAnalytics for FallbackSource<P>
+where
+    P: LoadRawRows + SdkEventMetricAnalytics,
+{
+    fn metrics_cache(&self) -> Option<&SdkEventMetricsCache> {
+        self.primary.metrics_cache()
+    }
+}
+
+impl<P: LoadRawRows> ToSql<FallbackSource<P>> for PrimitiveDateTime
+where
+    Self: ToSql<P>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<P>>::to_sql(self, table_engine)
+    }
+}
+
+impl<P: LoadRawRows> ToSql<FallbackSource<P>> for AnalyticsCollection
+where
+    Self: ToSql<P>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<P>>::to_sql(self, table_engine)
+    }
+}
+
+impl<P: LoadRawRows, T> ToSql<FallbackSource<P>> for Aggregate<T>
+where
+    Self: ToSql<P>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<P>>::to_sql(self, table_engine)
+    }
+}
+
+impl<P: LoadRawRows, T> ToSql<FallbackSource<P>> for Window<T>
+where
+    Self: ToSql<P>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<P>>::to_sql(self, table_engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_connection_failures_fall_back() {
+        assert!(falls_back(&QueryExecutionError::ConnectionFailure));
+        assert!(!falls_back(&QueryExecutionError::DatabaseError));
+        assert!(!falls_back(&QueryExecutionError::Timeout));
+        assert!(!falls_back(&QueryExecutionError::TooLarge));
+        assert!(!falls_back(&QueryExecutionError::RowExtractionFailure));
+    }
+}
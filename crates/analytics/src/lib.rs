@@ -1,7 +1,12 @@
+#[cfg(feature = "bigquery")]
+mod bigquery;
 mod clickhouse;
 pub mod core;
 pub mod disputes;
+#[cfg(feature = "duckdb")]
+mod duckdb;
 pub mod errors;
+mod fallback;
 pub mod metrics;
 pub mod payments;
 mod query;
@@ -14,6 +19,7 @@ pub mod outgoing_webhook_event;
 pub mod sdk_events;
 pub mod search;
 mod sqlx;
+pub mod time_range;
 mod types;
 use api_event::metrics::{ApiEventMetric, ApiEventMetricRow};
 use common_utils::errors::CustomResult;
@@ -23,12 +29,13 @@ use hyperswitch_interfaces::secrets_interface::{
     secret_state::{RawSecret, SecretStateContainer, SecuredSecret},
     SecretManagementInterface, SecretsManagementError,
 };
-pub use types::AnalyticsDomain;
+pub use types::{
+    AnalyticsDataSource, AnalyticsDomain, AnalyticsPoolStats, MerchantQueryLimit, MetricsError,
+    MetricsResult, PoolStats, QueryExecutionError, RetryConfig,
+};
 pub mod lambda_utils;
 pub mod utils;
 
-use std::sync::Arc;
-
 use api_models::analytics::{
     api_event::{
         ApiEventDimensions, ApiEventFilters, ApiEventMetrics, ApiEventMetricsBucketIdentifier,
@@ -42,23 +49,31 @@ use api_models::analytics::{
     Distribution, Granularity, TimeRange,
 };
 use clickhouse::ClickhouseClient;
-pub use clickhouse::ClickhouseConfig;
+pub use clickhouse::{ClickhouseConfig, ClickhousePoolConfig};
 use error_stack::report;
+pub use fallback::{FallbackSource, LoadRawRows, ServedBy};
 use router_env::{
     logger,
     tracing::{self, instrument},
 };
 use storage_impl::config::Database;
 
+#[cfg(feature = "bigquery")]
+pub use self::bigquery::{BigQueryClient, BigQueryConfig};
+#[cfg(feature = "duckdb")]
+pub use self::duckdb::{DuckDbClient, DuckDbConfig};
 use self::{
     payments::{
         distribution::{PaymentDistribution, PaymentDistributionRow},
         metrics::{PaymentMetric, PaymentMetricRow},
     },
     refunds::metrics::{RefundMetric, RefundMetricRow},
-    sdk_events::metrics::{SdkEventMetric, SdkEventMetricRow},
+    sdk_events::metrics::{
+        load_combined_metrics, load_metrics_batch, load_platform_rollup, load_recent_metrics,
+        render_metric_sql, SdkEventCombinedRow, SdkEventMetric, SdkEventMetricRow,
+        SdkEventMetricStream, SdkEventPlatformRollup, SdkEventQueryOptions,
+    },
     sqlx::SqlxClient,
-    types::MetricsError,
 };
 
 #[derive(Clone, Debug)]
@@ -502,6 +517,7 @@ impl AnalyticsProvider {
         .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_sdk_event_metrics(
         &self,
         metric: &SdkEventMetrics,
@@ -509,13 +525,22 @@ impl AnalyticsProvider {
         pub_key: &str,
         filters: &SdkEventFilters,
         granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
         time_range: &TimeRange,
     ) -> types::MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
         match self {
             Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
             Self::Clickhouse(pool) => {
                 metric
-                    .load_metrics(dimensions, pub_key, filters, granularity, time_range, pool)
+                    .load_metrics(
+                        dimensions,
+                        pub_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
                     .await
             }
             Self::CombinedCkh(_sqlx_pool, ckh_pool) | Self::CombinedSqlx(_sqlx_pool, ckh_pool) => {
@@ -525,6 +550,7 @@ impl AnalyticsProvider {
                         pub_key,
                         filters,
                         granularity,
+                        options,
                         // Since SDK events are ckh only use ckh here
                         time_range,
                         ckh_pool,
@@ -534,6 +560,198 @@ impl AnalyticsProvider {
         }
     }
 
+    /// Loads several sdk event metrics at once, see [`load_metrics_batch`]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_sdk_event_metrics_batch(
+        &self,
+        metrics: &[SdkEventMetrics],
+        dimensions: &[SdkEventDimensions],
+        pub_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+    ) -> types::MetricsResult<
+        Vec<(
+            SdkEventMetrics,
+            Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+        )>,
+    > {
+        match self {
+            Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
+            Self::Clickhouse(ckh_pool)
+            | Self::CombinedCkh(_, ckh_pool)
+            | Self::CombinedSqlx(_, ckh_pool) => {
+                // SDK events are ckh only
+                load_metrics_batch(
+                    metrics,
+                    dimensions,
+                    pub_key,
+                    filters,
+                    granularity,
+                    options,
+                    time_range,
+                    ckh_pool,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Loads sdk event metrics over a recent time range as a bucket each, see
+    /// [`load_recent_metrics`]
+    pub async fn get_sdk_event_recent_metrics(
+        &self,
+        metrics: &[SdkEventMetrics],
+        pub_key: &str,
+        filters: &SdkEventFilters,
+        time_range: &TimeRange,
+    ) -> types::MetricsResult<
+        Vec<(
+            SdkEventMetrics,
+            Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+        )>,
+    > {
+        match self {
+            Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
+            Self::Clickhouse(ckh_pool)
+            | Self::CombinedCkh(_, ckh_pool)
+            | Self::CombinedSqlx(_, ckh_pool) => {
+                // SDK events are ckh only
+                load_recent_metrics(metrics, pub_key, filters, time_range, ckh_pool).await
+            }
+        }
+    }
+
+    /// Loads several sdk event metrics into a single row per bucket, see
+    /// [`load_combined_metrics`]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_sdk_event_combined_metrics(
+        &self,
+        metrics: &[SdkEventMetrics],
+        dimensions: &[SdkEventDimensions],
+        pub_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+    ) -> types::MetricsResult<Vec<SdkEventCombinedRow>> {
+        match self {
+            Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
+            Self::Clickhouse(ckh_pool)
+            | Self::CombinedCkh(_, ckh_pool)
+            | Self::CombinedSqlx(_, ckh_pool) => {
+                // SDK events are ckh only
+                load_combined_metrics(
+                    metrics,
+                    dimensions,
+                    pub_key,
+                    filters,
+                    granularity,
+                    options,
+                    time_range,
+                    ckh_pool,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Loads a sdk event metric for the events of every merchant, see [`load_platform_rollup`].
+    /// Only for platform operators, merchants must always go through
+    /// [`Self::get_sdk_event_metrics`] which is scoped to their publishable key.
+    pub async fn get_platform_sdk_event_rollup(
+        &self,
+        metric: &SdkEventMetrics,
+        dimensions: &[SdkEventDimensions],
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+    ) -> types::MetricsResult<SdkEventPlatformRollup> {
+        match self {
+            Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
+            Self::Clickhouse(ckh_pool)
+            | Self::CombinedCkh(_, ckh_pool)
+            | Self::CombinedSqlx(_, ckh_pool) => {
+                // SDK events are ckh only
+                load_platform_rollup(
+                    metric,
+                    dimensions,
+                    filters,
+                    granularity,
+                    options,
+                    time_range,
+                    ckh_pool,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Like [`Self::get_sdk_event_metrics`] but hands out the buckets as they are loaded, which
+    /// only the count metrics and the funnel conversion rate are
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_sdk_event_metrics_stream(
+        &self,
+        metric: &SdkEventMetrics,
+        dimensions: &[SdkEventDimensions],
+        pub_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+    ) -> types::MetricsResult<SdkEventMetricStream> {
+        match self {
+            Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
+            Self::Clickhouse(ckh_pool)
+            | Self::CombinedCkh(_, ckh_pool)
+            | Self::CombinedSqlx(_, ckh_pool) => {
+                // SDK events are ckh only
+                metric
+                    .load_metrics_stream(
+                        dimensions,
+                        pub_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        ckh_pool,
+                    )
+                    .await
+            }
+        }
+    }
+
+    /// The SQL [`Self::get_sdk_event_metrics`] runs for these arguments, see [`render_metric_sql`]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_sdk_event_metrics_sql(
+        &self,
+        metric: &SdkEventMetrics,
+        dimensions: &[SdkEventDimensions],
+        pub_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+    ) -> types::MetricsResult<Vec<String>> {
+        match self {
+            Self::Sqlx(_pool) => Err(report!(MetricsError::NotImplemented)),
+            Self::Clickhouse(_) | Self::CombinedCkh(..) | Self::CombinedSqlx(..) => {
+                render_metric_sql(
+                    metric,
+                    dimensions,
+                    pub_key,
+                    filters,
+                    granularity,
+                    options,
+                    time_range,
+                )
+                .await
+            }
+        }
+    }
+
     pub async fn get_api_event_metrics(
         &self,
         metric: &ApiEventMetrics,
@@ -563,23 +781,39 @@ impl AnalyticsProvider {
         }
     }
 
+    /// Current utilization of the connections of every data source of the provider
+    pub fn pool_stats(&self) -> AnalyticsPoolStats {
+        match self {
+            Self::Sqlx(sqlx_pool) => AnalyticsPoolStats {
+                sqlx: sqlx_pool.pool_stats(),
+                clickhouse: None,
+            },
+            Self::Clickhouse(ckh_pool) => AnalyticsPoolStats {
+                sqlx: None,
+                clickhouse: ckh_pool.pool_stats(),
+            },
+            Self::CombinedCkh(sqlx_pool, ckh_pool) | Self::CombinedSqlx(sqlx_pool, ckh_pool) => {
+                AnalyticsPoolStats {
+                    sqlx: sqlx_pool.pool_stats(),
+                    clickhouse: ckh_pool.pool_stats(),
+                }
+            }
+        }
+    }
+
     pub async fn from_conf(config: &AnalyticsConfig) -> Self {
         match config {
             AnalyticsConfig::Sqlx { sqlx } => Self::Sqlx(SqlxClient::from_conf(sqlx).await),
-            AnalyticsConfig::Clickhouse { clickhouse } => Self::Clickhouse(ClickhouseClient {
-                config: Arc::new(clickhouse.clone()),
-            }),
+            AnalyticsConfig::Clickhouse { clickhouse } => {
+                Self::Clickhouse(ClickhouseClient::from_conf(clickhouse))
+            }
             AnalyticsConfig::CombinedCkh { sqlx, clickhouse } => Self::CombinedCkh(
                 SqlxClient::from_conf(sqlx).await,
-                ClickhouseClient {
-                    config: Arc::new(clickhouse.clone()),
-                },
+                ClickhouseClient::from_conf(clickhouse),
             ),
             AnalyticsConfig::CombinedSqlx { sqlx, clickhouse } => Self::CombinedSqlx(
                 SqlxClient::from_conf(sqlx).await,
-                ClickhouseClient {
-                    config: Arc::new(clickhouse.clone()),
-                },
+                ClickhouseClient::from_conf(clickhouse),
             ),
         }
     }
@@ -28,6 +28,36 @@ where
     result
 }
 
+/// Records how long a query of the sdk event `metric` took on the data source `source`, labelled
+/// with whether it succeeded, and counts a failed query by the kind of its error
+pub fn record_sdk_event_query<R>(
+    metric: &api_models::analytics::sdk_events::SdkEventMetrics,
+    source: &'static str,
+    result: &crate::types::MetricsResult<R>,
+    time: time::Duration,
+) {
+    let metric_name = add_attributes("metric_name", metric.to_string());
+    let source = add_attributes("source", source);
+    let outcome = if result.is_ok() { "success" } else { "error" };
+    super::SDK_EVENT_QUERY_TIME.record(
+        &super::CONTEXT,
+        time.as_secs_f64(),
+        &[
+            metric_name.clone(),
+            source.clone(),
+            add_attributes("outcome", outcome),
+        ],
+    );
+    if let Err(error) = result {
+        let kind: &'static str = error.current_context().into();
+        super::SDK_EVENT_QUERY_ERRORS.add(
+            &super::CONTEXT,
+            1,
+            &[metric_name, source, add_attributes("error_kind", kind)],
+        );
+    }
+}
+
 use std::time;
 
 #[inline]
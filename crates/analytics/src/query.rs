@@ -1,4 +1,10 @@
-use std::marker::PhantomData;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
 use api_models::{
     analytics::{
@@ -8,7 +14,7 @@ use api_models::{
         payments::{PaymentDimensions, PaymentDistributions},
         refunds::{RefundDimensions, RefundType},
         sdk_events::{SdkEventDimensions, SdkEventNames},
-        Granularity,
+        Granularity, QueryStats,
     },
     enums::{
         AttemptStatus, AuthenticationType, Connector, Currency, DisputeStage, PaymentMethod,
@@ -18,10 +24,10 @@ use api_models::{
 };
 use common_utils::errors::{CustomResult, ParsingError};
 use diesel_models::enums as storage_enums;
-use error_stack::ResultExt;
-use router_env::{logger, Flow};
+use error_stack::{report, ResultExt};
+use router_env::{logger, tracing, Flow};
 
-use super::types::{AnalyticsCollection, AnalyticsDataSource, LoadRow, TableEngine};
+use super::types::{AnalyticsCollection, AnalyticsDataSource, LoadRow, RowStream, TableEngine};
 use crate::types::QueryExecutionError;
 pub type QueryResult<T> = error_stack::Result<T, QueryBuildingError>;
 pub trait QueryFilter<T>
@@ -40,6 +46,14 @@ where
     fn set_group_by_clause(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()>;
 }
 
+pub trait HavingClause<T>
+where
+    T: AnalyticsDataSource,
+    AnalyticsCollection: ToSql<T>,
+{
+    fn set_having_clause(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()>;
+}
+
 pub trait SeriesBucket {
     type SeriesType;
     type GranularityLevel;
@@ -67,14 +81,33 @@ where
     Granularity: GroupByClause<T>,
 {
     fn set_filter_clause(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()> {
-        builder.add_custom_filter_clause("created_at", self.start_time, FilterTypes::Gte)?;
+        let time_column = builder.time_column;
+        builder.add_custom_filter_clause(time_column, self.start_time, FilterTypes::Gte)?;
         if let Some(end) = self.end_time {
-            builder.add_custom_filter_clause("created_at", end, FilterTypes::Lte)?;
+            builder.add_custom_filter_clause(time_column, end, FilterTypes::Lte)?;
         }
         Ok(())
     }
 }
 
+impl<T> HavingClause<T> for analytics_api::CountThreshold
+where
+    T: AnalyticsDataSource,
+    AnalyticsCollection: ToSql<T>,
+    Aggregate<&'static str>: ToSql<T>,
+{
+    fn set_having_clause(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()> {
+        builder.add_having_clause(
+            Aggregate::Count {
+                field: None,
+                alias: None,
+            },
+            self.comparison.into(),
+            self.value,
+        )
+    }
+}
+
 impl GroupByClause<super::SqlxClient> for Granularity {
     fn set_group_by_clause(
         &self,
@@ -85,18 +118,26 @@ impl GroupByClause<super::SqlxClient> for Granularity {
         let granularity_bucket_scale = match self {
             Self::OneMin => None,
             Self::FiveMin | Self::FifteenMin | Self::ThirtyMin => Some("minute"),
-            Self::OneHour | Self::OneDay => None,
+            Self::OneHour | Self::OneDay | Self::Weekly | Self::Monthly | Self::Quarterly => None,
         };
 
         let granularity_divisor = self.get_bucket_size();
 
+        // The time is stored as a UTC timestamp, shift it to the wall clock of the requested zone
+        let time_column = builder.time_column;
+        let time = builder
+            .time_zone
+            .as_ref()
+            .map(|tz| format!("(({time_column} AT TIME ZONE 'UTC') AT TIME ZONE '{tz}')"))
+            .unwrap_or_else(|| time_column.to_string());
+
         builder
-            .add_group_by_clause(format!("DATE_TRUNC('{trunc_scale}', created_at)"))
+            .add_group_by_clause(format!("DATE_TRUNC('{trunc_scale}', {time})"))
             .attach_printable("Error adding time prune group by")?;
         if let Some(scale) = granularity_bucket_scale {
             builder
                 .add_group_by_clause(format!(
-                    "FLOOR(DATE_PART('{scale}', created_at)/{granularity_divisor})"
+                    "FLOOR(DATE_PART('{scale}', {time})/{granularity_divisor})"
                 ))
                 .attach_printable("Error adding time binning group by")?;
         }
@@ -109,13 +150,23 @@ impl GroupByClause<super::ClickhouseClient> for Granularity {
         &self,
         builder: &mut QueryBuilder<super::ClickhouseClient>,
     ) -> QueryResult<()> {
+        let tz = builder
+            .time_zone
+            .as_ref()
+            .map(|tz| format!(", '{tz}'"))
+            .unwrap_or_default();
+        let time_column = builder.time_column;
         let interval = match self {
-            Self::OneMin => "toStartOfMinute(created_at)",
-            Self::FiveMin => "toStartOfFiveMinutes(created_at)",
-            Self::FifteenMin => "toStartOfFifteenMinutes(created_at)",
-            Self::ThirtyMin => "toStartOfInterval(created_at, INTERVAL 30 minute)",
-            Self::OneHour => "toStartOfHour(created_at)",
-            Self::OneDay => "toStartOfDay(created_at)",
+            Self::OneMin => format!("toStartOfMinute({time_column}{tz})"),
+            Self::FiveMin => format!("toStartOfFiveMinutes({time_column}{tz})"),
+            Self::FifteenMin => format!("toStartOfFifteenMinutes({time_column}{tz})"),
+            Self::ThirtyMin => format!("toStartOfInterval({time_column}, INTERVAL 30 minute{tz})"),
+            Self::OneHour => format!("toStartOfHour({time_column}{tz})"),
+            Self::OneDay => format!("toStartOfDay({time_column}{tz})"),
+            // Mode 1 starts weeks on Monday
+            Self::Weekly => format!("toStartOfWeek({time_column}, 1{tz})"),
+            Self::Monthly => format!("toStartOfMonth({time_column}{tz})"),
+            Self::Quarterly => format!("toStartOfQuarter({time_column}{tz})"),
         };
 
         builder
@@ -124,12 +175,68 @@ impl GroupByClause<super::ClickhouseClient> for Granularity {
     }
 }
 
+impl<P: super::LoadRawRows> GroupByClause<super::FallbackSource<P>> for Granularity
+where
+    AnalyticsCollection: ToSql<super::FallbackSource<P>>,
+{
+    fn set_group_by_clause(
+        &self,
+        builder: &mut QueryBuilder<super::FallbackSource<P>>,
+    ) -> QueryResult<()> {
+        let time_bucket = <super::FallbackSource<P> as AnalyticsDataSource>::get_time_bucket(
+            builder.time_column,
+            self,
+            builder.time_zone.as_deref(),
+        );
+        builder
+            .add_group_by_clause(time_bucket)
+            .attach_printable("Error adding interval group by")
+    }
+}
+
+#[cfg(feature = "duckdb")]
+impl GroupByClause<super::DuckDbClient> for Granularity {
+    fn set_group_by_clause(
+        &self,
+        builder: &mut QueryBuilder<super::DuckDbClient>,
+    ) -> QueryResult<()> {
+        let time_bucket = <super::DuckDbClient as AnalyticsDataSource>::get_time_bucket(
+            builder.time_column,
+            self,
+            builder.time_zone.as_deref(),
+        );
+        builder
+            .add_group_by_clause(time_bucket)
+            .attach_printable("Error adding interval group by")
+    }
+}
+
+#[cfg(feature = "bigquery")]
+impl GroupByClause<super::BigQueryClient> for Granularity {
+    fn set_group_by_clause(
+        &self,
+        builder: &mut QueryBuilder<super::BigQueryClient>,
+    ) -> QueryResult<()> {
+        let time_bucket = <super::BigQueryClient as AnalyticsDataSource>::get_time_bucket(
+            builder.time_column,
+            self,
+            builder.time_zone.as_deref(),
+        );
+        builder
+            .add_group_by_clause(time_bucket)
+            .attach_printable("Error adding interval group by")
+    }
+}
+
 #[derive(strum::Display)]
 #[strum(serialize_all = "lowercase")]
 pub enum TimeGranularityLevel {
     Minute,
     Hour,
     Day,
+    Week,
+    Month,
+    Quarter,
 }
 
 impl SeriesBucket for Granularity {
@@ -144,6 +251,9 @@ impl SeriesBucket for Granularity {
                 TimeGranularityLevel::Hour
             }
             Self::OneDay => TimeGranularityLevel::Day,
+            Self::Weekly => TimeGranularityLevel::Week,
+            Self::Monthly => TimeGranularityLevel::Month,
+            Self::Quarterly => TimeGranularityLevel::Quarter,
         }
     }
 
@@ -155,6 +265,9 @@ impl SeriesBucket for Granularity {
             Self::ThirtyMin => 30,
             Self::OneHour => 60,
             Self::OneDay => 24,
+            Self::Weekly => 7,
+            Self::Monthly => 1,
+            Self::Quarterly => 3,
         }
     }
 
@@ -178,10 +291,32 @@ impl SeriesBucket for Granularity {
             (TimeGranularityLevel::Day, i) => {
                 time::Time::MIDNIGHT.replace_hour(clip_start(value.hour(), i))
             }
+            (
+                TimeGranularityLevel::Week
+                | TimeGranularityLevel::Month
+                | TimeGranularityLevel::Quarter,
+                _,
+            ) => Ok(time::Time::MIDNIGHT),
         }
         .change_context(PostProcessingError::BucketClipping)?;
 
-        Ok(value.replace_time(clipped_time))
+        let clipped_date = match self.get_lowest_common_granularity_level() {
+            TimeGranularityLevel::Week => value.date().checked_sub(time::Duration::days(
+                value.weekday().number_days_from_monday().into(),
+            )),
+            TimeGranularityLevel::Month => value.date().replace_day(1).ok(),
+            TimeGranularityLevel::Quarter => value
+                .date()
+                .replace_day(1)
+                .and_then(|d| d.replace_month(quarter_start_month(d.month())))
+                .ok(),
+            TimeGranularityLevel::Minute
+            | TimeGranularityLevel::Hour
+            | TimeGranularityLevel::Day => Some(value.date()),
+        }
+        .ok_or(PostProcessingError::BucketClipping)?;
+
+        Ok(clipped_date.with_time(clipped_time))
     }
 
     fn clip_to_end(
@@ -204,12 +339,129 @@ impl SeriesBucket for Granularity {
             (TimeGranularityLevel::Day, i) => {
                 time::Time::MIDNIGHT.replace_hour(clip_end(value.hour(), i))
             }
+            (
+                TimeGranularityLevel::Week
+                | TimeGranularityLevel::Month
+                | TimeGranularityLevel::Quarter,
+                _,
+            ) => time::Time::from_hms(23, 59, 59),
         }
         .change_context(PostProcessingError::BucketClipping)
         .attach_printable_lazy(|| format!("Bucket Clip Error: {value}"))?;
 
-        Ok(value.replace_time(clipped_time))
+        let clipped_date = match self.get_lowest_common_granularity_level() {
+            TimeGranularityLevel::Week => value.date().checked_add(time::Duration::days(
+                (6 - value.weekday().number_days_from_monday()).into(),
+            )),
+            TimeGranularityLevel::Month => value
+                .date()
+                .replace_day(time::util::days_in_year_month(value.year(), value.month()))
+                .ok(),
+            TimeGranularityLevel::Quarter => {
+                let end_month = quarter_start_month(value.month()).nth_next(2);
+                value
+                    .date()
+                    .replace_day(1)
+                    .and_then(|d| d.replace_month(end_month))
+                    .and_then(|d| {
+                        d.replace_day(time::util::days_in_year_month(d.year(), end_month))
+                    })
+                    .ok()
+            }
+            TimeGranularityLevel::Minute
+            | TimeGranularityLevel::Hour
+            | TimeGranularityLevel::Day => Some(value.date()),
+        }
+        .ok_or(PostProcessingError::BucketClipping)
+        .attach_printable_lazy(|| format!("Bucket Clip Error: {value}"))?;
+
+        Ok(clipped_date.with_time(clipped_time))
+    }
+}
+
+/// Quarters are aligned to the calendar year, not to the start of the queried range
+fn quarter_start_month(month: time::Month) -> time::Month {
+    match month {
+        time::Month::January | time::Month::February | time::Month::March => time::Month::January,
+        time::Month::April | time::Month::May | time::Month::June => time::Month::April,
+        time::Month::July | time::Month::August | time::Month::September => time::Month::July,
+        time::Month::October | time::Month::November | time::Month::December => {
+            time::Month::October
+        }
+    }
+}
+
+/// Format of `time_bucket` for buckets aligned to a time zone: the wall clock time of the bucket
+/// start in that zone, e.g. `2024-03-10 00:00:00` for a daily bucket.
+pub const TIME_BUCKET_FORMAT: &str = "[year]-[month]-[day] [hour]:[minute]:[second]";
+
+/// Format of `time_bucket` for the buckets starting in an hour the clocks of their zone go
+/// through twice, when they're turned back: the wall clock time of the bucket start followed by
+/// its UTC offset, which tells the two apart. E.g. `2024-11-03 01:00:00-0400` for the first time
+/// New York reaches 1am that night and `2024-11-03 01:00:00-0500` for the second one.
+pub const REPEATED_TIME_BUCKET_FORMAT: &str =
+    "[year]-[month]-[day] [hour]:[minute]:[second][offset_hour sign:mandatory][offset_minute]";
+
+pub fn parse_time_bucket(
+    time_bucket: &str,
+) -> error_stack::Result<time::PrimitiveDateTime, ParsingError> {
+    let format = time::format_description::parse(TIME_BUCKET_FORMAT)
+        .change_context(ParsingError::DateTimeParsingError)
+        .attach_printable("Failed to parse format description")?;
+    time::PrimitiveDateTime::parse(time_bucket, &format)
+        .change_context(ParsingError::DateTimeParsingError)
+        .attach_printable_lazy(|| format!("Failed to parse time bucket: {time_bucket}"))
+}
+
+pub fn format_time_bucket(
+    time_bucket: time::PrimitiveDateTime,
+) -> error_stack::Result<String, ParsingError> {
+    let format = time::format_description::parse(TIME_BUCKET_FORMAT)
+        .change_context(ParsingError::DateTimeParsingError)
+        .attach_printable("Failed to parse format description")?;
+    time_bucket
+        .format(&format)
+        .change_context(ParsingError::EncodeError("failed to format time bucket"))
+}
+
+/// Wall clock time of a bucket start labelled in either [`TIME_BUCKET_FORMAT`] or
+/// [`REPEATED_TIME_BUCKET_FORMAT`], with the UTC offset of the latter
+pub fn parse_time_bucket_offset(
+    time_bucket: &str,
+) -> error_stack::Result<(time::PrimitiveDateTime, Option<time::UtcOffset>), ParsingError> {
+    if let Ok(bucket) = parse_time_bucket(time_bucket) {
+        return Ok((bucket, None));
     }
+    let format = time::format_description::parse(REPEATED_TIME_BUCKET_FORMAT)
+        .change_context(ParsingError::DateTimeParsingError)
+        .attach_printable("Failed to parse format description")?;
+    time::OffsetDateTime::parse(time_bucket, &format)
+        .map(|bucket| {
+            (
+                time::PrimitiveDateTime::new(bucket.date(), bucket.time()),
+                Some(bucket.offset()),
+            )
+        })
+        .change_context(ParsingError::DateTimeParsingError)
+        .attach_printable_lazy(|| format!("Failed to parse time bucket: {time_bucket}"))
+}
+
+/// Label of a bucket starting at `time_bucket` on the wall clock, in
+/// [`REPEATED_TIME_BUCKET_FORMAT`] if it's given the `offset` of a repeated hour
+pub fn format_time_bucket_offset(
+    time_bucket: time::PrimitiveDateTime,
+    offset: Option<time::UtcOffset>,
+) -> error_stack::Result<String, ParsingError> {
+    let Some(offset) = offset else {
+        return format_time_bucket(time_bucket);
+    };
+    let format = time::format_description::parse(REPEATED_TIME_BUCKET_FORMAT)
+        .change_context(ParsingError::DateTimeParsingError)
+        .attach_printable("Failed to parse format description")?;
+    time_bucket
+        .assume_offset(offset)
+        .format(&format)
+        .change_context(ParsingError::EncodeError("failed to format time bucket"))
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -247,6 +499,46 @@ pub enum Aggregate<R> {
         field: R,
         alias: Option<&'static str>,
     },
+    Percentile {
+        field: R,
+        alias: Option<&'static str>,
+        quantile: f64,
+    },
+    CountDistinct {
+        field: R,
+        alias: Option<&'static str>,
+    },
+    /// Estimated distinct count, cheaper than [`Aggregate::CountDistinct`] on large tables but
+    /// not exact. Data sources without an estimator fall back to the exact count.
+    CountDistinctApprox {
+        field: R,
+        alias: Option<&'static str>,
+    },
+    /// Median estimated from a t-digest sketch, cheaper than a [`Aggregate::Percentile`] of 0.5
+    /// on large tables but not exact. Data sources without a sketch fall back to the exact median.
+    MedianTDigest {
+        field: R,
+        alias: Option<&'static str>,
+    },
+    /// Several quantiles of `field` in a single aggregate rather than an [`Aggregate::Percentile`]
+    /// each, which would go over the values once per quantile. Each quantile is selected as a
+    /// column of its own, named by its alias, in the order they're listed.
+    Quantiles {
+        field: R,
+        quantiles: &'static [(&'static str, f64)],
+    },
+    /// Standard deviation of every value of `field`, taken as the whole population rather than a
+    /// sample of it
+    StdDev {
+        field: R,
+        alias: Option<&'static str>,
+    },
+    /// Variance of every value of `field`, taken as the whole population like
+    /// [`Aggregate::StdDev`]
+    Variance {
+        field: R,
+        alias: Option<&'static str>,
+    },
 }
 
 // Window functions in query
@@ -278,6 +570,27 @@ pub enum Window<R> {
         order_by: Option<(String, Order)>,
         alias: Option<&'static str>,
     },
+    /// Running total of `field` over the current row and every row before it
+    CumulativeSum {
+        field: R,
+        partition_by: Option<String>,
+        order_by: Option<(String, Order)>,
+        alias: Option<&'static str>,
+    },
+    /// Mean of `field` over the current row and up to `preceding` rows before it
+    MovingAverage {
+        field: R,
+        partition_by: Option<String>,
+        order_by: Option<(String, Order)>,
+        preceding: u32,
+        alias: Option<&'static str>,
+    },
+    /// `field` in percent of its sum over the partition, null where that sum is zero
+    PercentOfTotal {
+        field: R,
+        partition_by: Option<String>,
+        alias: Option<&'static str>,
+    },
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -327,12 +640,40 @@ where
 {
     columns: Vec<String>,
     filters: Vec<(String, FilterTypes, String)>,
+    /// Already parenthesized `(..) OR (..)` clauses, and-ed with `filters`
+    filter_groups: Vec<String>,
+    /// Already lowered joins, following the table
+    joins: Vec<String>,
     group_by: Vec<String>,
+    /// The columns of `group_by` which are rolled up into subtotals, in the order they were added
+    rollup_group_by: Vec<String>,
+    /// Whether the subtotals of `rollup_group_by` are returned along the groups
+    subtotals: bool,
     having: Option<Vec<(String, FilterTypes, String)>>,
     outer_select: Vec<String>,
+    outer_group_by: Vec<String>,
     top_n: Option<TopN>,
+    order_by: Vec<String>,
+    limit: Option<usize>,
+    /// Rows of the result which are kept, see [`Self::set_max_rows`]
+    max_rows: Option<usize>,
+    offset: Option<usize>,
     table: AnalyticsCollection,
+    /// Collections read along `table` as if their rows were in it, see
+    /// [`Self::add_union_collection`]
+    union_tables: Vec<AnalyticsCollection>,
+    /// Column of `table` holding the time of its rows, see [`AnalyticsDataSource::get_time_column`]
+    time_column: &'static str,
     distinct: bool,
+    time_zone: Option<String>,
+    null_label: Option<String>,
+    timeout: Option<Duration>,
+    log_query: bool,
+    /// Where the statistics of the query are recorded when it's run, see [`Self::collect_stats`]
+    stats: Option<QueryStatsCollector>,
+    sample_rate: Option<f64>,
+    /// Partitions of `table` the query may read at most, see [`Self::set_max_partitions`]
+    max_partitions: Option<u32>,
     db_type: PhantomData<T>,
     table_engine: TableEngine,
 }
@@ -379,7 +720,238 @@ impl_to_sql_for_to_string!(
     Order
 );
 
-impl_to_sql_for_to_string!(&SdkEventDimensions, SdkEventDimensions, SdkEventNames);
+impl_to_sql_for_to_string!(SdkEventNames);
+
+/// Label of the group of the events without a value of a dimension, e.g. the events logged
+/// before their device type was captured. Unlike `unknown`, which some merchants send as an os
+/// or connector of their own, it can't be mistaken for a value. A query can pick another one with
+/// [`QueryBuilder::set_null_label`].
+pub const NULL_DIMENSION_LABEL: &str = "__null__";
+
+/// Whether `label` can be inlined into a query and filtered on: up to 64 ASCII letters, digits,
+/// `_`, `-` and `.`
+pub fn is_valid_null_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 64
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Sdk event dimensions are lowered to the column or expression they're computed from, which
+/// doesn't always match the dimension name. Select them with
+/// [`QueryBuilder::add_aliased_select_column`] so that rows carry the dimension name.
+impl<T: AnalyticsDataSource> ToSql<T> for SdkEventDimensions {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        Ok(lower_sdk_event_dimension::<T>(self, NULL_DIMENSION_LABEL))
+    }
+}
+
+/// `dimension` as the column or expression it's computed from, missing values of the dimensions
+/// which are reported rather than dropped are replaced by `null_label`
+fn lower_sdk_event_dimension<T: AnalyticsDataSource>(
+    dimension: &SdkEventDimensions,
+    null_label: &str,
+) -> String {
+    match dimension {
+        SdkEventDimensions::PaymentMethod
+        | SdkEventDimensions::Platform
+        | SdkEventDimensions::BrowserName
+        | SdkEventDimensions::Source
+        | SdkEventDimensions::Component
+        | SdkEventDimensions::PaymentExperience
+        | SdkEventDimensions::Country
+        | SdkEventDimensions::EventName
+        | SdkEventDimensions::Currency => dimension.to_string(),
+        SdkEventDimensions::SdkVersion => "version".to_string(),
+        // Older events were logged before these were captured, they're labelled instead of being
+        // dropped from the results
+        SdkEventDimensions::DeviceType | SdkEventDimensions::Os => {
+            format!("coalesce({dimension}, '{null_label}')")
+        }
+        // The connector is only picked once the payment is attempted, the events before that
+        // are labelled
+        SdkEventDimensions::Connector => format!("coalesce({dimension}, '{null_label}')"),
+        // Only the events after a payment method is picked have its type
+        SdkEventDimensions::PaymentMethodType => format!("coalesce({dimension}, '{null_label}')"),
+        // Only unsuccessful authentications carry a reason
+        SdkEventDimensions::AuthenticationErrorReason => {
+            format!("coalesce({dimension}, '{null_label}')")
+        }
+        // Only the payment data filled events carry the input field, in the JSON object of
+        // their value
+        SdkEventDimensions::FieldName => T::get_json_string("value", "field_name"),
+        // Joined by [`customer_segment_join`]. The events of a payment without a segment
+        // get an empty string rather than NULL from a ClickHouse left join
+        SdkEventDimensions::CustomerSegment => {
+            format!("nullIf({CUSTOMER_SEGMENT_JOIN}_customer_segment, '')")
+        }
+        // Only the load failures carry the CDN region, in the JSON object of their value
+        SdkEventDimensions::CdnRegion => T::get_json_string("value", "cdn_region"),
+        // Only the events the sdk recorded an abandonment on carry its reason, in the JSON
+        // object of their value
+        SdkEventDimensions::AbandonmentReason => T::get_json_string("value", "abandonment_reason"),
+        // In UTC, lower a [`LocalSdkEventDimension`] for the wall clock of a time zone
+        SdkEventDimensions::DayOfWeek => T::get_date_part(
+            T::get_time_column(AnalyticsCollection::SdkEvents),
+            DatePart::DayOfWeek,
+            None,
+        ),
+        SdkEventDimensions::HourOfDay => T::get_date_part(
+            T::get_time_column(AnalyticsCollection::SdkEvents),
+            DatePart::HourOfDay,
+            None,
+        ),
+    }
+}
+
+/// Part of the time of an event which is grouped by regardless of the date
+#[derive(Clone, Copy, Debug)]
+pub enum DatePart {
+    /// `1` for Monday to `7` for Sunday
+    DayOfWeek,
+    /// `0` to `23`
+    HourOfDay,
+}
+
+/// A sdk event dimension lowered for the wall clock of `time_zone`, with `null_label` in place
+/// of a missing value. Only the dimensions derived from the event time depend on the zone.
+#[derive(Clone, Copy, Debug)]
+pub struct LocalSdkEventDimension<'a> {
+    pub dimension: &'a SdkEventDimensions,
+    pub time_zone: Option<&'a str>,
+    /// [`NULL_DIMENSION_LABEL`] without one
+    pub null_label: Option<&'a str>,
+}
+
+impl<T: AnalyticsDataSource> ToSql<T> for LocalSdkEventDimension<'_> {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        // The zone is inlined into the query, anything but a known zone name is ignored
+        let time_zone = self
+            .time_zone
+            .and_then(|time_zone| time_zone.parse::<chrono_tz::Tz>().ok())
+            .map(|time_zone| time_zone.name());
+        // So is the label, unless it's one which can be inlined
+        let null_label = self
+            .null_label
+            .filter(|null_label| is_valid_null_label(null_label))
+            .unwrap_or(NULL_DIMENSION_LABEL);
+        match self.dimension {
+            SdkEventDimensions::DayOfWeek => Ok(T::get_date_part(
+                T::get_time_column(AnalyticsCollection::SdkEvents),
+                DatePart::DayOfWeek,
+                time_zone,
+            )),
+            SdkEventDimensions::HourOfDay => Ok(T::get_date_part(
+                T::get_time_column(AnalyticsCollection::SdkEvents),
+                DatePart::HourOfDay,
+                time_zone,
+            )),
+            dimension => Ok(lower_sdk_event_dimension::<T>(dimension, null_label)),
+        }
+    }
+}
+
+/// Another collection left joined onto the table of a query with [`QueryBuilder::add_join`]. The
+/// columns of the collection are joined as `{prefix}_{column}`, so that they never clash with a
+/// column of the table: the columns of the table can still be used without naming it. The rows
+/// of the table without a match are kept.
+#[derive(Clone, Debug)]
+pub struct Join {
+    pub collection: AnalyticsCollection,
+    pub prefix: &'static str,
+    /// Pairs of a column of the table and the column of the collection it has to equal
+    pub on: Vec<(&'static str, &'static str)>,
+    /// Columns of the collection joined besides the ones of `on`
+    pub columns: Vec<&'static str>,
+}
+
+impl<T: AnalyticsDataSource> ToSql<T> for Join
+where
+    AnalyticsCollection: ToSql<T>,
+{
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        let prefix = self.prefix;
+        let columns = self
+            .on
+            .iter()
+            .map(|(_, column)| column)
+            .chain(self.columns.iter())
+            .map(|column| format!("{column} as {prefix}_{column}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let on = self
+            .on
+            .iter()
+            .map(|(table_column, column)| format!("{table_column} = {prefix}_{column}"))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        Ok(format!(
+            "LEFT JOIN (SELECT {columns} FROM {}) {prefix} ON {on}",
+            self.collection.to_sql(table_engine)?
+        ))
+    }
+}
+
+/// Prefix of the columns joined by [`customer_segment_join`]
+const CUSTOMER_SEGMENT_JOIN: &str = "segment";
+
+/// Join of the segment of the customer of each sdk event's payment, which
+/// [`SdkEventDimensions::CustomerSegment`] is read from. The customer segments hold a row per
+/// payment of a merchant. Only the queries grouping or filtering by the segment join them.
+pub fn customer_segment_join() -> Join {
+    Join {
+        collection: AnalyticsCollection::CustomerSegments,
+        prefix: CUSTOMER_SEGMENT_JOIN,
+        on: vec![("merchant_id", "merchant_id"), ("payment_id", "payment_id")],
+        columns: vec!["customer_segment"],
+    }
+}
+
+/// An aggregate rounded to `decimal_places` decimals by the data source and selected as
+/// `alias`, it keeps its full precision without them
+#[derive(Debug)]
+pub struct Rounded<C> {
+    pub column: C,
+    pub alias: &'static str,
+    pub decimal_places: Option<u32>,
+}
+
+impl<T: AnalyticsDataSource, C: ToSql<T>> ToSql<T> for Rounded<C> {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        let column = self.column.to_sql(table_engine)?;
+        let column = match self.decimal_places {
+            Some(decimal_places) => T::get_round(&column, decimal_places),
+            None => column,
+        };
+        Ok(format!("{column} as {}", self.alias))
+    }
+}
+
+/// Number of the events matching `condition`, selected as `alias`: the rows matching it, or the
+/// sum of `count_column` over them for a table rolling up several events per row
+#[derive(Clone, Copy, Debug)]
+pub struct EventCountIf<'a> {
+    pub condition: &'a str,
+    pub count_column: Option<&'a str>,
+    pub alias: &'a str,
+}
+
+impl<T: AnalyticsDataSource> ToSql<T> for EventCountIf<'_> {
+    fn to_sql(&self, _table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        Ok(format!(
+            "{} as {}",
+            T::get_count_if(self.condition, self.count_column),
+            self.alias
+        ))
+    }
+}
+
+impl<T: AnalyticsDataSource> ToSql<T> for &SdkEventDimensions {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <SdkEventDimensions as ToSql<T>>::to_sql(*self, table_engine)
+    }
+}
 
 impl_to_sql_for_to_string!(&ApiEventDimensions, ApiEventDimensions);
 
@@ -394,9 +966,23 @@ pub enum FilterTypes {
     Gte,
     Lte,
     Gt,
+    Lt,
     Like,
     NotLike,
     IsNotNull,
+    IsNull,
+}
+
+impl From<analytics_api::Comparison> for FilterTypes {
+    fn from(value: analytics_api::Comparison) -> Self {
+        match value {
+            analytics_api::Comparison::GreaterThan => Self::Gt,
+            analytics_api::Comparison::GreaterThanOrEqual => Self::Gte,
+            analytics_api::Comparison::LessThan => Self::Lt,
+            analytics_api::Comparison::LessThanOrEqual => Self::Lte,
+            analytics_api::Comparison::Equal => Self::Equal,
+        }
+    }
 }
 
 pub fn filter_type_to_sql(l: &String, op: &FilterTypes, r: &String) -> String {
@@ -407,10 +993,170 @@ pub fn filter_type_to_sql(l: &String, op: &FilterTypes, r: &String) -> String {
         FilterTypes::In => format!("{l} IN ({r})"),
         FilterTypes::Gte => format!("{l} >= '{r}'"),
         FilterTypes::Gt => format!("{l} > {r}"),
+        FilterTypes::Lt => format!("{l} < {r}"),
         FilterTypes::Lte => format!("{l} <= '{r}'"),
         FilterTypes::Like => format!("{l} LIKE '%{r}%'"),
         FilterTypes::NotLike => format!("{l} NOT LIKE '%{r}%'"),
         FilterTypes::IsNotNull => format!("{l} IS NOT NULL"),
+        FilterTypes::IsNull => format!("{l} IS NULL"),
+    }
+}
+
+/// Fingerprint of `query` which is the same for the queries differing only in their literals,
+/// e.g. in the merchant and time range they read: its strings and numbers are masked before it's
+/// hashed. Digits of an identifier, like the ones of `p95`, are kept.
+pub fn query_fingerprint(query: &str) -> String {
+    let mut masked = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    let mut in_identifier = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // A quote within a string is doubled
+            while let Some(c) = chars.next() {
+                if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                    break;
+                }
+            }
+            masked.push('?');
+            in_identifier = false;
+        } else if c.is_ascii_digit() && !in_identifier {
+            while chars.next_if(|c| c.is_ascii_digit() || *c == '.').is_some() {}
+            masked.push('?');
+        } else {
+            in_identifier = c.is_alphanumeric() || c == '_';
+            masked.push(c);
+        }
+    }
+    let mut hasher = DefaultHasher::new();
+    masked.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Statistics of the queries run by the builders it's handed to, see
+/// [`QueryBuilder::collect_stats`]. Its clones share the statistics, so that the queries of all
+/// the metrics of a request are recorded together.
+#[derive(Debug, Clone, Default)]
+pub struct QueryStatsCollector(Arc<Mutex<Vec<QueryStats>>>);
+
+impl QueryStatsCollector {
+    fn record(&self, stats: QueryStats) {
+        // The lock is only held to push or copy, a panic can't leave the statistics half written
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(stats);
+    }
+
+    /// Statistics of the queries run so far, in the order they finished
+    pub fn stats(&self) -> Vec<QueryStats> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Records the [`query_fingerprint`] of `query` as the `query_fingerprint` field of the current
+/// span, if it's enabled and has that field. Nothing is computed without a subscriber.
+fn record_query_fingerprint(query: &str) {
+    let span = tracing::Span::current();
+    if !span.is_disabled() {
+        span.record("query_fingerprint", query_fingerprint(query).as_str());
+    }
+}
+
+/// Fails with [`QueryExecutionError::TooLarge`] if `store` has a row limit and estimates the query
+/// to read more rows than that.
+async fn check_scanned_rows<P: AnalyticsDataSource>(
+    store: &P,
+    query: &str,
+) -> CustomResult<(), QueryExecutionError> {
+    let Some(max_rows) = store.max_scanned_rows() else {
+        return Ok(());
+    };
+    match store.estimate_scanned_rows(query).await? {
+        Some(rows) if rows > max_rows => Err(report!(QueryExecutionError::TooLarge))
+            .attach_printable_lazy(|| {
+                format!("Query is estimated to read {rows} rows, at most {max_rows} are allowed")
+            }),
+        _ => Ok(()),
+    }
+}
+
+/// Clickhouse lowering of [`AnalyticsDataSource::get_date_part`], as a string like the other
+/// dimensions
+pub(crate) fn clickhouse_date_part(
+    time_column: &str,
+    part: DatePart,
+    time_zone: Option<&str>,
+) -> String {
+    let tz = time_zone.map(|tz| format!(", '{tz}'")).unwrap_or_default();
+    match part {
+        // Mode 0 counts from Monday as 1
+        DatePart::DayOfWeek => format!("toString(toDayOfWeek({time_column}, 0{tz}))"),
+        DatePart::HourOfDay => format!("toString(toHour({time_column}{tz}))"),
+    }
+}
+
+/// Clickhouse lowering of [`AnalyticsDataSource::get_time_bucket`]
+pub(crate) fn clickhouse_time_bucket(
+    time_column: &str,
+    granularity: &Granularity,
+    time_zone: Option<&str>,
+) -> String {
+    let tz = time_zone.map(|tz| format!(", '{tz}'")).unwrap_or_default();
+    let time_bucket = match (granularity, time_zone.is_some()) {
+        (Granularity::OneMin, _) => {
+            format!("toStartOfInterval({time_column}, INTERVAL 1 MINUTE{tz})")
+        }
+        (Granularity::FiveMin, _) => {
+            format!("toStartOfInterval({time_column}, INTERVAL 5 MINUTE{tz})")
+        }
+        (Granularity::FifteenMin, _) => {
+            format!("toStartOfInterval({time_column}, INTERVAL 15 MINUTE{tz})")
+        }
+        (Granularity::ThirtyMin, _) => {
+            format!("toStartOfInterval({time_column}, INTERVAL 30 MINUTE{tz})")
+        }
+        // Minute intervals are counted from the unix epoch, which doesn't line up with the
+        // local clock for zones whose offset isn't a whole hour (e.g. +05:30)
+        (Granularity::OneHour, true) => format!("toStartOfHour({time_column}{tz})"),
+        (Granularity::OneHour, false) => {
+            format!("toStartOfInterval({time_column}, INTERVAL 60 MINUTE)")
+        }
+        (Granularity::OneDay, true) => format!("toStartOfDay({time_column}{tz})"),
+        (Granularity::OneDay, false) => {
+            format!("toStartOfInterval({time_column}, INTERVAL 1440 MINUTE)")
+        }
+        // Calendar aligned buckets don't have a fixed length in minutes, the Date returned
+        // is cast back to a DateTime so that time_bucket keeps the same format
+        (Granularity::Weekly, _) => format!("toDateTime(toStartOfWeek({time_column}, 1{tz}){tz})"),
+        (Granularity::Monthly, _) => format!("toDateTime(toStartOfMonth({time_column}{tz}){tz})"),
+        (Granularity::Quarterly, _) => {
+            format!("toDateTime(toStartOfQuarter({time_column}{tz}){tz})")
+        }
+    };
+    match (granularity, time_zone.is_some()) {
+        // The buckets shorter than a day start in the hour which is repeated when the clocks are
+        // turned back twice, once on either side of the change. They're labelled with their
+        // offset there, see [`REPEATED_TIME_BUCKET_FORMAT`], rather than being merged into one.
+        // A start is in that hour if it reads the same on the clock as the time an hour before
+        // or after it.
+        (
+            Granularity::OneMin
+            | Granularity::FiveMin
+            | Granularity::FifteenMin
+            | Granularity::ThirtyMin
+            | Granularity::OneHour,
+            true,
+        ) => format!(
+            "if(toString({time_bucket}) IN (toString({time_bucket} - INTERVAL 1 HOUR), \
+             toString({time_bucket} + INTERVAL 1 HOUR)), \
+             formatDateTime({time_bucket}, '%Y-%m-%d %H:%i:%S%z'), toString({time_bucket}))"
+        ),
+        // Rendered as a string, otherwise the iso output format converts it back to UTC
+        (_, true) => format!("toString({time_bucket})"),
+        (_, false) => time_bucket,
     }
 }
 
@@ -423,14 +1169,32 @@ where
         Self {
             columns: Default::default(),
             filters: Default::default(),
+            filter_groups: Default::default(),
+            joins: Default::default(),
             group_by: Default::default(),
+            rollup_group_by: Default::default(),
+            subtotals: false,
             having: Default::default(),
             outer_select: Default::default(),
+            outer_group_by: Default::default(),
             top_n: Default::default(),
+            order_by: Default::default(),
+            limit: Default::default(),
+            max_rows: Default::default(),
+            offset: Default::default(),
             table,
+            union_tables: Default::default(),
             distinct: Default::default(),
+            time_zone: Default::default(),
+            null_label: Default::default(),
+            timeout: Default::default(),
+            log_query: Default::default(),
+            stats: None,
+            sample_rate: None,
+            max_partitions: None,
             db_type: Default::default(),
             table_engine: T::get_table_engine(table),
+            time_column: T::get_time_column(table),
         }
     }
 
@@ -444,6 +1208,35 @@ where
         Ok(())
     }
 
+    pub fn add_aliased_select_column(
+        &mut self,
+        column: impl ToSql<T>,
+        alias: &str,
+    ) -> QueryResult<()> {
+        let column = column
+            .to_sql(&self.table_engine)
+            .change_context(QueryBuildingError::SqlSerializeError)
+            .attach_printable("Error serializing select column")?;
+        if column == alias {
+            self.columns.push(column);
+        } else {
+            self.columns.push(format!("{column} as {alias}"));
+        }
+        Ok(())
+    }
+
+    /// Joins `join` onto the table, a join which was already added is only joined once
+    pub fn add_join(&mut self, join: Join) -> QueryResult<()> {
+        let join = join
+            .to_sql(&self.table_engine)
+            .change_context(QueryBuildingError::SqlSerializeError)
+            .attach_printable("Error serializing join")?;
+        if !self.joins.contains(&join) {
+            self.joins.push(join);
+        }
+        Ok(())
+    }
+
     pub fn transform_to_sql_values(&mut self, values: &[impl ToSql<T>]) -> QueryResult<String> {
         let res = values
             .iter()
@@ -491,6 +1284,49 @@ where
         self.distinct = true
     }
 
+    /// Sorts the rows of the whole query, after any outer query or top n selection. Columns
+    /// are sorted by in the order they're added, a column may be an [`Aggregate`] to get the top
+    /// groups together with a limit. Nulls sort last in either order: clickhouse and postgres
+    /// disagree on where they go in descending order otherwise.
+    pub fn add_order_by_clause(&mut self, column: impl ToSql<T>, order: Order) -> QueryResult<()> {
+        self.order_by.push(format!(
+            "{} {} nulls last",
+            column
+                .to_sql(&self.table_engine)
+                .change_context(QueryBuildingError::SqlSerializeError)
+                .attach_printable("Error serializing order by column")?,
+            order.to_string()
+        ));
+        Ok(())
+    }
+
+    /// Returns at most `limit` rows
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = Some(limit)
+    }
+
+    /// Caps the result to `max_rows` rows, whatever the groups of the query. The data source
+    /// returns one row more than that if the result is larger, so that a result which was cut can
+    /// be told apart from one of exactly `max_rows` rows: the caller drops that row and flags the
+    /// result as truncated. The cap applies after the order by clause, the rows which are kept
+    /// are the first ones in the order of the query, and it only lowers a smaller limit.
+    pub fn set_max_rows(&mut self, max_rows: usize) {
+        self.max_rows = Some(max_rows)
+    }
+
+    /// Fails the query rather than have it read more than `max_partitions` partitions of its
+    /// tables, for the queries which are only meant to read the latest ones. It's left out on the
+    /// data sources which can't bound the partitions read, see
+    /// [`AnalyticsDataSource::get_max_partitions_clause`].
+    pub fn set_max_partitions(&mut self, max_partitions: u32) {
+        self.max_partitions = Some(max_partitions)
+    }
+
+    /// Skips the first `offset` rows, only deterministic together with an order by clause
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = Some(offset)
+    }
+
     pub fn add_filter_clause(
         &mut self,
         key: impl ToSql<T>,
@@ -507,6 +1343,59 @@ where
         self.add_custom_filter_clause(key, value, FilterTypes::EqualBool)
     }
 
+    /// Builder to collect the filters of one group for [`Self::add_any_of_filter_clause`]
+    pub fn new_filter_group(&self) -> Self {
+        Self {
+            time_zone: self.time_zone.clone(),
+            null_label: self.null_label.clone(),
+            ..Self::new(self.table)
+        }
+    }
+
+    /// Matches the rows which match all filters of at least one of the `groups`. Each group is
+    /// parenthesized, so that its filters can't bind to a neighbouring group. Nothing is added
+    /// when there are no groups or one of them has no filters, since that matches every row.
+    pub fn add_any_of_filter_clause(&mut self, groups: Vec<Self>) -> QueryResult<()> {
+        if groups.is_empty()
+            || groups
+                .iter()
+                .any(|group| group.filters.is_empty() && group.filter_groups.is_empty())
+        {
+            return Ok(());
+        }
+        let clause = groups
+            .iter()
+            .map(|group| format!("({})", group.get_filter_clause()))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        self.filter_groups.push(format!("({clause})"));
+        Ok(())
+    }
+
+    /// Matches the rows which match none of the `groups`. A row whose filter evaluates to null,
+    /// e.g. since a column it compares is null, doesn't match the group instead of being dropped.
+    pub fn add_none_of_filter_clause(&mut self, groups: Vec<Self>) -> QueryResult<()> {
+        if groups.is_empty() {
+            return Ok(());
+        }
+        if groups
+            .iter()
+            .any(|group| group.filters.is_empty() && group.filter_groups.is_empty())
+        {
+            return Err(report!(QueryBuildingError::InvalidQuery(
+                "A group matching every row would exclude all of them"
+            )));
+        }
+        let clause = groups
+            .iter()
+            .map(|group| format!("({})", group.get_filter_clause()))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        self.filter_groups
+            .push(format!("NOT coalesce({clause}, false)"));
+        Ok(())
+    }
+
     pub fn add_custom_filter_clause(
         &mut self,
         lhs: impl ToSql<T>,
@@ -530,7 +1419,13 @@ where
         key: impl ToSql<T>,
         values: &[impl ToSql<T>],
     ) -> QueryResult<()> {
-        let list = values
+        let list = self.range_list(values)?;
+        self.add_custom_filter_clause(key, list, FilterTypes::In)
+    }
+
+    /// `values` quoted and comma separated, for an `IN` clause
+    fn range_list(&self, values: &[impl ToSql<T>]) -> QueryResult<String> {
+        Ok(values
             .iter()
             .map(|i| {
                 // trimming whitespaces from the filter values received in request, to prevent a possibility of an SQL injection
@@ -542,8 +1437,30 @@ where
             .collect::<error_stack::Result<Vec<String>, ParsingError>>()
             .change_context(QueryBuildingError::SqlSerializeError)
             .attach_printable("Error serializing range filter value")?
-            .join(", ");
-        self.add_custom_filter_clause(key, list, FilterTypes::In)
+            .join(", "))
+    }
+
+    /// Keeps the rows whose `key` is within `range`, a range without bounds keeps every row
+    pub fn add_numeric_range_filter_clause(
+        &mut self,
+        key: impl ToSql<T>,
+        range: &analytics_api::NumericRange,
+    ) -> QueryResult<()> {
+        let key = key
+            .to_sql(&self.table_engine)
+            .change_context(QueryBuildingError::SqlSerializeError)
+            .attach_printable("Error serializing range filter key")?;
+        for (bound, comparison) in [
+            (range.gte, FilterTypes::Gte),
+            (range.lte, FilterTypes::Lte),
+            (range.gt, FilterTypes::Gt),
+            (range.lt, FilterTypes::Lt),
+        ] {
+            if let Some(bound) = bound {
+                self.add_custom_filter_clause(key.as_str(), bound, comparison)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn add_group_by_clause(&mut self, column: impl ToSql<T>) -> QueryResult<()> {
@@ -556,18 +1473,138 @@ where
         Ok(())
     }
 
+    /// Selects the start of the bucket for `granularity` as `time_bucket`.
+    ///
+    /// Without a time zone the bucket is a UTC `DateTime`. When a time zone has been set through
+    /// [`Self::set_time_zone`] the bucket is truncated in that zone and returned as the local wall
+    /// clock time of its start, formatted as [`TIME_BUCKET_FORMAT`].
     pub fn add_granularity_in_mins(&mut self, granularity: &Granularity) -> QueryResult<()> {
-        let interval = match granularity {
-            Granularity::OneMin => "1",
-            Granularity::FiveMin => "5",
-            Granularity::FifteenMin => "15",
-            Granularity::ThirtyMin => "30",
-            Granularity::OneHour => "60",
-            Granularity::OneDay => "1440",
-        };
-        let _ = self.add_select_column(format!(
-            "toStartOfInterval(created_at, INTERVAL {interval} MINUTE) as time_bucket"
-        ));
+        let time_bucket =
+            T::get_time_bucket(self.time_column, granularity, self.time_zone.as_deref());
+        let _ = self.add_select_column(format!("{time_bucket} as time_bucket"));
+        Ok(())
+    }
+
+    /// Selects the start of the bucket of the earliest row of each group as `time_bucket` like
+    /// [`Self::add_granularity_in_mins`], for the queries whose groups span several buckets, e.g.
+    /// the sessions. It isn't grouped by, it's an aggregate.
+    pub fn add_first_granularity_in_mins(&mut self, granularity: &Granularity) -> QueryResult<()> {
+        let first = format!("min({})", self.time_column);
+        let time_bucket = T::get_time_bucket(&first, granularity, self.time_zone.as_deref());
+        self.add_select_column(format!("{time_bucket} as time_bucket"))
+    }
+
+    /// Cancels the query if it's still running after `timeout`, queries don't time out by default.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Groups by `column` like [`Self::add_group_by_clause`], and rolls it up into subtotals when
+    /// [`Self::enable_subtotals`] is called
+    pub fn add_rollup_group_by_clause(&mut self, column: impl ToSql<T>) -> QueryResult<()> {
+        let column = column
+            .to_sql(&self.table_engine)
+            .change_context(QueryBuildingError::SqlSerializeError)
+            .attach_printable("Error serializing group by field")?;
+        self.rollup_group_by.push(column.clone());
+        self.group_by.push(column);
+        Ok(())
+    }
+
+    /// Returns the subtotals of the columns grouped by with [`Self::add_rollup_group_by_clause`]
+    /// besides the groups, as `GROUPING SETS` which clickhouse and postgres both support. Each
+    /// set keeps the other columns grouped by and the rolled up columns up to one of them, from
+    /// all of them down to none. A row of a set has the columns it doesn't group by set to null,
+    /// or to their default value by clickhouse, and selects the number of them as
+    /// `subtotal_level`, 0 for the rows of a single group.
+    pub fn enable_subtotals(&mut self) {
+        self.subtotals = true;
+    }
+
+    /// Logs the query at info level when it's run, instead of at debug level only
+    pub fn enable_query_logging(&mut self) {
+        self.log_query = true;
+    }
+
+    /// Records the [`QueryStats`] of the query in `stats` when it's run. The data source is asked
+    /// how much the query read, which may cost it some work on top of the query, see
+    /// [`AnalyticsDataSource::load_results_with_report`]. Streamed queries aren't recorded.
+    pub fn collect_stats(&mut self, stats: QueryStatsCollector) {
+        self.stats = Some(stats);
+    }
+
+    /// Runs the query on a sample of `sample_rate` of the rows of the table, which must be greater
+    /// than 0 and at most 1. A rate of 1 reads every row.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) -> QueryResult<()> {
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            return Err(error_stack::report!(QueryBuildingError::InvalidQuery(
+                "Sample rate must be greater than 0 and at most 1"
+            )))
+            .attach_printable_lazy(|| format!("Invalid sample rate: {sample_rate}"));
+        }
+        self.sample_rate = Some(sample_rate);
+        Ok(())
+    }
+
+    /// Also reads the rows of `collection`, e.g. the archived events along the recent ones, as if
+    /// they were rows of the table. The rows of every collection are filtered and aggregated
+    /// together, so that a group with rows in several of them is returned once with their sum.
+    /// The collection must have the same columns as the table, and hold its time in the same
+    /// column with the same table engine.
+    pub fn add_union_collection(&mut self, collection: AnalyticsCollection) -> QueryResult<()> {
+        if T::get_time_column(collection) != self.time_column
+            || T::get_table_engine(collection) != self.table_engine
+        {
+            return Err(error_stack::report!(QueryBuildingError::InvalidQuery(
+                "Collections of a union must have the same time column and table engine"
+            )))
+            .attach_printable_lazy(|| format!("Can't union {collection:?} with {:?}", self.table));
+        }
+        if collection != self.table && !self.union_tables.contains(&collection) {
+            self.union_tables.push(collection);
+        }
+        Ok(())
+    }
+
+    /// Column the time ranges of this query filter on and its granularities bucket
+    pub fn time_column(&self) -> &'static str {
+        self.time_column
+    }
+
+    /// The time zone set through [`Self::set_time_zone`], if any
+    pub fn time_zone(&self) -> Option<&str> {
+        self.time_zone.as_deref()
+    }
+
+    /// Aligns time buckets to the IANA time zone `time_zone` (e.g. `Asia/Kolkata`) instead of UTC.
+    pub fn set_time_zone(&mut self, time_zone: &str) -> QueryResult<()> {
+        // The zone name is interpolated into the query, so only names known to the tz database
+        // are accepted
+        let time_zone = time_zone
+            .parse::<chrono_tz::Tz>()
+            .map_err(|_| {
+                error_stack::report!(QueryBuildingError::InvalidQuery("Unknown time zone"))
+            })
+            .attach_printable_lazy(|| format!("Unknown time zone: {time_zone}"))?;
+        self.time_zone = Some(time_zone.name().to_string());
+        Ok(())
+    }
+
+    /// The label set through [`Self::set_null_label`], if any
+    pub fn null_label(&self) -> Option<&str> {
+        self.null_label.as_deref()
+    }
+
+    /// Labels the missing values of the dimensions with `null_label` instead of
+    /// [`NULL_DIMENSION_LABEL`], see [`is_valid_null_label`] for the labels which are accepted.
+    pub fn set_null_label(&mut self, null_label: &str) -> QueryResult<()> {
+        if !is_valid_null_label(null_label) {
+            return Err(error_stack::report!(QueryBuildingError::InvalidQuery(
+                "Unsupported null dimension label"
+            )))
+            .attach_printable_lazy(|| format!("Unsupported null dimension label: {null_label}"));
+        }
+        self.null_label = Some(null_label.to_string());
         Ok(())
     }
 
@@ -575,22 +1612,70 @@ where
         self.filters
             .iter()
             .map(|(l, op, r)| filter_type_to_sql(l, op, r))
+            .chain(self.filter_groups.iter().cloned())
             .collect::<Vec<String>>()
             .join(" AND ")
     }
 
     fn get_select_clause(&self) -> String {
-        self.columns.join(", ")
+        let subtotal_level = self.subtotal_level();
+        self.columns
+            .iter()
+            .chain(subtotal_level.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 
     fn get_group_by_clause(&self) -> String {
-        self.group_by.join(", ")
+        if !self.subtotals || self.rollup_group_by.is_empty() {
+            return self.group_by.join(", ");
+        }
+        let kept = self
+            .group_by
+            .iter()
+            .filter(|column| !self.rollup_group_by.contains(column))
+            .collect::<Vec<_>>();
+        // A set of the kept columns and the rolled up ones up to each, the longest set first
+        let sets = (0..=self.rollup_group_by.len())
+            .rev()
+            .map(|rolled| {
+                let set = kept
+                    .iter()
+                    .copied()
+                    .chain(self.rollup_group_by.iter().take(rolled))
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("({set})")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("GROUPING SETS ({sets})")
+    }
+
+    /// Number of the rolled up columns each row is a subtotal over, selected as `subtotal_level`
+    /// once subtotals are enabled. `grouping` is 1 for a column a row is aggregated over.
+    fn subtotal_level(&self) -> Option<String> {
+        (self.subtotals && !self.rollup_group_by.is_empty()).then(|| {
+            let level = self
+                .rollup_group_by
+                .iter()
+                .map(|column| format!("grouping({column})"))
+                .collect::<Vec<_>>()
+                .join(" + ");
+            format!("{level} as subtotal_level")
+        })
     }
 
     fn get_outer_select_clause(&self) -> String {
         self.outer_select.join(", ")
     }
 
+    fn get_outer_group_by_clause(&self) -> String {
+        self.outer_group_by.join(", ")
+    }
+
     pub fn add_having_clause<R>(
         &mut self,
         aggregate: Aggregate<R>,
@@ -617,6 +1702,37 @@ where
         Ok(())
     }
 
+    /// Keeps the groups whose `lhs` compares to `rhs`, for the conditions on an aggregate which
+    /// isn't an [`Aggregate`], e.g. a `countIf`
+    pub fn add_custom_having_clause(
+        &mut self,
+        lhs: impl ToSql<T>,
+        rhs: impl ToSql<T>,
+        comparison: FilterTypes,
+    ) -> QueryResult<()> {
+        let entry = (
+            lhs.to_sql(&self.table_engine)
+                .change_context(QueryBuildingError::SqlSerializeError)
+                .attach_printable("Error serializing having aggregate")?,
+            comparison,
+            rhs.to_sql(&self.table_engine)
+                .change_context(QueryBuildingError::SqlSerializeError)
+                .attach_printable("Error serializing having value")?,
+        );
+        self.having.get_or_insert_with(Vec::new).push(entry);
+        Ok(())
+    }
+
+    /// Keeps the groups whose `key`, an aggregate, is one of `values`
+    pub fn add_having_in_range_clause(
+        &mut self,
+        key: impl ToSql<T>,
+        values: &[impl ToSql<T>],
+    ) -> QueryResult<()> {
+        let list = self.range_list(values)?;
+        self.add_custom_having_clause(key, list, FilterTypes::In)
+    }
+
     pub fn add_outer_select_column(&mut self, column: impl ToSql<T>) -> QueryResult<()> {
         self.outer_select.push(
             column
@@ -627,6 +1743,56 @@ where
         Ok(())
     }
 
+    /// Groups the rows of the inner query again, for metrics which aggregate over an aggregate
+    /// (e.g. per session values summarised per bucket). Requires outer select columns.
+    pub fn add_outer_group_by_clause(&mut self, column: impl ToSql<T>) -> QueryResult<()> {
+        self.outer_group_by.push(
+            column
+                .to_sql(&self.table_engine)
+                .change_context(QueryBuildingError::SqlSerializeError)
+                .attach_printable("Error serializing outer group by field")?,
+        );
+        Ok(())
+    }
+
+    /// Fails with [`QueryBuildingError::InvalidQuery`] unless the query only reads the rows of a
+    /// single `key_column`, if it's given, between a lower and an upper bound of the time column:
+    /// the least it takes to not read the whole table, or every row written since the start. Only
+    /// the filters and-ed together count, one in an `OR` group doesn't bound the rows read.
+    pub fn check_scoped(&self, key_column: Option<&str>) -> QueryResult<()> {
+        let time_column = self.time_column;
+        let has_filter = |column: &str, bounds: fn(&FilterTypes) -> bool| {
+            self.filters
+                .iter()
+                .any(|(lhs, op, _)| lhs == column && bounds(op))
+        };
+        if let Some(key_column) =
+            key_column.filter(|column| !has_filter(column, |op| matches!(op, FilterTypes::Equal)))
+        {
+            return Err(error_stack::report!(QueryBuildingError::InvalidQuery(
+                "Query isn't filtered by its key"
+            )))
+            .attach_printable_lazy(|| format!("Missing a filter on {key_column}"));
+        }
+        if !has_filter(time_column, |op| {
+            matches!(op, FilterTypes::Gte | FilterTypes::Gt)
+        }) {
+            return Err(error_stack::report!(QueryBuildingError::InvalidQuery(
+                "Query isn't bounded by a time range"
+            )))
+            .attach_printable_lazy(|| format!("Missing a lower bound on {time_column}"));
+        }
+        if !has_filter(time_column, |op| {
+            matches!(op, FilterTypes::Lte | FilterTypes::Lt)
+        }) {
+            return Err(error_stack::report!(QueryBuildingError::InvalidQuery(
+                "Query isn't bounded by a time range"
+            )))
+            .attach_printable_lazy(|| format!("Missing an upper bound on {time_column}"));
+        }
+        Ok(())
+    }
+
     pub fn get_filter_type_clause(&self) -> Option<String> {
         self.having.as_ref().map(|vec| {
             vec.iter()
@@ -656,15 +1822,50 @@ where
 
         query.push_str(" FROM ");
 
-        query.push_str(
-            &self
-                .table
-                .to_sql(&self.table_engine)
-                .change_context(QueryBuildingError::SqlSerializeError)
-                .attach_printable("Error serializing table value")?,
-        );
+        let table = self
+            .table
+            .to_sql(&self.table_engine)
+            .change_context(QueryBuildingError::SqlSerializeError)
+            .attach_printable("Error serializing table value")?;
+        let sample_clause = self
+            .sample_rate
+            .filter(|sample_rate| *sample_rate < 1.0)
+            .map(T::get_sample_clause);
+
+        if self.union_tables.is_empty() {
+            query.push_str(&table);
+            if let Some(sample_clause) = &sample_clause {
+                query.push(' ');
+                query.push_str(sample_clause);
+            }
+        } else {
+            // Each collection is sampled on its own, a union can't be, and the union is named
+            // after the table so that the columns qualified by it still resolve
+            let union = std::iter::once(&self.table)
+                .chain(self.union_tables.iter())
+                .map(|collection| {
+                    let collection = collection
+                        .to_sql(&self.table_engine)
+                        .change_context(QueryBuildingError::SqlSerializeError)
+                        .attach_printable("Error serializing union collection")?;
+                    Ok(match &sample_clause {
+                        Some(sample_clause) => {
+                            format!("SELECT * FROM {collection} {sample_clause}")
+                        }
+                        None => format!("SELECT * FROM {collection}"),
+                    })
+                })
+                .collect::<QueryResult<Vec<_>>>()?
+                .join(" UNION ALL ");
+            query.push_str(&format!("({union}) {table}"));
+        }
+
+        for join in self.joins.iter() {
+            query.push(' ');
+            query.push_str(join);
+        }
 
-        if !self.filters.is_empty() {
+        if !self.filters.is_empty() || !self.filter_groups.is_empty() {
             query.push_str(" WHERE ");
             query.push_str(&self.get_filter_clause());
         }
@@ -697,6 +1898,10 @@ where
                 format!("SELECT {} FROM (", &self.get_outer_select_clause()).as_str(),
             );
             query.push_str(") _");
+            if !self.outer_group_by.is_empty() {
+                query.push_str(" GROUP BY ");
+                query.push_str(&self.get_outer_group_by_clause());
+            }
         }
 
         if let Some(top_n) = &self.top_n {
@@ -704,11 +1909,38 @@ where
             query.push_str(format!(") _ WHERE top_n <= {}", top_n.count).as_str());
         }
 
+        if !self.order_by.is_empty() {
+            query.push_str(" ORDER BY ");
+            query.push_str(&self.order_by.join(", "));
+        }
+
+        let max_rows = self.max_rows.map(|max_rows| max_rows.saturating_add(1));
+        if let Some(limit) = self.limit.into_iter().chain(max_rows).min() {
+            query.push_str(format!(" LIMIT {limit}").as_str());
+        }
+
+        if let Some(offset) = self.offset {
+            query.push_str(format!(" OFFSET {offset}").as_str());
+        }
+
+        if let Some(clause) = self.max_partitions.and_then(T::get_max_partitions_clause) {
+            query.push(' ');
+            query.push_str(&clause);
+        }
+
         println!("{}", query);
 
         Ok(query)
     }
 
+    fn log(&self, query: &str) {
+        if self.log_query {
+            logger::info!(?query, "Running analytics query");
+        } else {
+            logger::debug!(?query);
+        }
+    }
+
     pub async fn execute_query<R, P: AnalyticsDataSource>(
         &mut self,
         store: &P,
@@ -722,7 +1954,474 @@ where
             .build_query()
             .change_context(QueryBuildingError::SqlSerializeError)
             .attach_printable("Failed to execute query")?;
-        logger::debug!(?query);
-        Ok(store.load_results(query.as_str()).await)
+        self.log(&query);
+        record_query_fingerprint(&query);
+        if let Err(error) = check_scanned_rows(store, &query).await {
+            return Ok(Err(error));
+        }
+        if let Some(stats) = &self.stats {
+            let started = Instant::now();
+            return Ok(store
+                .load_results_with_report(query.as_str(), self.timeout)
+                .await
+                .map(|(rows, report)| {
+                    stats.record(QueryStats {
+                        query_fingerprint: query_fingerprint(&query),
+                        scanned_rows: report.scanned_rows,
+                        scanned_bytes: report.scanned_bytes,
+                        served_by_fallback: report.served_by_fallback,
+                        result_rows: u64::try_from(rows.len()).unwrap_or(u64::MAX),
+                        elapsed_ms: u64::try_from(started.elapsed().as_millis())
+                            .unwrap_or(u64::MAX),
+                    });
+                    rows
+                }));
+        }
+        match self.timeout {
+            Some(timeout) => Ok(store
+                .load_results_with_timeout(query.as_str(), timeout)
+                .await),
+            None => Ok(store.load_results(query.as_str()).await),
+        }
+    }
+
+    /// Like [`Self::execute_query`] but streams the rows, the timeout only bounds how long the
+    /// data source may take to start answering.
+    pub async fn execute_query_stream<R, P: AnalyticsDataSource>(
+        &mut self,
+        store: &P,
+    ) -> CustomResult<CustomResult<RowStream<R>, QueryExecutionError>, QueryBuildingError>
+    where
+        P: LoadRow<R>,
+        R: Send + 'static,
+        Aggregate<&'static str>: ToSql<T>,
+        Window<&'static str>: ToSql<T>,
+    {
+        let query = self
+            .build_query()
+            .change_context(QueryBuildingError::SqlSerializeError)
+            .attach_printable("Failed to execute query")?;
+        self.log(&query);
+        record_query_fingerprint(&query);
+        if let Err(error) = check_scanned_rows(store, &query).await {
+            return Ok(Err(error));
+        }
+        let rows = store.load_results_stream(query);
+        match self.timeout {
+            Some(timeout) => Ok(tokio::time::timeout(timeout, rows)
+                .await
+                .map_err(|_| report!(QueryExecutionError::Timeout))
+                .attach_printable_lazy(|| format!("Query did not start within {timeout:?}"))
+                .and_then(|rows| rows)),
+            None => Ok(rows.await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use time::{Date, Month, PrimitiveDateTime, Time, UtcOffset};
+
+    use super::*;
+    use crate::{clickhouse::ClickhouseClient, sqlx::SqlxClient};
+
+    fn utc(day: u8, hour: u8, minute: u8) -> time::OffsetDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2024, Month::March, day).expect("invalid date"),
+            Time::from_hms(hour, minute, 0).expect("invalid time"),
+        )
+        .assume_utc()
+    }
+
+    fn local_day_bucket(instant: time::OffsetDateTime, offset: UtcOffset) -> String {
+        let local = instant.to_offset(offset);
+        format_time_bucket(PrimitiveDateTime::new(local.date(), Time::MIDNIGHT))
+            .expect("failed to format time bucket")
+    }
+
+    #[test]
+    fn test_time_bucket_round_trip_positive_offset() {
+        let offset = UtcOffset::from_hms(5, 30, 0).expect("invalid offset");
+        // 20:00 UTC is already the next day in +05:30
+        let bucket = local_day_bucket(utc(9, 20, 0), offset);
+        assert_eq!(bucket, "2024-03-10 00:00:00");
+
+        let parsed = parse_time_bucket(&bucket).expect("failed to parse time bucket");
+        assert_eq!(parsed.assume_offset(offset), utc(9, 18, 30));
+    }
+
+    #[test]
+    fn test_time_bucket_round_trip_negative_offset() {
+        let offset = UtcOffset::from_hms(-8, 0, 0).expect("invalid offset");
+        // 03:00 UTC is still the previous day in -08:00
+        let bucket = local_day_bucket(utc(10, 3, 0), offset);
+        assert_eq!(bucket, "2024-03-09 00:00:00");
+
+        let parsed = parse_time_bucket(&bucket).expect("failed to parse time bucket");
+        assert_eq!(parsed.assume_offset(offset), utc(9, 8, 0));
+    }
+
+    #[test]
+    fn test_repeated_time_bucket_round_trip() {
+        let start = PrimitiveDateTime::new(
+            Date::from_calendar_date(2024, Month::November, 3).expect("invalid date"),
+            Time::from_hms(1, 0, 0).expect("invalid time"),
+        );
+        let offset = UtcOffset::from_hms(-5, 0, 0).expect("invalid offset");
+
+        let bucket =
+            format_time_bucket_offset(start, Some(offset)).expect("failed to format time bucket");
+        assert_eq!(bucket, "2024-11-03 01:00:00-0500");
+        assert_eq!(
+            parse_time_bucket_offset(&bucket).expect("failed to parse time bucket"),
+            (start, Some(offset))
+        );
+        assert_eq!(
+            parse_time_bucket_offset("2024-11-03 01:00:00").expect("failed to parse time bucket"),
+            (start, None)
+        );
+    }
+
+    #[test]
+    fn test_limit_is_applied_last() {
+        let mut builder: QueryBuilder<crate::ClickhouseClient> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        builder
+            .add_select_column("browser_name")
+            .expect("failed to add select column");
+        builder.set_distinct();
+        builder.set_limit(11);
+        assert_eq!(
+            builder.build_query().expect("failed to build query"),
+            "SELECT DISTINCT browser_name FROM sdk_events_audit LIMIT 11"
+        );
+    }
+
+    #[test]
+    fn test_order_by_comes_before_limit_and_offset() {
+        let mut builder: QueryBuilder<crate::ClickhouseClient> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        builder
+            .add_select_column("browser_name")
+            .expect("failed to add select column");
+        builder
+            .add_group_by_clause("browser_name")
+            .expect("failed to add group by clause");
+        builder
+            .add_order_by_clause("count(*)", Order::Descending)
+            .expect("failed to add order by clause");
+        builder
+            .add_order_by_clause("browser_name", Order::Ascending)
+            .expect("failed to add order by clause");
+        builder.set_limit(10);
+        builder.set_offset(20);
+        assert_eq!(
+            builder.build_query().expect("failed to build query"),
+            "SELECT browser_name FROM sdk_events_audit GROUP BY browser_name ORDER BY count(*) desc nulls last, browser_name asc nulls last LIMIT 10 OFFSET 20"
+        );
+    }
+
+    #[test]
+    fn test_order_by_aggregate_for_postgres() {
+        let mut builder: QueryBuilder<crate::SqlxClient> =
+            QueryBuilder::new(AnalyticsCollection::Payment);
+        builder
+            .add_select_column("connector")
+            .expect("failed to add select column");
+        builder
+            .add_group_by_clause("connector")
+            .expect("failed to add group by clause");
+        builder
+            .add_order_by_clause(
+                Aggregate::Sum {
+                    field: "amount",
+                    alias: None,
+                },
+                Order::Descending,
+            )
+            .expect("failed to add order by clause");
+        builder.set_limit(10);
+        assert!(builder
+            .build_query()
+            .expect("failed to build query")
+            .ends_with("GROUP BY connector ORDER BY sum(amount) desc nulls last LIMIT 10"));
+    }
+
+    #[test]
+    fn test_check_scoped_ignores_filters_in_or_groups() {
+        let mut builder: QueryBuilder<crate::ClickhouseClient> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        builder
+            .add_custom_filter_clause("created_at", "2024-03-01 00:00:00", FilterTypes::Gte)
+            .expect("failed to add filter clause");
+        builder
+            .add_custom_filter_clause("created_at", "2024-03-08 00:00:00", FilterTypes::Lte)
+            .expect("failed to add filter clause");
+        let mut group = builder.new_filter_group();
+        group
+            .add_filter_clause("merchant_id", "pk_test")
+            .expect("failed to add filter clause");
+        builder
+            .add_any_of_filter_clause(vec![group])
+            .expect("failed to add filter group");
+        assert!(builder.check_scoped(Some("merchant_id")).is_err());
+        assert!(builder.check_scoped(None).is_ok());
+
+        builder
+            .add_filter_clause("merchant_id", "pk_test")
+            .expect("failed to add filter clause");
+        assert!(builder.check_scoped(Some("merchant_id")).is_ok());
+    }
+
+    #[test]
+    fn test_check_scoped_requires_an_upper_time_bound() {
+        let mut builder: QueryBuilder<crate::ClickhouseClient> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        builder
+            .add_filter_clause("merchant_id", "pk_test")
+            .expect("failed to add filter clause");
+        builder
+            .add_custom_filter_clause("created_at", "2024-03-01 00:00:00", FilterTypes::Gte)
+            .expect("failed to add filter clause");
+        // Every row written since the start would be read
+        assert!(builder.check_scoped(Some("merchant_id")).is_err());
+
+        builder
+            .add_custom_filter_clause("created_at", "2024-03-08 00:00:00", FilterTypes::Lt)
+            .expect("failed to add filter clause");
+        assert!(builder.check_scoped(Some("merchant_id")).is_ok());
+    }
+
+    #[test]
+    fn test_query_fingerprint_ignores_literals() {
+        let query = |merchant_id: &str, start_time: &str, limit: u32| {
+            format!(
+                "SELECT quantile(0.95)(latency) as p95 FROM sdk_events_audit WHERE \
+                 merchant_id = '{merchant_id}' AND created_at >= '{start_time}' LIMIT {limit}"
+            )
+        };
+        let fingerprint = query_fingerprint(&query("pk_test", "2024-03-01 00:00:00", 10));
+
+        assert_eq!(
+            fingerprint,
+            query_fingerprint(&query("pk_o''brien", "2024-04-01 00:00:00", 25))
+        );
+        assert_ne!(
+            fingerprint,
+            query_fingerprint(&query("pk_test", "2024-03-01 00:00:00", 10).replace("p95", "p99"))
+        );
+        assert_ne!(
+            fingerprint,
+            query_fingerprint(&query("pk_test", "2024-03-01 00:00:00", 10).replace(">=", ">"))
+        );
+    }
+
+    /// Clickhouse, with the time of the sdk events stored in `event_time`
+    struct EventTimeSource;
+
+    #[async_trait::async_trait]
+    impl AnalyticsDataSource for EventTimeSource {
+        type Row = serde_json::Value;
+
+        fn source_name(&self) -> &'static str {
+            "EventTime"
+        }
+
+        async fn load_results<T>(&self, _query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
+        where
+            Self: LoadRow<T>,
+        {
+            Ok(Vec::new())
+        }
+
+        fn get_time_column(table: AnalyticsCollection) -> &'static str {
+            match table {
+                AnalyticsCollection::SdkEvents => "event_time",
+                _ => "created_at",
+            }
+        }
+    }
+
+    impl ToSql<EventTimeSource> for AnalyticsCollection {
+        fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+            <Self as ToSql<crate::ClickhouseClient>>::to_sql(self, table_engine)
+        }
+    }
+
+    impl ToSql<EventTimeSource> for PrimitiveDateTime {
+        fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+            <Self as ToSql<crate::ClickhouseClient>>::to_sql(self, table_engine)
+        }
+    }
+
+    impl GroupByClause<EventTimeSource> for Granularity {
+        fn set_group_by_clause(
+            &self,
+            builder: &mut QueryBuilder<EventTimeSource>,
+        ) -> QueryResult<()> {
+            let time_bucket =
+                EventTimeSource::get_time_bucket(builder.time_column(), self, builder.time_zone());
+            builder.add_group_by_clause(time_bucket)
+        }
+    }
+
+    #[test]
+    fn test_time_column_of_the_data_source_is_filtered_and_bucketed() {
+        let mut builder: QueryBuilder<EventTimeSource> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        builder
+            .add_aliased_select_column(SdkEventDimensions::HourOfDay, "hour_of_day")
+            .expect("failed to add select column");
+        builder
+            .add_granularity_in_mins(&Granularity::OneHour)
+            .expect("failed to add granularity");
+        analytics_api::TimeRange {
+            start_time: utc(1, 0, 0).date().midnight(),
+            end_time: Some(utc(2, 0, 0).date().midnight()),
+        }
+        .set_filter_clause(&mut builder)
+        .expect("failed to filter time range");
+
+        let query = builder.build_query().expect("failed to build query");
+        assert_eq!(
+            query,
+            "SELECT toString(toHour(event_time)) as hour_of_day, \
+             toStartOfInterval(event_time, INTERVAL 60 MINUTE) as time_bucket \
+             FROM sdk_events_audit WHERE event_time >= '2024-03-01 00:00:00' \
+             AND event_time <= '2024-03-02 00:00:00'"
+        );
+        assert!(builder.check_scoped(None).is_ok());
+        // The other tables keep theirs
+        assert_eq!(
+            QueryBuilder::<EventTimeSource>::new(AnalyticsCollection::Payment).time_column(),
+            "created_at"
+        );
+    }
+
+    #[test]
+    fn test_set_time_zone_rejects_unknown_zone() {
+        let mut builder: QueryBuilder<crate::ClickhouseClient> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        assert!(builder.set_time_zone("Asia/Kolkata").is_ok());
+        assert!(builder
+            .set_time_zone("UTC'); DROP TABLE sdk_events_audit; --")
+            .is_err());
+    }
+
+    #[test]
+    fn test_union_collections_are_aggregated_together() {
+        let mut builder: QueryBuilder<crate::ClickhouseClient> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        builder
+            .add_select_column("platform")
+            .expect("failed to add select column");
+        builder
+            .add_select_column(Aggregate::Count {
+                field: None,
+                alias: Some("count"),
+            })
+            .expect("failed to add select column");
+        builder
+            .add_union_collection(AnalyticsCollection::SdkEventsArchive)
+            .expect("failed to add union collection");
+        // Adding a collection again, or the table itself, doesn't read its rows twice
+        builder
+            .add_union_collection(AnalyticsCollection::SdkEventsArchive)
+            .expect("failed to add union collection");
+        builder
+            .add_union_collection(AnalyticsCollection::SdkEvents)
+            .expect("failed to add union collection");
+        builder
+            .set_sample_rate(0.5)
+            .expect("failed to set sample rate");
+        builder
+            .add_filter_clause("merchant_id", "pk_test")
+            .expect("failed to add filter");
+        builder
+            .add_group_by_clause("platform")
+            .expect("failed to add group by");
+
+        assert_eq!(
+            builder.build_query().expect("failed to build query"),
+            "SELECT platform, count(*) as count FROM (SELECT * FROM sdk_events_audit SAMPLE 0.5 \
+             UNION ALL SELECT * FROM sdk_events_audit_archive SAMPLE 0.5) sdk_events_audit \
+             WHERE merchant_id = 'pk_test' GROUP BY platform"
+        );
+
+        // Rows counted by a sign can't be added to rows counted one by one
+        assert!(builder
+            .add_union_collection(AnalyticsCollection::Payment)
+            .is_err());
+        // Nor filtered on another time column
+        let mut builder: QueryBuilder<EventTimeSource> =
+            QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        assert!(builder
+            .add_union_collection(AnalyticsCollection::SdkEventsArchive)
+            .is_err());
+    }
+
+    fn lower<T: AnalyticsDataSource>(aggregate: Aggregate<&'static str>) -> String
+    where
+        Aggregate<&'static str>: ToSql<T>,
+    {
+        aggregate
+            .to_sql(&TableEngine::BasicTree)
+            .expect("failed to lower aggregate")
+    }
+
+    #[test]
+    fn test_min_max_lowering() {
+        let min = || Aggregate::Min {
+            field: "latency",
+            alias: Some("min"),
+        };
+        let max = || Aggregate::Max {
+            field: "latency",
+            alias: Some("max"),
+        };
+        assert_eq!(lower::<ClickhouseClient>(min()), "min(latency) as min");
+        assert_eq!(lower::<ClickhouseClient>(max()), "max(latency) as max");
+        assert_eq!(lower::<SqlxClient>(min()), "min(latency) as min");
+        assert_eq!(lower::<SqlxClient>(max()), "max(latency) as max");
+    }
+
+    #[test]
+    fn test_event_count_if_lowering() {
+        let count = |count_column| EventCountIf {
+            condition: "event_name = 'PAYMENT_ATTEMPT'",
+            count_column,
+            alias: "payment_attempts",
+        };
+        let lower = |count: EventCountIf<'_>| {
+            (
+                <EventCountIf<'_> as ToSql<ClickhouseClient>>::to_sql(
+                    &count,
+                    &TableEngine::BasicTree,
+                )
+                .expect("failed to lower count"),
+                <EventCountIf<'_> as ToSql<SqlxClient>>::to_sql(&count, &TableEngine::BasicTree)
+                    .expect("failed to lower count"),
+            )
+        };
+        assert_eq!(
+            lower(count(None)),
+            (
+                "countIf(event_name = 'PAYMENT_ATTEMPT') as payment_attempts".to_string(),
+                "sum(CASE WHEN event_name = 'PAYMENT_ATTEMPT' THEN 1 ELSE 0 END) \
+                 as payment_attempts"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            lower(count(Some("event_count"))),
+            (
+                "sumIf(event_count, event_name = 'PAYMENT_ATTEMPT') as payment_attempts"
+                    .to_string(),
+                "sum(CASE WHEN event_name = 'PAYMENT_ATTEMPT' THEN event_count ELSE 0 END) \
+                 as payment_attempts"
+                    .to_string()
+            )
+        );
     }
 }
@@ -1,8 +1,12 @@
 pub mod accumulator;
+pub mod computed_dimension;
 mod core;
 pub mod events;
+pub mod export;
 pub mod filters;
 pub mod metrics;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
 pub mod types;
 pub use accumulator::{SdkEventMetricAccumulator, SdkEventMetricsAccumulator};
 pub trait SDKEventAnalytics: events::SdkEventsFilterAnalytics {}
@@ -11,4 +15,6 @@ pub trait SdkEventAnalytics:
 {
 }
 
-pub use self::core::{get_filters, get_metrics, sdk_events_core};
+pub use self::core::{
+    get_filters, get_metrics, get_recent_metrics, sdk_event_sample_core, sdk_events_core,
+};
@@ -1,4 +1,6 @@
-use api_models::analytics::sdk_events::SdkEventMetricsBucketValue;
+use api_models::analytics::sdk_events::{
+    FunnelStage, FunnelWaterfallStage, SdkEventMetrics, SdkEventMetricsBucketValue,
+};
 use router_env::logger;
 
 use super::metrics::SdkEventMetricRow;
@@ -8,6 +10,10 @@ pub struct SdkEventMetricsAccumulator {
     pub payment_attempts: CountAccumulator,
     pub payment_methods_call_count: CountAccumulator,
     pub average_payment_time: AverageAccumulator,
+    pub payment_time_percentiles: PercentileAccumulator,
+    pub payment_time_range: MinMaxAccumulator,
+    pub payment_time_median: MedianAccumulator,
+    pub payment_time_spread: SpreadAccumulator,
     pub sdk_initiated_count: CountAccumulator,
     pub sdk_rendered_count: CountAccumulator,
     pub payment_method_selected_count: CountAccumulator,
@@ -19,6 +25,27 @@ pub struct SdkEventMetricsAccumulator {
     pub authentication_unsuccessful_count: CountAccumulator,
     pub three_ds_challenge_flow_count: CountAccumulator,
     pub three_ds_frictionless_flow_count: CountAccumulator,
+    pub sdk_error_encountered_count: CountAccumulator,
+    pub session_duration_seconds: AverageAccumulator,
+    pub session_duration_percentiles: PercentileAccumulator,
+    pub funnel_conversion_rate: FunnelAccumulator,
+    pub unique_sessions_count: CountAccumulator,
+    pub payment_success_rate: SuccessRateAccumulator,
+    pub payment_retry_count: AverageAccumulator,
+    pub payment_retry_percentiles: PercentileAccumulator,
+    pub max_payment_retries: MaxRetriesAccumulator,
+    pub three_ds_flow_breakdown: ThreeDsFlowAccumulator,
+    pub sdk_bounce_rate: BounceRateAccumulator,
+    pub payment_experience_breakdown: CountAccumulator,
+    pub time_to_first_render: AverageAccumulator,
+    pub time_to_first_render_percentiles: PercentileAccumulator,
+    pub funnel_waterfall: FunnelWaterfallAccumulator,
+    pub sdk_load_failure_count: CountAccumulator,
+    pub average_payment_methods_displayed: AverageAccumulator,
+    pub payment_attempted_amount: SumAccumulator,
+    pub latency_breakdown: LatencyBreakdownAccumulator,
+    pub three_ds_method_success_rate: SuccessRateAccumulator,
+    pub abandoned_sessions_count: CountAccumulator,
 }
 
 #[derive(Debug, Default)]
@@ -27,12 +54,103 @@ pub struct CountAccumulator {
     pub count: Option<i64>,
 }
 
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct SumAccumulator {
+    pub total: Option<u64>,
+}
+
 #[derive(Debug, Default)]
 pub struct AverageAccumulator {
     pub total: u32,
     pub count: u32,
 }
 
+#[derive(Debug, Default)]
+pub struct LatencyBreakdownAccumulator {
+    pub network_total: f64,
+    pub render_total: f64,
+    pub count: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct MinMaxAccumulator {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct MedianAccumulator {
+    pub median: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub struct SpreadAccumulator {
+    pub stddev: Option<f64>,
+    pub variance: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct MaxRetriesAccumulator {
+    pub max_retries: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct BounceRateAccumulator {
+    pub initiated: CountAccumulator,
+    pub rendered: CountAccumulator,
+}
+
+#[derive(Debug, Default)]
+pub struct ThreeDsFlowAccumulator {
+    pub challenge: CountAccumulator,
+    pub frictionless: CountAccumulator,
+}
+
+#[derive(Debug, Default)]
+pub struct PercentileAccumulator {
+    pub p50: Option<f64>,
+    pub p90: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub struct FunnelAccumulator {
+    pub initiated: CountAccumulator,
+    pub rendered: CountAccumulator,
+    pub selected: CountAccumulator,
+    pub filled: CountAccumulator,
+    pub attempted: CountAccumulator,
+}
+
+#[derive(Debug, Default)]
+pub struct FunnelWaterfallAccumulator {
+    pub stages: Option<Vec<FunnelStage>>,
+    pub counts: FunnelAccumulator,
+}
+
+#[derive(Debug, Default)]
+pub struct SuccessRateAccumulator {
+    pub successful: CountAccumulator,
+    pub attempted: CountAccumulator,
+}
+
+#[derive(Debug)]
+pub struct FunnelOutput {
+    pub initiated: Option<u64>,
+    pub rendered: Option<u64>,
+    pub selected: Option<u64>,
+    pub filled: Option<u64>,
+    pub attempted: Option<u64>,
+    pub initiated_to_rendered_rate: Option<f64>,
+    pub rendered_to_selected_rate: Option<f64>,
+    pub selected_to_filled_rate: Option<f64>,
+    pub filled_to_attempted_rate: Option<f64>,
+}
+
 pub trait SdkEventMetricAccumulator {
     type MetricOutput;
 
@@ -41,22 +159,48 @@ pub trait SdkEventMetricAccumulator {
     fn collect(self) -> Self::MetricOutput;
 }
 
-impl SdkEventMetricAccumulator for CountAccumulator {
-    type MetricOutput = Option<u64>;
+impl CountAccumulator {
     #[inline]
-    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
-        self.count = match (self.count, metrics.count) {
+    fn add_count(&mut self, count: Option<i64>) {
+        self.count = match (self.count, count) {
             (None, None) => None,
             (None, i @ Some(_)) | (i @ Some(_), None) => i,
             (Some(a), Some(b)) => Some(a + b),
         }
     }
+}
+
+impl SdkEventMetricAccumulator for CountAccumulator {
+    type MetricOutput = Option<u64>;
+    #[inline]
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        self.add_count(metrics.count)
+    }
     #[inline]
     fn collect(self) -> Self::MetricOutput {
         self.count.and_then(|i| u64::try_from(i).ok())
     }
 }
 
+impl SdkEventMetricAccumulator for SumAccumulator {
+    type MetricOutput = Option<u64>;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        let total = metrics
+            .total
+            .as_ref()
+            .and_then(bigdecimal::ToPrimitive::to_u64);
+        self.total = match (self.total, total) {
+            (Some(a), Some(b)) => Some(a.saturating_add(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        self.total
+    }
+}
+
 impl SdkEventMetricAccumulator for AverageAccumulator {
     type MetricOutput = Option<f64>;
 
@@ -87,13 +231,420 @@ impl SdkEventMetricAccumulator for AverageAccumulator {
     }
 }
 
+impl SdkEventMetricAccumulator for LatencyBreakdownAccumulator {
+    /// Average network and render time
+    type MetricOutput = (Option<f64>, Option<f64>);
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        let to_f64 = |value: &Option<bigdecimal::BigDecimal>| {
+            value.as_ref().and_then(bigdecimal::ToPrimitive::to_f64)
+        };
+        let count = metrics.count.and_then(|count| u32::try_from(count).ok());
+
+        match (
+            to_f64(&metrics.network_total),
+            to_f64(&metrics.render_total),
+            count,
+        ) {
+            (Some(network_total), Some(render_total), Some(count)) => {
+                self.network_total += network_total;
+                self.render_total += render_total;
+                self.count = self.count.saturating_add(count);
+            }
+            _ => {
+                logger::error!(message="Dropping metrics for latency accumulator", metric=?metrics);
+            }
+        }
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        if self.count == 0 {
+            (None, None)
+        } else {
+            let count = f64::from(self.count);
+            (
+                Some(self.network_total / count),
+                Some(self.render_total / count),
+            )
+        }
+    }
+}
+
+impl SdkEventMetricAccumulator for MinMaxAccumulator {
+    type MetricOutput = (Option<f64>, Option<f64>);
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        let to_f64 = |value: &Option<bigdecimal::BigDecimal>| {
+            value.as_ref().and_then(bigdecimal::ToPrimitive::to_f64)
+        };
+        self.min = match (self.min, to_f64(&metrics.min)) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = match (self.max, to_f64(&metrics.max)) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        (self.min, self.max)
+    }
+}
+
+impl SdkEventMetricAccumulator for BounceRateAccumulator {
+    type MetricOutput = Option<f64>;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        // The bounce rate of the row is recomputed from the counts so that rows can merge
+        self.initiated.add_count(metrics.initiated);
+        self.rendered.add_count(metrics.rendered);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        match (self.initiated.collect(), self.rendered.collect()) {
+            (Some(initiated), rendered) if initiated > 0 => {
+                let initiated = f64::from(u32::try_from(initiated).ok()?);
+                let rendered = f64::from(u32::try_from(rendered.unwrap_or_default()).ok()?);
+                Some((initiated - rendered) / initiated)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl SdkEventMetricAccumulator for ThreeDsFlowAccumulator {
+    type MetricOutput = (Option<u64>, Option<u64>, Option<f64>);
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        // The challenge rate of the row is recomputed from the counts so that rows can merge
+        self.challenge.add_count(metrics.challenge_count);
+        self.frictionless.add_count(metrics.frictionless_count);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        let challenge = self.challenge.collect();
+        let frictionless = self.frictionless.collect();
+        let challenge_rate = match (challenge, frictionless) {
+            (Some(challenge), Some(frictionless)) if challenge + frictionless > 0 => {
+                u32::try_from(challenge)
+                    .ok()
+                    .zip(u32::try_from(frictionless).ok())
+                    .map(|(challenge, frictionless)| {
+                        f64::from(challenge) / (f64::from(challenge) + f64::from(frictionless))
+                    })
+            }
+            _ => None,
+        };
+        (challenge, frictionless, challenge_rate)
+    }
+}
+
+impl SdkEventMetricAccumulator for MedianAccumulator {
+    type MetricOutput = Option<f64>;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        // Like percentiles a median can't be merged across rows
+        self.median = metrics
+            .median
+            .as_ref()
+            .and_then(bigdecimal::ToPrimitive::to_f64)
+            .or(self.median);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        self.median
+    }
+}
+
+impl SdkEventMetricAccumulator for SpreadAccumulator {
+    type MetricOutput = (Option<f64>, Option<f64>);
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        // The spread of the values of several rows isn't the spread of their spreads either
+        let to_f64 = |value: &Option<bigdecimal::BigDecimal>| {
+            value.as_ref().and_then(bigdecimal::ToPrimitive::to_f64)
+        };
+        self.stddev = to_f64(&metrics.stddev).or(self.stddev);
+        self.variance = to_f64(&metrics.variance).or(self.variance);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        (self.stddev, self.variance)
+    }
+}
+
+impl SdkEventMetricAccumulator for MaxRetriesAccumulator {
+    type MetricOutput = Option<u64>;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        self.max_retries = match (self.max_retries, metrics.max_retries) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        self.max_retries.and_then(|i| u64::try_from(i).ok())
+    }
+}
+
+impl SdkEventMetricAccumulator for PercentileAccumulator {
+    type MetricOutput = (Option<f64>, Option<f64>, Option<f64>, Option<f64>);
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        // Percentiles can't be merged across rows, a bucket is expected to arrive as a single row
+        let to_f64 = |value: &Option<bigdecimal::BigDecimal>| {
+            value.as_ref().and_then(bigdecimal::ToPrimitive::to_f64)
+        };
+        self.p50 = to_f64(&metrics.p50).or(self.p50);
+        self.p90 = to_f64(&metrics.p90).or(self.p90);
+        self.p95 = to_f64(&metrics.p95).or(self.p95);
+        self.p99 = to_f64(&metrics.p99).or(self.p99);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        (self.p50, self.p90, self.p95, self.p99)
+    }
+}
+
+impl SdkEventMetricAccumulator for FunnelAccumulator {
+    type MetricOutput = FunnelOutput;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        self.initiated.add_count(metrics.initiated);
+        self.rendered.add_count(metrics.rendered);
+        self.selected.add_count(metrics.selected);
+        self.filled.add_count(metrics.filled);
+        self.attempted.add_count(metrics.attempted);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        // An empty stage has no conversion ratio to the next one
+        let rate = |from: Option<u64>, to: Option<u64>| match (from, to) {
+            (Some(from), Some(to)) if from > 0 => {
+                Some(f64::from(u32::try_from(to).ok()?) / f64::from(u32::try_from(from).ok()?))
+            }
+            _ => None,
+        };
+        let initiated = self.initiated.collect();
+        let rendered = self.rendered.collect();
+        let selected = self.selected.collect();
+        let filled = self.filled.collect();
+        let attempted = self.attempted.collect();
+        FunnelOutput {
+            initiated,
+            rendered,
+            selected,
+            filled,
+            attempted,
+            initiated_to_rendered_rate: rate(initiated, rendered),
+            rendered_to_selected_rate: rate(rendered, selected),
+            selected_to_filled_rate: rate(selected, filled),
+            filled_to_attempted_rate: rate(filled, attempted),
+        }
+    }
+}
+
+impl FunnelAccumulator {
+    fn stage(&self, stage: FunnelStage) -> &CountAccumulator {
+        match stage {
+            FunnelStage::Initiated => &self.initiated,
+            FunnelStage::Rendered => &self.rendered,
+            FunnelStage::Selected => &self.selected,
+            FunnelStage::Filled => &self.filled,
+            FunnelStage::Attempted => &self.attempted,
+        }
+    }
+}
+
+impl SdkEventMetricAccumulator for FunnelWaterfallAccumulator {
+    type MetricOutput = Option<Vec<FunnelWaterfallStage>>;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        self.stages = metrics.funnel_stages.clone().or(self.stages.take());
+        self.counts.add_metrics_bucket(metrics);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        let stages = self.stages?;
+        let counts = stages
+            .iter()
+            .map(|stage| self.counts.stage(*stage).count)
+            .collect::<Vec<_>>();
+        // The first stage has no previous one
+        let previous_counts = std::iter::once(None).chain(counts.iter().copied().map(Some));
+        let waterfall = stages
+            .into_iter()
+            .zip(counts.iter().copied())
+            .zip(previous_counts)
+            .map(|((stage, count), previous)| FunnelWaterfallStage {
+                stage,
+                count: count.and_then(|count| u64::try_from(count).ok()),
+                drop_from_previous: previous
+                    .flatten()
+                    .zip(count)
+                    .and_then(|(previous, count)| previous.checked_sub(count)),
+            })
+            .collect();
+        Some(waterfall)
+    }
+}
+
+impl SdkEventMetricAccumulator for SuccessRateAccumulator {
+    type MetricOutput = Option<f64>;
+
+    fn add_metrics_bucket(&mut self, metrics: &SdkEventMetricRow) {
+        // The rate of the row is recomputed from the counts so that rows of a bucket can merge
+        self.successful.add_count(metrics.successful);
+        self.attempted.add_count(metrics.attempted);
+    }
+
+    fn collect(self) -> Self::MetricOutput {
+        match (self.successful.collect(), self.attempted.collect()) {
+            (Some(successful), Some(attempted)) if attempted > 0 => Some(
+                f64::from(u32::try_from(successful).ok()?)
+                    / f64::from(u32::try_from(attempted).ok()?),
+            ),
+            _ => None,
+        }
+    }
+}
+
 impl SdkEventMetricsAccumulator {
+    /// Adds a bucket loaded by `metric` to the accumulators of its output fields
+    pub fn add_metric_bucket(&mut self, metric: &SdkEventMetrics, row: &SdkEventMetricRow) {
+        match metric {
+            SdkEventMetrics::PaymentAttempts => self.payment_attempts.add_metrics_bucket(row),
+            SdkEventMetrics::PaymentMethodsCallCount => {
+                self.payment_methods_call_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::SdkRenderedCount => self.sdk_rendered_count.add_metrics_bucket(row),
+            SdkEventMetrics::SdkInitiatedCount => self.sdk_initiated_count.add_metrics_bucket(row),
+            SdkEventMetrics::PaymentMethodSelectedCount => {
+                self.payment_method_selected_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::PaymentDataFilledCount => {
+                self.payment_data_filled_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::AveragePaymentTime => {
+                self.average_payment_time.add_metrics_bucket(row);
+                self.payment_time_percentiles.add_metrics_bucket(row);
+                self.payment_time_range.add_metrics_bucket(row);
+                self.payment_time_median.add_metrics_bucket(row);
+                self.payment_time_spread.add_metrics_bucket(row);
+            }
+            SdkEventMetrics::ThreeDsMethodInvokedCount => {
+                self.three_ds_method_invoked_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::ThreeDsMethodSkippedCount => {
+                self.three_ds_method_skipped_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::ThreeDsMethodSuccessfulCount => self
+                .three_ds_method_successful_count
+                .add_metrics_bucket(row),
+            SdkEventMetrics::ThreeDsMethodUnsuccessfulCount => self
+                .three_ds_method_unsuccessful_count
+                .add_metrics_bucket(row),
+            SdkEventMetrics::AuthenticationUnsuccessfulCount => self
+                .authentication_unsuccessful_count
+                .add_metrics_bucket(row),
+            SdkEventMetrics::ThreeDsChallengeFlowCount => {
+                self.three_ds_challenge_flow_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::ThreeDsFrictionlessFlowCount => self
+                .three_ds_frictionless_flow_count
+                .add_metrics_bucket(row),
+            SdkEventMetrics::SdkErrorEncounteredCount => {
+                self.sdk_error_encountered_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::SessionDurationSeconds => {
+                self.session_duration_seconds.add_metrics_bucket(row);
+                self.session_duration_percentiles.add_metrics_bucket(row);
+            }
+            SdkEventMetrics::FunnelConversionRate => {
+                self.funnel_conversion_rate.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::UniqueSessionsCount => {
+                self.unique_sessions_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::SdkLoadFailureCount => {
+                self.sdk_load_failure_count.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::PaymentSuccessRate => {
+                self.payment_success_rate.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::SdkBounceRate => self.sdk_bounce_rate.add_metrics_bucket(row),
+            SdkEventMetrics::ThreeDsFlowBreakdown => {
+                self.three_ds_flow_breakdown.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::PaymentRetryCount => {
+                self.payment_retry_count.add_metrics_bucket(row);
+                self.payment_retry_percentiles.add_metrics_bucket(row);
+                self.max_payment_retries.add_metrics_bucket(row);
+            }
+            SdkEventMetrics::PaymentExperienceBreakdown => {
+                self.payment_experience_breakdown.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::TimeToFirstRender => {
+                self.time_to_first_render.add_metrics_bucket(row);
+                self.time_to_first_render_percentiles
+                    .add_metrics_bucket(row);
+            }
+            SdkEventMetrics::FunnelWaterfall => self.funnel_waterfall.add_metrics_bucket(row),
+            SdkEventMetrics::AveragePaymentMethodsDisplayed => self
+                .average_payment_methods_displayed
+                .add_metrics_bucket(row),
+            SdkEventMetrics::PaymentAttemptedAmount => {
+                self.payment_attempted_amount.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::LatencyBreakdown => self.latency_breakdown.add_metrics_bucket(row),
+            SdkEventMetrics::ThreeDsMethodSuccessRate => {
+                self.three_ds_method_success_rate.add_metrics_bucket(row)
+            }
+            SdkEventMetrics::AbandonedSessionsCount => {
+                self.abandoned_sessions_count.add_metrics_bucket(row)
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn collect(self) -> SdkEventMetricsBucketValue {
+        let (payment_time_p50, payment_time_p90, payment_time_p95, payment_time_p99) =
+            self.payment_time_percentiles.collect();
+        let (payment_time_min, payment_time_max) = self.payment_time_range.collect();
+        let (payment_time_stddev, payment_time_variance) = self.payment_time_spread.collect();
+        let (
+            session_duration_p50,
+            session_duration_p90,
+            session_duration_p95,
+            session_duration_p99,
+        ) = self.session_duration_percentiles.collect();
+        let (payment_retries_p50, payment_retries_p90, payment_retries_p95, payment_retries_p99) =
+            self.payment_retry_percentiles.collect();
+        let funnel = self.funnel_conversion_rate.collect();
+        let (three_ds_challenge_count, three_ds_frictionless_count, three_ds_challenge_rate) =
+            self.three_ds_flow_breakdown.collect();
+        let (
+            time_to_first_render_p50,
+            time_to_first_render_p90,
+            time_to_first_render_p95,
+            time_to_first_render_p99,
+        ) = self.time_to_first_render_percentiles.collect();
+        let (average_network_ms, average_render_ms) = self.latency_breakdown.collect();
         SdkEventMetricsBucketValue {
             payment_attempts: self.payment_attempts.collect(),
             payment_methods_call_count: self.payment_methods_call_count.collect(),
             average_payment_time: self.average_payment_time.collect(),
+            payment_time_min,
+            payment_time_max,
+            payment_time_median: self.payment_time_median.collect(),
+            payment_time_stddev,
+            payment_time_variance,
+            payment_time_p50,
+            payment_time_p90,
+            payment_time_p95,
+            payment_time_p99,
             sdk_initiated_count: self.sdk_initiated_count.collect(),
             sdk_rendered_count: self.sdk_rendered_count.collect(),
             payment_method_selected_count: self.payment_method_selected_count.collect(),
@@ -105,6 +656,128 @@ impl SdkEventMetricsAccumulator {
             authentication_unsuccessful_count: self.authentication_unsuccessful_count.collect(),
             three_ds_challenge_flow_count: self.three_ds_challenge_flow_count.collect(),
             three_ds_frictionless_flow_count: self.three_ds_frictionless_flow_count.collect(),
+            sdk_error_encountered_count: self.sdk_error_encountered_count.collect(),
+            average_session_duration_seconds: self.session_duration_seconds.collect(),
+            session_duration_p50,
+            session_duration_p90,
+            session_duration_p95,
+            session_duration_p99,
+            funnel_initiated: funnel.initiated,
+            funnel_rendered: funnel.rendered,
+            funnel_selected: funnel.selected,
+            funnel_filled: funnel.filled,
+            funnel_attempted: funnel.attempted,
+            initiated_to_rendered_rate: funnel.initiated_to_rendered_rate,
+            rendered_to_selected_rate: funnel.rendered_to_selected_rate,
+            selected_to_filled_rate: funnel.selected_to_filled_rate,
+            filled_to_attempted_rate: funnel.filled_to_attempted_rate,
+            unique_sessions_count: self.unique_sessions_count.collect(),
+            payment_success_rate: self.payment_success_rate.collect(),
+            average_payment_retries: self.payment_retry_count.collect(),
+            max_payment_retries: self.max_payment_retries.collect(),
+            payment_retries_p50,
+            payment_retries_p90,
+            payment_retries_p95,
+            payment_retries_p99,
+            three_ds_challenge_count,
+            three_ds_frictionless_count,
+            three_ds_challenge_rate,
+            sdk_bounce_rate: self.sdk_bounce_rate.collect(),
+            payment_experience_attempt_count: self.payment_experience_breakdown.collect(),
+            average_time_to_first_render_ms: self.time_to_first_render.collect(),
+            time_to_first_render_p50,
+            time_to_first_render_p90,
+            time_to_first_render_p95,
+            time_to_first_render_p99,
+            funnel_waterfall: self.funnel_waterfall.collect(),
+            sdk_load_failure_count: self.sdk_load_failure_count.collect(),
+            average_payment_methods_displayed: self.average_payment_methods_displayed.collect(),
+            payment_attempted_amount: self.payment_attempted_amount.collect(),
+            average_network_ms,
+            average_render_ms,
+            success_rate: self.three_ds_method_success_rate.collect(),
+            abandoned_sessions_count: self.abandoned_sessions_count.collect(),
         }
     }
 }
+
+/// Fields of [`SdkEventMetricsBucketValue`] the accumulators of `metric` collect into
+pub fn output_fields(metric: &SdkEventMetrics) -> &'static [&'static str] {
+    match metric {
+        SdkEventMetrics::PaymentAttempts => &["payment_attempts"],
+        SdkEventMetrics::PaymentMethodsCallCount => &["payment_methods_call_count"],
+        SdkEventMetrics::SdkRenderedCount => &["sdk_rendered_count"],
+        SdkEventMetrics::SdkInitiatedCount => &["sdk_initiated_count"],
+        SdkEventMetrics::PaymentMethodSelectedCount => &["payment_method_selected_count"],
+        SdkEventMetrics::PaymentDataFilledCount => &["payment_data_filled_count"],
+        SdkEventMetrics::AveragePaymentTime => &[
+            "average_payment_time",
+            "payment_time_min",
+            "payment_time_max",
+            "payment_time_median",
+            "payment_time_stddev",
+            "payment_time_variance",
+            "payment_time_p50",
+            "payment_time_p90",
+            "payment_time_p95",
+            "payment_time_p99",
+        ],
+        SdkEventMetrics::ThreeDsMethodInvokedCount => &["three_ds_method_invoked_count"],
+        SdkEventMetrics::ThreeDsMethodSkippedCount => &["three_ds_method_skipped_count"],
+        SdkEventMetrics::ThreeDsMethodSuccessfulCount => &["three_ds_method_successful_count"],
+        SdkEventMetrics::ThreeDsMethodUnsuccessfulCount => &["three_ds_method_unsuccessful_count"],
+        SdkEventMetrics::AuthenticationUnsuccessfulCount => &["authentication_unsuccessful_count"],
+        SdkEventMetrics::ThreeDsChallengeFlowCount => &["three_ds_challenge_flow_count"],
+        SdkEventMetrics::ThreeDsFrictionlessFlowCount => &["three_ds_frictionless_flow_count"],
+        SdkEventMetrics::SdkErrorEncounteredCount => &["sdk_error_encountered_count"],
+        SdkEventMetrics::SessionDurationSeconds => &[
+            "average_session_duration_seconds",
+            "session_duration_p50",
+            "session_duration_p90",
+            "session_duration_p95",
+            "session_duration_p99",
+        ],
+        SdkEventMetrics::FunnelConversionRate => &[
+            "funnel_initiated",
+            "funnel_rendered",
+            "funnel_selected",
+            "funnel_filled",
+            "funnel_attempted",
+            "initiated_to_rendered_rate",
+            "rendered_to_selected_rate",
+            "selected_to_filled_rate",
+            "filled_to_attempted_rate",
+        ],
+        SdkEventMetrics::UniqueSessionsCount => &["unique_sessions_count"],
+        SdkEventMetrics::SdkLoadFailureCount => &["sdk_load_failure_count"],
+        SdkEventMetrics::PaymentSuccessRate => &["payment_success_rate"],
+        SdkEventMetrics::SdkBounceRate => &["sdk_bounce_rate"],
+        SdkEventMetrics::ThreeDsFlowBreakdown => &[
+            "three_ds_challenge_count",
+            "three_ds_frictionless_count",
+            "three_ds_challenge_rate",
+        ],
+        SdkEventMetrics::PaymentRetryCount => &[
+            "average_payment_retries",
+            "max_payment_retries",
+            "payment_retries_p50",
+            "payment_retries_p90",
+            "payment_retries_p95",
+            "payment_retries_p99",
+        ],
+        SdkEventMetrics::PaymentExperienceBreakdown => &["payment_experience_attempt_count"],
+        SdkEventMetrics::TimeToFirstRender => &[
+            "average_time_to_first_render_ms",
+            "time_to_first_render_p50",
+            "time_to_first_render_p90",
+            "time_to_first_render_p95",
+            "time_to_first_render_p99",
+        ],
+        SdkEventMetrics::FunnelWaterfall => &["funnel_waterfall"],
+        SdkEventMetrics::AveragePaymentMethodsDisplayed => &["average_payment_methods_displayed"],
+        SdkEventMetrics::PaymentAttemptedAmount => &["payment_attempted_amount"],
+        SdkEventMetrics::LatencyBreakdown => &["average_network_ms", "average_render_ms"],
+        SdkEventMetrics::ThreeDsMethodSuccessRate => &["success_rate"],
+        SdkEventMetrics::AbandonedSessionsCount => &["abandoned_sessions_count"],
+    }
+}
@@ -0,0 +1,593 @@
+use api_models::analytics::sdk_events::SdkEventDimensions;
+use common_utils::errors::ParsingError;
+use strum::IntoEnumIterator;
+
+use crate::{
+    query::{LocalSdkEventDimension, ToSql},
+    types::{AnalyticsDataSource, TableEngine},
+};
+
+/// Alias the value of a computed dimension is selected and grouped as
+pub const COMPUTED_DIMENSION: &str = "computed_dimension";
+
+/// Longest expression of a computed dimension, in bytes
+pub const MAX_EXPRESSION_LENGTH: usize = 1024;
+
+/// Deepest nesting of the expression of a computed dimension, which bounds the recursion of the
+/// parser
+const MAX_DEPTH: usize = 32;
+
+/// Reason the expression of a computed dimension was rejected
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct InvalidExpression(String);
+
+/// A dimension computed by an expression over the columns of the sdk events, e.g. to bucket
+/// amounts into tiers:
+///
+/// ```text
+/// CASE WHEN amount < 1000 THEN 'small' WHEN amount < 10000 THEN 'medium' ELSE 'large' END
+/// ```
+///
+/// The expression is parsed rather than passed through, and only the parsed expression is written
+/// into a query, fully parenthesized. Its grammar is limited to:
+/// - the sdk event dimensions by name (e.g. `platform`, `os`), and the `amount` of the payment,
+///   apart from the customer segment which has to be joined
+/// - integer and decimal numbers, `NULL`, and strings in single quotes made of ASCII letters,
+///   digits, spaces and `_ - . : / + @`
+/// - `+ - * / %`, `= != <> < <= > >=`, `AND OR NOT`, `IS [NOT] NULL` and parentheses
+/// - `CASE WHEN .. THEN .. [ELSE ..] END`
+/// - the functions `lower`, `upper`, `abs`, `floor`, `ceil`, `round`, `coalesce`, `least`,
+///   `greatest` and `concat`
+///
+/// Keywords and function names are case insensitive. The value is grouped by as a string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedDimension(Expression);
+
+impl ComputedDimension {
+    /// Parses `expression`, failing with the reason it's not part of the grammar
+    pub fn parse(expression: &str) -> Result<Self, InvalidExpression> {
+        if expression.len() > MAX_EXPRESSION_LENGTH {
+            return Err(InvalidExpression(format!(
+                "expression is longer than {MAX_EXPRESSION_LENGTH} bytes"
+            )));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+            depth: 0,
+        };
+        let parsed = parser.expression()?;
+        match parser.peek() {
+            None => Ok(Self(parsed)),
+            Some(token) => Err(InvalidExpression(format!("unexpected {token}"))),
+        }
+    }
+}
+
+/// A [`ComputedDimension`] lowered like a [`LocalSdkEventDimension`]: the dimensions it reads are
+/// on the wall clock of `time_zone`, with `null_label` in place of a missing value
+#[derive(Clone, Copy, Debug)]
+pub struct LocalComputedDimension<'a> {
+    pub dimension: &'a ComputedDimension,
+    pub time_zone: Option<&'a str>,
+    pub null_label: Option<&'a str>,
+}
+
+impl<T: AnalyticsDataSource> ToSql<T> for LocalComputedDimension<'_> {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        let expression = self.lower::<T>(&self.dimension.0, table_engine)?;
+        Ok(T::get_string_cast(&expression))
+    }
+}
+
+impl LocalComputedDimension<'_> {
+    fn lower<T: AnalyticsDataSource>(
+        &self,
+        expression: &Expression,
+        table_engine: &TableEngine,
+    ) -> error_stack::Result<String, ParsingError> {
+        let lower = |expression: &Expression| self.lower::<T>(expression, table_engine);
+        Ok(match expression {
+            Expression::Column(Column::Amount) => "amount".to_string(),
+            Expression::Column(Column::Dimension(dimension)) => {
+                <LocalSdkEventDimension<'_> as ToSql<T>>::to_sql(
+                    &LocalSdkEventDimension {
+                        dimension,
+                        time_zone: self.time_zone,
+                        null_label: self.null_label,
+                    },
+                    table_engine,
+                )?
+            }
+            Expression::Number(number) => number.clone(),
+            Expression::String(string) => format!("'{string}'"),
+            Expression::Null => "NULL".to_string(),
+            Expression::Negate(operand) => format!("(-{})", lower(operand.as_ref())?),
+            Expression::Not(operand) => format!("(NOT {})", lower(operand.as_ref())?),
+            Expression::IsNull { operand, negated } => format!(
+                "({} IS {}NULL)",
+                lower(operand.as_ref())?,
+                if *negated { "NOT " } else { "" }
+            ),
+            Expression::Binary(left, operator, right) => {
+                let (left, right) = (lower(left.as_ref())?, lower(right.as_ref())?);
+                format!("({left} {} {right})", operator.as_sql())
+            }
+            Expression::Function(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(lower)
+                    .collect::<error_stack::Result<Vec<_>, _>>()?;
+                format!("{}({})", function.as_sql(), arguments.join(", "))
+            }
+            Expression::Case {
+                branches,
+                otherwise,
+            } => {
+                let mut case = "CASE".to_string();
+                for (condition, value) in branches {
+                    case.push_str(&format!(
+                        " WHEN {} THEN {}",
+                        lower(condition)?,
+                        lower(value)?
+                    ));
+                }
+                if let Some(otherwise) = otherwise {
+                    case.push_str(&format!(" ELSE {}", lower(otherwise.as_ref())?));
+                }
+                case.push_str(" END");
+                case
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Column(Column),
+    /// Digits with at most one decimal point, as they were written
+    Number(String),
+    /// Only holds characters which don't need to be escaped, see [`is_string_char`]
+    String(String),
+    Null,
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    IsNull {
+        operand: Box<Expression>,
+        negated: bool,
+    },
+    Binary(Box<Expression>, BinaryOperator, Box<Expression>),
+    Function(Function, Vec<Expression>),
+    Case {
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Option<Box<Expression>>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Column {
+    Amount,
+    Dimension(SdkEventDimensions),
+}
+
+impl Column {
+    fn from_name(name: &str) -> Option<Self> {
+        if name == "amount" {
+            return Some(Self::Amount);
+        }
+        SdkEventDimensions::iter()
+            // Read from a join the queries only add for the dimension itself
+            .filter(|dimension| *dimension != SdkEventDimensions::CustomerSegment)
+            .find(|dimension| dimension.as_ref() == name)
+            .map(Self::Dimension)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+impl BinaryOperator {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Self::Or => "OR",
+            Self::And => "AND",
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Modulo => "%",
+        }
+    }
+
+    fn comparison(token: &Token) -> Option<Self> {
+        match token {
+            Token::Symbol("=") => Some(Self::Equal),
+            Token::Symbol("!=" | "<>") => Some(Self::NotEqual),
+            Token::Symbol("<") => Some(Self::Less),
+            Token::Symbol("<=") => Some(Self::LessOrEqual),
+            Token::Symbol(">") => Some(Self::Greater),
+            Token::Symbol(">=") => Some(Self::GreaterOrEqual),
+            _ => None,
+        }
+    }
+
+    fn additive(token: &Token) -> Option<Self> {
+        match token {
+            Token::Symbol("+") => Some(Self::Add),
+            Token::Symbol("-") => Some(Self::Subtract),
+            _ => None,
+        }
+    }
+
+    fn multiplicative(token: &Token) -> Option<Self> {
+        match token {
+            Token::Symbol("*") => Some(Self::Multiply),
+            Token::Symbol("/") => Some(Self::Divide),
+            Token::Symbol("%") => Some(Self::Modulo),
+            _ => None,
+        }
+    }
+}
+
+/// The functions an expression can call, which every data source has under the same name
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Lower,
+    Upper,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Coalesce,
+    Least,
+    Greatest,
+    Concat,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lower" => Some(Self::Lower),
+            "upper" => Some(Self::Upper),
+            "abs" => Some(Self::Abs),
+            "floor" => Some(Self::Floor),
+            "ceil" => Some(Self::Ceil),
+            "round" => Some(Self::Round),
+            "coalesce" => Some(Self::Coalesce),
+            "least" => Some(Self::Least),
+            "greatest" => Some(Self::Greatest),
+            "concat" => Some(Self::Concat),
+            _ => None,
+        }
+    }
+
+    fn as_sql(self) -> &'static str {
+        match self {
+            Self::Lower => "lower",
+            Self::Upper => "upper",
+            Self::Abs => "abs",
+            Self::Floor => "floor",
+            Self::Ceil => "ceil",
+            Self::Round => "round",
+            Self::Coalesce => "coalesce",
+            Self::Least => "least",
+            Self::Greatest => "greatest",
+            Self::Concat => "concat",
+        }
+    }
+
+    /// Whether the function can be called with `count` arguments
+    fn takes(self, count: usize) -> bool {
+        match self {
+            Self::Lower | Self::Upper | Self::Abs | Self::Floor | Self::Ceil => count == 1,
+            Self::Round => matches!(count, 1 | 2),
+            Self::Coalesce | Self::Least | Self::Greatest | Self::Concat => count >= 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A column, function or keyword, lowercased
+    Word(String),
+    Number(String),
+    String(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "`{word}`"),
+            Self::Number(number) => write!(f, "number {number}"),
+            Self::String(string) => write!(f, "string '{string}'"),
+            Self::Symbol(symbol) => write!(f, "`{symbol}`"),
+        }
+    }
+}
+
+/// Symbols of the grammar, the ones of two characters before their prefixes
+const SYMBOLS: [&str; 15] = [
+    "!=", "<>", "<=", ">=", "(", ")", ",", "+", "-", "*", "/", "%", "=", "<", ">",
+];
+
+/// Characters a string literal can hold, none of which has to be escaped in any data source
+fn is_string_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.' | ':' | '/' | '+' | '@')
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, InvalidExpression> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let (word, tail) = rest.split_at(end);
+            tokens.push(Token::Word(word.to_ascii_lowercase()));
+            rest = tail;
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let (number, tail) = rest.split_at(end);
+            if number.matches('.').count() > 1 || number.ends_with('.') {
+                return Err(InvalidExpression(format!("invalid number {number}")));
+            }
+            tokens.push(Token::Number(number.to_string()));
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix('\'') {
+            let (string, tail) = tail
+                .split_once('\'')
+                .ok_or_else(|| InvalidExpression("unterminated string".to_string()))?;
+            if let Some(c) = string.chars().find(|c| !is_string_char(*c)) {
+                return Err(InvalidExpression(format!(
+                    "character {c:?} isn't allowed in a string"
+                )));
+            }
+            tokens.push(Token::String(string.to_string()));
+            rest = tail;
+        } else {
+            let (symbol, tail) = SYMBOLS
+                .iter()
+                .find_map(|symbol| rest.strip_prefix(*symbol).map(|tail| (*symbol, tail)))
+                .ok_or_else(|| InvalidExpression(format!("character {c:?} isn't allowed")))?;
+            tokens.push(Token::Symbol(symbol));
+            rest = tail;
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser of the grammar of a [`ComputedDimension`], from the loosest binding
+/// operator to the tightest: `OR`, `AND`, `NOT`, comparisons, `+ -`, `* / %` and negation
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Number of nested sub-expressions being parsed
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes the next token if it's `keyword`
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let matches = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    /// Consumes the next token, failing unless it's `symbol`
+    fn expect(&mut self, symbol: &'static str) -> Result<(), InvalidExpression> {
+        match self.next() {
+            Some(Token::Symbol(next)) if next == symbol => Ok(()),
+            Some(token) => Err(InvalidExpression(format!(
+                "expected `{symbol}`, found {token}"
+            ))),
+            None => Err(InvalidExpression(format!("expected `{symbol}`"))),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), InvalidExpression> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            Err(InvalidExpression(format!(
+                "expected `{}`",
+                keyword.to_ascii_uppercase()
+            )))
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, InvalidExpression> {
+        self.nested(Self::or)
+    }
+
+    fn or(&mut self) -> Result<Expression, InvalidExpression> {
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            let right = self.and()?;
+            left = Expression::Binary(Box::new(left), BinaryOperator::Or, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, InvalidExpression> {
+        let mut left = self.not()?;
+        while self.keyword("and") {
+            let right = self.not()?;
+            left = Expression::Binary(Box::new(left), BinaryOperator::And, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expression, InvalidExpression> {
+        if self.keyword("not") {
+            return Ok(Expression::Not(Box::new(self.nested(Self::not)?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression, InvalidExpression> {
+        let left = self.additive()?;
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(Expression::IsNull {
+                operand: Box::new(left),
+                negated,
+            });
+        }
+        match self.peek().and_then(BinaryOperator::comparison) {
+            Some(operator) => {
+                self.position += 1;
+                let right = self.additive()?;
+                Ok(Expression::Binary(
+                    Box::new(left),
+                    operator,
+                    Box::new(right),
+                ))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expression, InvalidExpression> {
+        let mut left = self.multiplicative()?;
+        while let Some(operator) = self.peek().and_then(BinaryOperator::additive) {
+            self.position += 1;
+            let right = self.multiplicative()?;
+            left = Expression::Binary(Box::new(left), operator, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expression, InvalidExpression> {
+        let mut left = self.negation()?;
+        while let Some(operator) = self.peek().and_then(BinaryOperator::multiplicative) {
+            self.position += 1;
+            let right = self.negation()?;
+            left = Expression::Binary(Box::new(left), operator, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn negation(&mut self) -> Result<Expression, InvalidExpression> {
+        if self.peek() == Some(&Token::Symbol("-")) {
+            self.position += 1;
+            return Ok(Expression::Negate(Box::new(self.nested(Self::negation)?)));
+        }
+        self.primary()
+    }
+
+    /// Parses a sub-expression with `parse`, counting it towards the nesting of the expression
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expression, InvalidExpression>,
+    ) -> Result<Expression, InvalidExpression> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(InvalidExpression(format!(
+                "expression is nested deeper than {MAX_DEPTH} levels"
+            )));
+        }
+        let expression = parse(self);
+        self.depth -= 1;
+        expression
+    }
+
+    fn primary(&mut self) -> Result<Expression, InvalidExpression> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expression::Number(number)),
+            Some(Token::String(string)) => Ok(Expression::String(string)),
+            Some(Token::Symbol("(")) => {
+                let expression = self.expression()?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            Some(Token::Word(word)) if word == "null" => Ok(Expression::Null),
+            Some(Token::Word(word)) if word == "case" => self.case(),
+            Some(Token::Word(word)) if self.peek() == Some(&Token::Symbol("(")) => {
+                let function = Function::from_name(&word)
+                    .ok_or_else(|| InvalidExpression(format!("unknown function `{word}`")))?;
+                self.position += 1;
+                let mut arguments = vec![self.expression()?];
+                while self.peek() == Some(&Token::Symbol(",")) {
+                    self.position += 1;
+                    arguments.push(self.expression()?);
+                }
+                self.expect(")")?;
+                if !function.takes(arguments.len()) {
+                    return Err(InvalidExpression(format!(
+                        "`{word}` can't take {} arguments",
+                        arguments.len()
+                    )));
+                }
+                Ok(Expression::Function(function, arguments))
+            }
+            Some(Token::Word(word)) => Column::from_name(&word)
+                .map(Expression::Column)
+                .ok_or_else(|| InvalidExpression(format!("unknown column `{word}`"))),
+            Some(token) => Err(InvalidExpression(format!("unexpected {token}"))),
+            None => Err(InvalidExpression(
+                "unexpected end of expression".to_string(),
+            )),
+        }
+    }
+
+    fn case(&mut self) -> Result<Expression, InvalidExpression> {
+        let mut branches = Vec::new();
+        while self.keyword("when") {
+            let condition = self.expression()?;
+            self.expect_keyword("then")?;
+            branches.push((condition, self.expression()?));
+        }
+        if branches.is_empty() {
+            return Err(InvalidExpression("expected `WHEN`".to_string()));
+        }
+        let otherwise = if self.keyword("else") {
+            Some(Box::new(self.expression()?))
+        } else {
+            None
+        };
+        self.expect_keyword("end")?;
+        Ok(Expression::Case {
+            branches,
+            otherwise,
+        })
+    }
+}
@@ -2,22 +2,24 @@ use std::collections::HashMap;
 
 use api_models::analytics::{
     sdk_events::{
-        MetricsBucketResponse, SdkEventMetrics, SdkEventMetricsBucketIdentifier, SdkEventsRequest,
+        MetricsBucketResponse, SdkEventMetricsBucketIdentifier, SdkEventRecentMetricsRequest,
+        SdkEventSampleRequest, SdkEventTargetResult, SdkEventsRequest,
     },
     AnalyticsMetadata, GetSdkEventFiltersRequest, GetSdkEventMetricRequest, MetricsResponse,
     SdkEventFiltersResponse,
 };
-use common_utils::errors::ReportSwitchExt;
+use common_utils::{date_time, errors::ReportSwitchExt};
 use error_stack::ResultExt;
 use router_env::{instrument, logger, tracing};
 
 use super::{
-    events::{get_sdk_event, SdkEventsResult},
+    events::{get_sdk_event, get_sdk_event_sample, SdkEventsResult},
+    metrics::{compare_buckets, recent_time_range, target_result, SdkEventQueryOptions},
     SdkEventMetricsAccumulator,
 };
 use crate::{
     errors::{AnalyticsError, AnalyticsResult},
-    sdk_events::SdkEventMetricAccumulator,
+    query::QueryStatsCollector,
     types::FiltersError,
     AnalyticsProvider,
 };
@@ -42,6 +44,28 @@ pub async fn sdk_events_core(
     .switch()
 }
 
+/// Raw events behind a bucket of a metric, see [`get_sdk_event_sample`]
+pub async fn sdk_event_sample_core(
+    pool: &AnalyticsProvider,
+    req: SdkEventSampleRequest,
+    publishable_key: String,
+) -> AnalyticsResult<Vec<SdkEventsResult>> {
+    match pool {
+        AnalyticsProvider::Sqlx(_) => Err(FiltersError::NotImplemented(
+            "SDK Events not implemented for SQLX",
+        ))
+        .attach_printable("SQL Analytics is not implemented for Sdk Events"),
+        AnalyticsProvider::Clickhouse(pool) => {
+            get_sdk_event_sample(&publishable_key, &req, pool).await
+        }
+        AnalyticsProvider::CombinedSqlx(_sqlx_pool, ckh_pool)
+        | AnalyticsProvider::CombinedCkh(_sqlx_pool, ckh_pool) => {
+            get_sdk_event_sample(&publishable_key, &req, ckh_pool).await
+        }
+    }
+    .switch()
+}
+
 #[instrument(skip_all)]
 pub async fn get_metrics(
     pool: &AnalyticsProvider,
@@ -52,6 +76,10 @@ pub async fn get_metrics(
         SdkEventMetricsBucketIdentifier,
         SdkEventMetricsAccumulator,
     > = HashMap::new();
+    let mut truncated = false;
+    let query_stats = req.include_query_stats.then(QueryStatsCollector::default);
+    let mut metrics_targets: HashMap<SdkEventMetricsBucketIdentifier, Vec<SdkEventTargetResult>> =
+        HashMap::new();
 
     if let Some(publishable_key) = publishable_key {
         let mut set = tokio::task::JoinSet::new();
@@ -59,6 +87,10 @@ pub async fn get_metrics(
             let req = req.clone();
             let publishable_key_scoped = publishable_key.to_owned();
             let pool = pool.clone();
+            let options = SdkEventQueryOptions {
+                query_stats: query_stats.clone(),
+                ..SdkEventQueryOptions::from(&req)
+            };
             set.spawn(async move {
                 let data = pool
                     .get_sdk_event_metrics(
@@ -67,6 +99,7 @@ pub async fn get_metrics(
                         &publishable_key_scoped,
                         &req.filters,
                         &req.time_series.map(|t| t.granularity),
+                        &options,
                         &req.time_range,
                     )
                     .await
@@ -83,51 +116,14 @@ pub async fn get_metrics(
         {
             logger::info!("Logging Result {:?}", data);
             for (id, value) in data? {
-                let metrics_builder = metrics_accumulator.entry(id).or_default();
-                match metric {
-                    SdkEventMetrics::PaymentAttempts => {
-                        metrics_builder.payment_attempts.add_metrics_bucket(&value)
-                    }
-                    SdkEventMetrics::PaymentMethodsCallCount => metrics_builder
-                        .payment_methods_call_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::SdkRenderedCount => metrics_builder
-                        .sdk_rendered_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::SdkInitiatedCount => metrics_builder
-                        .sdk_initiated_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::PaymentMethodSelectedCount => metrics_builder
-                        .payment_method_selected_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::PaymentDataFilledCount => metrics_builder
-                        .payment_data_filled_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::AveragePaymentTime => metrics_builder
-                        .average_payment_time
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::ThreeDsMethodInvokedCount => metrics_builder
-                        .three_ds_method_invoked_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::ThreeDsMethodSkippedCount => metrics_builder
-                        .three_ds_method_skipped_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::ThreeDsMethodSuccessfulCount => metrics_builder
-                        .three_ds_method_successful_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::ThreeDsMethodUnsuccessfulCount => metrics_builder
-                        .three_ds_method_unsuccessful_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::AuthenticationUnsuccessfulCount => metrics_builder
-                        .authentication_unsuccessful_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::ThreeDsChallengeFlowCount => metrics_builder
-                        .three_ds_challenge_flow_count
-                        .add_metrics_bucket(&value),
-                    SdkEventMetrics::ThreeDsFrictionlessFlowCount => metrics_builder
-                        .three_ds_frictionless_flow_count
-                        .add_metrics_bucket(&value),
+                truncated |= value.truncated;
+                if let Some(target) = target_result(&metric, &id, &value, &req.targets) {
+                    metrics_targets.entry(id.clone()).or_default().push(target);
                 }
+                metrics_accumulator
+                    .entry(id)
+                    .or_default()
+                    .add_metric_bucket(&metric, &value);
             }
 
             logger::debug!(
@@ -137,18 +133,31 @@ pub async fn get_metrics(
             );
         }
 
-        let query_data: Vec<MetricsBucketResponse> = metrics_accumulator
+        let mut query_data: Vec<MetricsBucketResponse> = metrics_accumulator
             .into_iter()
-            .map(|(id, val)| MetricsBucketResponse {
-                values: val.collect(),
-                dimensions: id,
+            .map(|(id, val)| {
+                let mut targets = metrics_targets.remove(&id).unwrap_or_default();
+                targets.sort_by(|left, right| left.metric.as_ref().cmp(right.metric.as_ref()));
+                MetricsBucketResponse {
+                    values: val.collect(),
+                    dimensions: id,
+                    targets,
+                }
             })
             .collect();
+        query_data.sort_by(|left, right| {
+            compare_buckets(&left.dimensions, &right.dimensions, &req.group_by_names)
+        });
 
         Ok(MetricsResponse {
             query_data,
             meta_data: [AnalyticsMetadata {
                 current_time_range: req.time_range,
+                sample_rate: req.sample_rate,
+                truncated,
+                query_stats: query_stats
+                    .map(|query_stats| query_stats.stats())
+                    .unwrap_or_default(),
             }],
         })
     } else {
@@ -157,11 +166,76 @@ pub async fn get_metrics(
             query_data: vec![],
             meta_data: [AnalyticsMetadata {
                 current_time_range: req.time_range,
+                sample_rate: None,
+                truncated: false,
+                query_stats: Vec::new(),
             }],
         })
     }
 }
 
+/// Metrics of the last few minutes up to now for a live view, each over the whole window: a single
+/// bucket without dimensions, loaded fresh on every request, see
+/// [`super::metrics::load_recent_metrics`]
+#[instrument(skip_all)]
+pub async fn get_recent_metrics(
+    pool: &AnalyticsProvider,
+    publishable_key: Option<&String>,
+    req: SdkEventRecentMetricsRequest,
+) -> AnalyticsResult<MetricsResponse<MetricsBucketResponse>> {
+    let time_range = recent_time_range(req.window_mins, date_time::now())
+        .change_context(AnalyticsError::UnknownError)?;
+    let Some(publishable_key) = publishable_key else {
+        logger::error!("Publishable key not present for merchant ID");
+        return Ok(MetricsResponse {
+            query_data: vec![],
+            meta_data: [AnalyticsMetadata {
+                current_time_range: time_range,
+                sample_rate: None,
+                truncated: false,
+                query_stats: Vec::new(),
+            }],
+        });
+    };
+
+    let metrics = req.metrics.into_iter().collect::<Vec<_>>();
+    let mut metrics_accumulator: HashMap<
+        SdkEventMetricsBucketIdentifier,
+        SdkEventMetricsAccumulator,
+    > = HashMap::new();
+    let mut truncated = false;
+    for (metric, buckets) in pool
+        .get_sdk_event_recent_metrics(&metrics, publishable_key, &req.filters, &time_range)
+        .await
+        .change_context(AnalyticsError::UnknownError)?
+    {
+        for (id, value) in buckets {
+            truncated |= value.truncated;
+            metrics_accumulator
+                .entry(id)
+                .or_default()
+                .add_metric_bucket(&metric, &value);
+        }
+    }
+
+    Ok(MetricsResponse {
+        query_data: metrics_accumulator
+            .into_iter()
+            .map(|(id, val)| MetricsBucketResponse {
+                values: val.collect(),
+                dimensions: id,
+                targets: Vec::new(),
+            })
+            .collect(),
+        meta_data: [AnalyticsMetadata {
+            current_time_range: time_range,
+            sample_rate: None,
+            truncated,
+            query_stats: Vec::new(),
+        }],
+    })
+}
+
 #[allow(dead_code)]
 pub async fn get_filters(
     pool: &AnalyticsProvider,
@@ -170,21 +244,27 @@ pub async fn get_filters(
 ) -> AnalyticsResult<SdkEventFiltersResponse> {
     use api_models::analytics::{sdk_events::SdkEventDimensions, SdkEventFilterValue};
 
-    use super::filters::get_sdk_event_filter_for_dimension;
+    use super::filters::{get_sdk_event_filter_for_dimension, SDK_EVENT_FILTER_VALUES_LIMIT};
     use crate::sdk_events::filters::SdkEventFilter;
 
     let mut res = SdkEventFiltersResponse::default();
 
     if let Some(publishable_key) = publishable_key {
         for dim in req.group_by_names {
-            let values = match pool {
+            let (values, truncated) = match pool {
                 AnalyticsProvider::Sqlx(_pool) => Err(FiltersError::NotImplemented(
                     "SDK Events not implemented for SQLX",
                 ))
                 .attach_printable("SQL Analytics is not implemented for SDK Events"),
                 AnalyticsProvider::Clickhouse(pool) => {
-                    get_sdk_event_filter_for_dimension(dim, publishable_key, &req.time_range, pool)
-                        .await
+                    get_sdk_event_filter_for_dimension(
+                        dim,
+                        publishable_key,
+                        &req.time_range,
+                        SDK_EVENT_FILTER_VALUES_LIMIT,
+                        pool,
+                    )
+                    .await
                 }
                 AnalyticsProvider::CombinedSqlx(_sqlx_pool, ckh_pool)
                 | AnalyticsProvider::CombinedCkh(_sqlx_pool, ckh_pool) => {
@@ -192,25 +272,45 @@ pub async fn get_filters(
                         dim,
                         publishable_key,
                         &req.time_range,
+                        SDK_EVENT_FILTER_VALUES_LIMIT,
                         ckh_pool,
                     )
                     .await
                 }
             }
-            .change_context(AnalyticsError::UnknownError)?
-            .into_iter()
-            .filter_map(|fil: SdkEventFilter| match dim {
-                SdkEventDimensions::PaymentMethod => fil.payment_method,
-                SdkEventDimensions::Platform => fil.platform,
-                SdkEventDimensions::BrowserName => fil.browser_name,
-                SdkEventDimensions::Source => fil.source,
-                SdkEventDimensions::Component => fil.component,
-                SdkEventDimensions::PaymentExperience => fil.payment_experience,
-            })
-            .collect::<Vec<String>>();
+            .change_context(AnalyticsError::UnknownError)?;
+            let values = values
+                .into_iter()
+                .filter_map(|fil: SdkEventFilter| match dim {
+                    SdkEventDimensions::PaymentMethod => fil.payment_method,
+                    SdkEventDimensions::Platform => fil.platform,
+                    SdkEventDimensions::BrowserName => fil.browser_name,
+                    SdkEventDimensions::Source => fil.source,
+                    SdkEventDimensions::Component => fil.component,
+                    SdkEventDimensions::PaymentExperience => fil.payment_experience,
+                    SdkEventDimensions::SdkVersion => fil.sdk_version,
+                    SdkEventDimensions::DeviceType => fil.device_type,
+                    SdkEventDimensions::Os => fil.os,
+                    SdkEventDimensions::Country => fil.country,
+                    SdkEventDimensions::Connector => fil.connector,
+                    SdkEventDimensions::EventName => fil.event_name,
+                    SdkEventDimensions::PaymentMethodType => fil.payment_method_type,
+                    SdkEventDimensions::DayOfWeek => fil.day_of_week,
+                    SdkEventDimensions::HourOfDay => fil.hour_of_day,
+                    SdkEventDimensions::AuthenticationErrorReason => {
+                        fil.authentication_error_reason
+                    }
+                    SdkEventDimensions::FieldName => fil.field_name,
+                    SdkEventDimensions::CustomerSegment => fil.customer_segment,
+                    SdkEventDimensions::CdnRegion => fil.cdn_region,
+                    SdkEventDimensions::Currency => fil.currency,
+                    SdkEventDimensions::AbandonmentReason => fil.abandonment_reason,
+                })
+                .collect::<Vec<String>>();
             res.query_data.push(SdkEventFilterValue {
                 dimension: dim,
                 values,
+                truncated,
             })
         }
     } else {
@@ -1,5 +1,5 @@
 use api_models::analytics::{
-    sdk_events::{SdkEventNames, SdkEventsRequest},
+    sdk_events::{SdkEventDimensions, SdkEventNames, SdkEventSampleRequest, SdkEventsRequest},
     Granularity,
 };
 use common_utils::errors::ReportSwitchExt;
@@ -8,11 +8,18 @@ use strum::IntoEnumIterator;
 use time::PrimitiveDateTime;
 
 use crate::{
-    query::{Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
+    query::{
+        customer_segment_join, Aggregate, FilterTypes, GroupByClause, LocalSdkEventDimension,
+        Order, QueryBuilder, QueryFilter, ToSql, Window,
+    },
+    sdk_events::types::{filters_on_customer_segment, validate_filter_values},
     types::{AnalyticsCollection, AnalyticsDataSource, FiltersError, FiltersResult, LoadRow},
 };
 pub trait SdkEventsFilterAnalytics: LoadRow<SdkEventsResult> {}
 
+/// Most raw events returned by [`get_sdk_event_sample`]
+pub const SDK_EVENT_SAMPLE_LIMIT: usize = 500;
+
 pub async fn get_sdk_event<T>(
     merchant_id: &str,
     request: SdkEventsRequest,
@@ -55,6 +62,87 @@ where
         .change_context(FiltersError::QueryBuildingError)?
         .change_context(FiltersError::QueryExecutionFailure)
 }
+/// Raw events behind `request.bucket`, the first `request.limit` of them in the order they were
+/// created. This selects the events a metric was aggregated from rather than the aggregates,
+/// to tell why a bucket of the metric looks off.
+pub async fn get_sdk_event_sample<T>(
+    publishable_key: &str,
+    request: &SdkEventSampleRequest,
+    pool: &T,
+) -> FiltersResult<Vec<SdkEventsResult>>
+where
+    T: AnalyticsDataSource + SdkEventsFilterAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    validate_filter_values(&request.filters).change_context(FiltersError::QueryBuildingError)?;
+    let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+
+    if request
+        .group_by_names
+        .contains(&SdkEventDimensions::CustomerSegment)
+        || filters_on_customer_segment(&request.filters)
+    {
+        query_builder.add_join(customer_segment_join()).switch()?;
+    }
+    if let Some(time_zone) = &request.time_zone {
+        query_builder.set_time_zone(time_zone).switch()?;
+    }
+    if let Some(null_label) = &request.null_label {
+        query_builder.set_null_label(null_label).switch()?;
+    }
+
+    query_builder.add_select_column("*").switch()?;
+
+    query_builder
+        .add_filter_clause("merchant_id", publishable_key)
+        .switch()?;
+
+    for dimension in request.group_by_names.iter() {
+        let column = LocalSdkEventDimension {
+            dimension,
+            time_zone: request.time_zone.as_deref(),
+            null_label: request.null_label.as_deref(),
+        };
+        // The bucket of the events without a value has none either
+        match request.bucket.dimension(dimension) {
+            Some(value) => query_builder.add_filter_in_range_clause(column, &[value]),
+            None => query_builder.add_custom_filter_clause(column, "NULL", FilterTypes::IsNull),
+        }
+        .attach_printable("Error matching the bucket")
+        .switch()?;
+    }
+
+    request
+        .filters
+        .set_filter_clause(&mut query_builder)
+        .switch()?;
+
+    request
+        .time_range
+        .set_filter_clause(&mut query_builder)
+        .attach_printable("Error filtering time range")
+        .switch()?;
+
+    query_builder
+        .add_order_by_clause("created_at_precise", Order::Ascending)
+        .switch()?;
+    query_builder.set_limit(
+        usize::try_from(request.limit)
+            .unwrap_or(SDK_EVENT_SAMPLE_LIMIT)
+            .min(SDK_EVENT_SAMPLE_LIMIT),
+    );
+
+    query_builder
+        .execute_query::<SdkEventsResult, _>(pool)
+        .await
+        .change_context(FiltersError::QueryBuildingError)?
+        .change_context(FiltersError::QueryExecutionFailure)
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct SdkEventsResult {
     pub merchant_id: String,
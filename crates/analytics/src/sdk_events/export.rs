@@ -0,0 +1,174 @@
+use api_models::analytics::sdk_events::SdkEventMetricsBucketIdentifier;
+
+use super::metrics::SdkEventMetricRow;
+
+/// Columns of [`to_csv`] in the order they're written: the bucket identifier fields followed by
+/// the metric values. The dimension fields of [`SdkEventMetricRow`] aren't repeated since they
+/// are the same as the identifier ones.
+pub const CSV_COLUMNS: [&str; 51] = [
+    "time_bucket",
+    "payment_method",
+    "platform",
+    "browser_name",
+    "source",
+    "component",
+    "payment_experience",
+    "sdk_version",
+    "device_type",
+    "os",
+    "country",
+    "connector",
+    "event_name",
+    "payment_method_type",
+    "day_of_week",
+    "hour_of_day",
+    "authentication_error_reason",
+    "field_name",
+    "customer_segment",
+    "cdn_region",
+    "currency",
+    "abandonment_reason",
+    "count",
+    "total",
+    "min",
+    "max",
+    "p50",
+    "p90",
+    "p95",
+    "p99",
+    "median",
+    "stddev",
+    "variance",
+    "initiated",
+    "rendered",
+    "selected",
+    "filled",
+    "attempted",
+    "successful",
+    "rate",
+    "max_retries",
+    "challenge_count",
+    "frictionless_count",
+    "challenge_rate",
+    "moving_average",
+    "running_total",
+    "percent_of_total",
+    "bounce_rate",
+    "network_total",
+    "render_total",
+    "sample_rate",
+];
+
+/// Writes the buckets loaded by a sdk event metric as CSV with a header row of [`CSV_COLUMNS`],
+/// the header is written even when there are no buckets. Missing values are left empty.
+pub fn to_csv(buckets: &[(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)]) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push('\n');
+    for (id, row) in buckets {
+        let cells = csv_record(id, row)
+            .iter()
+            .map(|cell| cell.as_deref().map(escape).unwrap_or_default())
+            .collect::<Vec<_>>();
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_record(
+    id: &SdkEventMetricsBucketIdentifier,
+    row: &SdkEventMetricRow,
+) -> [Option<String>; CSV_COLUMNS.len()] {
+    [
+        id.time_bucket.clone(),
+        id.payment_method.clone(),
+        id.platform.clone(),
+        id.browser_name.clone(),
+        id.source.clone(),
+        id.component.clone(),
+        id.payment_experience.clone(),
+        id.sdk_version.clone(),
+        id.device_type.clone(),
+        id.os.clone(),
+        id.country.clone(),
+        id.connector.clone(),
+        id.event_name.clone(),
+        id.payment_method_type.clone(),
+        id.day_of_week.clone(),
+        id.hour_of_day.clone(),
+        id.authentication_error_reason.clone(),
+        id.field_name.clone(),
+        id.customer_segment.clone(),
+        id.cdn_region.clone(),
+        id.currency.clone(),
+        id.abandonment_reason.clone(),
+        row.count.map(|i| i.to_string()),
+        row.total.as_ref().map(ToString::to_string),
+        row.min.as_ref().map(ToString::to_string),
+        row.max.as_ref().map(ToString::to_string),
+        row.p50.as_ref().map(ToString::to_string),
+        row.p90.as_ref().map(ToString::to_string),
+        row.p95.as_ref().map(ToString::to_string),
+        row.p99.as_ref().map(ToString::to_string),
+        row.median.as_ref().map(ToString::to_string),
+        row.stddev.as_ref().map(ToString::to_string),
+        row.variance.as_ref().map(ToString::to_string),
+        row.initiated.map(|i| i.to_string()),
+        row.rendered.map(|i| i.to_string()),
+        row.selected.map(|i| i.to_string()),
+        row.filled.map(|i| i.to_string()),
+        row.attempted.map(|i| i.to_string()),
+        row.successful.map(|i| i.to_string()),
+        row.rate.as_ref().map(ToString::to_string),
+        row.max_retries.map(|i| i.to_string()),
+        row.challenge_count.map(|i| i.to_string()),
+        row.frictionless_count.map(|i| i.to_string()),
+        row.challenge_rate.as_ref().map(ToString::to_string),
+        row.moving_average.as_ref().map(ToString::to_string),
+        row.running_total.as_ref().map(ToString::to_string),
+        row.percent_of_total.as_ref().map(ToString::to_string),
+        row.bounce_rate.as_ref().map(ToString::to_string),
+        row.network_total.as_ref().map(ToString::to_string),
+        row.render_total.as_ref().map(ToString::to_string),
+        row.sample_rate.as_ref().map(ToString::to_string),
+    ]
+}
+
+/// Quotes the value if it contains a separator, quote or line break (RFC 4180)
+fn escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use super::*;
+
+    fn row(json: serde_json::Value) -> SdkEventMetricRow {
+        serde_json::from_value(json).expect("invalid row")
+    }
+
+    #[test]
+    fn test_header_is_written_for_empty_result() {
+        assert_eq!(to_csv(&[]), format!("{}\n", CSV_COLUMNS.join(",")));
+    }
+
+    #[test]
+    fn test_missing_values_are_empty_and_values_are_escaped() {
+        let id = SdkEventMetricsBucketIdentifier {
+            browser_name: Some("Chrome, \"Mobile\"".to_string()),
+            ..Default::default()
+        };
+        let csv = to_csv(&[(id, row(serde_json::json!({ "count": 7 })))]);
+
+        let record = csv.lines().nth(1).expect("missing record");
+        assert_eq!(
+            record,
+            ",,,\"Chrome, \"\"Mobile\"\"\",,,,,,,,,,,,,,,,,,,7,,,,,,,,,,,,,,,,,,,,,,,,,,,,"
+        );
+    }
+}
@@ -4,18 +4,27 @@ use error_stack::ResultExt;
 use time::PrimitiveDateTime;
 
 use crate::{
-    query::{Aggregate, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
+    query::{
+        customer_segment_join, Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter,
+        ToSql, Window,
+    },
     types::{AnalyticsCollection, AnalyticsDataSource, FiltersError, FiltersResult, LoadRow},
 };
 
 pub trait SdkEventFilterAnalytics: LoadRow<SdkEventFilter> {}
 
+/// Most distinct values listed for a dimension
+pub const SDK_EVENT_FILTER_VALUES_LIMIT: usize = 1000;
+
+/// Distinct values `dimension` takes in the time range, at most `limit` of them. Also returns
+/// whether the dimension has more values than that.
 pub async fn get_sdk_event_filter_for_dimension<T>(
     dimension: SdkEventDimensions,
     publishable_key: &String,
     time_range: &TimeRange,
+    limit: usize,
     pool: &T,
-) -> FiltersResult<Vec<SdkEventFilter>>
+) -> FiltersResult<(Vec<SdkEventFilter>, bool)>
 where
     T: AnalyticsDataSource + SdkEventFilterAnalytics,
     PrimitiveDateTime: ToSql<T>,
@@ -26,7 +35,13 @@ where
 {
     let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
 
-    query_builder.add_select_column(dimension).switch()?;
+    if dimension == SdkEventDimensions::CustomerSegment {
+        query_builder.add_join(customer_segment_join()).switch()?;
+    }
+
+    query_builder
+        .add_aliased_select_column(dimension, dimension.as_ref())
+        .switch()?;
     time_range
         .set_filter_clause(&mut query_builder)
         .attach_printable("Error filtering time range")
@@ -36,13 +51,24 @@ where
         .add_filter_clause("merchant_id", publishable_key)
         .switch()?;
 
+    // Events without a value don't add an option to filter on, and would use up the limit
+    query_builder
+        .add_custom_filter_clause(dimension, "NULL", FilterTypes::IsNotNull)
+        .switch()?;
+
     query_builder.set_distinct();
 
-    query_builder
+    // The extra row tells whether there are more values than the limit
+    query_builder.set_limit(limit.saturating_add(1));
+
+    let mut values = query_builder
         .execute_query::<SdkEventFilter, _>(pool)
         .await
         .change_context(FiltersError::QueryBuildingError)?
-        .change_context(FiltersError::QueryExecutionFailure)
+        .change_context(FiltersError::QueryExecutionFailure)?;
+    let truncated = values.len() > limit;
+    values.truncate(limit);
+    Ok((values, truncated))
 }
 
 #[derive(Debug, serde::Serialize, Eq, PartialEq, serde::Deserialize)]
@@ -53,4 +79,19 @@ pub struct SdkEventFilter {
     pub source: Option<String>,
     pub component: Option<String>,
     pub payment_experience: Option<String>,
+    pub sdk_version: Option<String>,
+    pub device_type: Option<String>,
+    pub os: Option<String>,
+    pub country: Option<String>,
+    pub connector: Option<String>,
+    pub event_name: Option<String>,
+    pub payment_method_type: Option<String>,
+    pub day_of_week: Option<String>,
+    pub hour_of_day: Option<String>,
+    pub authentication_error_reason: Option<String>,
+    pub field_name: Option<String>,
+    pub customer_segment: Option<String>,
+    pub cdn_region: Option<String>,
+    pub currency: Option<String>,
+    pub abandonment_reason: Option<String>,
 }
@@ -1,50 +1,206 @@
+use std::{
+    cmp::Ordering,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
 use api_models::analytics::{
     sdk_events::{
-        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+        FunnelStage, PercentileBucketing, SdkEventDimensions, SdkEventFilters, SdkEventMetrics,
+        SdkEventMetricsBucketIdentifier, SdkEventOrderBy, SdkEventPagination,
     },
-    Granularity, TimeRange,
+    CountThreshold, GetSdkEventMetricRequest, Granularity, SortDirection, TimeRange,
 };
+use bigdecimal::{FromPrimitive, ToPrimitive};
+use common_utils::date_time;
+use error_stack::{report, Report, ResultExt};
+use futures::{
+    stream::{BoxStream, Peekable},
+    StreamExt,
+};
+use router_env::{instrument, logger, tracing};
+use strum::IntoEnumIterator;
 use time::PrimitiveDateTime;
 
 use crate::{
-    query::{Aggregate, GroupByClause, ToSql, Window},
-    types::{AnalyticsCollection, AnalyticsDataSource, LoadRow, MetricsResult},
+    metrics::request::record_sdk_event_query,
+    query::{
+        Aggregate, GroupByClause, HavingClause, LocalSdkEventDimension, Order, QueryBuilder,
+        QueryBuildingError, QueryResult, QueryStatsCollector, ToSql, Window,
+    },
+    sdk_events::{
+        computed_dimension::{ComputedDimension, LocalComputedDimension, COMPUTED_DIMENSION},
+        types::{filters_on_customer_segment, validate_filter_values},
+    },
+    types::{
+        acquire_merchant_query_permit, AnalyticsCollection, AnalyticsDataSource, LoadRow,
+        MerchantQueryPermit, MetricsError, MetricsResult,
+    },
 };
 
+mod abandoned_sessions_count;
+mod anomalies;
 mod authentication_unsuccessful_count;
+mod average_payment_methods_displayed;
 mod average_payment_time;
+mod batch;
+mod cache;
+mod combined;
+mod compare;
+mod count;
+mod fill_gaps;
+mod funnel_conversion_rate;
+mod funnel_waterfall;
+mod latency_breakdown;
+mod materialized_view;
+mod metric_query;
+mod payment_attempted_amount;
 mod payment_attempts;
 mod payment_data_filled_count;
+mod payment_experience_breakdown;
 mod payment_method_selected_count;
 mod payment_methods_call_count;
+mod payment_retry_count;
+mod payment_success_rate;
+mod platform;
+mod recent;
+mod sdk_bounce_rate;
+mod sdk_error_encountered_count;
 mod sdk_initiated_count;
+mod sdk_load_failure_count;
 mod sdk_rendered_count;
+mod session_duration_seconds;
+mod sql_preview;
+mod targets;
 mod three_ds_challenge_flow_count;
+mod three_ds_flow_breakdown;
 mod three_ds_frictionless_flow_count;
 mod three_ds_method_invoked_count;
 mod three_ds_method_skipped_count;
+mod three_ds_method_success_rate;
 mod three_ds_method_successful_count;
 mod three_ds_method_unsuccessful_count;
+mod time_to_first_render;
+mod top_groups;
+mod unique_sessions_count;
+mod warm;
 
+use abandoned_sessions_count::AbandonedSessionsCount;
+pub use anomalies::{flag_anomalies, AnomalyDetection, SdkEventAnomalyBucket};
 use authentication_unsuccessful_count::AuthenticationUnsuccessfulCount;
+use average_payment_methods_displayed::AveragePaymentMethodsDisplayed;
 use average_payment_time::AveragePaymentTime;
+pub use batch::{load_metrics_batch, SdkEventBatchRow};
+use cache::CacheKey;
+pub use cache::{MetricsCacheConfig, SdkEventMetricsCache};
+pub use combined::{load_combined_metrics, SdkEventCombinedRow};
+pub use compare::{
+    contributions_to_change, load_metrics_for_key_pair, load_metrics_with_previous_period,
+    previous_time_range, SdkEventContribution, SdkEventKeyPairBucket, SdkEventKeyPairComparison,
+    SdkEventPeriodBucket, SdkEventPeriodComparison,
+};
+use fill_gaps::fill_gaps;
+use funnel_conversion_rate::FunnelConversionRate;
+use funnel_waterfall::FunnelWaterfall;
+use latency_breakdown::LatencyBreakdown;
+pub use materialized_view::{
+    materialized_view, pick_materialized_view, MaterializedView, SDK_EVENTS_HOURLY,
+};
+pub use metric_query::SdkMetricQuery;
+use payment_attempted_amount::PaymentAttemptedAmount;
 use payment_attempts::PaymentAttempts;
 use payment_data_filled_count::PaymentDataFilledCount;
+use payment_experience_breakdown::PaymentExperienceBreakdown;
 use payment_method_selected_count::PaymentMethodSelectedCount;
 use payment_methods_call_count::PaymentMethodsCallCount;
+use payment_retry_count::PaymentRetryCount;
+use payment_success_rate::PaymentSuccessRate;
+pub use platform::{load_platform_rollup, SdkEventMerchantBucket, SdkEventPlatformRollup};
+pub use recent::{
+    load_recent_metrics, recent_time_range, DEFAULT_RECENT_WINDOW_MINS, MAX_RECENT_WINDOW_MINS,
+    RECENT_MAX_PARTITIONS,
+};
+use sdk_bounce_rate::SdkBounceRate;
+use sdk_error_encountered_count::SdkErrorEncounteredCount;
 use sdk_initiated_count::SdkInitiatedCount;
+use sdk_load_failure_count::SdkLoadFailureCount;
 use sdk_rendered_count::SdkRenderedCount;
+use session_duration_seconds::SessionDurationSeconds;
+pub use sql_preview::{render_metric_sql, SdkEventSqlPreview};
+pub use targets::target_result;
 use three_ds_challenge_flow_count::ThreeDsChallengeFlowCount;
+use three_ds_flow_breakdown::ThreeDsFlowBreakdown;
 use three_ds_frictionless_flow_count::ThreeDsFrictionlessFlowCount;
 use three_ds_method_invoked_count::ThreeDsMethodInvokedCount;
 use three_ds_method_skipped_count::ThreeDsMethodSkippedCount;
+use three_ds_method_success_rate::ThreeDsMethodSuccessRate;
 use three_ds_method_successful_count::ThreeDsMethodSuccessfulCount;
 use three_ds_method_unsuccessful_count::ThreeDsMethodUnsuccessfulCount;
+use time_to_first_render::TimeToFirstRender;
+pub use top_groups::OTHER_GROUP;
+use unique_sessions_count::UniqueSessionsCount;
+pub use warm::{
+    warm_metrics_cache, SdkEventWarmQuery, SdkEventWarmResult, DEFAULT_WARM_CONCURRENCY,
+};
+
+/// Column identifying an event, the same for every copy of an event ingested more than once
+pub const EVENT_ID_COLUMN: &str = "event_id";
 
-#[derive(Debug, PartialEq, Eq, serde::Deserialize)]
+/// Quantiles the metrics with percentiles select, see [`has_percentiles`], each with the field of
+/// [`SdkEventMetricRow`] it's loaded into
+pub(crate) const PERCENTILES: &[(&str, f64)] =
+    &[("p50", 0.5), ("p90", 0.9), ("p95", 0.95), ("p99", 0.99)];
+
+/// A bucket loaded by a sdk event metric. The values are taken as the query returns them, without
+/// defaulting: a value is `None` if the metric doesn't select it or its aggregate had no rows to
+/// evaluate (e.g. the sum of a column which is null for every event), while `Some(0)` is a count
+/// or sum which did evaluate to zero. A bucket without any matching events isn't returned at all,
+/// unless gaps are filled, which adds it with zero counts.
+///
+/// Rows are read leniently so that the events schema and this struct can change independently: a
+/// column the struct has no field for is ignored, and a field without a column is `None`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct SdkEventMetricRow {
     pub total: Option<bigdecimal::BigDecimal>,
     pub count: Option<i64>,
+    pub min: Option<bigdecimal::BigDecimal>,
+    pub max: Option<bigdecimal::BigDecimal>,
+    pub p50: Option<bigdecimal::BigDecimal>,
+    pub p90: Option<bigdecimal::BigDecimal>,
+    pub p95: Option<bigdecimal::BigDecimal>,
+    pub p99: Option<bigdecimal::BigDecimal>,
+    pub median: Option<bigdecimal::BigDecimal>,
+    pub stddev: Option<bigdecimal::BigDecimal>,
+    pub variance: Option<bigdecimal::BigDecimal>,
+    pub initiated: Option<i64>,
+    pub rendered: Option<i64>,
+    pub selected: Option<i64>,
+    pub filled: Option<i64>,
+    pub attempted: Option<i64>,
+    pub successful: Option<i64>,
+    pub rate: Option<bigdecimal::BigDecimal>,
+    pub max_retries: Option<i64>,
+    pub challenge_count: Option<i64>,
+    pub frictionless_count: Option<i64>,
+    pub challenge_rate: Option<bigdecimal::BigDecimal>,
+    pub moving_average: Option<bigdecimal::BigDecimal>,
+    pub running_total: Option<bigdecimal::BigDecimal>,
+    pub percent_of_total: Option<bigdecimal::BigDecimal>,
+    pub bounce_rate: Option<bigdecimal::BigDecimal>,
+    /// Summed network and render parts of the payment time of the latency breakdown
+    pub network_total: Option<bigdecimal::BigDecimal>,
+    pub render_total: Option<bigdecimal::BigDecimal>,
+    /// Fraction of the events the row was computed from, `None` for a row of every event
+    pub sample_rate: Option<bigdecimal::BigDecimal>,
+    /// Whether the query returned more rows than the data source keeps, see
+    /// [`AnalyticsDataSource::max_result_rows`], and this row is one of the ones which were kept
+    pub truncated: bool,
+    /// Stages of the funnel waterfall the row counts, in the order they're reached. Set on the
+    /// rows of the funnel waterfall once they're loaded.
+    pub funnel_stages: Option<Vec<FunnelStage>>,
+    /// Merchant of the row, only selected by the rollups across merchants
+    pub merchant_id: Option<String>,
     pub time_bucket: Option<String>,
     pub payment_method: Option<String>,
     pub platform: Option<String>,
@@ -52,24 +208,1019 @@ pub struct SdkEventMetricRow {
     pub source: Option<String>,
     pub component: Option<String>,
     pub payment_experience: Option<String>,
+    pub sdk_version: Option<String>,
+    pub device_type: Option<String>,
+    pub os: Option<String>,
+    pub country: Option<String>,
+    pub connector: Option<String>,
+    pub event_name: Option<String>,
+    pub payment_method_type: Option<String>,
+    pub day_of_week: Option<String>,
+    pub hour_of_day: Option<String>,
+    pub authentication_error_reason: Option<String>,
+    pub field_name: Option<String>,
+    pub customer_segment: Option<String>,
+    pub cdn_region: Option<String>,
+    pub currency: Option<String>,
+    pub abandonment_reason: Option<String>,
+    pub computed_dimension: Option<String>,
+    /// Number of the dimensions a subtotal adds up, 0 for the row of a single group. Only
+    /// selected when subtotals are asked for.
+    pub subtotal_level: Option<i64>,
+}
+
+pub trait SdkEventMetricAnalytics: LoadRow<SdkEventMetricRow> + LoadRow<SdkEventBatchRow> {
+    /// Cache the buckets of metric queries are kept in, nothing is cached by default
+    fn metrics_cache(&self) -> Option<&SdkEventMetricsCache> {
+        None
+    }
 }
 
-pub trait SdkEventMetricAnalytics: LoadRow<SdkEventMetricRow> {}
+/// Request wide settings which shape the query of every sdk event metric
+#[derive(Debug, Default, Clone)]
+pub struct SdkEventQueryOptions {
+    pub time_zone: Option<String>,
+    pub count_threshold: Option<CountThreshold>,
+    pub timeout: Option<Duration>,
+    pub pagination: Option<SdkEventPagination>,
+    /// Number of time buckets, up to and including its own, the `moving_average` of a bucket's
+    /// count is taken over. Only the count metrics of a time series have one.
+    pub moving_average_buckets: Option<u32>,
+    /// Whether to select the `running_total` of the count of each group over its time buckets,
+    /// which is only taken for the count metrics of a time series as well
+    pub running_total: bool,
+    /// Whether to select the `percent_of_total` of the count of each group: its share of the
+    /// count of every group of the same time bucket, or of the whole range without time buckets.
+    /// Only the count metrics have one.
+    pub percent_of_total: bool,
+    /// Whether every time bucket of the range is returned for each group, with zero counts for
+    /// the ones without events. Applies only to a time series.
+    pub fill_gaps: bool,
+    /// Whether the SQL of every query is logged at info level, it's only logged at debug level
+    /// otherwise
+    pub log_queries: bool,
+    /// Number of groups with the most events which are kept, the others are merged into a
+    /// single group labelled [`OTHER_GROUP`]. Replaces the `pagination`.
+    pub top_groups: Option<u32>,
+    /// Fraction of the events, greater than 0 and at most 1, the metrics are approximated from.
+    /// The counts are scaled back up to all events. The metrics following sessions over their
+    /// events aren't sampled, see [`is_sampled`].
+    pub sample_rate: Option<f64>,
+    /// Number of decimals the data source rounds the `total` of each bucket to, it's returned
+    /// with its full precision without one
+    pub decimal_places: Option<u32>,
+    /// Stages of the funnel waterfall in the order they're reached, every stage in the order of
+    /// the sdk flow without them
+    pub funnel_stages: Option<Vec<FunnelStage>>,
+    /// Label of the missing values of the dimensions, [`crate::query::NULL_DIMENSION_LABEL`]
+    /// without one
+    pub null_label: Option<String>,
+    /// Expression of a [`ComputedDimension`] the events are grouped by as well, besides the
+    /// dimensions. Neither a page nor the top groups can be picked with one.
+    pub computed_dimension: Option<String>,
+    /// Whether the subtotals rolling up the dimensions, from the last one to the first, are
+    /// loaded along the groups. Neither a page nor the top groups can be picked with them.
+    pub subtotals: bool,
+    /// Whether the percentiles of the metrics having them, see [`has_percentiles`], are computed
+    /// for each time bucket or once over the whole time range of each group
+    pub percentile_bucketing: PercentileBucketing,
+    /// Whether the count metrics count the distinct [`EVENT_ID_COLUMN`]s of their events rather
+    /// than every row, so that an event ingested more than once is counted once. The exact
+    /// distinct count keeps every id of a group in memory, which is costlier than counting rows.
+    /// The count threshold and the order of the groups still go by the rows.
+    pub deduplicate: bool,
+    /// Whether the archived events, [`AnalyticsCollection::SdkEventsArchive`], are read along the
+    /// recent ones
+    pub include_archived: bool,
+    /// Rows a query returns at most, the data source's [`AnalyticsDataSource::max_result_rows`]
+    /// unless this is lower. The buckets of a result which was cut are flagged as `truncated`.
+    pub max_rows: Option<usize>,
+    /// Where the statistics of every query run are recorded, see
+    /// [`QueryBuilder::collect_stats`]. The queries answered from the cache aren't run and
+    /// aren't recorded.
+    pub query_stats: Option<QueryStatsCollector>,
+    /// Partitions of the events a query may read at most, the query fails rather than read more
+    pub max_partitions: Option<u32>,
+    /// Whether the buckets are loaded from the data source even if the cache of the pool holds
+    /// them, for live data which would be stale from the cache. They aren't cached either.
+    pub bypass_cache: bool,
+}
+
+impl From<&GetSdkEventMetricRequest> for SdkEventQueryOptions {
+    fn from(req: &GetSdkEventMetricRequest) -> Self {
+        Self {
+            time_zone: req.time_zone.clone(),
+            count_threshold: req.count_threshold,
+            timeout: req.timeout_ms.map(Duration::from_millis),
+            pagination: req.pagination,
+            // The response merges the rows of every metric, it has no place for the windows of one
+            moving_average_buckets: None,
+            running_total: false,
+            percent_of_total: false,
+            fill_gaps: req.fill_gaps,
+            log_queries: false,
+            top_groups: req.top_groups,
+            sample_rate: req.sample_rate,
+            decimal_places: req.decimal_places,
+            funnel_stages: req.funnel_stages.clone(),
+            null_label: req.null_label.clone(),
+            computed_dimension: req.computed_dimension.clone(),
+            subtotals: req.subtotals,
+            percentile_bucketing: req.percentile_bucketing,
+            deduplicate: req.deduplicate,
+            include_archived: req.include_archived,
+            max_rows: None,
+            // Shared by the metrics of the request, the caller sets it up if they're requested
+            query_stats: None,
+            max_partitions: None,
+            bypass_cache: false,
+        }
+    }
+}
+
+impl SdkEventQueryOptions {
+    /// `dimension` as it's selected and grouped by, on the wall clock of the requested time zone
+    /// and with the requested label of a missing value
+    pub(crate) fn dimension<'a>(
+        &'a self,
+        dimension: &'a SdkEventDimensions,
+    ) -> LocalSdkEventDimension<'a> {
+        LocalSdkEventDimension {
+            dimension,
+            time_zone: self.time_zone.as_deref(),
+            null_label: self.null_label.as_deref(),
+        }
+    }
+
+    pub(crate) fn set_query_options<T>(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()>
+    where
+        T: AnalyticsDataSource,
+        AnalyticsCollection: ToSql<T>,
+        Aggregate<&'static str>: ToSql<T>,
+    {
+        if let Some(time_zone) = &self.time_zone {
+            builder.set_time_zone(time_zone)?;
+        }
+        if let Some(null_label) = &self.null_label {
+            builder.set_null_label(null_label)?;
+        }
+        if let Some(expression) = &self.computed_dimension {
+            let dimension = ComputedDimension::parse(expression).change_context(
+                QueryBuildingError::InvalidQuery("Invalid computed dimension"),
+            )?;
+            let dimension = LocalComputedDimension {
+                dimension: &dimension,
+                time_zone: self.time_zone.as_deref(),
+                null_label: self.null_label.as_deref(),
+            };
+            builder.add_aliased_select_column(dimension, COMPUTED_DIMENSION)?;
+            builder.add_group_by_clause(dimension)?;
+        }
+        if self.subtotals {
+            builder.enable_subtotals();
+        }
+        self.set_source_options(builder)?;
+        if let Some(count_threshold) = &self.count_threshold {
+            count_threshold
+                .set_having_clause(builder)
+                .attach_printable("Error adding count threshold")?;
+        }
+        if let Some(timeout) = self.timeout {
+            builder.set_timeout(timeout);
+        }
+        if self.log_queries {
+            builder.enable_query_logging();
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            builder.set_sample_rate(sample_rate)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the collections `builder` reads, and the rows it returns and partitions it reads at
+    /// most, which the metrics aggregating sessions, which don't take the other options, are bound
+    /// by as well
+    pub(crate) fn set_source_options<T>(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()>
+    where
+        T: AnalyticsDataSource,
+        AnalyticsCollection: ToSql<T>,
+    {
+        if self.include_archived {
+            builder.add_union_collection(AnalyticsCollection::SdkEventsArchive)?;
+        }
+        if let Some(max_rows) = self.max_rows {
+            builder.set_max_rows(max_rows);
+        }
+        if let Some(stats) = &self.query_stats {
+            builder.collect_stats(stats.clone());
+        }
+        if let Some(max_partitions) = self.max_partitions {
+            builder.set_max_partitions(max_partitions);
+        }
+        Ok(())
+    }
+
+    /// Scales the values of buckets loaded from a sample of the events back up to all events, and
+    /// marks the rows with the rate they were sampled at. The values which don't grow with the
+    /// number of events, like the rates and percentiles, are kept as they are.
+    pub(crate) fn scale_sampled_buckets(
+        &self,
+        metric: &SdkEventMetrics,
+        buckets: &mut [(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+    ) -> MetricsResult<()> {
+        let Some(sample_rate) = self.sample_rate.filter(|_| is_sampled(metric)) else {
+            return Ok(());
+        };
+        let sample_rate = bigdecimal::BigDecimal::from_f64(sample_rate)
+            .filter(|sample_rate| *sample_rate > bigdecimal::BigDecimal::from(0))
+            .ok_or_else(|| report!(MetricsError::PostProcessingFailure))
+            .attach_printable_lazy(|| format!("Invalid sample rate: {sample_rate}"))?;
+        let scale = |value: &mut Option<bigdecimal::BigDecimal>| {
+            *value = value.take().map(|value| value / &sample_rate);
+        };
+        // Scaling a rounded total up brings its fractional tail back
+        let round_total = |value: &mut Option<bigdecimal::BigDecimal>| {
+            if let Some(decimal_places) = self.decimal_places {
+                *value = value
+                    .take()
+                    .map(|value| value.round(i64::from(decimal_places)));
+            }
+        };
+        let scale_count = |value: &mut Option<i64>| {
+            *value = value.and_then(|value| {
+                (bigdecimal::BigDecimal::from(value) / &sample_rate)
+                    .round(0)
+                    .to_i64()
+            });
+        };
+        for (_, row) in buckets.iter_mut() {
+            for count in [
+                &mut row.count,
+                &mut row.initiated,
+                &mut row.rendered,
+                &mut row.selected,
+                &mut row.filled,
+                &mut row.attempted,
+                &mut row.successful,
+                &mut row.challenge_count,
+                &mut row.frictionless_count,
+            ] {
+                scale_count(count);
+            }
+            for value in [
+                &mut row.total,
+                &mut row.network_total,
+                &mut row.render_total,
+                &mut row.moving_average,
+                &mut row.running_total,
+            ] {
+                scale(value);
+            }
+            for total in [
+                &mut row.total,
+                &mut row.network_total,
+                &mut row.render_total,
+            ] {
+                round_total(total);
+            }
+            row.sample_rate = Some(sample_rate.clone());
+        }
+        Ok(())
+    }
+
+    /// These options capped to the lower of [`Self::max_rows`] and `max_rows`
+    pub(crate) fn with_max_rows(&self, max_rows: Option<usize>) -> Self {
+        Self {
+            max_rows: self.max_rows.into_iter().chain(max_rows).min(),
+            ..self.clone()
+        }
+    }
+
+    /// Count of the events of a group selected as the `count` of the count metrics, see
+    /// [`Self::deduplicate`]
+    pub(crate) fn event_count(&self) -> Aggregate<&'static str> {
+        if self.deduplicate {
+            Aggregate::CountDistinct {
+                field: EVENT_ID_COLUMN,
+                alias: Some("count"),
+            }
+        } else {
+            Aggregate::Count {
+                field: None,
+                alias: Some("count"),
+            }
+        }
+    }
+
+    /// Expression of [`Self::event_count`] the windows over the count of a group are taken of
+    pub(crate) fn event_count_expression(&self) -> &'static str {
+        if self.deduplicate {
+            "count(DISTINCT event_id)"
+        } else {
+            "count(*)"
+        }
+    }
+
+    /// Granularity the buckets of `metric` are loaded with: none for a metric having percentiles
+    /// when they're computed over the whole time range, so that each group has a single bucket
+    pub(crate) fn metric_granularity(
+        &self,
+        metric: &SdkEventMetrics,
+        granularity: &Option<Granularity>,
+    ) -> Option<Granularity> {
+        match self.percentile_bucketing {
+            PercentileBucketing::WholeRange if has_percentiles(metric) => None,
+            PercentileBucketing::WholeRange | PercentileBucketing::PerBucket => *granularity,
+        }
+    }
+
+    /// Fills the gaps of the time series in `buckets` if that was asked for
+    pub(crate) fn fill_time_series_gaps(
+        &self,
+        buckets: Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+        dimensions: &[SdkEventDimensions],
+        granularity: &Option<Granularity>,
+        time_range: &TimeRange,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        match granularity {
+            Some(granularity) if self.fill_gaps => fill_gaps(
+                buckets,
+                !dimensions.is_empty() || self.computed_dimension.is_some(),
+                granularity,
+                self.time_zone.as_deref(),
+                time_range,
+            ),
+            _ => Ok(buckets),
+        }
+    }
+
+    /// Sorts buckets loaded without a page with [`compare_buckets`], a page keeps the order it
+    /// was sorted in by the query
+    pub(crate) fn sort_buckets(
+        &self,
+        buckets: &mut [(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+        dimensions: &[SdkEventDimensions],
+    ) {
+        if self.pagination.is_none() {
+            buckets.sort_by(|(left, _), (right, _)| compare_buckets(left, right, dimensions));
+        }
+    }
+
+    /// Selects the windows over the time buckets of each group which were asked for: the trailing
+    /// mean of `field` as `moving_average` and its running total as `running_total`. Queries
+    /// without time buckets have neither.
+    pub(crate) fn set_time_series_windows<T>(
+        &self,
+        builder: &mut QueryBuilder<T>,
+        field: &'static str,
+        dimensions: &[SdkEventDimensions],
+        granularity: &Option<Granularity>,
+    ) -> QueryResult<()>
+    where
+        T: AnalyticsDataSource,
+        AnalyticsCollection: ToSql<T>,
+        Window<&'static str>: ToSql<T>,
+    {
+        let Some(granularity) = granularity else {
+            return Ok(());
+        };
+        // Each group has its own series, so that e.g. the totals of two browsers don't add up
+        let partition_by = (!dimensions.is_empty()).then(|| {
+            dimensions
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+        // Postgres can't sort a window by an alias of the select list, so the window is
+        // ordered by the bucket expression itself
+        let order_by = Some((
+            T::get_time_bucket(
+                builder.time_column(),
+                granularity,
+                self.time_zone.as_deref(),
+            ),
+            Order::Ascending,
+        ));
+        if let Some(buckets) = self.moving_average_buckets {
+            builder.add_select_column(Window::MovingAverage {
+                field,
+                partition_by: partition_by.clone(),
+                order_by: order_by.clone(),
+                preceding: buckets.saturating_sub(1),
+                alias: Some("moving_average"),
+            })?;
+        }
+        if self.running_total {
+            builder.add_select_column(Window::CumulativeSum {
+                field,
+                partition_by,
+                order_by,
+                alias: Some("running_total"),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Selects the share of `field` of each group as `percent_of_total` if that was asked for.
+    /// The groups dropped by a count threshold aren't part of the total, the ones beyond a page
+    /// are.
+    pub(crate) fn set_percent_of_total<T>(
+        &self,
+        builder: &mut QueryBuilder<T>,
+        field: &'static str,
+        granularity: &Option<Granularity>,
+    ) -> QueryResult<()>
+    where
+        T: AnalyticsDataSource,
+        AnalyticsCollection: ToSql<T>,
+        Window<&'static str>: ToSql<T>,
+    {
+        if !self.percent_of_total {
+            return Ok(());
+        }
+        // Partitioned by the bucket expression, for the same reason the time series windows are
+        // ordered by it
+        let partition_by = granularity.as_ref().map(|granularity| {
+            T::get_time_bucket(
+                builder.time_column(),
+                granularity,
+                self.time_zone.as_deref(),
+            )
+        });
+        builder.add_select_column(Window::PercentOfTotal {
+            field,
+            partition_by,
+            alias: Some("percent_of_total"),
+        })
+    }
+
+    /// Sorts and pages the groups of the query, ties are broken by `dimensions` in order and then
+    /// by the time bucket. Without a page the rows are sorted like [`compare_buckets`] sorts
+    /// them.
+    pub(crate) fn set_pagination<T>(
+        &self,
+        builder: &mut QueryBuilder<T>,
+        dimensions: &[SdkEventDimensions],
+        granularity: &Option<Granularity>,
+    ) -> QueryResult<()>
+    where
+        T: AnalyticsDataSource,
+        AnalyticsCollection: ToSql<T>,
+        Aggregate<&'static str>: ToSql<T>,
+    {
+        let Some(pagination) = &self.pagination else {
+            if granularity.is_some() {
+                builder.add_order_by_clause("time_bucket", Order::Ascending)?;
+            }
+            for dim in dimensions {
+                builder.add_order_by_clause(dim.as_ref(), Order::Ascending)?;
+            }
+            return Ok(());
+        };
+        let order = match pagination.direction {
+            SortDirection::Ascending => Order::Ascending,
+            SortDirection::Descending => Order::Descending,
+        };
+        match pagination.order_by {
+            SdkEventOrderBy::EventCount => builder.add_order_by_clause(
+                Aggregate::Count {
+                    field: None,
+                    alias: None,
+                },
+                order,
+            )?,
+            SdkEventOrderBy::Dimension(dimension) => {
+                builder.add_order_by_clause(dimension.as_ref(), order)?
+            }
+        }
+        for dim in dimensions {
+            if !matches!(pagination.order_by, SdkEventOrderBy::Dimension(d) if d == *dim) {
+                builder.add_order_by_clause(dim.as_ref(), Order::Ascending)?;
+            }
+        }
+        if granularity.is_some() {
+            builder.add_order_by_clause("time_bucket", Order::Ascending)?;
+        }
+        builder.set_limit(
+            usize::try_from(pagination.limit)
+                .change_context(QueryBuildingError::InvalidQuery("Limit out of range"))?,
+        );
+        if pagination.offset > 0 {
+            builder.set_offset(
+                usize::try_from(pagination.offset)
+                    .change_context(QueryBuildingError::InvalidQuery("Offset out of range"))?,
+            );
+        }
+        Ok(())
+    }
+}
 
 #[async_trait::async_trait]
 pub trait SdkEventMetric<T>
 where
     T: AnalyticsDataSource + SdkEventMetricAnalytics,
 {
+    #[allow(clippy::too_many_arguments)]
     async fn load_metrics(
         &self,
         dimensions: &[SdkEventDimensions],
         publishable_key: &str,
         filters: &SdkEventFilters,
         granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
         time_range: &TimeRange,
         pool: &T,
     ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>>;
+
+    /// Like [`Self::load_metrics`] but hands out the buckets as the data source returns them.
+    /// Only the count metrics and the funnel conversion rate override this, the other metrics
+    /// can't be streamed.
+    #[allow(clippy::too_many_arguments)]
+    async fn load_metrics_stream(
+        &self,
+        _dimensions: &[SdkEventDimensions],
+        _publishable_key: &str,
+        _filters: &SdkEventFilters,
+        _granularity: &Option<Granularity>,
+        _options: &SdkEventQueryOptions,
+        _time_range: &TimeRange,
+        _pool: &T,
+    ) -> MetricsResult<SdkEventMetricStream> {
+        Err(report!(MetricsError::NotImplemented))
+            .attach_printable("The metric can't be streamed by its data source")
+    }
+}
+
+/// Whether `metric` is loaded from a sample of the events when a sample rate is set. The metrics
+/// following sessions over their events aren't, a sample would only have some of the events of a
+/// session.
+pub fn is_sampled(metric: &SdkEventMetrics) -> bool {
+    !matches!(
+        metric,
+        SdkEventMetrics::SessionDurationSeconds
+            | SdkEventMetrics::PaymentRetryCount
+            | SdkEventMetrics::TimeToFirstRender
+    )
+}
+
+/// Whether `metric` selects the percentiles (`p50` to `p99`) of its values
+pub fn has_percentiles(metric: &SdkEventMetrics) -> bool {
+    matches!(
+        metric,
+        SdkEventMetrics::AveragePaymentTime
+            | SdkEventMetrics::SessionDurationSeconds
+            | SdkEventMetrics::PaymentRetryCount
+            | SdkEventMetrics::TimeToFirstRender
+    )
+}
+
+/// Dimensions `metric` can be grouped by. The metrics over whole sessions can't be grouped by
+/// what changes within a session: its events come from several components, and the first ones
+/// are sent before a payment method or connector is picked. Neither they nor the per session
+/// counts can be grouped by the hour or weekday, a session can span several of them. The
+/// metrics which compare events of different names can't be grouped by the event name, which
+/// would split them apart. The sdk fails to load before any payment is made, and only its load
+/// failures carry the CDN region. Only the payment data filled events carry an input field,
+/// only the funnel metrics join the customer segments, and only the abandoned sessions count
+/// picks the abandonment reason of a session.
+pub fn supported_dimensions(metric: &SdkEventMetrics) -> Vec<SdkEventDimensions> {
+    let unsupported: &[SdkEventDimensions] = match metric {
+        SdkEventMetrics::SessionDurationSeconds
+        | SdkEventMetrics::FunnelConversionRate
+        | SdkEventMetrics::FunnelWaterfall
+        | SdkEventMetrics::TimeToFirstRender
+        | SdkEventMetrics::AbandonedSessionsCount => &[
+            SdkEventDimensions::Component,
+            SdkEventDimensions::PaymentMethod,
+            SdkEventDimensions::PaymentMethodType,
+            SdkEventDimensions::Connector,
+            SdkEventDimensions::EventName,
+            SdkEventDimensions::DayOfWeek,
+            SdkEventDimensions::HourOfDay,
+            SdkEventDimensions::AuthenticationErrorReason,
+        ],
+        SdkEventMetrics::SdkLoadFailureCount => &[
+            SdkEventDimensions::Component,
+            SdkEventDimensions::PaymentMethod,
+            SdkEventDimensions::PaymentMethodType,
+            SdkEventDimensions::PaymentExperience,
+            SdkEventDimensions::Connector,
+            SdkEventDimensions::EventName,
+            SdkEventDimensions::DayOfWeek,
+            SdkEventDimensions::HourOfDay,
+            SdkEventDimensions::AuthenticationErrorReason,
+        ],
+        SdkEventMetrics::UniqueSessionsCount | SdkEventMetrics::PaymentRetryCount => &[
+            SdkEventDimensions::Component,
+            SdkEventDimensions::DayOfWeek,
+            SdkEventDimensions::HourOfDay,
+        ],
+        SdkEventMetrics::PaymentSuccessRate
+        | SdkEventMetrics::SdkBounceRate
+        | SdkEventMetrics::ThreeDsMethodSuccessRate => &[SdkEventDimensions::EventName],
+        SdkEventMetrics::PaymentAttempts
+        | SdkEventMetrics::PaymentMethodsCallCount
+        | SdkEventMetrics::ThreeDsMethodInvokedCount
+        | SdkEventMetrics::ThreeDsMethodSkippedCount
+        | SdkEventMetrics::ThreeDsMethodSuccessfulCount
+        | SdkEventMetrics::ThreeDsMethodUnsuccessfulCount
+        | SdkEventMetrics::AuthenticationUnsuccessfulCount
+        | SdkEventMetrics::ThreeDsChallengeFlowCount
+        | SdkEventMetrics::ThreeDsFrictionlessFlowCount
+        | SdkEventMetrics::SdkRenderedCount
+        | SdkEventMetrics::SdkInitiatedCount
+        | SdkEventMetrics::PaymentMethodSelectedCount
+        | SdkEventMetrics::PaymentDataFilledCount
+        | SdkEventMetrics::AveragePaymentTime
+        | SdkEventMetrics::SdkErrorEncounteredCount
+        | SdkEventMetrics::AveragePaymentMethodsDisplayed
+        | SdkEventMetrics::PaymentAttemptedAmount
+        | SdkEventMetrics::LatencyBreakdown
+        | SdkEventMetrics::ThreeDsFlowBreakdown
+        | SdkEventMetrics::PaymentExperienceBreakdown => &[],
+    };
+    SdkEventDimensions::iter()
+        .filter(|dim| !unsupported.contains(dim))
+        .filter(|dim| {
+            *dim != SdkEventDimensions::FieldName
+                || *metric == SdkEventMetrics::PaymentDataFilledCount
+        })
+        .filter(|dim| {
+            *dim != SdkEventDimensions::CdnRegion || *metric == SdkEventMetrics::SdkLoadFailureCount
+        })
+        .filter(|dim| {
+            *dim != SdkEventDimensions::AbandonmentReason
+                || *metric == SdkEventMetrics::AbandonedSessionsCount
+        })
+        .filter(|dim| *dim != SdkEventDimensions::CustomerSegment || joins_customer_segment(metric))
+        .collect()
+}
+
+/// Fields of [`SdkEventFilters`], the groups of `any_of`, `all_of` and `none_of` taking the same
+const SDK_EVENT_FILTERS: &[&str] = &[
+    "payment_method",
+    "platform",
+    "browser_name",
+    "source",
+    "component",
+    "payment_experience",
+    "sdk_version",
+    "device_type",
+    "os",
+    "country",
+    "connector",
+    "event_name",
+    "payment_method_type",
+    "day_of_week",
+    "hour_of_day",
+    "hour_windows",
+    "authentication_error_reason",
+    "field_name",
+    "customer_segment",
+    "cdn_region",
+    "currency",
+    "abandonment_reason",
+    "amount",
+    "exclude_bots",
+    "any_of",
+    "all_of",
+    "none_of",
+];
+
+/// Fields of [`SdkEventFilters`] the events of `metric` can be filtered on, the ones
+/// [`validate_filters`] accepts
+pub fn supported_filters(metric: &SdkEventMetrics) -> Vec<&'static str> {
+    SDK_EVENT_FILTERS
+        .iter()
+        .copied()
+        .filter(|field| *field != "customer_segment" || joins_customer_segment(metric))
+        .collect()
+}
+
+/// Whether the queries of `metric` join the customer segments when they're grouped or filtered
+/// by them
+fn joins_customer_segment(metric: &SdkEventMetrics) -> bool {
+    matches!(
+        metric,
+        SdkEventMetrics::FunnelConversionRate | SdkEventMetrics::FunnelWaterfall
+    )
+}
+
+/// Fails with [`MetricsError::UnscopedQuery`] unless `query_builder` only reads the events of one
+/// publishable key within a time range. Every metric query is checked right before
+/// it's run, so that one which lost either filter fails instead of scanning the whole table.
+pub(super) fn check_scoped<T>(query_builder: &QueryBuilder<T>) -> MetricsResult<()>
+where
+    T: AnalyticsDataSource,
+    AnalyticsCollection: ToSql<T>,
+{
+    query_builder
+        .check_scoped(Some("merchant_id"))
+        .change_context(MetricsError::UnscopedQuery)
+}
+
+/// Names the cause of a query which couldn't be built: [`MetricsError::BadQuery`] or
+/// [`MetricsError::Unsupported`] where the query asked for something which can't be built, and
+/// [`MetricsError::Other`] for anything else. Other errors are kept as they are.
+fn name_query_building_error(report: Report<MetricsError>) -> Report<MetricsError> {
+    if !matches!(report.current_context(), MetricsError::QueryBuildingError) {
+        return report;
+    }
+    let error = match report.downcast_ref::<QueryBuildingError>() {
+        Some(QueryBuildingError::InvalidQuery(reason)) => {
+            MetricsError::BadQuery(reason.to_string())
+        }
+        Some(QueryBuildingError::NotImplemented(what)) => MetricsError::Unsupported(what.clone()),
+        Some(QueryBuildingError::SqlSerializeError) => MetricsError::Other,
+        None => return report,
+    };
+    report.change_context(error)
+}
+
+/// Fails with [`MetricsError::UnsupportedDimension`] for the first of `dimensions` `metric`
+/// can't be grouped by
+pub(crate) fn validate_dimensions(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+) -> MetricsResult<()> {
+    let supported = supported_dimensions(metric);
+    match dimensions.iter().find(|dim| !supported.contains(dim)) {
+        Some(dim) => Err(report!(MetricsError::UnsupportedDimension(dim.to_string())))
+            .attach_printable_lazy(|| format!("{metric} can't be grouped by {dim}")),
+        None => Ok(()),
+    }
+}
+
+/// Fails with [`MetricsError::InvalidFilter`] if a value of `filters` can't match any event, see
+/// [`validate_filter_values`], and with [`MetricsError::UnsupportedDimension`] if `filters`
+/// filter on the customer segment and `metric` doesn't join it, its queries would filter on a
+/// column they don't have
+pub(crate) fn validate_filters(
+    metric: &SdkEventMetrics,
+    filters: &SdkEventFilters,
+) -> MetricsResult<()> {
+    validate_filter_values(filters)?;
+    if filters_on_customer_segment(filters) && !joins_customer_segment(metric) {
+        return Err(report!(MetricsError::UnsupportedDimension(
+            SdkEventDimensions::CustomerSegment.to_string()
+        )))
+        .attach_printable_lazy(|| format!("{metric} can't be filtered by customer segment"));
+    }
+    Ok(())
+}
+
+/// Whether `metric` adds up the amounts of payments, each in the minor unit of its own currency
+fn sums_amounts(metric: &SdkEventMetrics) -> bool {
+    matches!(metric, SdkEventMetrics::PaymentAttemptedAmount)
+}
+
+/// Fails with [`MetricsError::MixedCurrencies`] if `metric` adds up amounts and a bucket could
+/// hold the payments of more than one currency: its events have to be filtered on a single
+/// currency, or grouped by the currency without the subtotals or the top groups, which merge
+/// groups of different currencies.
+pub(crate) fn validate_currency(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    filters: &SdkEventFilters,
+    options: &SdkEventQueryOptions,
+) -> MetricsResult<()> {
+    if !sums_amounts(metric) || filters.currency.len() == 1 {
+        return Ok(());
+    }
+    let reason = if !dimensions.contains(&SdkEventDimensions::Currency) {
+        "the buckets are neither grouped by currency nor filtered on a single one"
+    } else if options.subtotals {
+        "the subtotals roll up the currency"
+    } else if options.top_groups.is_some() {
+        "the groups after the top groups are merged across currencies"
+    } else {
+        return Ok(());
+    };
+    Err(report!(MetricsError::MixedCurrencies(reason.to_string())))
+        .attach_printable_lazy(|| format!("{metric} would add up amounts of several currencies"))
+}
+
+/// Fails with [`MetricsError::InvalidComputedDimension`] if the computed dimension of `options`
+/// isn't a valid [`ComputedDimension`], or is requested along options or for a metric which
+/// can't group by it
+pub(crate) fn validate_computed_dimension(
+    metric: &SdkEventMetrics,
+    options: &SdkEventQueryOptions,
+) -> MetricsResult<()> {
+    let Some(expression) = &options.computed_dimension else {
+        return Ok(());
+    };
+    ComputedDimension::parse(expression)
+        .map_err(|error| report!(MetricsError::InvalidComputedDimension(error.to_string())))?;
+    match unsupported_grouping(metric, options) {
+        Some(reason) => Err(report!(MetricsError::InvalidComputedDimension(format!(
+            "{reason} with it"
+        )))),
+        None => Ok(()),
+    }
+}
+
+/// Fails with [`MetricsError::UnsupportedSubtotals`] if subtotals are asked for along options or
+/// for a metric which can't return them
+pub(crate) fn validate_subtotals(
+    metric: &SdkEventMetrics,
+    options: &SdkEventQueryOptions,
+) -> MetricsResult<()> {
+    match unsupported_grouping(metric, options).filter(|_| options.subtotals) {
+        Some(reason) => Err(report!(MetricsError::UnsupportedSubtotals(reason))),
+        None => Ok(()),
+    }
+}
+
+/// Why the groups of `metric` can't be grouped any further than by their dimensions with
+/// `options`, e.g. by a computed dimension or into subtotals
+fn unsupported_grouping(
+    metric: &SdkEventMetrics,
+    options: &SdkEventQueryOptions,
+) -> Option<String> {
+    match metric {
+        // Grouped by session in a subquery, which only has the dimensions
+        SdkEventMetrics::PaymentRetryCount
+        | SdkEventMetrics::SessionDurationSeconds
+        | SdkEventMetrics::TimeToFirstRender => Some(format!("{metric} can't be grouped")),
+        _ if options.pagination.is_some() => Some("a page can't be picked".to_string()),
+        _ if options.top_groups.is_some() => Some("the top groups can't be picked".to_string()),
+        _ => None,
+    }
+}
+
+/// Marks the subtotals of `buckets` with their level, and clears the dimensions they add up: the
+/// last `level` of `dimensions`, in the order they were rolled up
+fn label_subtotals(
+    buckets: &mut [(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+    dimensions: &[SdkEventDimensions],
+) -> MetricsResult<()> {
+    for (id, row) in buckets.iter_mut() {
+        let level = row
+            .subtotal_level
+            .filter(|level| *level > 0)
+            .map(u32::try_from)
+            .transpose()
+            .change_context(MetricsError::PostProcessingFailure)
+            .attach_printable("Invalid subtotal level")?;
+        let rolled_up = level.map_or(0, |level| usize::try_from(level).unwrap_or(usize::MAX));
+        for dim in dimensions.iter().rev().take(rolled_up) {
+            *id.dimension_mut(dim) = None;
+        }
+        id.subtotal_level = level;
+    }
+    Ok(())
+}
+
+/// Drops the rows of a query over `max_rows`, which the data source returns at most one of, see
+/// [`QueryBuilder::set_max_rows`]. Returns whether any row was dropped.
+pub(crate) fn truncate_rows<R>(rows: &mut Vec<R>, max_rows: Option<usize>) -> bool {
+    match max_rows {
+        Some(max_rows) if rows.len() > max_rows => {
+            rows.truncate(max_rows);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// `time_range` as it's queried: fails with [`MetricsError::InvalidTimeRange`] if it ends before
+/// it starts, and a range spanning more than `max_span` is clamped to its last `max_span`. A range
+/// without an end ends now, so that its queries are bounded on both sides, see [`check_scoped`].
+pub(crate) fn validate_time_range(
+    time_range: &TimeRange,
+    max_span: Option<time::Duration>,
+) -> MetricsResult<TimeRange> {
+    let end_time = time_range.end_time.unwrap_or_else(date_time::now);
+    if time_range.start_time > end_time {
+        return Err(report!(MetricsError::InvalidTimeRange(format!(
+            "start time {} is after end time {end_time}",
+            time_range.start_time
+        ))));
+    }
+    match max_span.filter(|max_span| end_time - time_range.start_time > *max_span) {
+        Some(max_span) => {
+            logger::warn!(?time_range, ?max_span, "Clamping sdk event time range");
+            Ok(TimeRange {
+                start_time: end_time - max_span,
+                end_time: Some(end_time),
+            })
+        }
+        None => Ok(TimeRange {
+            start_time: time_range.start_time,
+            end_time: Some(end_time),
+        }),
+    }
+}
+
+/// Order of the buckets of a metric: by time bucket, then by the value of each of `dimensions` in
+/// the order they're listed, and last by the value of the computed dimension and by subtotal level.
+/// Values are compared byte-wise, as clickhouse sorts them, and a bucket without a value for a
+/// dimension sorts after the ones with a value.
+pub fn compare_buckets(
+    left: &SdkEventMetricsBucketIdentifier,
+    right: &SdkEventMetricsBucketIdentifier,
+    dimensions: &[SdkEventDimensions],
+) -> Ordering {
+    let nulls_last = |left: Option<&str>, right: Option<&str>| match (left, right) {
+        (Some(left), Some(right)) => left.cmp(right),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+    std::iter::once(nulls_last(
+        left.time_bucket.as_deref(),
+        right.time_bucket.as_deref(),
+    ))
+    .chain(
+        dimensions
+            .iter()
+            .map(|dim| nulls_last(left.dimension(dim), right.dimension(dim))),
+    )
+    .chain(std::iter::once(nulls_last(
+        left.computed_dimension.as_deref(),
+        right.computed_dimension.as_deref(),
+    )))
+    .chain(std::iter::once(
+        left.subtotal_level.cmp(&right.subtotal_level),
+    ))
+    .find(|ordering| ordering.is_ne())
+    .unwrap_or(Ordering::Equal)
+}
+
+pub type SdkEventMetricStream =
+    BoxStream<'static, MetricsResult<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>>;
+
+/// Buckets of a metric's stream, post-processed one at a time as they're handed out
+struct BucketStream {
+    buckets: Peekable<SdkEventMetricStream>,
+    metric: SdkEventMetrics,
+    options: SdkEventQueryOptions,
+    source: &'static str,
+    started: Instant,
+    handed_out: usize,
+    /// Whether the query has been recorded, after which no more buckets are handed out
+    done: bool,
+    _permit: Option<MerchantQueryPermit>,
+}
+
+impl BucketStream {
+    /// The data source returns at most one row over the cap, see [`truncate_rows`]. A bucket
+    /// can't be flagged once it has been handed out, so of a truncated stream only the last
+    /// bucket is flagged as [`SdkEventMetricRow::truncated`].
+    async fn next_bucket(
+        mut self,
+    ) -> Option<(
+        MetricsResult<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+        Self,
+    )> {
+        if self.done {
+            return None;
+        }
+        if self
+            .options
+            .max_rows
+            .is_some_and(|max_rows| self.handed_out >= max_rows)
+        {
+            self.finish(&Ok(()));
+            return None;
+        }
+        let Some(bucket) = self.buckets.next().await else {
+            self.finish(&Ok(()));
+            return None;
+        };
+        self.handed_out += 1;
+        let truncated = self.options.max_rows == Some(self.handed_out)
+            && Pin::new(&mut self.buckets).peek().await.is_some();
+        let bucket = bucket.and_then(|(mut id, mut row)| {
+            row.truncated = truncated;
+            if self.options.computed_dimension.is_some() {
+                id.computed_dimension = row.computed_dimension.clone();
+            }
+            let mut bucket = [(id, row)];
+            self.options
+                .scale_sampled_buckets(&self.metric, &mut bucket)?;
+            let [bucket] = bucket;
+            Ok(bucket)
+        });
+        if truncated {
+            logger::warn!(
+                sdk_event_metric = %self.metric,
+                max_rows = ?self.options.max_rows,
+                "Truncated the streamed buckets of an sdk event metric"
+            );
+        }
+        if truncated || bucket.is_err() {
+            self.finish(&bucket);
+        }
+        Some((bucket, self))
+    }
+
+    fn finish<R>(&mut self, result: &MetricsResult<R>) {
+        self.done = true;
+        record_sdk_event_query(&self.metric, self.source, result, self.started.elapsed());
+    }
 }
 
 #[async_trait::async_trait]
@@ -82,16 +1233,89 @@ where
     Aggregate<&'static str>: ToSql<T>,
     Window<&'static str>: ToSql<T>,
 {
+    /// Traced in a debug span carrying the metric, dimensions and data source, in which the
+    /// fingerprint of the query, the number of buckets it returned and how long it took are
+    /// recorded. A span without a subscriber is disabled, and none of its fields are computed.
+    #[instrument(
+        level = "debug",
+        name = "analytics_sdk_event_metrics_load",
+        skip_all,
+        fields(
+            sdk_event_metric = %self,
+            dimensions = ?dimensions,
+            source = pool.source_name(),
+            query_fingerprint,
+            rows,
+            duration_ms,
+        )
+    )]
     async fn load_metrics(
         &self,
         dimensions: &[SdkEventDimensions],
         publishable_key: &str,
         filters: &SdkEventFilters,
         granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
         time_range: &TimeRange,
         pool: &T,
     ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
-        match self {
+        validate_dimensions(self, dimensions)?;
+        validate_filters(self, filters)?;
+        validate_computed_dimension(self, options)?;
+        validate_subtotals(self, options)?;
+        validate_currency(self, dimensions, filters, options)?;
+        // Percentiles over the whole range are loaded without time buckets, a percentile of the
+        // percentiles of each time bucket would be another value
+        let granularity = &options.metric_granularity(self, granularity);
+        let open = time_range.end_time.is_none();
+        let time_range = &validate_time_range(time_range, pool.max_time_range())?;
+        let cache = pool
+            .metrics_cache()
+            .filter(|_| !options.bypass_cache)
+            .and_then(|cache| {
+                CacheKey::new(
+                    self,
+                    dimensions,
+                    publishable_key,
+                    filters,
+                    granularity,
+                    options,
+                    time_range,
+                    open,
+                )
+                .map(|key| (cache, key))
+            });
+        if let Some(buckets) = cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+            tracing::Span::current().record("rows", buckets.len());
+            return Ok(buckets);
+        }
+        if let Some(limit) = options.top_groups.filter(|_| !dimensions.is_empty()) {
+            // Runs a query per part of the result, which wait for a permit of their own
+            let buckets = top_groups::load_top_groups(
+                self,
+                limit,
+                dimensions,
+                publishable_key,
+                filters,
+                granularity,
+                options,
+                time_range,
+                pool,
+            )
+            .await
+            .map_err(name_query_building_error)?;
+            tracing::Span::current().record("rows", buckets.len());
+            if let Some((cache, key)) = cache {
+                cache.insert(key, &buckets);
+            }
+            return Ok(buckets);
+        }
+        // The top groups are picked by queries of their own, which are capped each
+        let options = &options.with_max_rows(pool.max_result_rows());
+        // Held until the buckets are loaded
+        let _permit = acquire_merchant_query_permit(pool, publishable_key).await?;
+        let started = Instant::now();
+        let buckets = match self {
             Self::PaymentAttempts => {
                 PaymentAttempts
                     .load_metrics(
@@ -99,6 +1323,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -111,6 +1336,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -123,6 +1349,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -135,6 +1362,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -147,6 +1375,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -159,6 +1388,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -171,6 +1401,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -183,6 +1414,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -195,6 +1427,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -207,6 +1440,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -219,6 +1453,33 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsMethodSuccessRate => {
+                ThreeDsMethodSuccessRate
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::AbandonedSessionsCount => {
+                AbandonedSessionsCount
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -231,6 +1492,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -243,6 +1505,7 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
@@ -255,11 +1518,516 @@ where
                         publishable_key,
                         filters,
                         granularity,
+                        options,
                         time_range,
                         pool,
                     )
                     .await
             }
-        }
-    }
-}
+            Self::SdkErrorEncounteredCount => {
+                SdkErrorEncounteredCount
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::SessionDurationSeconds => {
+                SessionDurationSeconds
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::FunnelConversionRate => {
+                FunnelConversionRate
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::UniqueSessionsCount => {
+                UniqueSessionsCount
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentSuccessRate => {
+                PaymentSuccessRate
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentRetryCount => {
+                PaymentRetryCount
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsFlowBreakdown => {
+                ThreeDsFlowBreakdown
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::SdkBounceRate => {
+                SdkBounceRate
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentExperienceBreakdown => {
+                PaymentExperienceBreakdown
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::TimeToFirstRender => {
+                TimeToFirstRender
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::FunnelWaterfall => {
+                FunnelWaterfall
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::SdkLoadFailureCount => {
+                SdkLoadFailureCount
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::AveragePaymentMethodsDisplayed => {
+                AveragePaymentMethodsDisplayed
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentAttemptedAmount => {
+                PaymentAttemptedAmount
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::LatencyBreakdown => {
+                LatencyBreakdown
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+        }
+        .map_err(name_query_building_error);
+        let elapsed = started.elapsed();
+        record_sdk_event_query(self, pool.source_name(), &buckets, elapsed);
+        let mut buckets = buckets?;
+        if truncate_rows(&mut buckets, options.max_rows) {
+            logger::warn!(
+                sdk_event_metric = %self,
+                max_rows = ?options.max_rows,
+                "Truncated the buckets of an sdk event metric"
+            );
+            for (_, row) in buckets.iter_mut() {
+                row.truncated = true;
+            }
+        }
+        let span = tracing::Span::current();
+        span.record("rows", buckets.len());
+        span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+        if options.computed_dimension.is_some() {
+            for (id, row) in buckets.iter_mut() {
+                id.computed_dimension = row.computed_dimension.clone();
+            }
+        }
+        let dimensions = match self {
+            Self::PaymentExperienceBreakdown => {
+                payment_experience_breakdown::breakdown_dimensions(dimensions)
+            }
+            _ => dimensions.to_vec(),
+        };
+        if options.subtotals {
+            label_subtotals(&mut buckets, &dimensions)?;
+        }
+        let mut buckets =
+            options.fill_time_series_gaps(buckets, &dimensions, granularity, time_range)?;
+        options.scale_sampled_buckets(self, &mut buckets)?;
+        if let Self::FunnelWaterfall = self {
+            funnel_waterfall::label_stages(options, &mut buckets)?;
+        }
+        options.sort_buckets(&mut buckets, &dimensions);
+        if let Some((cache, key)) = cache {
+            cache.insert(key, &buckets);
+        }
+        Ok(buckets)
+    }
+
+    /// Only the count metrics and the funnel conversion rate are streamed. The buckets are
+    /// capped, flagged and scaled like loaded buckets are, but as they're handed out in the order
+    /// of the query, gaps can't be filled nor the top groups picked or subtotals labelled.
+    async fn load_metrics_stream(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<SdkEventMetricStream> {
+        validate_dimensions(self, dimensions)?;
+        validate_filters(self, filters)?;
+        validate_computed_dimension(self, options)?;
+        validate_subtotals(self, options)?;
+        validate_currency(self, dimensions, filters, options)?;
+        let time_range = &validate_time_range(time_range, pool.max_time_range())?;
+        if options.fill_gaps || options.top_groups.is_some() || options.subtotals {
+            return Err(report!(MetricsError::UnsupportedMetric(self.to_string())))
+                .attach_printable(
+                    "Gaps, top groups and subtotals need every bucket to be loaded first",
+                );
+        }
+        let options = &options.with_max_rows(pool.max_result_rows());
+        // Held until the stream ends
+        let permit = acquire_merchant_query_permit(pool, publishable_key).await?;
+        let started = Instant::now();
+        let buckets = match self {
+            Self::PaymentAttempts => {
+                PaymentAttempts
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentMethodsCallCount => {
+                PaymentMethodsCallCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::SdkRenderedCount => {
+                SdkRenderedCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::SdkInitiatedCount => {
+                SdkInitiatedCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentMethodSelectedCount => {
+                PaymentMethodSelectedCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::PaymentDataFilledCount => {
+                PaymentDataFilledCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsMethodSkippedCount => {
+                ThreeDsMethodSkippedCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsMethodInvokedCount => {
+                ThreeDsMethodInvokedCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsMethodSuccessfulCount => {
+                ThreeDsMethodSuccessfulCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsMethodUnsuccessfulCount => {
+                ThreeDsMethodUnsuccessfulCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::AuthenticationUnsuccessfulCount => {
+                AuthenticationUnsuccessfulCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsChallengeFlowCount => {
+                ThreeDsChallengeFlowCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::ThreeDsFrictionlessFlowCount => {
+                ThreeDsFrictionlessFlowCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::SdkErrorEncounteredCount => {
+                SdkErrorEncounteredCount
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::FunnelConversionRate => {
+                FunnelConversionRate
+                    .load_metrics_stream(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+            }
+            Self::AveragePaymentTime
+            | Self::ThreeDsMethodSuccessRate
+            | Self::AbandonedSessionsCount
+            | Self::SessionDurationSeconds
+            | Self::UniqueSessionsCount
+            | Self::PaymentSuccessRate
+            | Self::PaymentRetryCount
+            | Self::ThreeDsFlowBreakdown
+            | Self::SdkBounceRate
+            | Self::PaymentExperienceBreakdown
+            | Self::TimeToFirstRender
+            | Self::FunnelWaterfall
+            | Self::SdkLoadFailureCount
+            | Self::AveragePaymentMethodsDisplayed
+            | Self::PaymentAttemptedAmount
+            | Self::LatencyBreakdown => {
+                return Err(report!(MetricsError::UnsupportedMetric(self.to_string())))
+                    .attach_printable(
+                        "Only the count metrics and the funnel conversion rate are streamed",
+                    );
+            }
+        }
+        .map_err(name_query_building_error);
+        if buckets.is_err() {
+            record_sdk_event_query(self, pool.source_name(), &buckets, started.elapsed());
+        }
+        let stream = BucketStream {
+            buckets: buckets?.peekable(),
+            metric: self.clone(),
+            options: options.clone(),
+            source: pool.source_name(),
+            started,
+            handed_out: 0,
+            done: false,
+            _permit: permit,
+        };
+        Ok(futures::stream::unfold(stream, BucketStream::next_bucket).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,225 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier, SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::ResultExt;
+use time::PrimitiveDateTime;
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Sessions which were abandoned: the sdk was initiated in them but no payment was attempted,
+/// the payment id identifies the sdk session. `count` holds the number of sessions, which are
+/// bucketed by the time they were initiated so that a session isn't split across buckets.
+///
+/// The abandonment reason and the payment method are per session here rather than per event:
+/// a session has the reason any of its events carries, and the payment method last picked in
+/// it. Their filters match the sessions, so that filtering on a payment method keeps the events
+/// of its sessions sent before the method was picked, the initiation among them. The other
+/// filters match the events.
+#[derive(Default)]
+pub(super) struct AbandonedSessionsCount;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for AbandonedSessionsCount
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        let dimensions = dimensions.to_vec();
+        let abandonment_reason =
+            format!("any({})", T::get_json_string("value", "abandonment_reason"));
+
+        // The inner query keeps the abandoned sessions, the outer one counts the sessions of each
+        // bucket
+        for dim in dimensions.iter() {
+            if *dim == SdkEventDimensions::AbandonmentReason {
+                query_builder
+                    .add_select_column(format!("{abandonment_reason} as {dim}"))
+                    .switch()?;
+            } else {
+                query_builder
+                    .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                    .switch()?;
+            }
+            query_builder
+                .add_outer_select_column(dim.as_ref())
+                .switch()?;
+        }
+
+        query_builder
+            .add_outer_select_column(Aggregate::Count {
+                field: None,
+                alias: Some("count"),
+            })
+            .switch()?;
+
+        // The count threshold would apply to the events of a single session here, only the time
+        // zone, the collections read, the row cap and the pagination, which pages the outer query,
+        // are taken from the options
+        if let Some(time_zone) = &options.time_zone {
+            query_builder.set_time_zone(time_zone).switch()?;
+        }
+        options.set_source_options(&mut query_builder).switch()?;
+        options
+            .set_pagination(&mut query_builder, &dimensions, granularity)
+            .switch()?;
+
+        if let Some(granularity) = granularity.as_ref() {
+            query_builder
+                .add_first_granularity_in_mins(granularity)
+                .switch()?;
+            query_builder
+                .add_outer_select_column("time_bucket")
+                .switch()?;
+        }
+
+        SdkEventFilters {
+            payment_method: Vec::new(),
+            abandonment_reason: Vec::new(),
+            ..filters.clone()
+        }
+        .set_filter_clause(&mut query_builder)
+        .switch()?;
+
+        query_builder
+            .add_filter_clause("merchant_id", publishable_key)
+            .switch()?;
+
+        time_range
+            .set_filter_clause(&mut query_builder)
+            .attach_printable("Error filtering time range")
+            .switch()?;
+
+        query_builder
+            .add_group_by_clause("payment_id")
+            .attach_printable("Error grouping by session")
+            .switch()?;
+
+        for dim in dimensions
+            .iter()
+            .filter(|dim| **dim != SdkEventDimensions::AbandonmentReason)
+        {
+            query_builder
+                .add_group_by_clause(options.dimension(dim))
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+        for dim in dimensions.iter() {
+            query_builder
+                .add_outer_group_by_clause(dim.as_ref())
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+
+        if let Some(_granularity) = granularity.as_ref() {
+            query_builder
+                .add_outer_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+        }
+
+        query_builder
+            .add_custom_having_clause(
+                format!(
+                    "countIf(event_name = '{}')",
+                    SdkEventNames::OrcaElementsCalled
+                ),
+                "0",
+                FilterTypes::Gt,
+            )
+            .attach_printable("Error keeping the initiated sessions")
+            .switch()?;
+        query_builder
+            .add_custom_having_clause(
+                format!("countIf(event_name = '{}')", SdkEventNames::PaymentAttempt),
+                "1",
+                FilterTypes::Lt,
+            )
+            .attach_printable("Error keeping the sessions without an attempt")
+            .switch()?;
+
+        if !filters.payment_method.is_empty() {
+            query_builder
+                .add_having_in_range_clause(
+                    "argMaxIf(payment_method, created_at_precise, isNotNull(payment_method))",
+                    &filters.payment_method,
+                )
+                .attach_printable("Error adding payment method filter")
+                .switch()?;
+        }
+        if !filters.abandonment_reason.is_empty() {
+            query_builder
+                .add_having_in_range_clause(
+                    abandonment_reason.as_str(),
+                    &filters.abandonment_reason,
+                )
+                .attach_printable("Error adding abandonment reason filter")
+                .switch()?;
+        }
+
+        super::check_scoped(&query_builder)?;
+
+        query_builder
+            .execute_query::<SdkEventMetricRow, _>(pool)
+            .await
+            .change_context(MetricsError::QueryBuildingError)?
+            .switch()?
+            .into_iter()
+            .map(|i| {
+                Ok((
+                    SdkEventMetricsBucketIdentifier::new(
+                        i.payment_method.clone(),
+                        i.platform.clone(),
+                        i.browser_name.clone(),
+                        i.source.clone(),
+                        i.component.clone(),
+                        i.payment_experience.clone(),
+                        i.sdk_version.clone(),
+                        i.device_type.clone(),
+                        i.os.clone(),
+                        i.country.clone(),
+                        i.connector.clone(),
+                        i.event_name.clone(),
+                        i.payment_method_type.clone(),
+                        i.day_of_week.clone(),
+                        i.hour_of_day.clone(),
+                        i.authentication_error_reason.clone(),
+                        i.field_name.clone(),
+                        i.customer_segment.clone(),
+                        i.cdn_region.clone(),
+                        i.currency.clone(),
+                        i.abandonment_reason.clone(),
+                        i.time_bucket.clone(),
+                    ),
+                    i,
+                ))
+            })
+            .collect::<error_stack::Result<
+                Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+                crate::query::PostProcessingError,
+            >>()
+            .change_context(MetricsError::PostProcessingFailure)
+    }
+}
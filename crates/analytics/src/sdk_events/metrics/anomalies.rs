@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use api_models::analytics::sdk_events::{SdkEventMetrics, SdkEventMetricsBucketIdentifier};
+
+use super::{compare::metric_value, SdkEventMetricRow};
+
+/// How the buckets of a time series are compared to the buckets before them
+#[derive(Clone, Copy, Debug)]
+pub struct AnomalyDetection {
+    /// Number of preceding buckets of the same group the baseline of a bucket is taken over
+    pub window: u32,
+    /// Standard deviations from the baseline mean beyond which a bucket is an anomaly
+    pub threshold: f64,
+}
+
+#[derive(Debug)]
+pub struct SdkEventAnomalyBucket {
+    pub id: SdkEventMetricsBucketIdentifier,
+    pub row: SdkEventMetricRow,
+    /// The value of the bucket the baseline is compared to, as accumulated for the response
+    pub value: Option<f64>,
+    /// Mean of the values of the preceding buckets, `None` while there aren't `window` of them
+    /// or if none of them has a value
+    pub baseline_mean: Option<f64>,
+    /// Population standard deviation of the same values
+    pub baseline_std_dev: Option<f64>,
+    /// Whether the value is further than `threshold` standard deviations from the baseline mean.
+    /// A bucket without a value or a baseline is never an anomaly, one differing at all from a
+    /// baseline which doesn't vary is.
+    pub is_anomaly: bool,
+}
+
+/// Flags the buckets of a time series loaded for `metric` whose value deviates sharply from the
+/// buckets of the same group before them. Buckets are compared in time order within their group
+/// and returned in the order they were given, the buckets of a group are expected to be
+/// consecutive time buckets, which [`super::SdkEventQueryOptions::fill_gaps`] ensures.
+pub fn flag_anomalies(
+    metric: &SdkEventMetrics,
+    buckets: Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+    detection: &AnomalyDetection,
+) -> Vec<SdkEventAnomalyBucket> {
+    let window = usize::try_from(detection.window).unwrap_or(usize::MAX);
+    let mut groups = HashMap::<_, Vec<usize>>::new();
+    for (position, (id, _)) in buckets.iter().enumerate() {
+        let group = SdkEventMetricsBucketIdentifier {
+            time_bucket: None,
+            ..id.clone()
+        };
+        groups.entry(group).or_default().push(position);
+    }
+
+    let values = buckets
+        .iter()
+        .map(|(_, row)| metric_value(metric, row))
+        .collect::<Vec<_>>();
+    let mut baselines = vec![None; buckets.len()];
+    for positions in groups.values_mut() {
+        positions.sort_by_key(|position| {
+            buckets
+                .get(*position)
+                .and_then(|(id, _)| id.time_bucket.clone())
+        });
+        for (index, position) in positions.iter().enumerate() {
+            let Some(start) = index.checked_sub(window) else {
+                continue;
+            };
+            let preceding = positions
+                .iter()
+                .skip(start)
+                .take(window)
+                .filter_map(|position| values.get(*position).copied().flatten())
+                .collect::<Vec<_>>();
+            if let Some(baseline) = baselines.get_mut(*position) {
+                *baseline = mean_and_std_dev(&preceding);
+            }
+        }
+    }
+
+    buckets
+        .into_iter()
+        .zip(values)
+        .zip(baselines)
+        .map(|(((id, row), value), baseline)| {
+            let is_anomaly = value.zip(baseline).is_some_and(|(value, (mean, std_dev))| {
+                (value - mean).abs() > detection.threshold * std_dev
+            });
+            SdkEventAnomalyBucket {
+                id,
+                row,
+                value,
+                baseline_mean: baseline.map(|(mean, _)| mean),
+                baseline_std_dev: baseline.map(|(_, std_dev)| std_dev),
+                is_anomaly,
+            }
+        })
+        .collect()
+}
+
+fn mean_and_std_dev(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let count = f64::from(u32::try_from(values.len()).ok()?);
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    Some((mean, variance.sqrt()))
+}
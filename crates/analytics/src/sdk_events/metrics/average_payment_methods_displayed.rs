@@ -0,0 +1,156 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier, SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::ResultExt;
+use time::PrimitiveDateTime;
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{
+        Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, Rounded, ToSql, Window,
+    },
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Key of the value of a render event holding the number of payment methods it displayed
+const PAYMENT_METHODS_DISPLAYED_KEY: &str = "payment_methods_count";
+
+/// Payment methods displayed per render of the sdk. Render events carry the number of payment
+/// methods they displayed, the ones logged without it are left out of the average.
+#[derive(Default)]
+pub(super) struct AveragePaymentMethodsDisplayed;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for AveragePaymentMethodsDisplayed
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        let dimensions = dimensions.to_vec();
+        let displayed = T::get_json_number("value", PAYMENT_METHODS_DISPLAYED_KEY);
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                .switch()?;
+        }
+
+        query_builder
+            .add_select_column(format!("count({displayed}) as count"))
+            .switch()?;
+
+        query_builder
+            .add_select_column(Rounded {
+                column: format!("sum({displayed})"),
+                alias: "total",
+                decimal_places: options.decimal_places,
+            })
+            .switch()?;
+
+        options.set_query_options(&mut query_builder).switch()?;
+        options
+            .set_pagination(&mut query_builder, &dimensions, granularity)
+            .switch()?;
+
+        if let Some(granularity) = granularity.as_ref() {
+            query_builder
+                .add_granularity_in_mins(granularity)
+                .switch()?;
+        }
+
+        filters.set_filter_clause(&mut query_builder).switch()?;
+
+        query_builder
+            .add_filter_clause("merchant_id", publishable_key)
+            .switch()?;
+
+        query_builder
+            .add_filter_clause("event_name", SdkEventNames::AppRendered)
+            .switch()?;
+
+        query_builder
+            .add_custom_filter_clause(displayed, "NULL", FilterTypes::IsNotNull)
+            .switch()?;
+
+        time_range
+            .set_filter_clause(&mut query_builder)
+            .attach_printable("Error filtering time range")
+            .switch()?;
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_rollup_group_by_clause(options.dimension(dim))
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+
+        if let Some(_granularity) = granularity.as_ref() {
+            query_builder
+                .add_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+        }
+
+        super::check_scoped(&query_builder)?;
+
+        query_builder
+            .execute_query::<SdkEventMetricRow, _>(pool)
+            .await
+            .change_context(MetricsError::QueryBuildingError)?
+            .switch()?
+            .into_iter()
+            .map(|i| {
+                Ok((
+                    SdkEventMetricsBucketIdentifier::new(
+                        i.payment_method.clone(),
+                        i.platform.clone(),
+                        i.browser_name.clone(),
+                        i.source.clone(),
+                        i.component.clone(),
+                        i.payment_experience.clone(),
+                        i.sdk_version.clone(),
+                        i.device_type.clone(),
+                        i.os.clone(),
+                        i.country.clone(),
+                        i.connector.clone(),
+                        i.event_name.clone(),
+                        i.payment_method_type.clone(),
+                        i.day_of_week.clone(),
+                        i.hour_of_day.clone(),
+                        i.authentication_error_reason.clone(),
+                        i.field_name.clone(),
+                        i.customer_segment.clone(),
+                        i.cdn_region.clone(),
+                        i.currency.clone(),
+                        i.abandonment_reason.clone(),
+                        i.time_bucket.clone(),
+                    ),
+                    i,
+                ))
+            })
+            .collect::<error_stack::Result<
+                Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+                crate::query::PostProcessingError,
+            >>()
+            .change_context(MetricsError::PostProcessingFailure)
+    }
+}
@@ -10,7 +10,9 @@ use time::PrimitiveDateTime;
 
 use super::SdkEventMetricRow;
 use crate::{
-    query::{Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
+    query::{
+        Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, Rounded, ToSql, Window,
+    },
     types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
 };
 
@@ -33,6 +35,7 @@ where
         publishable_key: &str,
         filters: &SdkEventFilters,
         granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
         time_range: &TimeRange,
         pool: &T,
     ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
@@ -40,7 +43,9 @@ where
         let dimensions = dimensions.to_vec();
 
         for dim in dimensions.iter() {
-            query_builder.add_select_column(dim).switch()?;
+            query_builder
+                .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                .switch()?;
         }
 
         query_builder
@@ -51,12 +56,63 @@ where
             .switch()?;
 
         query_builder
-            .add_select_column(Aggregate::Sum {
+            .add_select_column(Rounded {
+                column: Aggregate::Sum {
+                    field: "latency",
+                    alias: None,
+                },
+                alias: "total",
+                decimal_places: options.decimal_places,
+            })
+            .switch()?;
+
+        query_builder
+            .add_select_column(Aggregate::Min {
+                field: "latency",
+                alias: Some("min"),
+            })
+            .switch()?;
+
+        query_builder
+            .add_select_column(Aggregate::Max {
                 field: "latency",
-                alias: Some("total"),
+                alias: Some("max"),
             })
             .switch()?;
 
+        query_builder
+            .add_select_column(Aggregate::Quantiles {
+                field: "latency",
+                quantiles: super::PERCENTILES,
+            })
+            .switch()?;
+
+        query_builder
+            .add_select_column(Aggregate::MedianTDigest {
+                field: "latency",
+                alias: Some("median"),
+            })
+            .switch()?;
+
+        query_builder
+            .add_select_column(Aggregate::StdDev {
+                field: "latency",
+                alias: Some("stddev"),
+            })
+            .switch()?;
+
+        query_builder
+            .add_select_column(Aggregate::Variance {
+                field: "latency",
+                alias: Some("variance"),
+            })
+            .switch()?;
+
+        options.set_query_options(&mut query_builder).switch()?;
+        options
+            .set_pagination(&mut query_builder, &dimensions, granularity)
+            .switch()?;
+
         if let Some(granularity) = granularity.as_ref() {
             query_builder
                 .add_granularity_in_mins(granularity)
@@ -88,7 +144,7 @@ where
 
         for dim in dimensions.iter() {
             query_builder
-                .add_group_by_clause(dim)
+                .add_rollup_group_by_clause(options.dimension(dim))
                 .attach_printable("Error grouping by dimensions")
                 .switch()?;
         }
@@ -100,11 +156,13 @@ where
                 .switch()?;
         }
 
+        super::check_scoped(&query_builder)?;
+
         query_builder
             .execute_query::<SdkEventMetricRow, _>(pool)
             .await
             .change_context(MetricsError::QueryBuildingError)?
-            .change_context(MetricsError::QueryExecutionFailure)?
+            .switch()?
             .into_iter()
             .map(|i| {
                 Ok((
@@ -115,6 +173,21 @@ where
                         i.source.clone(),
                         i.component.clone(),
                         i.payment_experience.clone(),
+                        i.sdk_version.clone(),
+                        i.device_type.clone(),
+                        i.os.clone(),
+                        i.country.clone(),
+                        i.connector.clone(),
+                        i.event_name.clone(),
+                        i.payment_method_type.clone(),
+                        i.day_of_week.clone(),
+                        i.hour_of_day.clone(),
+                        i.authentication_error_reason.clone(),
+                        i.field_name.clone(),
+                        i.customer_segment.clone(),
+                        i.cdn_region.clone(),
+                        i.currency.clone(),
+                        i.abandonment_reason.clone(),
                         i.time_bucket.clone(),
                     ),
                     i,
@@ -0,0 +1,408 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+        SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::{report, ResultExt};
+use time::PrimitiveDateTime;
+
+use super::{SdkEventMetric, SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventQueryOptions};
+use crate::{
+    query::{
+        filter_type_to_sql, Aggregate, EventCountIf, FilterTypes, GroupByClause, QueryBuilder,
+        QueryFilter, ToSql, Window,
+    },
+    types::{
+        acquire_merchant_query_permit, AnalyticsCollection, AnalyticsDataSource, MetricsError,
+        MetricsResult,
+    },
+};
+
+/// Row of a batched query, the count of every batched metric is in a column named after it
+#[derive(Debug, serde::Deserialize)]
+#[serde(transparent)]
+pub struct SdkEventBatchRow(serde_json::Map<String, serde_json::Value>);
+
+impl SdkEventBatchRow {
+    /// The row the query of `metric` alone would have returned, `None` if the bucket has no
+    /// events for it
+    fn metric_row(
+        &self,
+        metric: &SdkEventMetrics,
+        batched: &[SdkEventMetrics],
+    ) -> MetricsResult<Option<SdkEventMetricRow>> {
+        let count = self.0.get(metric.as_ref()).cloned().unwrap_or_default();
+        if count.as_i64() == Some(0) {
+            return Ok(None);
+        }
+        let mut row = self
+            .0
+            .iter()
+            .filter(|(column, _)| !batched.iter().any(|metric| metric.as_ref() == *column))
+            .map(|(column, value)| (column.clone(), value.clone()))
+            .collect::<serde_json::Map<_, _>>();
+        row.insert("count".to_string(), count);
+        serde_json::from_value(serde_json::Value::Object(row))
+            .change_context(MetricsError::PostProcessingFailure)
+            .attach_printable_lazy(|| format!("Failed to split batched row for {metric}"))
+            .map(Some)
+    }
+}
+
+/// Conditions an event has to match to be counted by `metric`, mirroring the filters of the
+/// metric's own query. `None` for metrics which aren't a plain count of events.
+pub(super) fn count_conditions(
+    metric: &SdkEventMetrics,
+) -> Option<Vec<(&'static str, FilterTypes, String)>> {
+    let first_event = || ("first_event", FilterTypes::EqualBool, "1".to_string());
+    let event_name = |name: SdkEventNames| ("event_name", FilterTypes::Equal, name.to_string());
+    let log_type = |log_type: &str| ("log_type", FilterTypes::Equal, log_type.to_string());
+    let category = |category: &str| ("category", FilterTypes::Equal, category.to_string());
+    let value = |value: &str| ("value", FilterTypes::Equal, value.to_string());
+
+    Some(match metric {
+        SdkEventMetrics::PaymentAttempts => {
+            vec![first_event(), event_name(SdkEventNames::PaymentAttempt)]
+        }
+        SdkEventMetrics::PaymentMethodsCallCount => vec![
+            first_event(),
+            event_name(SdkEventNames::PaymentMethodsCall),
+            log_type("INFO"),
+            category("API"),
+        ],
+        SdkEventMetrics::SdkRenderedCount => {
+            vec![first_event(), event_name(SdkEventNames::AppRendered)]
+        }
+        SdkEventMetrics::SdkInitiatedCount => {
+            vec![first_event(), event_name(SdkEventNames::OrcaElementsCalled)]
+        }
+        SdkEventMetrics::PaymentMethodSelectedCount => {
+            vec![
+                first_event(),
+                event_name(SdkEventNames::PaymentMethodChanged),
+            ]
+        }
+        SdkEventMetrics::PaymentDataFilledCount => {
+            vec![first_event(), event_name(SdkEventNames::PaymentDataFilled)]
+        }
+        SdkEventMetrics::ThreeDsMethodInvokedCount => vec![
+            event_name(SdkEventNames::ThreeDsMethod),
+            log_type("INFO"),
+            category("USER_EVENT"),
+            value("Y"),
+        ],
+        SdkEventMetrics::ThreeDsMethodSkippedCount => vec![
+            event_name(SdkEventNames::ThreeDsMethod),
+            log_type("INFO"),
+            category("USER_EVENT"),
+            value("N"),
+        ],
+        SdkEventMetrics::ThreeDsMethodSuccessfulCount => vec![
+            event_name(SdkEventNames::ThreeDsMethodResult),
+            log_type("INFO"),
+            category("USER_EVENT"),
+        ],
+        SdkEventMetrics::ThreeDsMethodUnsuccessfulCount => vec![
+            event_name(SdkEventNames::ThreeDsMethodResult),
+            log_type("ERROR"),
+            category("USER_EVENT"),
+        ],
+        SdkEventMetrics::AuthenticationUnsuccessfulCount => vec![
+            event_name(SdkEventNames::AuthenticationCall),
+            log_type("ERROR"),
+            category("USER_EVENT"),
+        ],
+        SdkEventMetrics::ThreeDsChallengeFlowCount => vec![
+            event_name(SdkEventNames::DisplayThreeDsSdk),
+            log_type("INFO"),
+            category("USER_EVENT"),
+            value("C"),
+        ],
+        SdkEventMetrics::ThreeDsFrictionlessFlowCount => vec![
+            event_name(SdkEventNames::DisplayThreeDsSdk),
+            log_type("INFO"),
+            category("USER_EVENT"),
+            ("value", FilterTypes::NotEqual, "C".to_string()),
+        ],
+        SdkEventMetrics::SdkErrorEncounteredCount => vec![log_type("ERROR")],
+        SdkEventMetrics::AveragePaymentTime
+        | SdkEventMetrics::SessionDurationSeconds
+        | SdkEventMetrics::FunnelConversionRate
+        | SdkEventMetrics::UniqueSessionsCount
+        | SdkEventMetrics::PaymentSuccessRate
+        | SdkEventMetrics::ThreeDsMethodSuccessRate
+        | SdkEventMetrics::PaymentRetryCount
+        | SdkEventMetrics::ThreeDsFlowBreakdown
+        | SdkEventMetrics::SdkBounceRate
+        | SdkEventMetrics::PaymentExperienceBreakdown
+        | SdkEventMetrics::TimeToFirstRender
+        | SdkEventMetrics::FunnelWaterfall
+        | SdkEventMetrics::SdkLoadFailureCount
+        | SdkEventMetrics::AveragePaymentMethodsDisplayed
+        | SdkEventMetrics::PaymentAttemptedAmount
+        | SdkEventMetrics::LatencyBreakdown
+        | SdkEventMetrics::AbandonedSessionsCount => return None,
+    })
+}
+
+/// Loads several metrics over the same dimensions, filters and time range. The metrics which are
+/// a plain count of events are computed by a single query with a count per metric, every other
+/// metric is loaded by its own query. The buckets are returned per metric in the order of
+/// `metrics`.
+#[allow(clippy::too_many_arguments)]
+pub async fn load_metrics_batch<T>(
+    metrics: &[SdkEventMetrics],
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<
+    Vec<(
+        SdkEventMetrics,
+        Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+    )>,
+>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    for metric in metrics {
+        super::validate_dimensions(metric, dimensions)?;
+        super::validate_filters(metric, filters)?;
+    }
+    let time_range = &super::validate_time_range(time_range, pool.max_time_range())?;
+    let options = &options.with_max_rows(pool.max_result_rows());
+
+    // A count threshold, a page, a window or the top groups apply to the groups of one metric,
+    // which a shared query can't tell apart, and its rows have no computed dimension or subtotals.
+    // Its counts are of rows, so deduplicated counts are loaded by each metric's own query.
+    let batched = if options.count_threshold.is_none()
+        && options.pagination.is_none()
+        && options.top_groups.is_none()
+        && options.computed_dimension.is_none()
+        && !options.subtotals
+        && options.moving_average_buckets.is_none()
+        && !options.running_total
+        && !options.percent_of_total
+        && !options.deduplicate
+    {
+        metrics
+            .iter()
+            .enumerate()
+            // A metric asked for twice is still counted once
+            .filter(|(index, metric)| !metrics.iter().take(*index).any(|other| other == *metric))
+            .filter_map(|(_, metric)| Some((metric.clone(), count_conditions(metric)?)))
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+    let batched = if batched.len() > 1 {
+        batched
+    } else {
+        Vec::new()
+    };
+    let batched_metrics = batched
+        .iter()
+        .map(|(metric, _)| metric.clone())
+        .collect::<Vec<_>>();
+
+    let individual = futures::future::try_join_all(
+        metrics
+            .iter()
+            .filter(|metric| !batched_metrics.contains(metric))
+            .map(|metric| async move {
+                metric
+                    .load_metrics(
+                        dimensions,
+                        publishable_key,
+                        filters,
+                        granularity,
+                        options,
+                        time_range,
+                        pool,
+                    )
+                    .await
+                    .map(|buckets| (metric.clone(), buckets))
+            }),
+    )
+    .await?;
+
+    let mut batched_rows = if batched.is_empty() {
+        Vec::new()
+    } else {
+        // The counts are read from a materialized view if every batched metric would read it
+        let view = batched
+            .iter()
+            .map(|(metric, _)| {
+                super::pick_materialized_view(
+                    metric,
+                    dimensions,
+                    filters,
+                    granularity,
+                    options,
+                    time_range,
+                )
+            })
+            .collect::<Option<Vec<_>>>()
+            .and_then(|views| {
+                let first = *views.first()?;
+                views.iter().all(|view| *view == first).then_some(first)
+            })
+            .filter(|_| pool.has_materialized_views());
+        let mut query_builder: QueryBuilder<T> =
+            QueryBuilder::new(view.map_or(AnalyticsCollection::SdkEvents, |view| view.collection));
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                .switch()?;
+        }
+
+        for (metric, conditions) in batched.iter() {
+            let condition = conditions
+                .iter()
+                .map(|(column, filter_type, value)| {
+                    filter_type_to_sql(&column.to_string(), filter_type, value)
+                })
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            query_builder
+                .add_select_column(EventCountIf {
+                    condition: &condition,
+                    count_column: view.map(|view| view.count_column),
+                    alias: metric.as_ref(),
+                })
+                .switch()?;
+        }
+
+        options.set_query_options(&mut query_builder).switch()?;
+        // Batches aren't paged, this only sorts them
+        options
+            .set_pagination(&mut query_builder, dimensions, granularity)
+            .switch()?;
+
+        if let Some(granularity) = granularity.as_ref() {
+            query_builder
+                .add_granularity_in_mins(granularity)
+                .switch()?;
+        }
+
+        filters.set_filter_clause(&mut query_builder).switch()?;
+
+        query_builder
+            .add_filter_clause("merchant_id", publishable_key)
+            .switch()?;
+
+        // Only scan the events one of the batched metrics counts
+        let event_names = batched
+            .iter()
+            .map(|(_, conditions)| {
+                conditions
+                    .iter()
+                    .find(|(column, _, _)| *column == "event_name")
+                    .map(|(_, _, event_name)| event_name.clone())
+            })
+            .collect::<Option<Vec<_>>>();
+        if let Some(event_names) = event_names {
+            query_builder
+                .add_filter_in_range_clause("event_name", &event_names)
+                .switch()?;
+        }
+
+        time_range
+            .set_filter_clause(&mut query_builder)
+            .attach_printable("Error filtering time range")
+            .switch()?;
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_group_by_clause(options.dimension(dim))
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+
+        if let Some(_granularity) = granularity.as_ref() {
+            query_builder
+                .add_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+        }
+
+        super::check_scoped(&query_builder)?;
+        let _permit = acquire_merchant_query_permit(pool, publishable_key).await?;
+        query_builder
+            .execute_query::<SdkEventBatchRow, _>(pool)
+            .await
+            .change_context(MetricsError::QueryBuildingError)?
+            .switch()?
+    };
+
+    // The buckets of every batched metric are cut together, they're all flagged as truncated
+    let truncated = super::truncate_rows(&mut batched_rows, options.max_rows);
+
+    // The individually loaded metrics are in the same order as in `metrics`
+    let mut individual = individual.into_iter();
+    metrics
+        .iter()
+        .map(|metric| {
+            if !batched_metrics.contains(metric) {
+                return individual
+                    .next()
+                    .ok_or_else(|| report!(MetricsError::PostProcessingFailure));
+            }
+            let buckets = batched_rows
+                .iter()
+                .map(|row| row.metric_row(metric, &batched_metrics))
+                .filter_map(Result::transpose)
+                .map(|row| row.map(|row| into_bucket(SdkEventMetricRow { truncated, ..row })))
+                .collect::<MetricsResult<Vec<_>>>()?;
+            let mut buckets =
+                options.fill_time_series_gaps(buckets, dimensions, granularity, time_range)?;
+            options.scale_sampled_buckets(metric, &mut buckets)?;
+            options.sort_buckets(&mut buckets, dimensions);
+            Ok((metric.clone(), buckets))
+        })
+        .collect()
+}
+
+pub(super) fn into_bucket(
+    i: SdkEventMetricRow,
+) -> (SdkEventMetricsBucketIdentifier, SdkEventMetricRow) {
+    (
+        SdkEventMetricsBucketIdentifier::new(
+            i.payment_method.clone(),
+            i.platform.clone(),
+            i.browser_name.clone(),
+            i.source.clone(),
+            i.component.clone(),
+            i.payment_experience.clone(),
+            i.sdk_version.clone(),
+            i.device_type.clone(),
+            i.os.clone(),
+            i.country.clone(),
+            i.connector.clone(),
+            i.event_name.clone(),
+            i.payment_method_type.clone(),
+            i.day_of_week.clone(),
+            i.hour_of_day.clone(),
+            i.authentication_error_reason.clone(),
+            i.field_name.clone(),
+            i.customer_segment.clone(),
+            i.cdn_region.clone(),
+            i.currency.clone(),
+            i.abandonment_reason.clone(),
+            i.time_bucket.clone(),
+        ),
+        i,
+    )
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::date_time;
+use router_env::logger;
+
+use super::{SdkEventMetricRow, SdkEventQueryOptions};
+
+/// Sizing of [`SdkEventMetricsCache`]
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct MetricsCacheConfig {
+    /// Results kept at once, the one expiring first is dropped to make room for another
+    pub max_entries: usize,
+    /// Seconds the result of a time range which has ended is kept for
+    pub ttl_secs: u64,
+    /// Seconds the result of a time range ending now or later is kept for, its last buckets
+    /// still change as events arrive. Such results aren't kept if this is zero.
+    pub live_ttl_secs: u64,
+}
+
+impl Default for MetricsCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            ttl_secs: 60,
+            live_ttl_secs: 0,
+        }
+    }
+}
+
+type CachedBuckets = Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>;
+
+#[derive(Debug)]
+struct CacheEntry {
+    buckets: CachedBuckets,
+    expires_at: Instant,
+}
+
+/// In memory cache of the buckets loaded by sdk event metrics, keyed on everything that shapes
+/// the result of a query so that only identical queries share an entry
+#[derive(Debug)]
+pub struct SdkEventMetricsCache {
+    max_entries: usize,
+    ttl: Duration,
+    live_ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+/// Signature of a metric query, two queries with the same key load the same buckets
+#[derive(Debug)]
+pub(crate) struct CacheKey {
+    key: String,
+    /// Whether the time range ends now or later
+    live: bool,
+}
+
+impl CacheKey {
+    /// `time_range` is the validated range the query reads, see
+    /// [`super::validate_time_range`], and `open` whether the requested range had no end
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        metric: &SdkEventMetrics,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+        open: bool,
+    ) -> Option<Self> {
+        // The options which don't change the buckets, like the timeout, are left out
+        let key = serde_json::json!({
+            "metric": metric,
+            "dimensions": dimensions,
+            "publishable_key": publishable_key,
+            "filters": filters,
+            "granularity": granularity,
+            "time_range": time_range,
+            "time_zone": options.time_zone,
+            "count_threshold": options.count_threshold,
+            "pagination": options.pagination,
+            "moving_average_buckets": options.moving_average_buckets,
+            "running_total": options.running_total,
+            "percent_of_total": options.percent_of_total,
+            "fill_gaps": options.fill_gaps,
+            "top_groups": options.top_groups,
+            "sample_rate": options.sample_rate,
+            "decimal_places": options.decimal_places,
+            "funnel_stages": options.funnel_stages,
+            "null_label": options.null_label,
+            "computed_dimension": options.computed_dimension,
+            "subtotals": options.subtotals,
+            "percentile_bucketing": options.percentile_bucketing,
+            "deduplicate": options.deduplicate,
+            "include_archived": options.include_archived,
+            "max_rows": options.max_rows,
+            "max_partitions": options.max_partitions,
+        });
+        // An open range ends when it's validated, it's as live as one ending later
+        let live = open
+            || time_range
+                .end_time
+                .map_or(true, |end_time| end_time >= date_time::now());
+        serde_json::to_string(&key)
+            .map_err(|error| logger::warn!(?error, "Failed to build metrics cache key"))
+            .ok()
+            .map(|key| Self { key, live })
+    }
+}
+
+impl SdkEventMetricsCache {
+    pub fn new(config: &MetricsCacheConfig) -> Self {
+        Self {
+            max_entries: config.max_entries,
+            ttl: Duration::from_secs(config.ttl_secs),
+            live_ttl: Duration::from_secs(config.live_ttl_secs),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The buckets stored for `key`, `None` if there are none or they've expired
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<CachedBuckets> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .get(&key.key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.buckets.clone())
+    }
+
+    /// Stores `buckets` for `key` for as long as its time range is kept for
+    pub(crate) fn insert(&self, key: CacheKey, buckets: &CachedBuckets) {
+        let ttl = if key.live { self.live_ttl } else { self.ttl };
+        if ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key.key) {
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key.key) {
+            let first_to_expire = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(first_to_expire) = first_to_expire {
+                entries.remove(&first_to_expire);
+            }
+        }
+        entries.insert(
+            key.key,
+            CacheEntry {
+                buckets: buckets.clone(),
+                expires_at: now + ttl,
+            },
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+        SdkEventMetricsBucketValue,
+    },
+    Granularity, TimeRange,
+};
+use error_stack::report;
+use time::PrimitiveDateTime;
+
+use super::{
+    compare_buckets, load_metrics_batch, supported_dimensions, SdkEventMetricAnalytics,
+    SdkEventQueryOptions,
+};
+use crate::{
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    sdk_events::SdkEventMetricsAccumulator,
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Every requested metric of one bucket
+#[derive(Debug)]
+pub struct SdkEventCombinedRow {
+    pub id: SdkEventMetricsBucketIdentifier,
+    /// The values of the requested metrics, the fields of the other metrics are `None`. A metric
+    /// without events in the bucket has `None` values as well.
+    pub values: SdkEventMetricsBucketValue,
+}
+
+/// Fails with [`MetricsError::IncompatibleMetrics`] if the buckets of `metrics` can't be lined up:
+/// one of them can't be grouped by one of `dimensions`, or `options` pick the groups of each
+/// metric on their own
+fn validate_combination(
+    metrics: &[SdkEventMetrics],
+    dimensions: &[SdkEventDimensions],
+    options: &SdkEventQueryOptions,
+) -> MetricsResult<()> {
+    if options.pagination.is_some()
+        || options.top_groups.is_some()
+        || options.count_threshold.is_some()
+    {
+        return Err(report!(MetricsError::IncompatibleMetrics(
+            "a page, the top groups or a count threshold keep different groups for each metric"
+                .to_string()
+        )));
+    }
+    for metric in metrics {
+        let supported = supported_dimensions(metric);
+        if let Some(dim) = dimensions.iter().find(|dim| !supported.contains(dim)) {
+            return Err(report!(MetricsError::IncompatibleMetrics(format!(
+                "{metric} can't be grouped by {dim}"
+            ))));
+        }
+    }
+    Ok(())
+}
+
+/// Loads `metrics` over the same dimensions, filters and time range with
+/// [`load_metrics_batch`], and merges their buckets into a single row per bucket. The rows are
+/// sorted like [`compare_buckets`] sorts them.
+#[allow(clippy::too_many_arguments)]
+pub async fn load_combined_metrics<T>(
+    metrics: &[SdkEventMetrics],
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<Vec<SdkEventCombinedRow>>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    validate_combination(metrics, dimensions, options)?;
+    // A metric asked for twice would be accumulated twice
+    let metrics = metrics
+        .iter()
+        .enumerate()
+        .filter(|(index, metric)| !metrics.iter().take(*index).any(|other| other == *metric))
+        .map(|(_, metric)| metric.clone())
+        .collect::<Vec<_>>();
+
+    let loaded = load_metrics_batch(
+        &metrics,
+        dimensions,
+        publishable_key,
+        filters,
+        granularity,
+        options,
+        time_range,
+        pool,
+    )
+    .await?;
+
+    let mut rows = HashMap::<SdkEventMetricsBucketIdentifier, SdkEventMetricsAccumulator>::new();
+    for (metric, buckets) in loaded {
+        for (id, row) in buckets {
+            rows.entry(id).or_default().add_metric_bucket(&metric, &row);
+        }
+    }
+    let mut rows = rows
+        .into_iter()
+        .map(|(id, values)| SdkEventCombinedRow {
+            id,
+            values: values.collect(),
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|left, right| compare_buckets(&left.id, &right.id, dimensions));
+    Ok(rows)
+}
@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::date_time;
+use error_stack::{report, ResultExt};
+use time::PrimitiveDateTime;
+
+use super::{SdkEventMetric, SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventQueryOptions};
+use crate::{
+    query::{
+        format_time_bucket, parse_time_bucket_offset, Aggregate, GroupByClause, ToSql, Window,
+    },
+    sdk_events::accumulator::{
+        AverageAccumulator, BounceRateAccumulator, FunnelWaterfallAccumulator,
+        LatencyBreakdownAccumulator, SdkEventMetricAccumulator, SuccessRateAccumulator,
+        ThreeDsFlowAccumulator,
+    },
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// A bucket of a metric in the requested period and the matching bucket of the preceding period,
+/// either side is `None` if only the other period has events for the bucket
+#[derive(Debug)]
+pub struct SdkEventPeriodBucket {
+    /// Identifier of the bucket in the requested period
+    pub id: SdkEventMetricsBucketIdentifier,
+    pub current: Option<SdkEventMetricRow>,
+    pub previous: Option<SdkEventMetricRow>,
+    /// Value of the current bucket minus the value of the previous one, `None` if either is
+    pub delta: Option<f64>,
+    /// `delta` in percent of the previous value, `None` if that is zero
+    pub percent_change: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct SdkEventPeriodComparison {
+    pub current_range: TimeRange,
+    pub previous_range: TimeRange,
+    /// The buckets of the requested period in the order they were loaded, followed by the ones
+    /// only the preceding period has
+    pub buckets: Vec<SdkEventPeriodBucket>,
+}
+
+/// A bucket of a metric for the two keys of [`load_metrics_for_key_pair`], either side is `None`
+/// if only the other key has events for the bucket
+#[derive(Debug)]
+pub struct SdkEventKeyPairBucket {
+    pub id: SdkEventMetricsBucketIdentifier,
+    /// Bucket of the first key
+    pub first: Option<SdkEventMetricRow>,
+    /// Bucket of the second key
+    pub second: Option<SdkEventMetricRow>,
+    /// Value of the second key's bucket minus the value of the first one's, `None` if either is
+    pub delta: Option<f64>,
+    /// `delta` in percent of the first key's value, `None` if that is zero
+    pub percent_change: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct SdkEventKeyPairComparison {
+    /// Publishable key the `first` side of each bucket was loaded for
+    pub first_key: String,
+    /// Publishable key the `second` side of each bucket was loaded for
+    pub second_key: String,
+    /// The buckets of the first key in the order they were loaded, followed by the ones only the
+    /// second key has
+    pub buckets: Vec<SdkEventKeyPairBucket>,
+}
+
+/// The part of a dimension group in the change of a metric between two periods, see
+/// [`contributions_to_change`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdkEventContribution {
+    /// Identifier of the group, without a time bucket
+    pub id: SdkEventMetricsBucketIdentifier,
+    /// Value of the group in the current period minus its value in the prior one. A group only
+    /// one of the periods has contributes its whole value.
+    pub absolute: f64,
+    /// `absolute` as a fraction of the change of the total, `None` if the total didn't change.
+    /// The fractions add up to one, a group which moved against the total has a negative one.
+    pub relative: Option<f64>,
+}
+
+/// The range of the same length right before `time_range`, a range without an end ends now
+pub fn previous_time_range(time_range: &TimeRange) -> TimeRange {
+    let end_time = time_range.end_time.unwrap_or_else(date_time::now);
+    let length = end_time - time_range.start_time;
+    TimeRange {
+        start_time: time_range.start_time - length,
+        end_time: Some(end_time - length),
+    }
+}
+
+/// Loads `metric` for `time_range` and for the [`previous_time_range`], and pairs up the buckets
+/// of both periods. A bucket of the preceding period is matched to the current bucket with the
+/// same dimensions whose time bucket is one range length later, so buckets only line up if the
+/// range is a whole number of `granularity` buckets.
+#[allow(clippy::too_many_arguments)]
+pub async fn load_metrics_with_previous_period<T>(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<SdkEventPeriodComparison>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let current_range = TimeRange {
+        start_time: time_range.start_time,
+        end_time: Some(time_range.end_time.unwrap_or_else(date_time::now)),
+    };
+    let previous_range = previous_time_range(&current_range);
+
+    let (current, previous) = futures::future::try_join(
+        metric.load_metrics(
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            &current_range,
+            pool,
+        ),
+        metric.load_metrics(
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            &previous_range,
+            pool,
+        ),
+    )
+    .await?;
+
+    let length = current_range.start_time - previous_range.start_time;
+    let previous = previous
+        .into_iter()
+        .map(|(mut id, row)| {
+            id.time_bucket = id
+                .time_bucket
+                .map(|time_bucket| {
+                    // Labelled on the clock alone once shifted, the offset of a repeated hour
+                    // is dropped
+                    parse_time_bucket_offset(&time_bucket)
+                        .and_then(|(time_bucket, _)| format_time_bucket(time_bucket + length))
+                })
+                .transpose()
+                .change_context(MetricsError::PostProcessingFailure)
+                .attach_printable("Failed to shift time bucket of the previous period")?;
+            Ok((id, row))
+        })
+        .collect::<MetricsResult<Vec<_>>>()?;
+
+    let buckets = pair_buckets(current, previous)
+        .into_iter()
+        .map(|(id, current, previous)| {
+            let (delta, percent_change) = change(metric, previous.as_ref(), current.as_ref());
+            SdkEventPeriodBucket {
+                id,
+                current,
+                previous,
+                delta,
+                percent_change,
+            }
+        })
+        .collect();
+
+    Ok(SdkEventPeriodComparison {
+        current_range,
+        previous_range,
+        buckets,
+    })
+}
+
+/// Loads `metric` for both `first_key` and `second_key`, e.g. the test and the live key of a
+/// merchant, over the same range and dimensions, and pairs up their buckets with the same
+/// identifier. Unlike the other metric queries, this one reads the events of two keys.
+#[allow(clippy::too_many_arguments)]
+pub async fn load_metrics_for_key_pair<T>(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    first_key: &str,
+    second_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<SdkEventKeyPairComparison>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let (first, second) = futures::future::try_join(
+        metric.load_metrics(
+            dimensions,
+            first_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            pool,
+        ),
+        metric.load_metrics(
+            dimensions,
+            second_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            pool,
+        ),
+    )
+    .await?;
+
+    let buckets = pair_buckets(first, second)
+        .into_iter()
+        .map(|(id, first, second)| {
+            let (delta, percent_change) = change(metric, first.as_ref(), second.as_ref());
+            SdkEventKeyPairBucket {
+                id,
+                first,
+                second,
+                delta,
+                percent_change,
+            }
+        })
+        .collect();
+
+    Ok(SdkEventKeyPairComparison {
+        first_key: first_key.to_string(),
+        second_key: second_key.to_string(),
+        buckets,
+    })
+}
+
+/// The contribution of each dimension group to the change of `metric` from the `prior` buckets to
+/// the `current` ones, the largest first whichever way they moved. The buckets of a group are
+/// summed over their time buckets, so that the periods needn't line up bucket by bucket, and
+/// subtotals aren't counted again.
+///
+/// Only the metrics whose total is the sum of their groups, the counts and the attempted amount,
+/// can be broken down this way: the change of a rate or an average isn't the sum of the changes
+/// of its groups.
+pub fn contributions_to_change(
+    metric: &SdkEventMetrics,
+    current: &[(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+    prior: &[(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+) -> MetricsResult<Vec<SdkEventContribution>> {
+    if !is_additive(metric) {
+        return Err(report!(MetricsError::UnsupportedMetric(metric.to_string())))
+            .attach_printable("Only the counts and sums can be broken down into contributions");
+    }
+    // The groups in the order they were loaded, with their summed values
+    let group_totals = |buckets: &[(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)]| {
+        let mut groups = Vec::new();
+        let mut totals = HashMap::<SdkEventMetricsBucketIdentifier, f64>::new();
+        for (id, row) in buckets.iter().filter(|(id, _)| id.subtotal_level.is_none()) {
+            let group = SdkEventMetricsBucketIdentifier {
+                time_bucket: None,
+                ..id.clone()
+            };
+            let value = metric_value(metric, row).unwrap_or_default();
+            match totals.get_mut(&group) {
+                Some(total) => *total += value,
+                None => {
+                    groups.push(group.clone());
+                    totals.insert(group, value);
+                }
+            }
+        }
+        (groups, totals)
+    };
+    let (current_groups, current_totals) = group_totals(current);
+    let (prior_groups, prior_totals) = group_totals(prior);
+
+    let mut contributions = current_groups
+        .into_iter()
+        .chain(
+            prior_groups
+                .into_iter()
+                .filter(|group| !current_totals.contains_key(group)),
+        )
+        .map(|group| {
+            let total = |totals: &HashMap<_, f64>| totals.get(&group).copied().unwrap_or_default();
+            let absolute = total(&current_totals) - total(&prior_totals);
+            (group, absolute)
+        })
+        .collect::<Vec<_>>();
+    let total_change = contributions
+        .iter()
+        .map(|(_, absolute)| absolute)
+        .sum::<f64>();
+    // Stable, groups which contributed as much keep the order they were loaded in
+    contributions.sort_by(|(_, left), (_, right)| right.abs().total_cmp(&left.abs()));
+
+    Ok(contributions
+        .into_iter()
+        .map(|(id, absolute)| SdkEventContribution {
+            id,
+            absolute,
+            relative: (total_change != 0.0).then(|| absolute / total_change),
+        })
+        .collect())
+}
+
+/// Whether the value of `metric` over several groups is the sum of their values
+fn is_additive(metric: &SdkEventMetrics) -> bool {
+    matches!(
+        metric,
+        SdkEventMetrics::PaymentAttempts
+            | SdkEventMetrics::PaymentMethodsCallCount
+            | SdkEventMetrics::SdkRenderedCount
+            | SdkEventMetrics::SdkInitiatedCount
+            | SdkEventMetrics::PaymentMethodSelectedCount
+            | SdkEventMetrics::PaymentDataFilledCount
+            | SdkEventMetrics::ThreeDsMethodInvokedCount
+            | SdkEventMetrics::ThreeDsMethodSkippedCount
+            | SdkEventMetrics::ThreeDsMethodSuccessfulCount
+            | SdkEventMetrics::ThreeDsMethodUnsuccessfulCount
+            | SdkEventMetrics::AuthenticationUnsuccessfulCount
+            | SdkEventMetrics::ThreeDsChallengeFlowCount
+            | SdkEventMetrics::ThreeDsFrictionlessFlowCount
+            | SdkEventMetrics::SdkErrorEncounteredCount
+            | SdkEventMetrics::UniqueSessionsCount
+            | SdkEventMetrics::SdkLoadFailureCount
+            | SdkEventMetrics::AbandonedSessionsCount
+            | SdkEventMetrics::PaymentExperienceBreakdown
+            | SdkEventMetrics::PaymentAttemptedAmount
+    )
+}
+
+/// Pairs up the buckets of `left` and `right` with the same identifier: the buckets of `left` in
+/// the order they were loaded, followed by the ones only `right` has
+fn pair_buckets(
+    left: Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+    right: Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+) -> Vec<(
+    SdkEventMetricsBucketIdentifier,
+    Option<SdkEventMetricRow>,
+    Option<SdkEventMetricRow>,
+)> {
+    let mut buckets = left
+        .into_iter()
+        .map(|(id, row)| (id, Some(row), None))
+        .collect::<Vec<_>>();
+    let mut positions = buckets
+        .iter()
+        .enumerate()
+        .map(|(position, (id, _, _))| (id.clone(), position))
+        .collect::<HashMap<_, _>>();
+
+    for (id, row) in right {
+        match positions.get(&id) {
+            Some(position) => {
+                if let Some((_, _, right)) = buckets.get_mut(*position) {
+                    *right = Some(row);
+                }
+            }
+            None => {
+                positions.insert(id.clone(), buckets.len());
+                buckets.push((id, None, Some(row)));
+            }
+        }
+    }
+    buckets
+}
+
+/// Value of `to` minus the value of `from`, and that in percent of the value of `from`. Either is
+/// `None` without both values, the percentage as well if `from` is zero.
+fn change(
+    metric: &SdkEventMetrics,
+    from: Option<&SdkEventMetricRow>,
+    to: Option<&SdkEventMetricRow>,
+) -> (Option<f64>, Option<f64>) {
+    let from = from.and_then(|row| metric_value(metric, row));
+    let to = to.and_then(|row| metric_value(metric, row));
+    let delta = to.zip(from).map(|(to, from)| to - from);
+    let percent_change = delta
+        .zip(from)
+        .filter(|(_, from)| *from != 0.0)
+        .map(|(delta, from)| delta / from * 100.0);
+    (delta, percent_change)
+}
+
+/// The single value of a bucket the periods are compared on, as accumulated for the response
+pub(super) fn metric_value(metric: &SdkEventMetrics, row: &SdkEventMetricRow) -> Option<f64> {
+    let to_f64 = |value: i64| u32::try_from(value).ok().map(f64::from);
+    match metric {
+        SdkEventMetrics::AveragePaymentTime
+        | SdkEventMetrics::SessionDurationSeconds
+        | SdkEventMetrics::PaymentRetryCount
+        | SdkEventMetrics::TimeToFirstRender
+        | SdkEventMetrics::AveragePaymentMethodsDisplayed => {
+            let mut average = AverageAccumulator::default();
+            average.add_metrics_bucket(row);
+            average.collect()
+        }
+        SdkEventMetrics::PaymentAttemptedAmount => {
+            row.total.as_ref().and_then(bigdecimal::ToPrimitive::to_f64)
+        }
+        // compared on the network part, the render part is in the bucket values
+        SdkEventMetrics::LatencyBreakdown => {
+            let mut latency = LatencyBreakdownAccumulator::default();
+            latency.add_metrics_bucket(row);
+            latency.collect().0
+        }
+        SdkEventMetrics::PaymentSuccessRate | SdkEventMetrics::ThreeDsMethodSuccessRate => {
+            let mut success_rate = SuccessRateAccumulator::default();
+            success_rate.add_metrics_bucket(row);
+            success_rate.collect()
+        }
+        SdkEventMetrics::SdkBounceRate => {
+            let mut bounce_rate = BounceRateAccumulator::default();
+            bounce_rate.add_metrics_bucket(row);
+            bounce_rate.collect()
+        }
+        SdkEventMetrics::ThreeDsFlowBreakdown => {
+            let mut flows = ThreeDsFlowAccumulator::default();
+            flows.add_metrics_bucket(row);
+            flows.collect().2
+        }
+        // The conversion of the whole funnel
+        SdkEventMetrics::FunnelConversionRate => match (row.initiated, row.attempted) {
+            (Some(initiated), Some(attempted)) if initiated > 0 => {
+                Some(to_f64(attempted)? / to_f64(initiated)?)
+            }
+            _ => None,
+        },
+        // The share of the first requested stage which reached the last one
+        SdkEventMetrics::FunnelWaterfall => {
+            let mut waterfall = FunnelWaterfallAccumulator::default();
+            waterfall.add_metrics_bucket(row);
+            let waterfall = waterfall.collect()?;
+            let first = waterfall
+                .first()?
+                .count
+                .and_then(|count| u32::try_from(count).ok())?;
+            let last = waterfall
+                .last()?
+                .count
+                .and_then(|count| u32::try_from(count).ok())?;
+            (first > 0).then(|| f64::from(last) / f64::from(first))
+        }
+        SdkEventMetrics::PaymentAttempts
+        | SdkEventMetrics::PaymentMethodsCallCount
+        | SdkEventMetrics::SdkRenderedCount
+        | SdkEventMetrics::SdkInitiatedCount
+        | SdkEventMetrics::PaymentMethodSelectedCount
+        | SdkEventMetrics::PaymentDataFilledCount
+        | SdkEventMetrics::ThreeDsMethodInvokedCount
+        | SdkEventMetrics::ThreeDsMethodSkippedCount
+        | SdkEventMetrics::ThreeDsMethodSuccessfulCount
+        | SdkEventMetrics::ThreeDsMethodUnsuccessfulCount
+        | SdkEventMetrics::AuthenticationUnsuccessfulCount
+        | SdkEventMetrics::ThreeDsChallengeFlowCount
+        | SdkEventMetrics::ThreeDsFrictionlessFlowCount
+        | SdkEventMetrics::SdkErrorEncounteredCount
+        | SdkEventMetrics::UniqueSessionsCount
+        | SdkEventMetrics::SdkLoadFailureCount
+        | SdkEventMetrics::AbandonedSessionsCount
+        | SdkEventMetrics::PaymentExperienceBreakdown => row.count.and_then(to_f64),
+    }
+}
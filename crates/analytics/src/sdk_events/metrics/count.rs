@@ -0,0 +1,219 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::{report, ResultExt};
+use futures::StreamExt;
+use time::PrimitiveDateTime;
+
+use super::{
+    batch::{count_conditions, into_bucket},
+    SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventMetricStream, SdkEventQueryOptions,
+};
+use crate::{
+    query::{Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Loads `metric`, which has to be a plain count of the events matching its
+/// [`count_conditions`]
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn load_count_metric<T>(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    build_count_query(
+        metric,
+        dimensions,
+        publishable_key,
+        filters,
+        granularity,
+        options,
+        time_range,
+        pool,
+    )?
+    .execute_query::<SdkEventMetricRow, _>(pool)
+    .await
+    .change_context(MetricsError::QueryBuildingError)?
+    .switch()
+    .map(|rows| rows.into_iter().map(into_bucket).collect())
+}
+
+/// Like [`load_count_metric`] but hands out the buckets as the data source returns them
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn load_count_metric_stream<T>(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<SdkEventMetricStream>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    Ok(build_count_query(
+        metric,
+        dimensions,
+        publishable_key,
+        filters,
+        granularity,
+        options,
+        time_range,
+        pool,
+    )?
+    .execute_query_stream::<SdkEventMetricRow, _>(pool)
+    .await
+    .change_context(MetricsError::QueryBuildingError)?
+    .switch()?
+    .map(|row| row.switch().map(into_bucket))
+    .boxed())
+}
+
+/// The query of a count metric, read from the metric's materialized view where the view can
+/// answer it. Every option of the count metrics is applied here.
+#[allow(clippy::too_many_arguments)]
+fn build_count_query<T>(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<QueryBuilder<T>>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let conditions = count_conditions(metric)
+        .ok_or_else(|| report!(MetricsError::UnsupportedMetric(metric.to_string())))
+        .attach_printable("Only the count metrics are loaded by a count query")?;
+    let view = super::pick_materialized_view(
+        metric,
+        dimensions,
+        filters,
+        granularity,
+        options,
+        time_range,
+    )
+    .filter(|_| pool.has_materialized_views());
+    let mut query_builder: QueryBuilder<T> =
+        QueryBuilder::new(view.map_or(AnalyticsCollection::SdkEvents, |view| view.collection));
+    let dimensions = dimensions.to_vec();
+
+    for dim in dimensions.iter() {
+        query_builder
+            .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+            .switch()?;
+    }
+
+    query_builder
+        .add_select_column(view.map_or_else(|| options.event_count(), |view| view.event_count()))
+        .switch()?;
+
+    options.set_query_options(&mut query_builder).switch()?;
+    options
+        .set_pagination(&mut query_builder, &dimensions, granularity)
+        .switch()?;
+    options
+        .set_time_series_windows(
+            &mut query_builder,
+            options.event_count_expression(),
+            &dimensions,
+            granularity,
+        )
+        .switch()?;
+    options
+        .set_percent_of_total(
+            &mut query_builder,
+            options.event_count_expression(),
+            granularity,
+        )
+        .switch()?;
+
+    if let Some(granularity) = granularity.as_ref() {
+        query_builder
+            .add_granularity_in_mins(granularity)
+            .switch()?;
+    }
+
+    filters.set_filter_clause(&mut query_builder).switch()?;
+
+    query_builder
+        .add_filter_clause("merchant_id", publishable_key)
+        .switch()?;
+
+    for (column, filter_type, value) in conditions {
+        if *metric == SdkEventMetrics::PaymentDataFilledCount
+            && column == "first_event"
+            && dimensions.contains(&SdkEventDimensions::FieldName)
+        {
+            // Every field filled is counted, not only the first one of a session, and the
+            // events without a field are left out instead of being grouped as null
+            query_builder
+                .add_custom_filter_clause(
+                    SdkEventDimensions::FieldName,
+                    "NULL",
+                    FilterTypes::IsNotNull,
+                )
+                .switch()?;
+            continue;
+        }
+        query_builder
+            .add_custom_filter_clause(column, value, filter_type)
+            .switch()?;
+    }
+
+    time_range
+        .set_filter_clause(&mut query_builder)
+        .attach_printable("Error filtering time range")
+        .switch()?;
+
+    for dim in dimensions.iter() {
+        query_builder
+            .add_rollup_group_by_clause(options.dimension(dim))
+            .attach_printable("Error grouping by dimensions")
+            .switch()?;
+    }
+
+    if let Some(_granularity) = granularity.as_ref() {
+        query_builder
+            .add_group_by_clause("time_bucket")
+            .attach_printable("Error adding granularity")
+            .switch()?;
+    }
+
+    super::check_scoped(&query_builder)?;
+
+    Ok(query_builder)
+}
@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+
+use api_models::analytics::{sdk_events::SdkEventMetricsBucketIdentifier, Granularity, TimeRange};
+use chrono::{Offset, TimeZone};
+use common_utils::date_time;
+use error_stack::{report, ResultExt};
+use time::{format_description::well_known::Rfc3339, PrimitiveDateTime, UtcOffset};
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{format_time_bucket_offset, parse_time_bucket_offset, SeriesBucket},
+    types::{MetricsError, MetricsResult},
+};
+
+/// How the `time_bucket` of a row is written: the local wall clock time of a bucket aligned to a
+/// time zone, or the RFC 3339 UTC time clickhouse returns for the other ones
+#[derive(Clone, Copy)]
+enum BucketFormat {
+    WallClock,
+    Rfc3339,
+}
+
+/// Start of a bucket as it's labelled: its time on the clock, along its UTC offset if it starts
+/// in an hour the clock goes through twice
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct BucketStart {
+    time: PrimitiveDateTime,
+    repeated_offset: Option<UtcOffset>,
+}
+
+impl BucketStart {
+    fn new(time: PrimitiveDateTime) -> Self {
+        Self {
+            time,
+            repeated_offset: None,
+        }
+    }
+
+    /// Orders the buckets by when they start, the first of the two buckets with the same time
+    /// on the clock is the one with the larger offset
+    fn sort_key(&self) -> (PrimitiveDateTime, i32) {
+        (
+            self.time,
+            -self.repeated_offset.map_or(0, UtcOffset::whole_seconds),
+        )
+    }
+}
+
+fn parse_bucket(time_bucket: &str) -> MetricsResult<(BucketStart, BucketFormat)> {
+    if let Ok((time, repeated_offset)) = parse_time_bucket_offset(time_bucket) {
+        return Ok((
+            BucketStart {
+                time,
+                repeated_offset,
+            },
+            BucketFormat::WallClock,
+        ));
+    }
+    time::OffsetDateTime::parse(time_bucket, &Rfc3339)
+        .map(|bucket| {
+            let bucket = bucket.to_offset(UtcOffset::UTC);
+            (
+                BucketStart::new(PrimitiveDateTime::new(bucket.date(), bucket.time())),
+                BucketFormat::Rfc3339,
+            )
+        })
+        .change_context(MetricsError::PostProcessingFailure)
+        .attach_printable_lazy(|| format!("Failed to parse time bucket: {time_bucket}"))
+}
+
+fn format_bucket(bucket: BucketStart, format: BucketFormat) -> MetricsResult<String> {
+    match format {
+        BucketFormat::WallClock => format_time_bucket_offset(bucket.time, bucket.repeated_offset)
+            .change_context(MetricsError::PostProcessingFailure),
+        BucketFormat::Rfc3339 => bucket
+            .time
+            .assume_utc()
+            .format(&Rfc3339)
+            .change_context(MetricsError::PostProcessingFailure),
+    }
+    .attach_printable_lazy(|| format!("Failed to format time bucket: {}", bucket.time))
+}
+
+/// `value` as a chrono date time with the same wall clock
+fn to_naive(value: PrimitiveDateTime) -> MetricsResult<chrono::NaiveDateTime> {
+    chrono::DateTime::from_timestamp(value.assume_utc().unix_timestamp(), 0)
+        .map(|value| value.naive_utc())
+        .ok_or_else(|| report!(MetricsError::PostProcessingFailure))
+        .attach_printable_lazy(|| format!("Time out of range: {value}"))
+}
+
+/// Wall clock time in `time_zone` of the UTC time `value`
+fn to_local(
+    value: PrimitiveDateTime,
+    time_zone: chrono_tz::Tz,
+) -> MetricsResult<PrimitiveDateTime> {
+    let offset = time_zone
+        .offset_from_utc_datetime(&to_naive(value)?)
+        .fix()
+        .local_minus_utc();
+    Ok(value + time::Duration::seconds(offset.into()))
+}
+
+fn to_offset(offset: chrono::FixedOffset) -> MetricsResult<UtcOffset> {
+    let seconds = offset.local_minus_utc();
+    UtcOffset::from_whole_seconds(seconds)
+        .change_context(MetricsError::PostProcessingFailure)
+        .attach_printable_lazy(|| format!("Offset out of range: {seconds}s"))
+}
+
+/// Whether the buckets of `granularity` which start in an hour the clocks go through twice are
+/// labelled with their offset, only the buckets shorter than a day can start twice at the same
+/// time on the clock
+fn labels_repeated_hours(granularity: &Granularity) -> bool {
+    match granularity {
+        Granularity::OneMin
+        | Granularity::FiveMin
+        | Granularity::FifteenMin
+        | Granularity::ThirtyMin
+        | Granularity::OneHour => true,
+        Granularity::OneDay
+        | Granularity::Weekly
+        | Granularity::Monthly
+        | Granularity::Quarterly => false,
+    }
+}
+
+/// Start of the bucket following the one starting at `bucket`
+fn next_bucket(
+    granularity: &Granularity,
+    bucket: PrimitiveDateTime,
+) -> MetricsResult<PrimitiveDateTime> {
+    granularity
+        .clip_to_end(bucket)
+        .map(|end| end + time::Duration::SECOND)
+        .change_context(MetricsError::PostProcessingFailure)
+}
+
+/// Starts of the time buckets which overlap `time_range`, as they're labelled by the query. The
+/// bucket the range starts in is included even if the range doesn't start with it.
+///
+/// Mirrors the bucketing of clickhouse: minute intervals, and hours and days without a time zone,
+/// are counted from the unix epoch and only then shifted to the local clock. The other buckets
+/// start on the local clock, so that a bucket whose start doesn't exist on it (e.g. the hour
+/// skipped by a daylight saving change) isn't returned, and one whose start exists twice on it
+/// (e.g. the hour repeated by the change back) is returned twice, with either offset.
+fn expected_buckets(
+    granularity: &Granularity,
+    time_zone: Option<&str>,
+    time_range: &TimeRange,
+) -> MetricsResult<Vec<BucketStart>> {
+    let time_zone = time_zone
+        .map(|time_zone| {
+            time_zone
+                .parse::<chrono_tz::Tz>()
+                .map_err(|_| report!(MetricsError::PostProcessingFailure))
+                .attach_printable_lazy(|| format!("Unknown time zone: {time_zone}"))
+        })
+        .transpose()?;
+    let start_time = time_range.start_time;
+    let end_time = time_range.end_time.unwrap_or_else(date_time::now);
+    let clip_to_start = |value| {
+        granularity
+            .clip_to_start(value)
+            .change_context(MetricsError::PostProcessingFailure)
+    };
+
+    let mut buckets = Vec::new();
+    match (granularity, time_zone) {
+        (
+            Granularity::OneMin
+            | Granularity::FiveMin
+            | Granularity::FifteenMin
+            | Granularity::ThirtyMin,
+            _,
+        )
+        | (Granularity::OneHour | Granularity::OneDay, None) => {
+            let mut bucket = clip_to_start(start_time)?;
+            while bucket <= end_time {
+                buckets.push(match time_zone {
+                    Some(time_zone) => {
+                        // Like the query, a start reading the same on the clock as the time an
+                        // hour before or after it is in a repeated hour
+                        let local = to_local(bucket, time_zone)?;
+                        let hour = time::Duration::HOUR;
+                        let repeated_offset = if to_local(bucket - hour, time_zone)? == local
+                            || to_local(bucket + hour, time_zone)? == local
+                        {
+                            let offset = time_zone.offset_from_utc_datetime(&to_naive(bucket)?);
+                            Some(to_offset(offset.fix())?)
+                        } else {
+                            None
+                        };
+                        BucketStart {
+                            time: local,
+                            repeated_offset,
+                        }
+                    }
+                    None => BucketStart::new(bucket),
+                });
+                bucket = next_bucket(granularity, bucket)?;
+            }
+        }
+        (_, time_zone) => {
+            let (start_time, end_time) = match time_zone {
+                Some(time_zone) => (
+                    to_local(start_time, time_zone)?,
+                    to_local(end_time, time_zone)?,
+                ),
+                None => (start_time, end_time),
+            };
+            let mut bucket = clip_to_start(start_time)?;
+            while bucket <= end_time {
+                let naive = to_naive(bucket)?;
+                match time_zone.map(|time_zone| time_zone.from_local_datetime(&naive)) {
+                    Some(chrono::LocalResult::None) => {}
+                    Some(chrono::LocalResult::Ambiguous(first, second))
+                        if labels_repeated_hours(granularity) =>
+                    {
+                        for offset in [first.offset().fix(), second.offset().fix()] {
+                            buckets.push(BucketStart {
+                                time: bucket,
+                                repeated_offset: Some(to_offset(offset)?),
+                            });
+                        }
+                    }
+                    _ => buckets.push(BucketStart::new(bucket)),
+                }
+                bucket = next_bucket(granularity, bucket)?;
+            }
+        }
+    }
+    Ok(buckets)
+}
+
+/// Row of a bucket without events: every count is zero, which is what counting no events gives,
+/// and every other value is missing
+fn zero_row(group: &SdkEventMetricsBucketIdentifier, time_bucket: String) -> SdkEventMetricRow {
+    SdkEventMetricRow {
+        total: Some(0.into()),
+        count: Some(0),
+        initiated: Some(0),
+        rendered: Some(0),
+        selected: Some(0),
+        filled: Some(0),
+        attempted: Some(0),
+        successful: Some(0),
+        challenge_count: Some(0),
+        frictionless_count: Some(0),
+        time_bucket: Some(time_bucket),
+        payment_method: group.payment_method.clone(),
+        platform: group.platform.clone(),
+        browser_name: group.browser_name.clone(),
+        source: group.source.clone(),
+        component: group.component.clone(),
+        payment_experience: group.payment_experience.clone(),
+        sdk_version: group.sdk_version.clone(),
+        device_type: group.device_type.clone(),
+        os: group.os.clone(),
+        country: group.country.clone(),
+        connector: group.connector.clone(),
+        event_name: group.event_name.clone(),
+        payment_method_type: group.payment_method_type.clone(),
+        day_of_week: group.day_of_week.clone(),
+        hour_of_day: group.hour_of_day.clone(),
+        authentication_error_reason: group.authentication_error_reason.clone(),
+        field_name: group.field_name.clone(),
+        customer_segment: group.customer_segment.clone(),
+        cdn_region: group.cdn_region.clone(),
+        currency: group.currency.clone(),
+        abandonment_reason: group.abandonment_reason.clone(),
+        computed_dimension: group.computed_dimension.clone(),
+        ..Default::default()
+    }
+}
+
+/// Adds a zero valued bucket for every time bucket of `time_range` a group of `buckets` has no
+/// row for. The groups are the ones which have rows, a query without dimensions always has its
+/// single group. The buckets are returned by group, in the order the groups first appear, and in
+/// time order within a group.
+pub(crate) fn fill_gaps(
+    buckets: Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+    has_dimensions: bool,
+    granularity: &Granularity,
+    time_zone: Option<&str>,
+    time_range: &TimeRange,
+) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+    let mut format = if time_zone.is_some() {
+        BucketFormat::WallClock
+    } else {
+        BucketFormat::Rfc3339
+    };
+
+    let mut groups = Vec::<(SdkEventMetricsBucketIdentifier, Vec<_>)>::new();
+    let mut positions = HashMap::new();
+    if !has_dimensions {
+        positions.insert(SdkEventMetricsBucketIdentifier::default(), 0);
+        groups.push((SdkEventMetricsBucketIdentifier::default(), Vec::new()));
+    }
+    for (id, row) in buckets {
+        let bucket = id
+            .time_bucket
+            .as_deref()
+            .map(parse_bucket)
+            .transpose()?
+            .map(|(bucket, row_format)| {
+                // Filled buckets are written like the loaded ones
+                format = row_format;
+                bucket
+            });
+        let group = SdkEventMetricsBucketIdentifier {
+            time_bucket: None,
+            ..id.clone()
+        };
+        let position = *positions.entry(group.clone()).or_insert_with(|| {
+            groups.push((group, Vec::new()));
+            groups.len() - 1
+        });
+        if let Some((_, rows)) = groups.get_mut(position) {
+            rows.push((bucket, id, row));
+        }
+    }
+
+    let expected = expected_buckets(granularity, time_zone, time_range)?;
+    let mut filled = Vec::new();
+    for (group, mut rows) in groups {
+        let loaded = rows
+            .iter()
+            .filter_map(|(bucket, _, _)| *bucket)
+            .collect::<HashSet<_>>();
+        for bucket in expected.iter() {
+            if !loaded.contains(bucket) {
+                let time_bucket = format_bucket(*bucket, format)?;
+                let id = SdkEventMetricsBucketIdentifier {
+                    time_bucket: Some(time_bucket.clone()),
+                    ..group.clone()
+                };
+                rows.push((Some(*bucket), id, zero_row(&group, time_bucket)));
+            }
+        }
+        rows.sort_by_key(|(bucket, _, _)| bucket.map(|bucket| bucket.sort_key()));
+        filled.extend(rows.into_iter().map(|(_, id, row)| (id, row)));
+    }
+    Ok(filled)
+}
@@ -0,0 +1,234 @@
+use api_models::analytics::{
+    sdk_events::{
+        FunnelStage, SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier,
+        SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::ResultExt;
+use futures::StreamExt;
+use time::PrimitiveDateTime;
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{
+        customer_segment_join, Aggregate, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window,
+    },
+    sdk_events::types::filters_on_customer_segment,
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Every stage of the sdk funnel in the order of the sdk flow
+pub(super) const FUNNEL_STAGES: [FunnelStage; 5] = [
+    FunnelStage::Initiated,
+    FunnelStage::Rendered,
+    FunnelStage::Selected,
+    FunnelStage::Filled,
+    FunnelStage::Attempted,
+];
+
+/// Event reaching `stage`, and the column of the row its count is selected as
+fn stage_event(stage: FunnelStage) -> (SdkEventNames, &'static str) {
+    match stage {
+        FunnelStage::Initiated => (SdkEventNames::OrcaElementsCalled, "initiated"),
+        FunnelStage::Rendered => (SdkEventNames::AppRendered, "rendered"),
+        FunnelStage::Selected => (SdkEventNames::PaymentMethodChanged, "selected"),
+        FunnelStage::Filled => (SdkEventNames::PaymentDataFilled, "filled"),
+        FunnelStage::Attempted => (SdkEventNames::PaymentAttempt, "attempted"),
+    }
+}
+
+/// Counts every stage of the sdk funnel in a single pass, so that all stages of a bucket are
+/// computed over the same set of events.
+#[derive(Default)]
+pub(super) struct FunnelConversionRate;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for FunnelConversionRate
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        build_query(
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            &FUNNEL_STAGES,
+        )?
+        .execute_query::<SdkEventMetricRow, _>(pool)
+        .await
+        .change_context(MetricsError::QueryBuildingError)?
+        .switch()
+        .map(|rows| rows.into_iter().map(into_bucket).collect())
+    }
+
+    async fn load_metrics_stream(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<super::SdkEventMetricStream> {
+        Ok(build_query(
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            &FUNNEL_STAGES,
+        )?
+        .execute_query_stream::<SdkEventMetricRow, _>(pool)
+        .await
+        .change_context(MetricsError::QueryBuildingError)?
+        .switch()?
+        .map(|row| row.switch().map(into_bucket))
+        .boxed())
+    }
+}
+
+/// Query counting each of `stages` in a single pass
+pub(super) fn build_query<T>(
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &super::SdkEventQueryOptions,
+    time_range: &TimeRange,
+    stages: &[FunnelStage],
+) -> MetricsResult<QueryBuilder<T>>
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+    let dimensions = dimensions.to_vec();
+
+    if dimensions.contains(&SdkEventDimensions::CustomerSegment)
+        || filters_on_customer_segment(filters)
+    {
+        query_builder.add_join(customer_segment_join()).switch()?;
+    }
+
+    for dim in dimensions.iter() {
+        query_builder
+            .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+            .switch()?;
+    }
+
+    for (event_name, alias) in stages.iter().map(|stage| stage_event(*stage)) {
+        query_builder
+            .add_select_column(format!("countIf(event_name = '{event_name}') as {alias}"))
+            .switch()?;
+    }
+
+    options.set_query_options(&mut query_builder).switch()?;
+    options
+        .set_pagination(&mut query_builder, &dimensions, granularity)
+        .switch()?;
+
+    if let Some(granularity) = granularity.as_ref() {
+        query_builder
+            .add_granularity_in_mins(granularity)
+            .switch()?;
+    }
+
+    filters.set_filter_clause(&mut query_builder).switch()?;
+
+    query_builder
+        .add_filter_clause("merchant_id", publishable_key)
+        .switch()?;
+
+    query_builder
+        .add_bool_filter_clause("first_event", 1)
+        .switch()?;
+
+    query_builder
+        .add_filter_in_range_clause(
+            "event_name",
+            &stages
+                .iter()
+                .map(|stage| stage_event(*stage).0)
+                .collect::<Vec<_>>(),
+        )
+        .switch()?;
+
+    time_range
+        .set_filter_clause(&mut query_builder)
+        .attach_printable("Error filtering time range")
+        .switch()?;
+
+    for dim in dimensions.iter() {
+        query_builder
+            .add_rollup_group_by_clause(options.dimension(dim))
+            .attach_printable("Error grouping by dimensions")
+            .switch()?;
+    }
+
+    if let Some(_granularity) = granularity.as_ref() {
+        query_builder
+            .add_group_by_clause("time_bucket")
+            .attach_printable("Error adding granularity")
+            .switch()?;
+    }
+
+    super::check_scoped(&query_builder)?;
+    Ok(query_builder)
+}
+
+pub(super) fn into_bucket(
+    i: SdkEventMetricRow,
+) -> (SdkEventMetricsBucketIdentifier, SdkEventMetricRow) {
+    (
+        SdkEventMetricsBucketIdentifier::new(
+            i.payment_method.clone(),
+            i.platform.clone(),
+            i.browser_name.clone(),
+            i.source.clone(),
+            i.component.clone(),
+            i.payment_experience.clone(),
+            i.sdk_version.clone(),
+            i.device_type.clone(),
+            i.os.clone(),
+            i.country.clone(),
+            i.connector.clone(),
+            i.event_name.clone(),
+            i.payment_method_type.clone(),
+            i.day_of_week.clone(),
+            i.hour_of_day.clone(),
+            i.authentication_error_reason.clone(),
+            i.field_name.clone(),
+            i.customer_segment.clone(),
+            i.cdn_region.clone(),
+            i.currency.clone(),
+            i.abandonment_reason.clone(),
+            i.time_bucket.clone(),
+        ),
+        i,
+    )
+}
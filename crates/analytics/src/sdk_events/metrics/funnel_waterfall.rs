@@ -0,0 +1,99 @@
+use api_models::analytics::{
+    sdk_events::{
+        FunnelStage, SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::{report, ResultExt};
+use time::PrimitiveDateTime;
+
+use super::{
+    funnel_conversion_rate::{build_query, into_bucket, FUNNEL_STAGES},
+    SdkEventMetricRow, SdkEventQueryOptions,
+};
+use crate::{
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Counts the requested stages of the sdk funnel in a single pass like
+/// [`super::funnel_conversion_rate::FunnelConversionRate`], so that the drops between them are
+/// computed over the same set of events.
+#[derive(Default)]
+pub(super) struct FunnelWaterfall;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for FunnelWaterfall
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        build_query(
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            &waterfall_stages(options)?,
+        )?
+        .execute_query::<SdkEventMetricRow, _>(pool)
+        .await
+        .change_context(MetricsError::QueryBuildingError)?
+        .switch()
+        .map(|rows| rows.into_iter().map(into_bucket).collect())
+    }
+}
+
+/// Stages of the waterfall in the order they're reached: the requested ones, or every stage in
+/// the order of the sdk flow. Fails with [`MetricsError::InvalidFunnelStages`] if none or one of
+/// them twice is requested.
+pub(super) fn waterfall_stages(options: &SdkEventQueryOptions) -> MetricsResult<Vec<FunnelStage>> {
+    let Some(stages) = &options.funnel_stages else {
+        return Ok(FUNNEL_STAGES.to_vec());
+    };
+    if stages.is_empty() {
+        return Err(report!(MetricsError::InvalidFunnelStages(
+            "no stage was requested".to_string()
+        )));
+    }
+    if let Some(stage) = stages
+        .iter()
+        .enumerate()
+        .find(|(index, stage)| stages.iter().take(*index).any(|other| other == *stage))
+        .map(|(_, stage)| stage)
+    {
+        return Err(report!(MetricsError::InvalidFunnelStages(format!(
+            "{stage} was requested twice"
+        ))));
+    }
+    Ok(stages.clone())
+}
+
+/// Marks every bucket with the stages of the waterfall, which the accumulator takes the drops
+/// between. This runs once the gaps are filled, so that the filled buckets are marked as well.
+pub(super) fn label_stages(
+    options: &SdkEventQueryOptions,
+    buckets: &mut [(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+) -> MetricsResult<()> {
+    let stages = waterfall_stages(options)?;
+    for (_, row) in buckets.iter_mut() {
+        row.funnel_stages = Some(stages.clone());
+    }
+    Ok(())
+}
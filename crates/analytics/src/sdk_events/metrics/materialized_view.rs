@@ -0,0 +1,126 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventOrderBy, SdkEventPagination,
+    },
+    Granularity, TimeRange,
+};
+use time::PrimitiveDateTime;
+
+use super::SdkEventQueryOptions;
+use crate::{query::Aggregate, sdk_events::types::filters_only_on, types::AnalyticsCollection};
+
+/// A materialized view pre-aggregating the events some metrics count, which is far cheaper to
+/// read than the events. Its rows hold the number of events of each hour, merchant, event name,
+/// log type, category, first event flag and combination of its `dimensions`. A metric reads it
+/// instead of the events when its query needs nothing else, see [`pick_materialized_view`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaterializedView {
+    pub collection: AnalyticsCollection,
+    /// Dimensions the events are rolled up by, the only ones the view can be grouped or
+    /// filtered by
+    pub dimensions: &'static [SdkEventDimensions],
+    /// Column with the number of events each row rolls up
+    pub count_column: &'static str,
+}
+
+impl MaterializedView {
+    /// Count of the events of a group, aliased `count` like the count of the events
+    pub(super) fn event_count(&self) -> Aggregate<&'static str> {
+        Aggregate::Sum {
+            field: self.count_column,
+            alias: Some("count"),
+        }
+    }
+}
+
+/// Hourly rollup of the events of the sdk funnel
+pub const SDK_EVENTS_HOURLY: MaterializedView = MaterializedView {
+    collection: AnalyticsCollection::SdkEventsHourly,
+    dimensions: &[
+        SdkEventDimensions::PaymentMethod,
+        SdkEventDimensions::Platform,
+        SdkEventDimensions::BrowserName,
+        SdkEventDimensions::Source,
+        SdkEventDimensions::Component,
+        SdkEventDimensions::SdkVersion,
+        SdkEventDimensions::Country,
+        SdkEventDimensions::EventName,
+    ],
+    count_column: "event_count",
+};
+
+/// The materialized view `metric` declares it can be read from, if any
+pub fn materialized_view(metric: &SdkEventMetrics) -> Option<MaterializedView> {
+    match metric {
+        SdkEventMetrics::PaymentAttempts
+        | SdkEventMetrics::PaymentMethodsCallCount
+        | SdkEventMetrics::SdkRenderedCount
+        | SdkEventMetrics::SdkInitiatedCount => Some(SDK_EVENTS_HOURLY),
+        _ => None,
+    }
+}
+
+/// The materialized view the query of `metric` reads if the data source has it, see
+/// [`crate::types::AnalyticsDataSource::has_materialized_views`], `None` to read the events.
+/// The view of the metric is read if it has everything the query needs:
+/// - the dimensions and filters are all ones the view keeps
+/// - the buckets are of an hour or more, in UTC, and the time range is of whole hours
+/// - the counts are of the events of the range, not of a sample, of the distinct event ids or
+///   of the archived events too, and the groups aren't picked or computed from single events,
+///   as the count threshold, the event count order, the top groups, the computed dimension and
+///   the windows over the counts are
+pub fn pick_materialized_view(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+) -> Option<MaterializedView> {
+    let view = materialized_view(metric)?;
+    let starts_hour = |time: &PrimitiveDateTime| {
+        time.minute() == 0 && time.second() == 0 && time.nanosecond() == 0
+    };
+    // The range includes its end and the events are timed to the second, a range of whole hours
+    // ends on the last second of an hour
+    let ends_hour = |time: &PrimitiveDateTime| time.minute() == 59 && time.second() == 59;
+    let hourly = match granularity {
+        None
+        | Some(
+            Granularity::OneHour
+            | Granularity::OneDay
+            | Granularity::Weekly
+            | Granularity::Monthly
+            | Granularity::Quarterly,
+        ) => true,
+        Some(
+            Granularity::OneMin
+            | Granularity::FiveMin
+            | Granularity::FifteenMin
+            | Granularity::ThirtyMin,
+        ) => false,
+    };
+    let readable = dimensions.iter().all(|dim| view.dimensions.contains(dim))
+        && filters_only_on(filters, view.dimensions)
+        && hourly
+        && options.time_zone.is_none()
+        && starts_hour(&time_range.start_time)
+        && time_range.end_time.as_ref().map_or(true, ends_hour)
+        && options.sample_rate.is_none()
+        && !options.deduplicate
+        && !options.include_archived
+        && options.count_threshold.is_none()
+        && !matches!(
+            options.pagination,
+            Some(SdkEventPagination {
+                order_by: SdkEventOrderBy::EventCount,
+                ..
+            })
+        )
+        && options.top_groups.is_none()
+        && options.computed_dimension.is_none()
+        && options.moving_average_buckets.is_none()
+        && !options.running_total
+        && !options.percent_of_total;
+    readable.then_some(view)
+}
@@ -0,0 +1,108 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+    },
+    Granularity, TimeRange,
+};
+use error_stack::report;
+use time::PrimitiveDateTime;
+
+use super::{SdkEventMetric, SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventQueryOptions};
+use crate::{
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Query of a single sdk event metric, set up field by field instead of through the positional
+/// arguments of [`SdkEventMetric::load_metrics`], which it runs. The publishable key and the
+/// time range have to be set, everything else defaults to the metric over every event of the
+/// range in a single bucket.
+#[derive(Debug, Clone)]
+pub struct SdkMetricQuery {
+    metric: SdkEventMetrics,
+    dimensions: Vec<SdkEventDimensions>,
+    filters: SdkEventFilters,
+    granularity: Option<Granularity>,
+    options: SdkEventQueryOptions,
+    publishable_key: Option<String>,
+    time_range: Option<TimeRange>,
+}
+
+impl SdkMetricQuery {
+    pub fn new(metric: SdkEventMetrics) -> Self {
+        Self {
+            metric,
+            dimensions: Vec::new(),
+            filters: SdkEventFilters::default(),
+            granularity: None,
+            options: SdkEventQueryOptions::default(),
+            publishable_key: None,
+            time_range: None,
+        }
+    }
+
+    pub fn dimensions(mut self, dimensions: Vec<SdkEventDimensions>) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    pub fn filters(mut self, filters: SdkEventFilters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn granularity(mut self, granularity: Granularity) -> Self {
+        self.granularity = Some(granularity);
+        self
+    }
+
+    pub fn options(mut self, options: SdkEventQueryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn publishable_key(mut self, publishable_key: impl Into<String>) -> Self {
+        self.publishable_key = Some(publishable_key.into());
+        self
+    }
+
+    pub fn time_range(mut self, time_range: TimeRange) -> Self {
+        self.time_range = Some(time_range);
+        self
+    }
+
+    /// Loads the buckets of the metric, failing with [`MetricsError::IncompleteQuery`] before
+    /// anything is queried if the publishable key or the time range wasn't set
+    pub async fn execute<T>(
+        &self,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>>
+    where
+        T: AnalyticsDataSource + SdkEventMetricAnalytics,
+        PrimitiveDateTime: ToSql<T>,
+        AnalyticsCollection: ToSql<T>,
+        Granularity: GroupByClause<T>,
+        Aggregate<&'static str>: ToSql<T>,
+        Window<&'static str>: ToSql<T>,
+    {
+        let publishable_key = self
+            .publishable_key
+            .as_deref()
+            .ok_or_else(|| report!(MetricsError::IncompleteQuery("publishable key".to_string())))?;
+        let time_range = self
+            .time_range
+            .as_ref()
+            .ok_or_else(|| report!(MetricsError::IncompleteQuery("time range".to_string())))?;
+        self.metric
+            .load_metrics(
+                &self.dimensions,
+                publishable_key,
+                &self.filters,
+                &self.granularity,
+                &self.options,
+                time_range,
+                pool,
+            )
+            .await
+    }
+}
@@ -0,0 +1,163 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier, SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::ResultExt;
+use time::PrimitiveDateTime;
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{
+        Aggregate, FilterTypes, GroupByClause, QueryBuilder, QueryFilter, Rounded, ToSql, Window,
+    },
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Sum of the amounts of the attempted payments, in the minor unit of their currency. The
+/// amounts of different currencies can't be added up, the dispatcher only loads the metric if
+/// its buckets are of a single currency, see [`super::validate_currency`].
+#[derive(Default)]
+pub(super) struct PaymentAttemptedAmount;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for PaymentAttemptedAmount
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        let dimensions = dimensions.to_vec();
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                .switch()?;
+        }
+
+        query_builder
+            .add_select_column(Aggregate::Count {
+                field: None,
+                alias: Some("count"),
+            })
+            .switch()?;
+
+        query_builder
+            .add_select_column(Rounded {
+                column: Aggregate::Sum {
+                    field: "amount",
+                    alias: None,
+                },
+                alias: "total",
+                decimal_places: options.decimal_places,
+            })
+            .switch()?;
+
+        options.set_query_options(&mut query_builder).switch()?;
+        options
+            .set_pagination(&mut query_builder, &dimensions, granularity)
+            .switch()?;
+
+        if let Some(granularity) = granularity.as_ref() {
+            query_builder
+                .add_granularity_in_mins(granularity)
+                .switch()?;
+        }
+
+        filters.set_filter_clause(&mut query_builder).switch()?;
+
+        query_builder
+            .add_filter_clause("merchant_id", publishable_key)
+            .switch()?;
+
+        query_builder
+            .add_bool_filter_clause("first_event", 1)
+            .switch()?;
+
+        query_builder
+            .add_filter_clause("event_name", SdkEventNames::PaymentAttempt)
+            .switch()?;
+
+        query_builder
+            .add_custom_filter_clause("amount", "NULL", FilterTypes::IsNotNull)
+            .switch()?;
+
+        time_range
+            .set_filter_clause(&mut query_builder)
+            .attach_printable("Error filtering time range")
+            .switch()?;
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_rollup_group_by_clause(options.dimension(dim))
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+
+        if let Some(_granularity) = granularity.as_ref() {
+            query_builder
+                .add_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+        }
+
+        super::check_scoped(&query_builder)?;
+
+        query_builder
+            .execute_query::<SdkEventMetricRow, _>(pool)
+            .await
+            .change_context(MetricsError::QueryBuildingError)?
+            .switch()?
+            .into_iter()
+            .map(|i| {
+                Ok((
+                    SdkEventMetricsBucketIdentifier::new(
+                        i.payment_method.clone(),
+                        i.platform.clone(),
+                        i.browser_name.clone(),
+                        i.source.clone(),
+                        i.component.clone(),
+                        i.payment_experience.clone(),
+                        i.sdk_version.clone(),
+                        i.device_type.clone(),
+                        i.os.clone(),
+                        i.country.clone(),
+                        i.connector.clone(),
+                        i.event_name.clone(),
+                        i.payment_method_type.clone(),
+                        i.day_of_week.clone(),
+                        i.hour_of_day.clone(),
+                        i.authentication_error_reason.clone(),
+                        i.field_name.clone(),
+                        i.customer_segment.clone(),
+                        i.cdn_region.clone(),
+                        i.currency.clone(),
+                        i.abandonment_reason.clone(),
+                        i.time_bucket.clone(),
+                    ),
+                    i,
+                ))
+            })
+            .collect::<error_stack::Result<
+                Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+                crate::query::PostProcessingError,
+            >>()
+            .change_context(MetricsError::PostProcessingFailure)
+    }
+}
@@ -1,17 +1,18 @@
 use api_models::analytics::{
     sdk_events::{
-        SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier, SdkEventNames,
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
     },
     Granularity, TimeRange,
 };
-use common_utils::errors::ReportSwitchExt;
-use error_stack::ResultExt;
 use time::PrimitiveDateTime;
 
-use super::SdkEventMetricRow;
+use super::{
+    count::{load_count_metric, load_count_metric_stream},
+    SdkEventMetricRow,
+};
 use crate::{
-    query::{Aggregate, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
-    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsResult},
 };
 
 #[derive(Default)]
@@ -33,86 +34,43 @@ where
         publishable_key: &str,
         filters: &SdkEventFilters,
         granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
         time_range: &TimeRange,
         pool: &T,
     ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
-        let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
-        let dimensions = dimensions.to_vec();
-
-        for dim in dimensions.iter() {
-            query_builder.add_select_column(dim).switch()?;
-        }
-
-        query_builder
-            .add_select_column(Aggregate::Count {
-                field: None,
-                alias: Some("count"),
-            })
-            .switch()?;
-
-        if let Some(granularity) = granularity.as_ref() {
-            query_builder
-                .add_granularity_in_mins(granularity)
-                .switch()?;
-        }
-
-        filters.set_filter_clause(&mut query_builder).switch()?;
-
-        query_builder
-            .add_filter_clause("merchant_id", publishable_key)
-            .switch()?;
-
-        query_builder
-            .add_bool_filter_clause("first_event", 1)
-            .switch()?;
-
-        query_builder
-            .add_filter_clause("event_name", SdkEventNames::PaymentAttempt)
-            .switch()?;
-
-        time_range
-            .set_filter_clause(&mut query_builder)
-            .attach_printable("Error filtering time range")
-            .switch()?;
-
-        for dim in dimensions.iter() {
-            query_builder
-                .add_group_by_clause(dim)
-                .attach_printable("Error grouping by dimensions")
-                .switch()?;
-        }
-
-        if let Some(_granularity) = granularity.as_ref() {
-            query_builder
-                .add_group_by_clause("time_bucket")
-                .attach_printable("Error adding granularity")
-                .switch()?;
-        }
+        load_count_metric(
+            &SdkEventMetrics::PaymentAttempts,
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            pool,
+        )
+        .await
+    }
 
-        query_builder
-            .execute_query::<SdkEventMetricRow, _>(pool)
-            .await
-            .change_context(MetricsError::QueryBuildingError)?
-            .change_context(MetricsError::QueryExecutionFailure)?
-            .into_iter()
-            .map(|i| {
-                Ok((
-                    SdkEventMetricsBucketIdentifier::new(
-                        i.payment_method.clone(),
-                        i.platform.clone(),
-                        i.browser_name.clone(),
-                        i.source.clone(),
-                        i.component.clone(),
-                        i.payment_experience.clone(),
-                        i.time_bucket.clone(),
-                    ),
-                    i,
-                ))
-            })
-            .collect::<error_stack::Result<
-                Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
-                crate::query::PostProcessingError,
-            >>()
-            .change_context(MetricsError::PostProcessingFailure)
+    async fn load_metrics_stream(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<super::SdkEventMetricStream> {
+        load_count_metric_stream(
+            &SdkEventMetrics::PaymentAttempts,
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            pool,
+        )
+        .await
     }
 }
@@ -0,0 +1,200 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier, SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::ResultExt;
+use time::PrimitiveDateTime;
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{Aggregate, GroupByClause, QueryBuilder, QueryFilter, Rounded, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Payment attempts a session made after its first one, the payment id identifies the sdk session.
+/// Only sessions with at least one attempt are counted, whether they succeeded or were abandoned.
+/// `total` holds the summed retries, `count` the number of sessions and `max_retries` the most
+/// retries of a single session.
+#[derive(Default)]
+pub(super) struct PaymentRetryCount;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for PaymentRetryCount
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        let dimensions = dimensions.to_vec();
+
+        // The inner query counts the retries of every session, the outer one aggregates the
+        // sessions of each bucket
+        for dim in dimensions.iter() {
+            query_builder
+                .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                .switch()?;
+            query_builder
+                .add_outer_select_column(dim.as_ref())
+                .switch()?;
+        }
+
+        query_builder
+            .add_select_column("count(*) - 1 as retries")
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column(Aggregate::Count {
+                field: None,
+                alias: Some("count"),
+            })
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column(Rounded {
+                column: Aggregate::Sum {
+                    field: "retries",
+                    alias: None,
+                },
+                alias: "total",
+                decimal_places: options.decimal_places,
+            })
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column(Aggregate::Max {
+                field: "retries",
+                alias: Some("max_retries"),
+            })
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column(Aggregate::Quantiles {
+                field: "retries",
+                quantiles: super::PERCENTILES,
+            })
+            .switch()?;
+
+        // The count threshold would apply to the events of a single session here, only the time
+        // zone, the collections read, the row cap and the pagination, which pages the outer query,
+        // are taken from the options
+        if let Some(time_zone) = &options.time_zone {
+            query_builder.set_time_zone(time_zone).switch()?;
+        }
+        options.set_source_options(&mut query_builder).switch()?;
+        options
+            .set_pagination(&mut query_builder, &dimensions, granularity)
+            .switch()?;
+
+        if let Some(granularity) = granularity.as_ref() {
+            query_builder
+                .add_granularity_in_mins(granularity)
+                .switch()?;
+            query_builder
+                .add_outer_select_column("time_bucket")
+                .switch()?;
+        }
+
+        filters.set_filter_clause(&mut query_builder).switch()?;
+
+        query_builder
+            .add_filter_clause("merchant_id", publishable_key)
+            .switch()?;
+
+        // Not restricted to the first event, every attempt of the session is a row
+        query_builder
+            .add_filter_clause("event_name", SdkEventNames::PaymentAttempt)
+            .switch()?;
+
+        time_range
+            .set_filter_clause(&mut query_builder)
+            .attach_printable("Error filtering time range")
+            .switch()?;
+
+        query_builder
+            .add_group_by_clause("payment_id")
+            .attach_printable("Error grouping by session")
+            .switch()?;
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_group_by_clause(options.dimension(dim))
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+            query_builder
+                .add_outer_group_by_clause(dim.as_ref())
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+
+        if let Some(_granularity) = granularity.as_ref() {
+            query_builder
+                .add_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+            query_builder
+                .add_outer_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+        }
+
+        super::check_scoped(&query_builder)?;
+
+        query_builder
+            .execute_query::<SdkEventMetricRow, _>(pool)
+            .await
+            .change_context(MetricsError::QueryBuildingError)?
+            .switch()?
+            .into_iter()
+            .map(|i| {
+                Ok((
+                    SdkEventMetricsBucketIdentifier::new(
+                        i.payment_method.clone(),
+                        i.platform.clone(),
+                        i.browser_name.clone(),
+                        i.source.clone(),
+                        i.component.clone(),
+                        i.payment_experience.clone(),
+                        i.sdk_version.clone(),
+                        i.device_type.clone(),
+                        i.os.clone(),
+                        i.country.clone(),
+                        i.connector.clone(),
+                        i.event_name.clone(),
+                        i.payment_method_type.clone(),
+                        i.day_of_week.clone(),
+                        i.hour_of_day.clone(),
+                        i.authentication_error_reason.clone(),
+                        i.field_name.clone(),
+                        i.customer_segment.clone(),
+                        i.cdn_region.clone(),
+                        i.currency.clone(),
+                        i.abandonment_reason.clone(),
+                        i.time_bucket.clone(),
+                    ),
+                    i,
+                ))
+            })
+            .collect::<error_stack::Result<
+                Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+                crate::query::PostProcessingError,
+            >>()
+            .change_context(MetricsError::PostProcessingFailure)
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::{report, ResultExt};
+use time::PrimitiveDateTime;
+
+use super::{
+    batch::{count_conditions, into_bucket},
+    compare_buckets, SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventQueryOptions,
+};
+use crate::{
+    query::{Aggregate, GroupByClause, QueryBuilder, QueryFilter, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// A bucket of one merchant
+#[derive(Debug)]
+pub struct SdkEventMerchantBucket {
+    pub merchant_id: String,
+    pub id: SdkEventMetricsBucketIdentifier,
+    pub row: SdkEventMetricRow,
+}
+
+/// A metric over the events of every merchant
+#[derive(Debug)]
+pub struct SdkEventPlatformRollup {
+    /// The buckets of each merchant, sorted by merchant and then like [`compare_buckets`] sorts
+    /// them
+    pub buckets: Vec<SdkEventMerchantBucket>,
+    /// Number of merchants with a bucket in each group, in the order of [`compare_buckets`]
+    pub merchants_per_group: Vec<(SdkEventMetricsBucketIdentifier, usize)>,
+    /// Number of merchants with any bucket
+    pub distinct_merchants: usize,
+}
+
+/// Loads `metric` for the events of every merchant, grouped by merchant and `dimensions`.
+///
+/// This is meant for platform operators only and must never be reachable by a merchant: unlike
+/// [`super::SdkEventMetric::load_metrics`], which is always scoped to a single publishable key,
+/// it reads the events of all merchants. Only the metrics which are a plain count of events can
+/// be rolled up. Of `options` only the time zone, count threshold, timeout, query logging and
+/// sample rate apply, the threshold applies to the groups of each merchant.
+#[allow(clippy::too_many_arguments)]
+pub async fn load_platform_rollup<T>(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<SdkEventPlatformRollup>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let conditions = count_conditions(metric)
+        .ok_or_else(|| report!(MetricsError::UnsupportedMetric(metric.to_string())))
+        .attach_printable("Only the count metrics can be rolled up across merchants")?;
+    super::validate_dimensions(metric, dimensions)?;
+    super::validate_filters(metric, filters)?;
+    // The count conditions are the ones of the metric without input fields
+    if dimensions.contains(&SdkEventDimensions::FieldName) {
+        return Err(report!(MetricsError::UnsupportedDimension(
+            SdkEventDimensions::FieldName.to_string()
+        )))
+        .attach_printable("The rollups across merchants can't be grouped by input field");
+    }
+    let time_range = &super::validate_time_range(time_range, pool.max_time_range())?;
+    // The buckets of a merchant aren't grouped by a computed dimension or into subtotals
+    let options = &SdkEventQueryOptions {
+        computed_dimension: None,
+        subtotals: false,
+        ..options.clone()
+    };
+
+    let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+
+    query_builder.add_select_column("merchant_id").switch()?;
+    for dim in dimensions.iter() {
+        query_builder
+            .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+            .switch()?;
+    }
+
+    query_builder
+        .add_select_column(Aggregate::Count {
+            field: None,
+            alias: Some("count"),
+        })
+        .switch()?;
+
+    options.set_query_options(&mut query_builder).switch()?;
+
+    if let Some(granularity) = granularity.as_ref() {
+        query_builder
+            .add_granularity_in_mins(granularity)
+            .switch()?;
+    }
+
+    filters.set_filter_clause(&mut query_builder).switch()?;
+
+    for (column, filter_type, value) in conditions {
+        query_builder
+            .add_custom_filter_clause(column, value, filter_type)
+            .switch()?;
+    }
+
+    time_range
+        .set_filter_clause(&mut query_builder)
+        .attach_printable("Error filtering time range")
+        .switch()?;
+
+    query_builder
+        .add_group_by_clause("merchant_id")
+        .attach_printable("Error grouping by merchant")
+        .switch()?;
+
+    for dim in dimensions.iter() {
+        query_builder
+            .add_group_by_clause(options.dimension(dim))
+            .attach_printable("Error grouping by dimensions")
+            .switch()?;
+    }
+
+    if let Some(_granularity) = granularity.as_ref() {
+        query_builder
+            .add_group_by_clause("time_bucket")
+            .attach_printable("Error adding granularity")
+            .switch()?;
+    }
+
+    // Every merchant is read on purpose, but never from the start of time
+    query_builder
+        .check_scoped(None)
+        .change_context(MetricsError::UnscopedQuery)?;
+    let rows = query_builder
+        .execute_query::<SdkEventMetricRow, _>(pool)
+        .await
+        .change_context(MetricsError::QueryBuildingError)?
+        .switch()?;
+
+    let mut by_merchant = HashMap::<String, Vec<_>>::new();
+    for row in rows {
+        let merchant_id = row
+            .merchant_id
+            .clone()
+            .ok_or_else(|| report!(MetricsError::PostProcessingFailure))
+            .attach_printable("Rolled up row without a merchant")?;
+        by_merchant
+            .entry(merchant_id)
+            .or_default()
+            .push(into_bucket(row));
+    }
+    let mut by_merchant = by_merchant.into_iter().collect::<Vec<_>>();
+    by_merchant.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+    let mut buckets = Vec::new();
+    let mut groups = HashMap::<SdkEventMetricsBucketIdentifier, HashSet<String>>::new();
+    for (merchant_id, merchant_buckets) in by_merchant.iter_mut() {
+        let loaded = std::mem::take(merchant_buckets);
+        let mut loaded =
+            options.fill_time_series_gaps(loaded, dimensions, granularity, time_range)?;
+        options.scale_sampled_buckets(metric, &mut loaded)?;
+        loaded.sort_by(|(left, _), (right, _)| compare_buckets(left, right, dimensions));
+        for (id, row) in loaded {
+            // A filled gap has no events of the merchant
+            if row.count.unwrap_or_default() > 0 {
+                groups
+                    .entry(id.clone())
+                    .or_default()
+                    .insert(merchant_id.clone());
+            }
+            buckets.push(SdkEventMerchantBucket {
+                merchant_id: merchant_id.clone(),
+                id,
+                row,
+            });
+        }
+    }
+
+    let mut merchants_per_group = groups
+        .into_iter()
+        .map(|(id, merchants)| (id, merchants.len()))
+        .collect::<Vec<_>>();
+    merchants_per_group.sort_by(|(left, _), (right, _)| compare_buckets(left, right, dimensions));
+
+    Ok(SdkEventPlatformRollup {
+        distinct_merchants: by_merchant.len(),
+        buckets,
+        merchants_per_group,
+    })
+}
@@ -0,0 +1,92 @@
+use api_models::analytics::{
+    sdk_events::{SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier},
+    Granularity, TimeRange,
+};
+use error_stack::report;
+use router_env::logger;
+use time::PrimitiveDateTime;
+
+use super::{
+    batch::load_metrics_batch, SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventQueryOptions,
+};
+use crate::{
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Minutes the recent metrics are taken over unless the request picks a window
+pub const DEFAULT_RECENT_WINDOW_MINS: u32 = 5;
+
+/// Longest window of the recent metrics, longer ones are capped to it. The window is read in full
+/// on every poll, its queries are only meant for the last few minutes.
+pub const MAX_RECENT_WINDOW_MINS: u32 = 60;
+
+/// Partitions of the events a recent metric query reads at most. The events are partitioned by
+/// day like the other tables, a window of at most an hour reads two of them when it spans
+/// midnight.
+pub const RECENT_MAX_PARTITIONS: u32 = 2;
+
+/// The last `window_mins` minutes up to `now`, the default window without one
+pub fn recent_time_range(
+    window_mins: Option<u32>,
+    now: PrimitiveDateTime,
+) -> MetricsResult<TimeRange> {
+    let window_mins = window_mins.unwrap_or(DEFAULT_RECENT_WINDOW_MINS);
+    if window_mins == 0 {
+        return Err(report!(MetricsError::InvalidTimeRange(
+            "the recent window is empty".to_string()
+        )));
+    }
+    if window_mins > MAX_RECENT_WINDOW_MINS {
+        logger::warn!(window_mins, "Capping the recent sdk event metrics window");
+    }
+    let window = time::Duration::minutes(i64::from(window_mins.min(MAX_RECENT_WINDOW_MINS)));
+    Ok(TimeRange {
+        start_time: now - window,
+        end_time: Some(now),
+    })
+}
+
+/// Loads `metrics` over the recent `time_range`, see [`recent_time_range`], as a single bucket per
+/// metric: without dimensions or time buckets, and with none of the options reshaping the groups.
+/// The count metrics share one query, see [`load_metrics_batch`].
+///
+/// The events are still arriving, so the buckets are loaded from the data source rather than from
+/// its cache and aren't cached, and each query reads at most [`RECENT_MAX_PARTITIONS`] partitions.
+pub async fn load_recent_metrics<T>(
+    metrics: &[SdkEventMetrics],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<
+    Vec<(
+        SdkEventMetrics,
+        Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+    )>,
+>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let options = SdkEventQueryOptions {
+        max_partitions: Some(RECENT_MAX_PARTITIONS),
+        bypass_cache: true,
+        ..Default::default()
+    };
+    load_metrics_batch(
+        metrics,
+        &[],
+        publishable_key,
+        filters,
+        &None,
+        &options,
+        time_range,
+        pool,
+    )
+    .await
+}
@@ -0,0 +1,136 @@
+use std::sync::Mutex;
+
+use api_models::analytics::{
+    sdk_events::{SdkEventDimensions, SdkEventFilters, SdkEventMetrics},
+    Granularity, TimeRange,
+};
+use common_utils::errors::{CustomResult, ParsingError};
+use error_stack::report;
+use time::PrimitiveDateTime;
+
+use super::{SdkEventBatchRow, SdkEventMetric, SdkEventMetricAnalytics, SdkEventMetricRow};
+use crate::{
+    clickhouse::ClickhouseClient,
+    query::{Aggregate, GroupByClause, QueryBuilder, QueryResult, ToSql, Window},
+    types::{
+        AnalyticsCollection, AnalyticsDataSource, LoadRow, MetricsError, MetricsResult,
+        QueryExecutionError, TableEngine,
+    },
+};
+
+/// Data source which records the queries it's asked to run instead of running them, and answers
+/// every query without rows. Queries are lowered the same way as for clickhouse, which is where
+/// sdk events are stored.
+#[derive(Debug, Default)]
+pub struct SdkEventSqlPreview {
+    queries: Mutex<Vec<String>>,
+}
+
+impl SdkEventSqlPreview {
+    /// The queries recorded so far, in the order they were asked for
+    pub fn into_queries(self) -> MetricsResult<Vec<String>> {
+        self.queries
+            .into_inner()
+            .map_err(|_| report!(MetricsError::PostProcessingFailure))
+    }
+}
+
+#[async_trait::async_trait]
+impl AnalyticsDataSource for SdkEventSqlPreview {
+    type Row = serde_json::Value;
+
+    fn source_name(&self) -> &'static str {
+        "SqlPreview"
+    }
+
+    async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        self.queries
+            .lock()
+            .map_err(|_| report!(QueryExecutionError::DatabaseError))?
+            .push(query.to_string());
+        Ok(Vec::new())
+    }
+
+    fn get_table_engine(table: AnalyticsCollection) -> TableEngine {
+        ClickhouseClient::get_table_engine(table)
+    }
+}
+
+impl LoadRow<SdkEventMetricRow> for SdkEventSqlPreview {
+    fn load_row(row: Self::Row) -> CustomResult<SdkEventMetricRow, QueryExecutionError> {
+        ClickhouseClient::load_row(row)
+    }
+}
+
+impl LoadRow<SdkEventBatchRow> for SdkEventSqlPreview {
+    fn load_row(row: Self::Row) -> CustomResult<SdkEventBatchRow, QueryExecutionError> {
+        ClickhouseClient::load_row(row)
+    }
+}
+
+impl SdkEventMetricAnalytics for SdkEventSqlPreview {}
+
+impl ToSql<SdkEventSqlPreview> for PrimitiveDateTime {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl ToSql<SdkEventSqlPreview> for AnalyticsCollection {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl ToSql<SdkEventSqlPreview> for Aggregate<&'static str> {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl ToSql<SdkEventSqlPreview> for Window<&'static str> {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl GroupByClause<SdkEventSqlPreview> for Granularity {
+    fn set_group_by_clause(
+        &self,
+        _builder: &mut QueryBuilder<SdkEventSqlPreview>,
+    ) -> QueryResult<()> {
+        // Sdk event metrics bucket with `add_granularity_in_mins` instead
+        Ok(())
+    }
+}
+
+/// The SQL `metric` runs for these arguments, without running it. Values are inlined into the
+/// query, so it can be pasted into a clickhouse console as is. Metrics which run several queries
+/// return all of them in the order they're run.
+#[allow(clippy::too_many_arguments)]
+pub async fn render_metric_sql(
+    metric: &SdkEventMetrics,
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &super::SdkEventQueryOptions,
+    time_range: &TimeRange,
+) -> MetricsResult<Vec<String>> {
+    let preview = SdkEventSqlPreview::default();
+    metric
+        .load_metrics(
+            dimensions,
+            publishable_key,
+            filters,
+            granularity,
+            options,
+            time_range,
+            &preview,
+        )
+        .await?;
+    preview.into_queries()
+}
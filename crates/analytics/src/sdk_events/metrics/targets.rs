@@ -0,0 +1,42 @@
+use api_models::analytics::sdk_events::{
+    SdkEventMetricTarget, SdkEventMetrics, SdkEventMetricsBucketIdentifier, SdkEventTargetResult,
+    TargetDirection,
+};
+
+use super::{compare::metric_value, SdkEventMetricRow};
+
+/// Compares the value of `metric` in the bucket `id` to the target of `targets` applying to it.
+/// The targets of the metric apply to the buckets having every one of their dimension values,
+/// the one with the most of them applies if several do, the first listed of those if they're as
+/// many. `None` if no target applies to the bucket.
+pub fn target_result(
+    metric: &SdkEventMetrics,
+    id: &SdkEventMetricsBucketIdentifier,
+    row: &SdkEventMetricRow,
+    targets: &[SdkEventMetricTarget],
+) -> Option<SdkEventTargetResult> {
+    let target = targets
+        .iter()
+        .filter(|target| &target.metric == metric)
+        .filter(|target| {
+            target
+                .dimensions
+                .iter()
+                .all(|(dimension, value)| id.dimension(dimension) == Some(value.as_str()))
+        })
+        // `max_by_key` keeps the last of the equal ones, whereas the first listed should win
+        .rev()
+        .max_by_key(|target| target.dimensions.len())?;
+
+    let value = metric_value(metric, row);
+    Some(SdkEventTargetResult {
+        metric: metric.clone(),
+        target: target.target,
+        direction: target.direction,
+        value,
+        meets_target: value.map(|value| match target.direction {
+            TargetDirection::AtLeast => value >= target.target,
+            TargetDirection::AtMost => value <= target.target,
+        }),
+    })
+}
@@ -0,0 +1,255 @@
+#![allow(clippy::expect_used)]
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+};
+
+use api_models::analytics::{
+    sdk_events::{
+        FunnelWaterfallStage, SdkEventMetricTarget, SdkEventSampleRequest, TargetDirection,
+    },
+    HourWindow, NumericRange,
+};
+use common_utils::errors::{CustomResult, ParsingError};
+use error_stack::ResultExt;
+use time::{Date, Duration, Month, Time};
+
+use super::*;
+use crate::{
+    clickhouse::ClickhouseClient,
+    health_check::HealthCheck,
+    query::{QueryBuilder, QueryFilter, QueryResult, NULL_DIMENSION_LABEL},
+    sdk_events::{
+        accumulator::{
+            AverageAccumulator, BounceRateAccumulator, MinMaxAccumulator,
+            SdkEventMetricAccumulator, SdkEventMetricsAccumulator, SuccessRateAccumulator,
+            ThreeDsFlowAccumulator,
+        },
+        events::{get_sdk_event_sample, SdkEventsFilterAnalytics, SdkEventsResult},
+    },
+    types::{
+        MerchantQueryLimit, MerchantQueryLimiter, MetricsError, QueryExecutionError, TableEngine,
+    },
+    FallbackSource, LoadRawRows,
+};
+
+mod batching;
+mod caching;
+mod comparisons;
+mod data_sources;
+mod dimensions;
+mod filters;
+mod funnels;
+mod gap_filling;
+mod insights;
+mod limits;
+mod materialized_views;
+mod query_options;
+mod ranking;
+mod recency;
+mod sampling;
+mod streaming;
+mod values;
+
+/// Data source which records the queries it runs and answers every query with `rows`,
+/// queries are lowered the same way as for clickhouse
+#[derive(Default)]
+struct MockPool {
+    queries: Mutex<Vec<String>>,
+    rows: Vec<serde_json::Value>,
+    /// Rows of the next queries, in the order they're run, before falling back to `rows`
+    responses: Mutex<VecDeque<Vec<serde_json::Value>>>,
+    max_scanned_rows: Option<u64>,
+    max_result_rows: Option<usize>,
+    /// Has the materialized views of the sdk events
+    materialized_views: bool,
+    max_time_range: Option<Duration>,
+    merchant_query_limiter: Option<MerchantQueryLimiter>,
+    /// Never answers the health check, and fails its queries as if it couldn't be reached
+    unreachable: bool,
+    metrics_cache: Option<SdkEventMetricsCache>,
+}
+
+impl MockPool {
+    fn with_rows(rows: Vec<serde_json::Value>) -> Self {
+        Self {
+            queries: Mutex::default(),
+            rows,
+            responses: Mutex::default(),
+            max_scanned_rows: None,
+            max_result_rows: None,
+            materialized_views: false,
+            max_time_range: None,
+            merchant_query_limiter: None,
+            unreachable: false,
+            metrics_cache: None,
+        }
+    }
+
+    fn last_query(&self) -> String {
+        self.queries
+            .lock()
+            .expect("queries lock poisoned")
+            .last()
+            .cloned()
+            .expect("no query was run")
+    }
+}
+
+#[async_trait::async_trait]
+impl AnalyticsDataSource for MockPool {
+    type Row = serde_json::Value;
+
+    fn source_name(&self) -> &'static str {
+        "Mock"
+    }
+
+    async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        self.load_raw_rows(query, None)
+            .await?
+            .into_iter()
+            .map(<Self as LoadRow<T>>::load_row)
+            .collect()
+    }
+
+    /// Every query is estimated to read as many rows as it returns
+    async fn estimate_scanned_rows(
+        &self,
+        _query: &str,
+    ) -> CustomResult<Option<u64>, QueryExecutionError> {
+        Ok(u64::try_from(self.rows.len()).ok())
+    }
+
+    fn max_scanned_rows(&self) -> Option<u64> {
+        self.max_scanned_rows
+    }
+
+    fn max_result_rows(&self) -> Option<usize> {
+        self.max_result_rows
+    }
+
+    fn has_materialized_views(&self) -> bool {
+        self.materialized_views
+    }
+
+    fn max_time_range(&self) -> Option<Duration> {
+        self.max_time_range
+    }
+
+    fn merchant_query_limiter(&self) -> Option<&MerchantQueryLimiter> {
+        self.merchant_query_limiter.as_ref()
+    }
+
+    fn health_check_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(10)
+    }
+}
+
+#[async_trait::async_trait]
+impl LoadRawRows for MockPool {
+    async fn load_raw_rows(
+        &self,
+        query: &str,
+        _timeout: Option<std::time::Duration>,
+    ) -> CustomResult<Vec<serde_json::Value>, QueryExecutionError> {
+        if self.unreachable {
+            return Err(error_stack::report!(QueryExecutionError::ConnectionFailure));
+        }
+        self.queries
+            .lock()
+            .expect("queries lock poisoned")
+            .push(query.to_string());
+        Ok(self
+            .responses
+            .lock()
+            .expect("responses lock poisoned")
+            .pop_front()
+            .unwrap_or_else(|| self.rows.clone()))
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for MockPool {
+    async fn deep_health_check(&self) -> CustomResult<(), QueryExecutionError> {
+        if self.unreachable {
+            std::future::pending::<()>().await;
+        }
+        self.queries
+            .lock()
+            .expect("queries lock poisoned")
+            .push("SELECT 1".to_string());
+        Ok(())
+    }
+}
+
+impl LoadRow<SdkEventMetricRow> for MockPool {
+    fn load_row(row: Self::Row) -> CustomResult<SdkEventMetricRow, QueryExecutionError> {
+        serde_json::from_value(row).change_context(QueryExecutionError::RowExtractionFailure)
+    }
+}
+
+impl LoadRow<SdkEventBatchRow> for MockPool {
+    fn load_row(row: Self::Row) -> CustomResult<SdkEventBatchRow, QueryExecutionError> {
+        serde_json::from_value(row).change_context(QueryExecutionError::RowExtractionFailure)
+    }
+}
+
+impl LoadRow<SdkEventsResult> for MockPool {
+    fn load_row(row: Self::Row) -> CustomResult<SdkEventsResult, QueryExecutionError> {
+        serde_json::from_value(row).change_context(QueryExecutionError::RowExtractionFailure)
+    }
+}
+
+impl SdkEventsFilterAnalytics for MockPool {}
+
+impl SdkEventMetricAnalytics for MockPool {
+    fn metrics_cache(&self) -> Option<&SdkEventMetricsCache> {
+        self.metrics_cache.as_ref()
+    }
+}
+
+impl ToSql<MockPool> for PrimitiveDateTime {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl ToSql<MockPool> for AnalyticsCollection {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl ToSql<MockPool> for Aggregate<&'static str> {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl ToSql<MockPool> for Window<&'static str> {
+    fn to_sql(&self, table_engine: &TableEngine) -> error_stack::Result<String, ParsingError> {
+        <Self as ToSql<ClickhouseClient>>::to_sql(self, table_engine)
+    }
+}
+
+impl GroupByClause<MockPool> for Granularity {
+    fn set_group_by_clause(&self, _builder: &mut QueryBuilder<MockPool>) -> QueryResult<()> {
+        // Sdk event metrics bucket with `add_granularity_in_mins` instead
+        Ok(())
+    }
+}
+
+fn seven_day_range() -> TimeRange {
+    let start_time = PrimitiveDateTime::new(
+        Date::from_calendar_date(2024, Month::March, 1).expect("invalid date"),
+        Time::MIDNIGHT,
+    );
+    TimeRange {
+        start_time,
+        end_time: Some(start_time + Duration::days(7)),
+    }
+}
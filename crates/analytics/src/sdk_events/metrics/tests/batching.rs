@@ -0,0 +1,213 @@
+use super::*;
+
+#[tokio::test]
+async fn test_count_metrics_are_batched_into_one_query() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "payment_attempts": 5, "sdk_rendered_count": 9 }),
+        serde_json::json!({ "platform": "ios", "payment_attempts": 0, "sdk_rendered_count": 2 }),
+    ]);
+
+    let results = load_metrics_batch(
+        &[
+            SdkEventMetrics::PaymentAttempts,
+            SdkEventMetrics::SdkRenderedCount,
+        ],
+        &[SdkEventDimensions::Platform],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to load metrics");
+
+    let counts = results
+        .iter()
+        .map(|(metric, buckets)| {
+            let buckets = buckets
+                .iter()
+                .map(|(id, row)| (id.platform.clone(), row.count))
+                .collect::<Vec<_>>();
+            (metric.clone(), buckets)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (
+                SdkEventMetrics::PaymentAttempts,
+                vec![(Some("web".to_string()), Some(5))]
+            ),
+            (
+                SdkEventMetrics::SdkRenderedCount,
+                vec![
+                    (Some("ios".to_string()), Some(2)),
+                    (Some("web".to_string()), Some(9))
+                ]
+            ),
+        ]
+    );
+
+    assert_eq!(pool.queries.lock().expect("queries lock poisoned").len(), 1);
+    let query = pool.last_query();
+    assert!(query.contains(
+        "countIf(first_event = 1 AND event_name = 'PAYMENT_ATTEMPT') as payment_attempts"
+    ));
+    assert!(query.contains("event_name IN ('PAYMENT_ATTEMPT', 'APP_RENDERED')"));
+}
+
+#[tokio::test]
+async fn test_combined_metrics_share_a_row_per_bucket() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "payment_attempts": 5, "sdk_rendered_count": 9 }),
+        serde_json::json!({ "platform": "ios", "payment_attempts": 0, "sdk_rendered_count": 2 }),
+    ]);
+
+    let rows = load_combined_metrics(
+        &[
+            SdkEventMetrics::PaymentAttempts,
+            SdkEventMetrics::SdkRenderedCount,
+            SdkEventMetrics::PaymentAttempts,
+        ],
+        &[SdkEventDimensions::Platform],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to load metrics");
+
+    let values = rows
+        .iter()
+        .map(|row| {
+            (
+                row.id.platform.clone(),
+                row.values.payment_attempts,
+                row.values.sdk_rendered_count,
+                row.values.average_payment_time,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        vec![
+            (Some("ios".to_string()), None, Some(2), None),
+            (Some("web".to_string()), Some(5), Some(9), None)
+        ]
+    );
+    assert_eq!(pool.queries.lock().expect("queries lock poisoned").len(), 1);
+}
+
+#[tokio::test]
+async fn test_incompatible_metrics_are_not_combined() {
+    let pool = MockPool::default();
+
+    let error = load_combined_metrics(
+        &[
+            SdkEventMetrics::PaymentAttempts,
+            SdkEventMetrics::SessionDurationSeconds,
+        ],
+        &[SdkEventDimensions::Component],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect_err("metrics with different dimensions were combined");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::IncompatibleMetrics(_)
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_platform_rollup_counts_distinct_merchants() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "merchant_id": "pk_b", "platform": "web", "count": 3 }),
+        serde_json::json!({ "merchant_id": "pk_a", "platform": "web", "count": 2 }),
+        serde_json::json!({ "merchant_id": "pk_a", "platform": "ios", "count": 1 }),
+    ]);
+
+    let rollup = load_platform_rollup(
+        &SdkEventMetrics::SdkErrorEncounteredCount,
+        &[SdkEventDimensions::Platform],
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to load rollup");
+
+    let buckets = rollup
+        .buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket.merchant_id.as_str(),
+                bucket.id.platform.clone(),
+                bucket.row.count,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        buckets,
+        vec![
+            ("pk_a", Some("ios".to_string()), Some(1)),
+            ("pk_a", Some("web".to_string()), Some(2)),
+            ("pk_b", Some("web".to_string()), Some(3)),
+        ]
+    );
+    let merchants_per_group = rollup
+        .merchants_per_group
+        .iter()
+        .map(|(id, merchants)| (id.platform.clone(), *merchants))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        merchants_per_group,
+        vec![(Some("ios".to_string()), 1), (Some("web".to_string()), 2)]
+    );
+    assert_eq!(rollup.distinct_merchants, 2);
+
+    let query = pool.last_query();
+    assert!(!query.contains("merchant_id ="));
+    assert!(query.contains("log_type = 'ERROR'"));
+    assert!(query.contains("GROUP BY merchant_id, platform"));
+}
+
+#[tokio::test]
+async fn test_platform_rollup_of_a_metric_which_isnt_a_count() {
+    let pool = MockPool::default();
+
+    let error = load_platform_rollup(
+        &SdkEventMetrics::AveragePaymentTime,
+        &[],
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect_err("average payment time was rolled up");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedMetric(_)
+    ));
+}
@@ -0,0 +1,246 @@
+use super::*;
+
+#[tokio::test]
+async fn test_identical_queries_return_buckets_in_the_same_order() {
+    let rows = vec![
+        serde_json::json!({ "time_bucket": "2024-03-02T00:00:00Z", "platform": "web", "browser_name": "Chrome", "count": 1 }),
+        serde_json::json!({ "time_bucket": "2024-03-01T00:00:00Z", "platform": "web", "browser_name": "Safari", "count": 2 }),
+        serde_json::json!({ "time_bucket": "2024-03-01T00:00:00Z", "platform": "ios", "count": 3 }),
+        serde_json::json!({ "time_bucket": "2024-03-01T00:00:00Z", "platform": "ios", "browser_name": "Safari", "count": 4 }),
+        serde_json::json!({ "time_bucket": "2024-03-01T00:00:00Z", "platform": "web", "browser_name": "Chrome", "count": 5 }),
+    ];
+    let pool = MockPool {
+        responses: Mutex::new(VecDeque::from([
+            rows.clone(),
+            rows.into_iter().rev().collect(),
+        ])),
+        ..Default::default()
+    };
+    let load = || {
+        SdkEventMetrics::PaymentAttempts.load_metrics(
+            &[
+                SdkEventDimensions::Platform,
+                SdkEventDimensions::BrowserName,
+            ],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneDay),
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+    };
+
+    let first = load().await.expect("failed to load metrics");
+    let second = load().await.expect("failed to load metrics");
+
+    let ids = |buckets: &[(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)]| {
+        serde_json::to_string(&buckets.iter().map(|(id, _)| id).collect::<Vec<_>>())
+            .expect("bucket identifiers serialize")
+    };
+    assert_eq!(ids(&first), ids(&second));
+    // By time bucket, then platform and browser in the order they were asked for, buckets
+    // without a browser after the ones with one
+    let counts = first.iter().map(|(_, row)| row.count).collect::<Vec<_>>();
+    assert_eq!(counts, vec![Some(4), Some(3), Some(5), Some(2), Some(1)]);
+
+    let queries = pool.queries.lock().expect("queries lock poisoned");
+    assert_eq!(queries.first(), queries.last());
+}
+
+#[tokio::test]
+async fn test_warming_reports_each_query_and_fills_the_cache() {
+    let pool = MockPool {
+        metrics_cache: Some(SdkEventMetricsCache::new(&MetricsCacheConfig::default())),
+        ..MockPool::with_rows(vec![serde_json::json!({ "platform": "web", "count": 3 })])
+    };
+    let query = |metric: SdkEventMetrics, dimension: SdkEventDimensions| SdkEventWarmQuery {
+        metric,
+        dimensions: vec![dimension],
+        filters: SdkEventFilters::default(),
+        granularity: None,
+        options: SdkEventQueryOptions::default(),
+        time_range: seven_day_range(),
+    };
+
+    let results = warm_metrics_cache(
+        "pk_test",
+        vec![
+            query(
+                SdkEventMetrics::PaymentAttempts,
+                SdkEventDimensions::Platform,
+            ),
+            query(
+                SdkEventMetrics::PaymentAttempts,
+                SdkEventDimensions::FieldName,
+            ),
+            query(
+                SdkEventMetrics::SdkRenderedCount,
+                SdkEventDimensions::Platform,
+            ),
+        ],
+        Some(2),
+        &pool,
+    )
+    .await;
+
+    // The unsupported dimension fails on its own, in its place
+    let outcomes = results
+        .iter()
+        .map(|warmed| match &warmed.result {
+            Ok(buckets) => Ok(*buckets),
+            Err(error) => Err(error.current_context().to_string()),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outcomes,
+        vec![
+            Ok(1),
+            Err("Metric can't be grouped by the field_name dimension".to_string()),
+            Ok(1)
+        ]
+    );
+    assert_eq!(pool.queries.lock().expect("queries lock poisoned").len(), 2);
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+    assert_eq!(pool.queries.lock().expect("queries lock poisoned").len(), 2);
+}
+
+#[tokio::test]
+async fn test_identical_queries_are_answered_from_the_cache() {
+    let pool = MockPool {
+        metrics_cache: Some(SdkEventMetricsCache::new(&MetricsCacheConfig::default())),
+        ..MockPool::with_rows(vec![serde_json::json!({ "platform": "web", "count": 3 })])
+    };
+    let load = |filters: SdkEventFilters, time_range: TimeRange| {
+        let pool = &pool;
+        async move {
+            SdkEventMetrics::PaymentAttempts
+                .load_metrics(
+                    &[SdkEventDimensions::Platform],
+                    "pk_test",
+                    &filters,
+                    &None,
+                    &SdkEventQueryOptions::default(),
+                    &time_range,
+                    pool,
+                )
+                .await
+                .expect("failed to load metrics")
+        }
+    };
+    let queries = || pool.queries.lock().expect("queries lock poisoned").len();
+
+    let first = load(SdkEventFilters::default(), seven_day_range()).await;
+    let second = load(SdkEventFilters::default(), seven_day_range()).await;
+    assert_eq!(queries(), 1);
+    assert_eq!(first, second);
+
+    // Another filter is another query
+    let web = SdkEventFilters {
+        platform: vec!["web".to_string()],
+        ..Default::default()
+    };
+    load(web, seven_day_range()).await;
+    assert_eq!(queries(), 2);
+
+    // A range which hasn't ended isn't kept, its buckets still change
+    let live = TimeRange {
+        start_time: seven_day_range().start_time,
+        end_time: None,
+    };
+    load(SdkEventFilters::default(), live).await;
+    load(SdkEventFilters::default(), live).await;
+    assert_eq!(queries(), 4);
+}
+
+#[tokio::test]
+async fn test_queries_with_other_caps_are_not_shared() {
+    let pool = MockPool {
+        metrics_cache: Some(SdkEventMetricsCache::new(&MetricsCacheConfig::default())),
+        ..MockPool::with_rows(vec![serde_json::json!({ "platform": "web", "count": 3 })])
+    };
+    let load = |options: SdkEventQueryOptions| {
+        let pool = &pool;
+        async move {
+            SdkEventMetrics::PaymentAttempts
+                .load_metrics(
+                    &[SdkEventDimensions::Platform],
+                    "pk_test",
+                    &SdkEventFilters::default(),
+                    &None,
+                    &options,
+                    &seven_day_range(),
+                    pool,
+                )
+                .await
+                .expect("failed to load metrics")
+        }
+    };
+    let queries = || pool.queries.lock().expect("queries lock poisoned").len();
+
+    load(SdkEventQueryOptions::default()).await;
+    load(SdkEventQueryOptions {
+        max_rows: Some(1),
+        ..Default::default()
+    })
+    .await;
+    load(SdkEventQueryOptions {
+        max_partitions: Some(2),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(queries(), 3);
+
+    load(SdkEventQueryOptions {
+        max_rows: Some(1),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(queries(), 3);
+}
+
+#[tokio::test]
+async fn test_ranges_clamped_to_the_same_range_share_an_entry() {
+    let pool = MockPool {
+        metrics_cache: Some(SdkEventMetricsCache::new(&MetricsCacheConfig::default())),
+        max_time_range: Some(Duration::days(2)),
+        ..MockPool::with_rows(vec![serde_json::json!({ "platform": "web", "count": 3 })])
+    };
+    let load = |start_time: PrimitiveDateTime| {
+        let pool = &pool;
+        async move {
+            SdkEventMetrics::PaymentAttempts
+                .load_metrics(
+                    &[SdkEventDimensions::Platform],
+                    "pk_test",
+                    &SdkEventFilters::default(),
+                    &None,
+                    &SdkEventQueryOptions::default(),
+                    &TimeRange {
+                        start_time,
+                        end_time: seven_day_range().end_time,
+                    },
+                    pool,
+                )
+                .await
+                .expect("failed to load metrics")
+        }
+    };
+
+    // Both read the last two days of the range
+    load(seven_day_range().start_time).await;
+    load(seven_day_range().start_time + Duration::days(1)).await;
+    assert_eq!(pool.queries.lock().expect("queries lock poisoned").len(), 1);
+}
@@ -0,0 +1,201 @@
+use super::*;
+
+#[test]
+fn test_previous_time_range_has_the_same_length() {
+    let previous = previous_time_range(&seven_day_range());
+
+    assert_eq!(
+        previous.start_time,
+        seven_day_range().start_time - Duration::days(7)
+    );
+    assert_eq!(previous.end_time, Some(seven_day_range().start_time));
+}
+
+#[tokio::test]
+async fn test_period_comparison_pairs_buckets_by_dimensions() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "count": 8 }),
+        serde_json::json!({ "platform": "ios", "count": 0 }),
+    ]);
+
+    let comparison = load_metrics_with_previous_period(
+        &SdkEventMetrics::PaymentAttempts,
+        &[SdkEventDimensions::Platform],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to compare periods");
+
+    let changes = comparison
+        .buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket.id.platform.as_deref(),
+                bucket.delta,
+                bucket.percent_change,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![
+            (Some("ios"), Some(0.0), None),
+            (Some("web"), Some(0.0), Some(0.0))
+        ]
+    );
+    let queries = pool.queries.lock().expect("queries lock poisoned");
+    assert!(queries
+        .iter()
+        .any(|query| query.contains("created_at >= '2024-02-23 00:00:00'")));
+}
+
+#[test]
+fn test_contributions_to_change_of_groups_in_either_period() {
+    let bucket = |platform: &str, time_bucket: &str, count: i64| {
+        (
+            SdkEventMetricsBucketIdentifier {
+                platform: Some(platform.to_string()),
+                time_bucket: Some(time_bucket.to_string()),
+                ..Default::default()
+            },
+            SdkEventMetricRow {
+                count: Some(count),
+                ..Default::default()
+            },
+        )
+    };
+    let current = vec![
+        bucket("web", "2024-03-08 00:00:00", 5),
+        bucket("ios", "2024-03-08 00:00:00", 2),
+        bucket("web", "2024-03-09 00:00:00", 3),
+    ];
+    let prior = vec![
+        bucket("android", "2024-03-01 00:00:00", 3),
+        bucket("web", "2024-03-02 00:00:00", 4),
+    ];
+
+    let contributions =
+        contributions_to_change(&SdkEventMetrics::PaymentAttempts, &current, &prior)
+            .expect("failed to compute contributions");
+
+    // The total went up by 3, android is only in the prior period and ios only in the current
+    let contributions = contributions
+        .iter()
+        .map(|contribution| {
+            (
+                contribution.id.platform.as_deref(),
+                contribution.id.time_bucket.as_deref(),
+                contribution.absolute,
+                contribution.relative,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        contributions,
+        vec![
+            (Some("web"), None, 4.0, Some(4.0 / 3.0)),
+            (Some("android"), None, -3.0, Some(-1.0)),
+            (Some("ios"), None, 2.0, Some(2.0 / 3.0)),
+        ]
+    );
+
+    let rate = contributions_to_change(&SdkEventMetrics::PaymentSuccessRate, &current, &prior)
+        .expect_err("a rate isn't the sum of its groups");
+    assert!(matches!(
+        rate.current_context(),
+        MetricsError::UnsupportedMetric(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_period_comparison_keeps_buckets_of_one_period() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({
+        "time_bucket": "2024-03-07 00:00:00",
+        "count": 4
+    })]);
+
+    let comparison = load_metrics_with_previous_period(
+        &SdkEventMetrics::PaymentAttempts,
+        &[],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &Some(Granularity::OneDay),
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to compare periods");
+
+    // The previous period's bucket lines up with 2024-03-14, which the current one hasn't
+    let buckets = comparison
+        .buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket.id.time_bucket.as_deref(),
+                bucket.current.is_some(),
+                bucket.previous.is_some(),
+                bucket.delta,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        buckets,
+        vec![
+            (Some("2024-03-07 00:00:00"), true, false, None),
+            (Some("2024-03-14 00:00:00"), false, true, None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_key_pair_comparison_labels_both_keys() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({
+        "platform": "web",
+        "count": 4
+    })]);
+
+    let comparison = load_metrics_for_key_pair(
+        &SdkEventMetrics::PaymentAttempts,
+        &[SdkEventDimensions::Platform],
+        "pk_test",
+        "pk_live",
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to compare keys");
+
+    assert_eq!(comparison.first_key, "pk_test");
+    assert_eq!(comparison.second_key, "pk_live");
+    let buckets = comparison
+        .buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket.id.platform.as_deref(),
+                bucket.first.is_some(),
+                bucket.second.is_some(),
+                bucket.delta,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(buckets, vec![(Some("web"), true, true, Some(0.0))]);
+    let queries = pool.queries.lock().expect("queries lock poisoned");
+    assert!(queries
+        .iter()
+        .any(|query| query.contains("merchant_id = 'pk_test'")));
+    assert!(queries
+        .iter()
+        .any(|query| query.contains("merchant_id = 'pk_live'")));
+}
@@ -0,0 +1,224 @@
+use super::*;
+
+#[tokio::test]
+async fn test_query_stats_are_recorded_only_when_collected() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "count": 3 }),
+        serde_json::json!({ "platform": "ios", "count": 1 }),
+    ]);
+    let stats = crate::query::QueryStatsCollector::default();
+    let options = SdkEventQueryOptions {
+        query_stats: Some(stats.clone()),
+        ..Default::default()
+    };
+
+    for options in [&options, &SdkEventQueryOptions::default()] {
+        SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                options,
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+    }
+
+    let stats = stats.stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(
+        stats[0].query_fingerprint,
+        crate::query::query_fingerprint(&pool.last_query())
+    );
+    assert_eq!(stats[0].result_rows, 2);
+    // The mock pool can't tell how much a query read
+    assert_eq!(stats[0].scanned_rows, None);
+    assert_eq!(stats[0].scanned_bytes, None);
+}
+
+#[tokio::test]
+async fn test_fallback_answers_the_queries_its_primary_cannot_be_reached_for() {
+    let primary_rows = vec![serde_json::json!({ "platform": "web", "count": 1 })];
+    let secondary_rows = vec![serde_json::json!({ "platform": "web", "count": 3 })];
+    let reachable = FallbackSource::new(
+        MockPool::with_rows(primary_rows.clone()),
+        MockPool::with_rows(secondary_rows.clone()),
+    );
+    let unreachable = FallbackSource::new(
+        MockPool {
+            unreachable: true,
+            ..MockPool::with_rows(primary_rows)
+        },
+        MockPool::with_rows(secondary_rows),
+    );
+
+    for (pool, count, served_by_fallback) in [(reachable, 1, false), (unreachable, 3, true)] {
+        let stats = crate::query::QueryStatsCollector::default();
+        let options = SdkEventQueryOptions {
+            query_stats: Some(stats.clone()),
+            ..Default::default()
+        };
+        let buckets = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &options,
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+
+        let counts = buckets.iter().map(|(_, row)| row.count).collect::<Vec<_>>();
+        assert_eq!(counts, vec![Some(count)]);
+        let stats = stats.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].served_by_fallback, served_by_fallback);
+    }
+}
+
+#[tokio::test]
+async fn test_metric_query_runs_the_metric_with_its_fields() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({ "platform": "web", "count": 3 })]);
+
+    let buckets = SdkMetricQuery::new(SdkEventMetrics::PaymentAttempts)
+        .dimensions(vec![SdkEventDimensions::Platform])
+        .time_range(seven_day_range())
+        .publishable_key("pk_test")
+        .execute(&pool)
+        .await
+        .expect("failed to load metrics");
+
+    assert_eq!(buckets.len(), 1);
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT platform as platform, count(*) as count"));
+    assert!(query.contains("merchant_id = 'pk_test'"));
+    assert!(query.contains("GROUP BY platform"));
+}
+
+#[tokio::test]
+async fn test_metric_query_without_a_publishable_key_fails_before_querying() {
+    let pool = MockPool::default();
+
+    let error = SdkMetricQuery::new(SdkEventMetrics::PaymentAttempts)
+        .time_range(seven_day_range())
+        .execute(&pool)
+        .await
+        .expect_err("metric query ran without a publishable key");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::IncompleteQuery(field) if field == "publishable key"
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_health_check_times_out_on_unreachable_data_source() {
+    let pool = MockPool::default();
+    pool.health_check().await.expect("health check failed");
+    assert_eq!(pool.last_query(), "SELECT 1");
+
+    let unreachable = MockPool {
+        unreachable: true,
+        ..Default::default()
+    };
+    let error = unreachable
+        .health_check()
+        .await
+        .expect_err("unreachable data source passed the health check");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::QueryTimeout
+    ));
+}
+
+#[tokio::test]
+async fn test_render_metric_sql_without_running_it() {
+    let queries = render_metric_sql(
+        &SdkEventMetrics::PaymentAttempts,
+        &[SdkEventDimensions::Platform],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &Some(Granularity::OneHour),
+        &SdkEventQueryOptions::default(),
+        &seven_day_range(),
+    )
+    .await
+    .expect("failed to render sql");
+
+    assert_eq!(queries.len(), 1);
+    let query = queries.first().expect("missing query");
+    assert!(query.starts_with(
+        "SELECT platform, count(*) as count, toStartOfInterval(created_at, INTERVAL 60 MINUTE) as time_bucket FROM sdk_events_audit"
+    ));
+    assert!(query.contains("merchant_id = 'pk_test'"));
+    assert!(query.ends_with(
+        "GROUP BY platform, time_bucket ORDER BY time_bucket asc nulls last, platform asc nulls last"
+    ));
+}
+
+#[test]
+fn test_metrics_metadata_lists_every_output_field() {
+    let value = serde_json::to_value(SdkEventMetricsAccumulator::default().collect())
+        .expect("bucket value serializes");
+    let fields = value.as_object().expect("bucket value is an object");
+    let metadata = crate::utils::get_sdk_event_metrics_metadata();
+    assert_eq!(metadata.len(), SdkEventMetrics::iter().count());
+
+    let filters = serde_json::to_value(SdkEventFilters::default()).expect("filters serialize");
+    let filters = filters.as_object().expect("filters are an object");
+    for metric in metadata.iter() {
+        assert!(!metric.output_fields.is_empty());
+        for field in metric.output_fields.iter() {
+            assert!(fields.contains_key(*field), "{field} is not a bucket field");
+        }
+        for filter in metric.filters.iter() {
+            assert!(filters.contains_key(*filter), "{filter} is not a filter");
+        }
+        // The customer segment is only listed for the metrics which accept it
+        let by_segment = SdkEventFilters {
+            customer_segment: vec!["enterprise".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            metric.filters.contains(&"customer_segment"),
+            validate_filters(&metric.name, &by_segment).is_ok(),
+            "{} lists the customer segment filter wrongly",
+            metric.name
+        );
+    }
+    for filter in filters.keys() {
+        assert!(
+            metadata
+                .iter()
+                .any(|metric| metric.filters.contains(&filter.as_str())),
+            "{filter} is not listed for any metric"
+        );
+    }
+    for field in fields.keys() {
+        assert!(
+            metadata
+                .iter()
+                .any(|metric| metric.output_fields.contains(&field.as_str())),
+            "{field} is not set by any metric"
+        );
+    }
+
+    let session_duration = metadata
+        .iter()
+        .find(|metric| metric.name == SdkEventMetrics::SessionDurationSeconds)
+        .expect("session duration has metadata");
+    assert!(!session_duration
+        .dimensions
+        .contains(&SdkEventDimensions::Component));
+}
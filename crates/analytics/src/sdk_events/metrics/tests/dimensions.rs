@@ -0,0 +1,768 @@
+use super::*;
+
+#[tokio::test]
+async fn test_payment_attempts_grouped_by_local_hour_of_day() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "hour_of_day": "20", "count": 12 }),
+        serde_json::json!({ "hour_of_day": "9", "count": 5 }),
+    ]);
+    let filters = SdkEventFilters {
+        day_of_week: vec!["6".to_string(), "7".to_string()],
+        ..Default::default()
+    };
+    let options = SdkEventQueryOptions {
+        time_zone: Some("Asia/Kolkata".to_string()),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::HourOfDay],
+            "pk_test",
+            &filters,
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.hour_of_day.as_deref(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![(Some("20"), Some(12)), (Some("9"), Some(5))]);
+
+    let query = pool.last_query();
+    assert!(query.starts_with(
+        "SELECT toString(toHour(created_at, 'Asia/Kolkata')) as hour_of_day, count(*) as count"
+    ));
+    assert!(query.contains("toString(toDayOfWeek(created_at, 0, 'Asia/Kolkata')) IN ('6', '7')"));
+    assert!(query.contains("GROUP BY toString(toHour(created_at, 'Asia/Kolkata'))"));
+}
+
+#[test]
+fn test_session_metrics_cannot_be_grouped_by_hour_of_day() {
+    assert!(
+        !supported_dimensions(&SdkEventMetrics::SessionDurationSeconds)
+            .contains(&SdkEventDimensions::HourOfDay)
+    );
+    assert!(!supported_dimensions(&SdkEventMetrics::UniqueSessionsCount)
+        .contains(&SdkEventDimensions::DayOfWeek));
+    assert!(supported_dimensions(&SdkEventMetrics::PaymentAttempts)
+        .contains(&SdkEventDimensions::HourOfDay));
+}
+
+#[tokio::test]
+async fn test_payment_attempts_grouped_by_country() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "country": "IN", "count": 3 }),
+        serde_json::json!({ "country": "US", "count": 5 }),
+    ]);
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Country],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.country.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("IN".to_string()), Some(3)),
+            (Some("US".to_string()), Some(5))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT country, count(*) as count"));
+    assert!(query.contains("GROUP BY country"));
+}
+
+#[tokio::test]
+async fn test_rendered_count_grouped_by_connector() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "connector": "stripe", "count": 4 }),
+        serde_json::json!({ "connector": "__null__", "count": 9 }),
+    ]);
+    let filters = SdkEventFilters {
+        connector: vec!["stripe".to_string(), "__null__".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[SdkEventDimensions::Connector],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.connector.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("__null__".to_string()), Some(9)),
+            (Some("stripe".to_string()), Some(4))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT coalesce(connector, '__null__') as connector"));
+    assert!(query.contains("coalesce(connector, '__null__') IN ('stripe', '__null__')"));
+}
+
+#[tokio::test]
+async fn test_error_count_grouped_and_filtered_by_event_name() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "event_name": "CUSTOM_EVENT", "count": 3 }),
+    ]);
+    let filters = SdkEventFilters {
+        // Names no event has are part of the list like any other, they match nothing
+        event_name: vec!["CUSTOM_EVENT".to_string(), "NO_SUCH_EVENT".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::SdkErrorEncounteredCount
+        .load_metrics(
+            &[SdkEventDimensions::EventName],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.event_name.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![(Some("CUSTOM_EVENT".to_string()), Some(3))]);
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT event_name, count(*) as count"));
+    assert!(query.contains("event_name IN ('CUSTOM_EVENT', 'NO_SUCH_EVENT')"));
+    assert!(query.contains("GROUP BY event_name"));
+}
+
+#[tokio::test]
+async fn test_bounce_rate_cannot_be_grouped_by_event_name() {
+    let pool = MockPool::default();
+
+    let error = SdkEventMetrics::SdkBounceRate
+        .load_metrics(
+            &[SdkEventDimensions::EventName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("bounce rate was grouped by event name");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedDimension(dim) if dim == "event_name"
+    ));
+}
+
+#[tokio::test]
+async fn test_data_filled_count_grouped_by_payment_method_type() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "payment_method_type": "__null__", "count": 2 }),
+        serde_json::json!({ "payment_method_type": "credit", "count": 7 }),
+    ]);
+    let filters = SdkEventFilters {
+        payment_method_type: vec!["credit".to_string(), "__null__".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentDataFilledCount
+        .load_metrics(
+            &[SdkEventDimensions::PaymentMethodType],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.payment_method_type.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("__null__".to_string()), Some(2)),
+            (Some("credit".to_string()), Some(7))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query
+        .starts_with("SELECT coalesce(payment_method_type, '__null__') as payment_method_type"));
+    assert!(query.contains("coalesce(payment_method_type, '__null__') IN ('credit', '__null__')"));
+    assert!(query.contains("GROUP BY coalesce(payment_method_type, '__null__')"));
+}
+
+#[tokio::test]
+async fn test_missing_os_stays_apart_from_an_unknown_os() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "os": "unknown", "count": 5 }),
+        serde_json::json!({ "os": "__null__", "count": 3 }),
+    ]);
+
+    let buckets = SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[SdkEventDimensions::Os],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    // The merchant's own `unknown` isn't merged with the events without an os
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.os.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some(NULL_DIMENSION_LABEL.to_string()), Some(3)),
+            (Some("unknown".to_string()), Some(5))
+        ]
+    );
+    assert!(pool
+        .last_query()
+        .starts_with("SELECT coalesce(os, '__null__') as os"));
+}
+
+#[tokio::test]
+async fn test_requested_null_label_is_grouped_and_filtered_on() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({ "os": "none", "count": 3 })]);
+    let filters = SdkEventFilters {
+        none_of: vec![SdkEventFilters {
+            os: vec!["none".to_string()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let options = SdkEventQueryOptions {
+        null_label: Some("none".to_string()),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[SdkEventDimensions::Os],
+            "pk_test",
+            &filters,
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT coalesce(os, 'none') as os"));
+    assert!(query.contains("NOT coalesce((coalesce(os, 'none') IN ('none')), false)"));
+    assert!(query.contains("GROUP BY coalesce(os, 'none')"));
+
+    let options = SdkEventQueryOptions {
+        null_label: Some("n'a".to_string()),
+        ..Default::default()
+    };
+    let error = SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[SdkEventDimensions::Os],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("a label which can't be inlined was accepted");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::QueryBuildingError
+    ));
+}
+
+#[tokio::test]
+async fn test_unsuccessful_authentications_grouped_by_error_reason() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "authentication_error_reason": "issuer_timeout", "count": 3 }),
+        serde_json::json!({ "authentication_error_reason": "challenge_cancelled", "count": 9 }),
+    ]);
+    let filters = SdkEventFilters {
+        authentication_error_reason: vec![
+            "challenge_cancelled".to_string(),
+            "issuer_timeout".to_string(),
+        ],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::AuthenticationUnsuccessfulCount
+        .load_metrics(
+            &[SdkEventDimensions::AuthenticationErrorReason],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.authentication_error_reason.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("challenge_cancelled".to_string()), Some(9)),
+            (Some("issuer_timeout".to_string()), Some(3))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.starts_with(
+        "SELECT coalesce(authentication_error_reason, '__null__') as authentication_error_reason"
+    ));
+    assert!(query.contains(
+        "coalesce(authentication_error_reason, '__null__') IN ('challenge_cancelled', \
+         'issuer_timeout')"
+    ));
+    assert!(query.contains("GROUP BY coalesce(authentication_error_reason, '__null__')"));
+}
+
+#[tokio::test]
+async fn test_payment_data_filled_grouped_by_field_name() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "field_name": "cvv", "count": 4 }),
+        serde_json::json!({ "field_name": "card_number", "count": 11 }),
+    ]);
+
+    let buckets = SdkEventMetrics::PaymentDataFilledCount
+        .load_metrics(
+            &[SdkEventDimensions::FieldName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.field_name.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("card_number".to_string()), Some(11)),
+            (Some("cvv".to_string()), Some(4))
+        ]
+    );
+
+    let field_name = "nullIf(JSONExtractString(value, 'field_name'), '')";
+    let query = pool.last_query();
+    assert!(query.starts_with(&format!("SELECT {field_name} as field_name")));
+    assert!(query.contains(&format!("{field_name} IS NOT NULL")));
+    assert!(!query.contains("first_event"));
+    assert!(query.contains(&format!("GROUP BY {field_name}")));
+}
+
+#[tokio::test]
+async fn test_field_name_is_only_supported_by_payment_data_filled() {
+    let pool = MockPool::default();
+
+    let error = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::FieldName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("payment attempts were grouped by field name");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedDimension(dim) if dim == "field_name"
+    ));
+}
+
+#[tokio::test]
+async fn test_sdk_load_failures_grouped_by_cdn_region() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "cdn_region": "eu-west-1", "count": 2 }),
+        serde_json::json!({ "cdn_region": "ap-south-1", "count": 31 }),
+    ]);
+
+    let buckets = SdkEventMetrics::SdkLoadFailureCount
+        .load_metrics(
+            &[SdkEventDimensions::CdnRegion],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+    for (_, row) in buckets.iter() {
+        accumulator.add_metric_bucket(&SdkEventMetrics::SdkLoadFailureCount, row);
+    }
+    assert_eq!(accumulator.collect().sdk_load_failure_count, Some(33));
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.cdn_region.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("ap-south-1".to_string()), Some(31)),
+            (Some("eu-west-1".to_string()), Some(2))
+        ]
+    );
+
+    let cdn_region = "nullIf(JSONExtractString(value, 'cdn_region'), '')";
+    let query = pool.last_query();
+    assert!(query.starts_with(&format!(
+        "SELECT {cdn_region} as cdn_region, uniqExact(payment_id) as count"
+    )));
+    assert!(query.contains("event_name = 'SDK_LOAD_FAILED'"));
+    assert!(query.contains(&format!("GROUP BY {cdn_region}")));
+    // Only the load failures carry a region
+    assert!(!supported_dimensions(&SdkEventMetrics::SdkRenderedCount)
+        .contains(&SdkEventDimensions::CdnRegion));
+}
+
+#[tokio::test]
+async fn test_computed_dimension_is_grouped_by_and_labels_buckets() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "computed_dimension": "small", "count": 3 }),
+        serde_json::json!({ "platform": "web", "computed_dimension": "large", "count": 1 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        computed_dimension: Some(
+            "case when amount < 1000 then 'small' when os = 'ios' then lower(platform) \
+             else 'large' end"
+                .to_string(),
+        ),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let expression = "toString(CASE WHEN (amount < 1000) THEN 'small' WHEN (coalesce(os, '__null__') = 'ios') THEN lower(platform) ELSE 'large' END)";
+    let query = pool.last_query();
+    assert!(query.contains(&format!("{expression} as computed_dimension")));
+    assert!(query.contains(&format!("GROUP BY {expression}, platform")));
+    let groups = buckets
+        .iter()
+        .map(|(id, row)| (id.computed_dimension.as_deref(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        groups,
+        vec![(Some("large"), Some(1)), (Some("small"), Some(3))]
+    );
+}
+
+#[tokio::test]
+async fn test_computed_dimension_outside_its_grammar_is_rejected() {
+    let pool = MockPool::default();
+    let nested = format!("{}1{}", "(".repeat(40), ")".repeat(40));
+    for expression in [
+        "amount; DROP TABLE sdk_events",
+        "os = 'ios' OR '1'='1' --",
+        "'it''s'",
+        "sleep(3)",
+        "payment_id",
+        "customer_segment",
+        "lower(os, platform)",
+        "amount /* comment */",
+        "os = \"ios\"",
+        nested.as_str(),
+    ] {
+        let options = SdkEventQueryOptions {
+            computed_dimension: Some(expression.to_string()),
+            ..Default::default()
+        };
+        let error = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &options,
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect_err(expression);
+
+        assert!(
+            matches!(
+                error.current_context(),
+                MetricsError::InvalidComputedDimension(_)
+            ),
+            "{expression}"
+        );
+    }
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_subtotals_roll_up_the_dimensions() {
+    // Clickhouse fills the dimensions a subtotal adds up with their default value
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "browser_name": "", "os": "", "count": 9, "subtotal_level": 2 }),
+        serde_json::json!({
+            "browser_name": "Chrome",
+            "os": "",
+            "count": 5,
+            "subtotal_level": 1
+        }),
+        serde_json::json!({
+            "browser_name": "Chrome",
+            "os": "ios",
+            "count": 2,
+            "subtotal_level": 0
+        }),
+    ]);
+    let options = SdkEventQueryOptions {
+        subtotals: true,
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::BrowserName, SdkEventDimensions::Os],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains(
+        "grouping(browser_name) + grouping(coalesce(os, '__null__')) as subtotal_level FROM"
+    ));
+    assert!(query.contains(
+        "GROUP BY GROUPING SETS ((browser_name, coalesce(os, '__null__')), (browser_name), ())"
+    ));
+    let groups = buckets
+        .iter()
+        .map(|(id, row)| {
+            (
+                id.browser_name.as_deref(),
+                id.os.as_deref(),
+                id.subtotal_level,
+                row.count,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        groups,
+        vec![
+            (Some("Chrome"), Some("ios"), None, Some(2)),
+            (Some("Chrome"), None, Some(1), Some(5)),
+            (None, None, Some(2), Some(9)),
+        ]
+    );
+
+    // The sessions are grouped in a subquery which can't be rolled up
+    let error = SdkEventMetrics::PaymentRetryCount
+        .load_metrics(
+            &[SdkEventDimensions::BrowserName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("subtotals of sessions were accepted");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedSubtotals(_)
+    ));
+}
+
+#[test]
+fn test_supported_dimensions_of_every_metric() {
+    use SdkEventDimensions::{
+        AuthenticationErrorReason, Component, Connector, DayOfWeek, EventName, HourOfDay,
+        PaymentExperience, PaymentMethod, PaymentMethodType,
+    };
+
+    let whole_session = vec![
+        Component,
+        PaymentMethod,
+        PaymentMethodType,
+        Connector,
+        EventName,
+        DayOfWeek,
+        HourOfDay,
+        AuthenticationErrorReason,
+    ];
+    let per_session = vec![Component, DayOfWeek, HourOfDay];
+    let exclusions = [
+        (
+            SdkEventMetrics::SessionDurationSeconds,
+            whole_session.clone(),
+        ),
+        (SdkEventMetrics::FunnelConversionRate, whole_session.clone()),
+        (SdkEventMetrics::FunnelWaterfall, whole_session.clone()),
+        (SdkEventMetrics::TimeToFirstRender, whole_session.clone()),
+        (SdkEventMetrics::AbandonedSessionsCount, whole_session),
+        (SdkEventMetrics::UniqueSessionsCount, per_session.clone()),
+        (SdkEventMetrics::PaymentRetryCount, per_session),
+        (SdkEventMetrics::PaymentSuccessRate, vec![EventName]),
+        (SdkEventMetrics::SdkBounceRate, vec![EventName]),
+        (SdkEventMetrics::ThreeDsMethodSuccessRate, vec![EventName]),
+        (
+            SdkEventMetrics::SdkLoadFailureCount,
+            vec![
+                Component,
+                PaymentMethod,
+                PaymentMethodType,
+                PaymentExperience,
+                Connector,
+                EventName,
+                DayOfWeek,
+                HourOfDay,
+                AuthenticationErrorReason,
+            ],
+        ),
+    ];
+    for metric in SdkEventMetrics::iter() {
+        let unsupported = exclusions
+            .iter()
+            .find(|(excluding, _)| *excluding == metric)
+            .map(|(_, unsupported)| unsupported.clone())
+            .unwrap_or_default();
+        let expected = SdkEventDimensions::iter()
+            .filter(|dim| !unsupported.contains(dim))
+            .filter(|dim| {
+                *dim != SdkEventDimensions::FieldName
+                    || metric == SdkEventMetrics::PaymentDataFilledCount
+            })
+            .filter(|dim| {
+                *dim != SdkEventDimensions::CdnRegion
+                    || metric == SdkEventMetrics::SdkLoadFailureCount
+            })
+            .filter(|dim| {
+                *dim != SdkEventDimensions::AbandonmentReason
+                    || metric == SdkEventMetrics::AbandonedSessionsCount
+            })
+            .filter(|dim| {
+                *dim != SdkEventDimensions::CustomerSegment
+                    || matches!(
+                        metric,
+                        SdkEventMetrics::FunnelConversionRate | SdkEventMetrics::FunnelWaterfall
+                    )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(supported_dimensions(&metric), expected, "{metric}");
+    }
+}
+
+#[tokio::test]
+async fn test_unsupported_dimension_is_rejected() {
+    let pool = MockPool::default();
+
+    let error = SdkEventMetrics::SessionDurationSeconds
+        .load_metrics(
+            &[SdkEventDimensions::Platform, SdkEventDimensions::Component],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("component was accepted");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedDimension(dim) if dim == "component"
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
@@ -0,0 +1,438 @@
+use super::*;
+
+#[tokio::test]
+async fn test_payment_attempts_filtered_by_local_hour_windows() {
+    let pool = MockPool::default();
+    let filters = SdkEventFilters {
+        hour_windows: vec![
+            HourWindow {
+                start_hour: 12,
+                end_hour: 14,
+            },
+            // Wraps around midnight
+            HourWindow {
+                start_hour: 22,
+                end_hour: 2,
+            },
+            HourWindow {
+                start_hour: 13,
+                end_hour: 14,
+            },
+        ],
+        ..Default::default()
+    };
+    let options = SdkEventQueryOptions {
+        time_zone: Some("Asia/Kolkata".to_string()),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &filters,
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    assert!(pool.last_query().contains(
+        "toString(toHour(created_at, 'Asia/Kolkata')) IN ('0', '1', '12', '13', '22', '23')"
+    ));
+}
+
+#[tokio::test]
+async fn test_amount_range_filters_bounds_which_are_set() {
+    let pool = MockPool::default();
+    let high_value = SdkEventFilters {
+        amount: Some(NumericRange {
+            gte: Some(10000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let low_value = SdkEventFilters {
+        amount: Some(NumericRange {
+            gt: Some(0),
+            lt: Some(10000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    for filters in [&high_value, &low_value] {
+        SdkEventMetrics::PaymentSuccessRate
+            .load_metrics(
+                &[],
+                "pk_test",
+                filters,
+                &None,
+                &SdkEventQueryOptions::default(),
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+    }
+
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    let mut queries = queries.iter();
+    let high_value = queries.next().expect("missing high value query");
+    assert!(high_value.contains("amount >= '10000'"));
+    assert!(!high_value.contains("amount <"));
+    let low_value = queries.next().expect("missing low value query");
+    assert!(low_value.contains("amount > 0 AND amount < 10000"));
+    assert!(!low_value.contains("amount >="));
+}
+
+#[tokio::test]
+async fn test_bot_sessions_are_only_excluded_when_asked_to() {
+    let pool = MockPool::default();
+    let real_users = SdkEventFilters {
+        exclude_bots: true,
+        ..Default::default()
+    };
+
+    for filters in [&SdkEventFilters::default(), &real_users] {
+        SdkEventMetrics::SdkRenderedCount
+            .load_metrics(
+                &[],
+                "pk_test",
+                filters,
+                &None,
+                &SdkEventQueryOptions::default(),
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+    }
+
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    let mut queries = queries.iter();
+    let every_session = queries.next().expect("missing query of every session");
+    assert!(!every_session.contains("is_bot"));
+    let real_users = queries.next().expect("missing query of real users");
+    assert!(real_users.contains("is_bot = false"));
+}
+
+#[tokio::test]
+async fn test_customer_segment_filter_of_a_metric_without_the_join_is_rejected() {
+    let pool = MockPool::default();
+    let filters = SdkEventFilters {
+        any_of: vec![SdkEventFilters {
+            customer_segment: vec!["new".to_string()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let error = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("a customer segment filter was accepted");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedDimension(dim) if dim == "customer_segment"
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_sample_of_a_bucket_matches_its_group() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({
+        "merchant_id": "pk_test",
+        "payment_id": "pay_1",
+        "event_name": "PAYMENT_ATTEMPT",
+        "log_type": "INFO",
+        "first_event": true,
+        "browser_name": "Chrome",
+        "browser_version": "120",
+        "source": "ORCA",
+        "category": "API",
+        "version": "0.27.2",
+        "value": null,
+        "platform": "web",
+        "component": "PAYMENT",
+        "payment_method": null,
+        "payment_experience": null,
+        "latency": 120,
+        "created_at_precise": "2024-03-02T10:00:00.000Z",
+        "created_at": "2024-03-02T10:00:00.000Z",
+    })]);
+    let request = SdkEventSampleRequest {
+        bucket: SdkEventMetricsBucketIdentifier {
+            platform: Some("web".to_string()),
+            browser_name: Some("Chrome".to_string()),
+            ..Default::default()
+        },
+        group_by_names: vec![
+            SdkEventDimensions::Platform,
+            SdkEventDimensions::PaymentMethod,
+        ],
+        filters: SdkEventFilters::default(),
+        time_range: seven_day_range(),
+        time_zone: None,
+        null_label: None,
+        limit: 10_000,
+    };
+
+    let events = get_sdk_event_sample("pk_test", &request, &pool)
+        .await
+        .expect("failed to sample events");
+
+    assert_eq!(events.len(), 1);
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT * FROM sdk_events_audit WHERE"));
+    // Only the dimensions the metric was grouped by are matched, the bucket's missing
+    // payment method matches the events without one
+    assert!(query.contains("platform IN ('web') AND payment_method IS NULL"));
+    assert!(!query.contains("browser_name"));
+    assert!(query.ends_with("ORDER BY created_at_precise asc nulls last LIMIT 500"));
+}
+
+#[tokio::test]
+async fn test_unknown_country_filter_is_rejected() {
+    let pool = MockPool::default();
+    let filters = SdkEventFilters {
+        country: vec!["IN".to_string(), "XX".to_string()],
+        ..Default::default()
+    };
+
+    let result = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Country],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await;
+
+    assert!(result.is_err());
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_unknown_filter_values_are_rejected_with_the_valid_ones() {
+    let load = |filters: SdkEventFilters| async move {
+        let pool = MockPool::default();
+        SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[],
+                "pk_test",
+                &filters,
+                &None,
+                &SdkEventQueryOptions::default(),
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .map(|_| ())
+    };
+    let invalid_filter = |filters: SdkEventFilters| async move {
+        load(filters)
+            .await
+            .expect_err("invalid filter was accepted")
+            .current_context()
+            .to_string()
+    };
+
+    let message = invalid_filter(SdkEventFilters {
+        // The groups are checked as well
+        any_of: vec![SdkEventFilters {
+            payment_method: vec!["card".to_string(), "cards".to_string()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    })
+    .await;
+    assert!(message.contains("unknown payment_method \"cards\""));
+    assert!(message.contains("expected one of card, card_redirect, pay_later, wallet"));
+
+    let message = invalid_filter(SdkEventFilters {
+        currency: vec!["usd".to_string()],
+        ..Default::default()
+    })
+    .await;
+    assert!(message.contains("expected an ISO 4217 currency code, e.g. USD"));
+
+    let message = invalid_filter(SdkEventFilters {
+        hour_of_day: vec!["24".to_string()],
+        ..Default::default()
+    })
+    .await;
+    assert!(message.contains("expected one of 0 to 23"));
+
+    let message = invalid_filter(SdkEventFilters {
+        hour_windows: vec![HourWindow {
+            start_hour: 9,
+            end_hour: 9,
+        }],
+        ..Default::default()
+    })
+    .await;
+    assert!(message.contains("invalid hour window from 9 to 9"));
+
+    let message = invalid_filter(SdkEventFilters {
+        platform: vec!["smart_tv".to_string()],
+        ..Default::default()
+    })
+    .await;
+    assert!(message.contains("unknown platform \"smart_tv\", expected one of web, ios, android"));
+
+    let message = invalid_filter(SdkEventFilters {
+        day_of_week: vec!["07".to_string()],
+        ..Default::default()
+    })
+    .await;
+    assert!(message.contains("unknown day_of_week \"07\""));
+
+    assert!(load(SdkEventFilters {
+        payment_method: vec!["card".to_string()],
+        day_of_week: vec!["7".to_string()],
+        hour_of_day: vec!["0".to_string(), "23".to_string()],
+        currency: vec!["USD".to_string()],
+        platform: vec!["android".to_string()],
+        // The event names aren't a known set
+        event_name: vec!["CUSTOM_EVENT".to_string()],
+        ..Default::default()
+    })
+    .await
+    .is_ok());
+}
+
+fn browser_on_platform(browser_name: &str, platform: &str) -> SdkEventFilters {
+    SdkEventFilters {
+        browser_name: vec![browser_name.to_string()],
+        platform: vec![platform.to_string()],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_any_of_filter_groups_are_parenthesized() {
+    let pool = MockPool::default();
+    let filters = SdkEventFilters {
+        payment_method: vec!["card".to_string()],
+        any_of: vec![
+            browser_on_platform("Chrome", "web"),
+            browser_on_platform("Safari", "ios"),
+        ],
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    assert!(pool.last_query().contains(
+        " WHERE payment_method IN ('card') AND ((browser_name IN ('Chrome') AND platform IN ('web')) OR (browser_name IN ('Safari') AND platform IN ('ios'))) AND "
+    ));
+}
+
+#[tokio::test]
+async fn test_empty_any_of_group_matches_every_event() {
+    let pool = MockPool::default();
+    let filters = SdkEventFilters {
+        any_of: vec![
+            browser_on_platform("Chrome", "web"),
+            SdkEventFilters::default(),
+        ],
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(!query.contains(" OR "));
+    assert!(!query.contains("browser_name"));
+}
+
+#[tokio::test]
+async fn test_sdk_bounce_rate_filtered_by_platform() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({
+            "platform": "web",
+            "initiated": 20,
+            "rendered": 15,
+            "bounce_rate": 0.25
+        }),
+        serde_json::json!({ "platform": "web", "initiated": 0, "rendered": 0 }),
+    ]);
+    let filters = SdkEventFilters {
+        platform: vec!["web".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::SdkBounceRate
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let rates = buckets
+        .iter()
+        .map(|(_, row)| {
+            let mut bounce_rate = BounceRateAccumulator::default();
+            bounce_rate.add_metrics_bucket(row);
+            bounce_rate.collect()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(rates, vec![Some(0.25), None]);
+    let query = pool.last_query();
+    assert!(query.contains(
+        "(countIf(event_name = 'ORCA_ELEMENTS_CALLED') - countIf(event_name = 'APP_RENDERED')) / nullIf(countIf(event_name = 'ORCA_ELEMENTS_CALLED'), 0) as bounce_rate"
+    ));
+    assert!(query.contains("platform IN ('web')"));
+    assert!(query.contains("event_name IN ('ORCA_ELEMENTS_CALLED', 'APP_RENDERED')"));
+}
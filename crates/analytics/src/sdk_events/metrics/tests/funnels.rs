@@ -0,0 +1,150 @@
+use super::*;
+
+#[tokio::test]
+async fn test_funnel_waterfall_drops_between_requested_stages() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "initiated": 10, "selected": 6, "attempted": 2 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        funnel_stages: Some(vec![
+            FunnelStage::Initiated,
+            FunnelStage::Selected,
+            FunnelStage::Attempted,
+        ]),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::FunnelWaterfall
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+    for (_, row) in buckets.iter() {
+        accumulator.add_metric_bucket(&SdkEventMetrics::FunnelWaterfall, row);
+    }
+    let waterfall = accumulator
+        .collect()
+        .funnel_waterfall
+        .expect("no waterfall was collected");
+    assert_eq!(
+        waterfall,
+        vec![
+            FunnelWaterfallStage {
+                stage: FunnelStage::Initiated,
+                count: Some(10),
+                drop_from_previous: None,
+            },
+            FunnelWaterfallStage {
+                stage: FunnelStage::Selected,
+                count: Some(6),
+                drop_from_previous: Some(4),
+            },
+            FunnelWaterfallStage {
+                stage: FunnelStage::Attempted,
+                count: Some(2),
+                drop_from_previous: Some(4),
+            },
+        ]
+    );
+    // A single query counts only the requested stages
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    assert_eq!(queries.len(), 1);
+    let query = queries.first().expect("missing waterfall query");
+    assert!(["as initiated", "as selected", "as attempted"]
+        .iter()
+        .all(|stage| query.contains(stage)));
+    assert!(!query.contains("as rendered"));
+}
+
+#[tokio::test]
+async fn test_funnel_stage_requested_twice_is_rejected() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        funnel_stages: Some(vec![FunnelStage::Rendered, FunnelStage::Rendered]),
+        ..Default::default()
+    };
+
+    let error = SdkEventMetrics::FunnelWaterfall
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("a repeated stage was accepted");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::InvalidFunnelStages(_)
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_funnel_grouped_by_customer_segment_joins_the_segments() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "customer_segment": "returning", "initiated": 4, "rendered": 3 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        sample_rate: Some(0.5),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::FunnelConversionRate
+        .load_metrics(
+            &[SdkEventDimensions::CustomerSegment],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    // The events are sampled before they're joined
+    assert!(query.contains(
+        "FROM sdk_events_audit SAMPLE 0.5 LEFT JOIN (SELECT merchant_id as segment_merchant_id, payment_id as segment_payment_id, customer_segment as segment_customer_segment FROM customer_segments) segment ON merchant_id = segment_merchant_id AND payment_id = segment_payment_id WHERE"
+    ));
+    assert!(query.contains("nullIf(segment_customer_segment, '') as customer_segment"));
+    assert_eq!(query.matches("LEFT JOIN").count(), 1);
+}
+
+#[tokio::test]
+async fn test_funnel_without_customer_segment_is_not_joined() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({ "initiated": 4 })]);
+
+    SdkEventMetrics::FunnelConversionRate
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    assert!(!pool.last_query().contains("JOIN"));
+}
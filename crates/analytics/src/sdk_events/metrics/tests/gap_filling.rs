@@ -0,0 +1,250 @@
+use super::*;
+
+#[tokio::test]
+async fn test_fill_gaps_adds_zero_buckets_to_each_group() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "time_bucket": "2024-03-03T00:00:00Z", "count": 4 }),
+        serde_json::json!({ "platform": "ios", "time_bucket": "2024-03-05T00:00:00Z", "count": 2 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        fill_gaps: true,
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneDay),
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    // The range ends at midnight of the 8th, which is in its bucket
+    assert_eq!(buckets.len(), 16);
+    let web = buckets
+        .iter()
+        .filter(|(id, _)| id.platform.as_deref() == Some("web"))
+        .map(|(id, row)| (id.time_bucket.clone().unwrap_or_default(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(web.len(), 8);
+    assert_eq!(
+        web.first(),
+        Some(&("2024-03-01T00:00:00Z".to_string(), Some(0)))
+    );
+    assert_eq!(
+        web.get(2),
+        Some(&("2024-03-03T00:00:00Z".to_string(), Some(4)))
+    );
+    assert_eq!(
+        web.last(),
+        Some(&("2024-03-08T00:00:00Z".to_string(), Some(0)))
+    );
+}
+
+#[tokio::test]
+async fn test_fill_gaps_keeps_every_payment_experience() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "payment_experience": "redirect_to_url", "time_bucket": "2024-03-02T00:00:00Z", "count": 3 }),
+        serde_json::json!({ "payment_experience": "display_qr_code", "time_bucket": "2024-03-06T00:00:00Z", "count": 1 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        fill_gaps: true,
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentExperienceBreakdown
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneDay),
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    // Every experience has a bucket for each day, and there's no bucket without experience
+    assert_eq!(buckets.len(), 16);
+    assert!(buckets
+        .iter()
+        .all(|(id, _)| id.payment_experience.is_some()));
+    let qr_code = buckets
+        .iter()
+        .filter(|(id, _)| id.payment_experience.as_deref() == Some("display_qr_code"))
+        .map(|(_, row)| row.count)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        qr_code,
+        vec![
+            Some(0),
+            Some(0),
+            Some(0),
+            Some(0),
+            Some(0),
+            Some(1),
+            Some(0),
+            Some(0)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_fill_gaps_skips_hour_missing_from_local_clock() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        time_zone: Some("America/New_York".to_string()),
+        fill_gaps: true,
+        ..Default::default()
+    };
+    // 01:00 to 04:00 on the local clock, which skips from 02:00 to 03:00 that night
+    let start_time = PrimitiveDateTime::new(
+        Date::from_calendar_date(2024, Month::March, 10).expect("invalid date"),
+        Time::from_hms(6, 0, 0).expect("invalid time"),
+    );
+    let time_range = TimeRange {
+        start_time,
+        end_time: Some(start_time + Duration::hours(2)),
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &options,
+            &time_range,
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let time_buckets = buckets
+        .iter()
+        .map(|(id, _)| id.time_bucket.as_deref().unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        time_buckets,
+        [
+            "2024-03-10 01:00:00",
+            "2024-03-10 03:00:00",
+            "2024-03-10 04:00:00"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_fill_gaps_keeps_both_hours_of_the_repeated_local_hour() {
+    // Loaded from the second time the clock reads 1am
+    let pool = MockPool::with_rows(vec![serde_json::json!({
+        "time_bucket": "2024-11-03 01:00:00-0500",
+        "count": 4
+    })]);
+    let options = SdkEventQueryOptions {
+        time_zone: Some("America/New_York".to_string()),
+        fill_gaps: true,
+        ..Default::default()
+    };
+    // 00:00 to 03:00 on the local clock, which goes back from 02:00 to 01:00 that night
+    let start_time = PrimitiveDateTime::new(
+        Date::from_calendar_date(2024, Month::November, 3).expect("invalid date"),
+        Time::from_hms(4, 0, 0).expect("invalid time"),
+    );
+    let time_range = TimeRange {
+        start_time,
+        end_time: Some(start_time + Duration::hours(4)),
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &options,
+            &time_range,
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let time_buckets = buckets
+        .iter()
+        .map(|(id, row)| (id.time_bucket.as_deref().unwrap_or_default(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        time_buckets,
+        [
+            ("2024-11-03 00:00:00", Some(0)),
+            ("2024-11-03 01:00:00-0400", Some(0)),
+            ("2024-11-03 01:00:00-0500", Some(4)),
+            ("2024-11-03 02:00:00", Some(0)),
+            ("2024-11-03 03:00:00", Some(0))
+        ]
+    );
+    assert!(pool.last_query().contains(
+        "if(toString(toStartOfHour(created_at, 'America/New_York')) IN (toString(toStartOfHour(created_at, 'America/New_York') - INTERVAL 1 HOUR), toString(toStartOfHour(created_at, 'America/New_York') + INTERVAL 1 HOUR)), formatDateTime(toStartOfHour(created_at, 'America/New_York'), '%Y-%m-%d %H:%i:%S%z'), toString(toStartOfHour(created_at, 'America/New_York'))) as time_bucket"
+    ));
+}
+
+#[tokio::test]
+async fn test_fill_gaps_buckets_days_of_daylight_saving_changes_by_the_hour() {
+    let options = SdkEventQueryOptions {
+        time_zone: Some("America/New_York".to_string()),
+        fill_gaps: true,
+        ..Default::default()
+    };
+    // The local days, from midnight to the second before the next one
+    let day = |month, day, start_hour, hours| {
+        let start_time = PrimitiveDateTime::new(
+            Date::from_calendar_date(2024, month, day).expect("invalid date"),
+            Time::from_hms(start_hour, 0, 0).expect("invalid time"),
+        );
+        TimeRange {
+            start_time,
+            end_time: Some(start_time + Duration::hours(hours) - Duration::SECOND),
+        }
+    };
+
+    for (time_range, granularity, expected) in [
+        (day(Month::March, 10, 5, 23), Granularity::OneHour, 23),
+        (day(Month::November, 3, 4, 25), Granularity::OneHour, 25),
+        (day(Month::March, 10, 5, 23), Granularity::ThirtyMin, 46),
+        (day(Month::November, 3, 4, 25), Granularity::ThirtyMin, 50),
+    ] {
+        let buckets = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &Some(granularity),
+                &options,
+                &time_range,
+                &MockPool::default(),
+            )
+            .await
+            .expect("failed to load metrics");
+
+        let time_buckets = buckets
+            .iter()
+            .filter_map(|(id, _)| id.time_bucket.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            time_buckets.len(),
+            expected,
+            "{time_range:?} {granularity:?}"
+        );
+        assert_eq!(
+            time_buckets.iter().collect::<HashSet<_>>().len(),
+            expected,
+            "a bucket of {time_range:?} {granularity:?} is duplicated"
+        );
+    }
+}
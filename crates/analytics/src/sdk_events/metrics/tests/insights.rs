@@ -0,0 +1,137 @@
+use super::*;
+
+#[test]
+fn test_buckets_far_from_trailing_baseline_are_flagged() {
+    let counts = [("web", [10, 10, 12, 10, 11, 40]), ("ios", [5; 6])];
+    // The groups' buckets are interleaved, the way rows of a time series arrive
+    let buckets = (0..6)
+        .flat_map(|hour| {
+            counts.iter().map(move |(platform, counts)| {
+                let id = SdkEventMetricsBucketIdentifier {
+                    platform: Some(platform.to_string()),
+                    time_bucket: Some(format!("2024-03-01 {hour:02}:00:00")),
+                    ..Default::default()
+                };
+                let count = counts.get(hour).copied();
+                let row = serde_json::from_value(serde_json::json!({ "count": count }))
+                    .expect("invalid row");
+                (id, row)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let flagged = flag_anomalies(
+        &SdkEventMetrics::PaymentAttempts,
+        buckets,
+        &AnomalyDetection {
+            window: 3,
+            threshold: 3.0,
+        },
+    );
+
+    let web = flagged
+        .iter()
+        .filter(|bucket| bucket.id.platform.as_deref() == Some("web"))
+        .map(|bucket| (bucket.baseline_mean.is_some(), bucket.is_anomaly))
+        .collect::<Vec<_>>();
+    // The first buckets have fewer than three buckets before them to compare to
+    assert_eq!(
+        web,
+        vec![
+            (false, false),
+            (false, false),
+            (false, false),
+            (true, false),
+            (true, false),
+            (true, true)
+        ]
+    );
+    let last = flagged
+        .iter()
+        .rev()
+        .nth(1)
+        .expect("missing last web bucket");
+    assert_eq!(last.value, Some(40.0));
+    assert_eq!(last.baseline_mean, Some(11.0));
+    assert!(flagged
+        .iter()
+        .filter(|bucket| bucket.id.platform.as_deref() == Some("ios"))
+        .all(|bucket| !bucket.is_anomaly));
+}
+
+#[test]
+fn test_buckets_get_the_most_specific_target_of_their_metric() {
+    let target = |target: f64, dimensions: &[(SdkEventDimensions, &str)]| SdkEventMetricTarget {
+        metric: SdkEventMetrics::PaymentSuccessRate,
+        target,
+        direction: TargetDirection::AtLeast,
+        dimensions: dimensions
+            .iter()
+            .map(|(dimension, value)| (*dimension, value.to_string()))
+            .collect(),
+    };
+    let targets = [
+        target(0.95, &[]),
+        target(0.9, &[(SdkEventDimensions::Platform, "ios")]),
+        target(0.8, &[(SdkEventDimensions::Platform, "ios")]),
+        SdkEventMetricTarget {
+            metric: SdkEventMetrics::SdkBounceRate,
+            target: 0.1,
+            direction: TargetDirection::AtMost,
+            dimensions: Default::default(),
+        },
+    ];
+    let row = SdkEventMetricRow {
+        successful: Some(92),
+        attempted: Some(100),
+        ..Default::default()
+    };
+    let bucket = |platform: &str| SdkEventMetricsBucketIdentifier {
+        platform: Some(platform.to_string()),
+        ..Default::default()
+    };
+
+    // The first of the targets of the platform applies over the one of every bucket
+    let ios = target_result(
+        &SdkEventMetrics::PaymentSuccessRate,
+        &bucket("ios"),
+        &row,
+        &targets,
+    )
+    .expect("no target of ios");
+    assert_eq!(ios.target, 0.9);
+    assert_eq!(ios.value, Some(0.92));
+    assert_eq!(ios.meets_target, Some(true));
+
+    let web = target_result(
+        &SdkEventMetrics::PaymentSuccessRate,
+        &bucket("web"),
+        &row,
+        &targets,
+    )
+    .expect("no target of web");
+    assert_eq!(web.target, 0.95);
+    assert_eq!(web.meets_target, Some(false));
+
+    // A bucket without a value still gets its target, but neither meets nor misses it
+    let empty = target_result(
+        &SdkEventMetrics::PaymentSuccessRate,
+        &bucket("web"),
+        &SdkEventMetricRow::default(),
+        &targets,
+    )
+    .expect("no target of an empty bucket");
+    assert_eq!(empty.value, None);
+    assert_eq!(empty.meets_target, None);
+
+    // Metrics without a target get none
+    assert_eq!(
+        target_result(
+            &SdkEventMetrics::PaymentAttempts,
+            &bucket("ios"),
+            &row,
+            &targets,
+        ),
+        None
+    );
+}
@@ -0,0 +1,238 @@
+use super::*;
+
+#[tokio::test]
+async fn test_query_above_scan_limit_is_rejected() {
+    let pool = MockPool {
+        max_scanned_rows: Some(1),
+        ..MockPool::with_rows(vec![
+            serde_json::json!({ "platform": "web", "count": 3 }),
+            serde_json::json!({ "platform": "ios", "count": 5 }),
+        ])
+    };
+
+    let error = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("query above the scan limit was run");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::QueryTooLarge
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_results_over_the_row_cap_are_truncated_and_flagged() {
+    let load = |rows: Vec<serde_json::Value>| async move {
+        let pool = MockPool {
+            max_result_rows: Some(2),
+            ..MockPool::with_rows(rows)
+        };
+        let buckets = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &SdkEventQueryOptions::default(),
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+        (pool.last_query(), buckets)
+    };
+    let row = |platform: &str| serde_json::json!({ "platform": platform, "count": 1 });
+
+    // One row over the cap is asked for, once the groups are ordered
+    let (query, buckets) = load(vec![row("android"), row("ios"), row("web")]).await;
+    assert!(query.ends_with("LIMIT 3"));
+    let platforms = buckets
+        .iter()
+        .map(|(id, _)| id.platform.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(platforms, vec![Some("android"), Some("ios")]);
+    assert!(buckets.iter().all(|(_, row)| row.truncated));
+
+    // A result of exactly as many rows as the cap is whole
+    let (_, buckets) = load(vec![row("android"), row("ios")]).await;
+    assert_eq!(buckets.len(), 2);
+    assert!(buckets.iter().all(|(_, row)| !row.truncated));
+}
+
+#[test]
+fn test_query_without_time_range_is_rejected() {
+    let mut query_builder: QueryBuilder<MockPool> =
+        QueryBuilder::new(AnalyticsCollection::SdkEvents);
+    query_builder
+        .add_select_column("count(*) as count")
+        .expect("failed to add select column");
+    query_builder
+        .add_filter_clause("merchant_id", "pk_test")
+        .expect("failed to add filter clause");
+
+    let error = check_scoped(&query_builder).expect_err("a query of all time was allowed");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnscopedQuery
+    ));
+
+    TimeRange {
+        end_time: None,
+        ..seven_day_range()
+    }
+    .set_filter_clause(&mut query_builder)
+    .expect("failed to filter time range");
+    let error = check_scoped(&query_builder).expect_err("a query up to any time was allowed");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnscopedQuery
+    ));
+
+    seven_day_range()
+        .set_filter_clause(&mut query_builder)
+        .expect("failed to filter time range");
+    assert!(check_scoped(&query_builder).is_ok());
+}
+
+#[tokio::test]
+async fn test_range_without_an_end_is_queried_up_to_now() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({ "count": 3 })]);
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &TimeRange {
+                end_time: None,
+                ..seven_day_range()
+            },
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    assert_eq!(buckets.len(), 1);
+    assert!(pool.last_query().contains("created_at <= '"));
+}
+
+#[tokio::test]
+async fn test_queries_beyond_merchant_limit_are_rejected() {
+    let pool = MockPool {
+        merchant_query_limiter: Some(MerchantQueryLimiter::new(&MerchantQueryLimit {
+            max_concurrent_queries: 1,
+            max_queued_queries: 0,
+        })),
+        ..Default::default()
+    };
+    let load = |publishable_key: &'static str| {
+        SdkEventMetrics::PaymentAttempts.load_metrics(
+            &[],
+            publishable_key,
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+    };
+
+    let running = pool
+        .merchant_query_limiter
+        .as_ref()
+        .expect("missing limiter")
+        .acquire("pk_busy")
+        .await
+        .expect("no slot for the first query");
+    let error = load("pk_busy")
+        .await
+        .expect_err("query beyond the merchant limit was run");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::TooManyQueries
+    ));
+    // Other merchants aren't held up by the busy one
+    load("pk_quiet")
+        .await
+        .expect("query of another merchant was rejected");
+
+    drop(running);
+    load("pk_busy")
+        .await
+        .expect("query after the running one finished was rejected");
+}
+
+#[tokio::test]
+async fn test_inverted_time_range_is_rejected() {
+    let pool = MockPool::default();
+    let range = seven_day_range();
+    let inverted = TimeRange {
+        start_time: range.end_time.expect("range has an end"),
+        end_time: Some(range.start_time),
+    };
+
+    let error = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &inverted,
+            &pool,
+        )
+        .await
+        .expect_err("inverted time range was queried");
+
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::InvalidTimeRange(message)
+            if message.contains("2024-03-08") && message.contains("2024-03-01")
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_time_range_above_max_span_is_clamped_to_its_end() {
+    let pool = MockPool {
+        max_time_range: Some(Duration::days(2)),
+        ..MockPool::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains("created_at >= '2024-03-06 00:00:00'"));
+    assert!(query.contains("created_at <= '2024-03-08 00:00:00'"));
+}
@@ -0,0 +1,200 @@
+use super::*;
+
+/// The seven days of [`seven_day_range`] as whole hours, ending on their last second
+fn seven_whole_days_range() -> TimeRange {
+    let range = seven_day_range();
+    TimeRange {
+        end_time: range
+            .end_time
+            .map(|end_time| end_time - Duration::seconds(1)),
+        ..range
+    }
+}
+
+#[test]
+fn test_materialized_view_is_picked_only_when_it_has_what_the_query_needs() {
+    let pick = |metric: SdkEventMetrics,
+                dimensions: &[SdkEventDimensions],
+                filters: SdkEventFilters,
+                granularity: Option<Granularity>,
+                options: SdkEventQueryOptions,
+                time_range: TimeRange| {
+        pick_materialized_view(
+            &metric,
+            dimensions,
+            &filters,
+            &granularity,
+            &options,
+            &time_range,
+        )
+    };
+    let attempts = |dimensions: &[SdkEventDimensions], granularity, time_range| {
+        pick(
+            SdkEventMetrics::PaymentAttempts,
+            dimensions,
+            SdkEventFilters::default(),
+            granularity,
+            SdkEventQueryOptions::default(),
+            time_range,
+        )
+    };
+    let platform = [SdkEventDimensions::Platform];
+
+    assert_eq!(
+        attempts(
+            &platform,
+            Some(Granularity::OneDay),
+            seven_whole_days_range()
+        ),
+        Some(SDK_EVENTS_HOURLY)
+    );
+    assert_eq!(
+        attempts(&platform, None, seven_whole_days_range()),
+        Some(SDK_EVENTS_HOURLY)
+    );
+    // The view's rows are of whole hours
+    assert_eq!(
+        attempts(
+            &platform,
+            Some(Granularity::FifteenMin),
+            seven_whole_days_range()
+        ),
+        None
+    );
+    assert_eq!(attempts(&platform, None, seven_day_range()), None);
+    // The view doesn't keep the device type
+    assert_eq!(
+        attempts(
+            &[SdkEventDimensions::DeviceType],
+            None,
+            seven_whole_days_range()
+        ),
+        None
+    );
+    assert_eq!(
+        pick(
+            SdkEventMetrics::PaymentAttempts,
+            &platform,
+            SdkEventFilters {
+                any_of: vec![SdkEventFilters {
+                    os: vec!["ios".to_string()],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            None,
+            SdkEventQueryOptions::default(),
+            seven_whole_days_range(),
+        ),
+        None
+    );
+    for options in [
+        SdkEventQueryOptions {
+            time_zone: Some("Asia/Kolkata".to_string()),
+            ..Default::default()
+        },
+        SdkEventQueryOptions {
+            sample_rate: Some(0.1),
+            ..Default::default()
+        },
+        SdkEventQueryOptions {
+            deduplicate: true,
+            ..Default::default()
+        },
+        SdkEventQueryOptions {
+            top_groups: Some(5),
+            ..Default::default()
+        },
+    ] {
+        assert_eq!(
+            pick(
+                SdkEventMetrics::PaymentAttempts,
+                &platform,
+                SdkEventFilters::default(),
+                None,
+                options.clone(),
+                seven_whole_days_range(),
+            ),
+            None,
+            "view picked with {options:?}"
+        );
+    }
+    // Only the metrics declaring a view read one
+    assert_eq!(
+        pick(
+            SdkEventMetrics::AveragePaymentTime,
+            &platform,
+            SdkEventFilters::default(),
+            None,
+            SdkEventQueryOptions::default(),
+            seven_whole_days_range(),
+        ),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_count_metrics_read_the_materialized_view_of_a_data_source_having_it() {
+    let load = |materialized_views: bool| async move {
+        let pool = MockPool {
+            materialized_views,
+            ..MockPool::with_rows(vec![serde_json::json!({ "platform": "web", "count": 5 })])
+        };
+        let buckets = SdkEventMetrics::PaymentAttempts
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &Some(Granularity::OneDay),
+                &SdkEventQueryOptions::default(),
+                &seven_whole_days_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+        assert_eq!(buckets.len(), 1);
+        pool.last_query()
+    };
+
+    let query = load(true).await;
+    assert!(query.contains("sum(event_count) as count"));
+    assert!(query.contains("FROM sdk_events_hourly WHERE"));
+    assert!(query.contains("event_name = 'PAYMENT_ATTEMPT'"));
+
+    let query = load(false).await;
+    assert!(query.contains("count(*) as count"));
+    assert!(query.contains("FROM sdk_events_audit WHERE"));
+}
+
+#[tokio::test]
+async fn test_batched_counts_read_the_materialized_view() {
+    let pool = MockPool {
+        materialized_views: true,
+        ..MockPool::with_rows(vec![
+            serde_json::json!({ "platform": "web", "payment_attempts": 5, "sdk_rendered_count": 9 }),
+        ])
+    };
+
+    load_metrics_batch(
+        &[
+            SdkEventMetrics::PaymentAttempts,
+            SdkEventMetrics::SdkRenderedCount,
+        ],
+        &[SdkEventDimensions::Platform],
+        "pk_test",
+        &SdkEventFilters::default(),
+        &None,
+        &SdkEventQueryOptions::default(),
+        &seven_whole_days_range(),
+        &pool,
+    )
+    .await
+    .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains(
+        "sumIf(event_count, first_event = 1 AND event_name = 'PAYMENT_ATTEMPT') as \
+         payment_attempts"
+    ));
+    assert!(query.contains("FROM sdk_events_hourly WHERE"));
+}
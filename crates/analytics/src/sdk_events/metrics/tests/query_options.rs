@@ -0,0 +1,322 @@
+use common_utils::errors::ReportSwitchExt;
+
+use super::*;
+
+#[tokio::test]
+async fn test_count_threshold_adds_having_clause() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        count_threshold: Some(CountThreshold {
+            comparison: api_models::analytics::Comparison::GreaterThan,
+            value: 1000,
+        }),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::BrowserName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    assert!(pool
+        .last_query()
+        .contains("GROUP BY browser_name HAVING count(*) > 1000"));
+}
+
+#[tokio::test]
+async fn test_every_count_metric_applies_the_count_threshold() {
+    let options = SdkEventQueryOptions {
+        count_threshold: Some(CountThreshold {
+            comparison: api_models::analytics::Comparison::GreaterThan,
+            value: 1000,
+        }),
+        ..Default::default()
+    };
+
+    for metric in SdkEventMetrics::iter().filter(|metric| batch::count_conditions(metric).is_some())
+    {
+        let pool = MockPool::default();
+        metric
+            .load_metrics(
+                &[SdkEventDimensions::BrowserName],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &options,
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+
+        assert!(
+            pool.last_query()
+                .contains("GROUP BY browser_name HAVING count(*) > 1000"),
+            "{metric} ignored the count threshold"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_pagination_orders_groups_by_event_count() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        pagination: Some(SdkEventPagination {
+            order_by: SdkEventOrderBy::EventCount,
+            direction: SortDirection::Descending,
+            limit: 10,
+            offset: 20,
+        }),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[
+                SdkEventDimensions::BrowserName,
+                SdkEventDimensions::Platform,
+            ],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains("ORDER BY count(*) desc nulls last, browser_name asc nulls last"));
+    assert!(query.ends_with("LIMIT 10 OFFSET 20"));
+}
+
+#[tokio::test]
+async fn test_moving_average_over_time_buckets_of_each_group() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        moving_average_buckets: Some(3),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    assert!(pool.last_query().contains(
+        "count(*) as count, avg(count(*)) over (partition by platform order by toStartOfInterval(created_at, INTERVAL 60 MINUTE) asc rows between 2 preceding and current row) as moving_average"
+    ));
+}
+
+#[tokio::test]
+async fn test_running_total_is_partitioned_by_dimensions() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        running_total: true,
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[
+                SdkEventDimensions::BrowserName,
+                SdkEventDimensions::Platform,
+            ],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains(
+        "sum(count(*)) over (partition by browser_name, platform order by toStartOfInterval(created_at, INTERVAL 60 MINUTE) asc rows unbounded preceding) as running_total"
+    ));
+    assert!(!query.contains("moving_average"));
+}
+
+#[tokio::test]
+async fn test_deduplicated_counts_count_distinct_event_ids() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        running_total: true,
+        deduplicate: true,
+        ..Default::default()
+    };
+
+    SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT platform, uniqExact(event_id) as count"));
+    assert!(query.contains(
+        "sum(count(DISTINCT event_id)) over (partition by platform order by toStartOfInterval(created_at, INTERVAL 60 MINUTE) asc rows unbounded preceding) as running_total"
+    ));
+    assert!(!query.contains("count(*)"));
+
+    // Without it every row is counted, copies included
+    SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+    assert!(pool
+        .last_query()
+        .starts_with("SELECT platform, count(*) as count"));
+}
+
+#[tokio::test]
+async fn test_archived_events_are_read_along_the_recent_ones() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        include_archived: true,
+        ..Default::default()
+    };
+
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    // Both tables are grouped in one aggregation, so a platform in both is a single bucket
+    let query = pool.last_query();
+    assert!(query.starts_with(
+        "SELECT platform, count(*) as count FROM (SELECT * FROM sdk_events_audit \
+         UNION ALL SELECT * FROM sdk_events_audit_archive) sdk_events_audit WHERE"
+    ));
+    assert!(query.contains("GROUP BY platform"));
+
+    // The sessions, which may have events in both, are aggregated over both as well
+    SdkEventMetrics::SessionDurationSeconds
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+    assert!(pool.last_query().contains(
+        "FROM (SELECT * FROM sdk_events_audit UNION ALL \
+         SELECT * FROM sdk_events_audit_archive) sdk_events_audit WHERE"
+    ));
+}
+
+#[tokio::test]
+async fn test_percent_of_total_is_partitioned_by_time_bucket() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        percent_of_total: true,
+        ..Default::default()
+    };
+
+    for granularity in [Some(Granularity::OneHour), None] {
+        SdkEventMetrics::SdkRenderedCount
+            .load_metrics(
+                &[SdkEventDimensions::Platform],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &granularity,
+                &options,
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+    }
+
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    let mut queries = queries.iter();
+    let time_series = queries.next().expect("missing time series query");
+    assert!(time_series.contains(
+        "count(*) * 100 / nullIf(sum(count(*)) over (partition by toStartOfInterval(created_at, INTERVAL 60 MINUTE)), 0) as percent_of_total"
+    ));
+    let whole_range = queries.next().expect("missing whole range query");
+    assert!(whole_range
+        .contains("count(*) * 100 / nullIf(sum(count(*)) over (), 0) as percent_of_total"));
+}
+
+#[tokio::test]
+async fn test_unknown_time_zone_is_a_bad_query() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        time_zone: Some("Mars/Olympus_Mons".to_string()),
+        ..Default::default()
+    };
+
+    let error = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("an unknown time zone was queried");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::BadQuery(reason) if reason == "Unknown time zone"
+    ));
+
+    // The metrics of the other domains keep switching to the query building error
+    let error: MetricsResult<()> = Err(report!(QueryBuildingError::InvalidQuery(
+        "Unknown time zone"
+    )))
+    .switch();
+    assert!(matches!(
+        error.expect_err("the error was dropped").current_context(),
+        MetricsError::QueryBuildingError
+    ));
+}
@@ -0,0 +1,111 @@
+use super::*;
+
+#[tokio::test]
+async fn test_top_groups_merge_the_rest_into_other() {
+    let pool = MockPool {
+        responses: Mutex::new(VecDeque::from([
+            vec![
+                serde_json::json!({ "browser_name": "Chrome", "count": 10 }),
+                serde_json::json!({ "browser_name": "Safari", "count": 4 }),
+            ],
+            vec![
+                serde_json::json!({ "browser_name": "Chrome", "count": 10 }),
+                serde_json::json!({ "browser_name": "Safari", "count": 4 }),
+            ],
+            vec![serde_json::json!({ "count": 3 })],
+        ])),
+        ..Default::default()
+    };
+    let options = SdkEventQueryOptions {
+        top_groups: Some(2),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::BrowserName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.browser_name.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("Chrome".to_string()), Some(10)),
+            (Some("Safari".to_string()), Some(4)),
+            (Some(OTHER_GROUP.to_string()), Some(3))
+        ]
+    );
+
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    assert_eq!(queries.len(), 3);
+    let mut queries = queries.iter();
+    let ranking = queries.next().expect("no ranking query");
+    let top = queries.next().expect("no query of the top groups");
+    let other = queries.next().expect("no query of the other group");
+    assert!(ranking.contains("ORDER BY count(*) desc nulls last"));
+    assert!(ranking.ends_with("LIMIT 2"));
+    assert!(top.contains("((browser_name IN ('Chrome')) OR (browser_name IN ('Safari')))"));
+    assert!(top.contains("GROUP BY browser_name"));
+    assert!(other.contains(
+        "NOT coalesce((browser_name IN ('Chrome')) OR (browser_name IN ('Safari')), false)"
+    ));
+    assert!(!other.contains("GROUP BY"));
+}
+
+#[tokio::test]
+async fn test_percent_of_total_of_top_groups_includes_the_other_group() {
+    let top = vec![
+        serde_json::json!({ "browser_name": "Chrome", "count": 10, "percent_of_total": 62.5 }),
+        serde_json::json!({ "browser_name": "Safari", "count": 6, "percent_of_total": 37.5 }),
+    ];
+    let pool = MockPool {
+        responses: Mutex::new(VecDeque::from([
+            top.clone(),
+            top,
+            vec![serde_json::json!({ "count": 4, "percent_of_total": 100 })],
+        ])),
+        ..Default::default()
+    };
+    let options = SdkEventQueryOptions {
+        top_groups: Some(2),
+        percent_of_total: true,
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::BrowserName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let shares = buckets
+        .iter()
+        .map(|(id, row)| (id.browser_name.clone(), row.percent_of_total.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        shares,
+        vec![
+            (Some("Chrome".to_string()), Some(50.into())),
+            (Some("Safari".to_string()), Some(30.into())),
+            (Some(OTHER_GROUP.to_string()), Some(20.into()))
+        ]
+    );
+}
@@ -0,0 +1,95 @@
+use super::*;
+
+#[tokio::test]
+async fn test_recent_metrics_are_loaded_fresh_from_the_latest_partitions() {
+    let pool = MockPool {
+        responses: Mutex::new(VecDeque::from([
+            vec![serde_json::json!({ "count": 3 })],
+            vec![serde_json::json!({ "count": 4 })],
+            vec![serde_json::json!({ "payment_attempts": 4, "sdk_rendered_count": 6 })],
+        ])),
+        metrics_cache: Some(SdkEventMetricsCache::new(&MetricsCacheConfig::default())),
+        ..Default::default()
+    };
+    let now = PrimitiveDateTime::new(
+        Date::from_calendar_date(2024, Month::March, 1).expect("invalid date"),
+        Time::from_hms(0, 2, 0).expect("invalid time"),
+    );
+    let time_range = recent_time_range(None, now).expect("invalid recent window");
+    let load = |metrics: Vec<SdkEventMetrics>| {
+        let pool = &pool;
+        async move {
+            load_recent_metrics(
+                &metrics,
+                "pk_test",
+                &SdkEventFilters::default(),
+                &time_range,
+                pool,
+            )
+            .await
+            .expect("failed to load recent metrics")
+            .into_iter()
+            .map(|(metric, buckets)| {
+                let counts = buckets
+                    .into_iter()
+                    .map(|(id, row)| (id.time_bucket, row.count))
+                    .collect::<Vec<_>>();
+                (metric, counts)
+            })
+            .collect::<Vec<_>>()
+        }
+    };
+
+    // Polled again, the metric is loaded again rather than from the cache
+    for count in [3, 4] {
+        assert_eq!(
+            load(vec![SdkEventMetrics::PaymentAttempts]).await,
+            [(SdkEventMetrics::PaymentAttempts, vec![(None, Some(count))])]
+        );
+    }
+    // The count metrics share a single query
+    assert_eq!(
+        load(vec![
+            SdkEventMetrics::PaymentAttempts,
+            SdkEventMetrics::SdkRenderedCount
+        ])
+        .await,
+        [
+            (SdkEventMetrics::PaymentAttempts, vec![(None, Some(4))]),
+            (SdkEventMetrics::SdkRenderedCount, vec![(None, Some(6))])
+        ]
+    );
+
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    assert_eq!(queries.len(), 3);
+    for query in queries {
+        assert!(query.contains("created_at >= '2024-02-29 23:57:00'"));
+        assert!(query.contains("created_at <= '2024-03-01 00:02:00'"));
+        assert!(!query.contains("time_bucket"));
+        assert!(query.ends_with(" SETTINGS max_partitions_to_read = 2"));
+    }
+}
+
+#[test]
+fn test_recent_window_is_capped_and_not_empty() {
+    let now = PrimitiveDateTime::new(
+        Date::from_calendar_date(2024, Month::March, 1).expect("invalid date"),
+        Time::MIDNIGHT,
+    );
+
+    let time_range = recent_time_range(Some(15), now).expect("invalid recent window");
+    assert_eq!(time_range.start_time, now - Duration::minutes(15));
+    assert_eq!(time_range.end_time, Some(now));
+
+    let time_range = recent_time_range(Some(24 * 60), now).expect("invalid recent window");
+    assert_eq!(
+        time_range.start_time,
+        now - Duration::minutes(MAX_RECENT_WINDOW_MINS.into())
+    );
+
+    let error = recent_time_range(Some(0), now).expect_err("empty window was accepted");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::InvalidTimeRange(_)
+    ));
+}
@@ -0,0 +1,123 @@
+use super::*;
+
+#[tokio::test]
+async fn test_sampled_counts_are_scaled_back_up() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "count": 12 }),
+        serde_json::json!({ "platform": "ios", "count": 3 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        sample_rate: Some(0.1),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.platform.clone(), row.count, row.sample_rate.clone()))
+        .collect::<Vec<_>>();
+    let sample_rate = bigdecimal::BigDecimal::from_f64(0.1);
+    assert_eq!(
+        counts,
+        vec![
+            (Some("ios".to_string()), Some(30), sample_rate.clone()),
+            (Some("web".to_string()), Some(120), sample_rate)
+        ]
+    );
+    assert!(pool
+        .last_query()
+        .contains("FROM sdk_events_audit SAMPLE 0.1 WHERE"));
+}
+
+#[tokio::test]
+async fn test_session_metrics_are_not_sampled() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({ "count": 4, "total": 600 })]);
+    let options = SdkEventQueryOptions {
+        sample_rate: Some(0.5),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::SessionDurationSeconds
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let (_, row) = buckets.first().expect("no bucket was loaded");
+    assert_eq!(row.count, Some(4));
+    assert_eq!(row.sample_rate, None);
+    assert!(!pool.last_query().contains("SAMPLE"));
+}
+
+#[tokio::test]
+async fn test_invalid_sample_rate_is_rejected() {
+    let pool = MockPool::default();
+    let options = SdkEventQueryOptions {
+        sample_rate: Some(1.5),
+        ..Default::default()
+    };
+
+    SdkEventMetrics::SdkRenderedCount
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect_err("a sample rate above 1 was accepted");
+
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_sampled_totals_are_rounded_after_scaling() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({ "count": 3, "total": 10.01 })]);
+    let options = SdkEventQueryOptions {
+        sample_rate: Some(0.3),
+        decimal_places: Some(2),
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::AveragePaymentTime
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let (_, row) = buckets.first().expect("no bucket was loaded");
+    assert_eq!(row.total, Some("33.37".parse().expect("invalid decimal")));
+}
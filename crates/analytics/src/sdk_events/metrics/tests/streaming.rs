@@ -0,0 +1,127 @@
+use super::*;
+
+#[tokio::test]
+async fn test_funnel_buckets_are_streamed() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "initiated": 10, "rendered": 8 }),
+        serde_json::json!({ "platform": "ios", "initiated": 4, "rendered": 4 }),
+    ]);
+
+    let buckets = SdkEventMetrics::FunnelConversionRate
+        .load_metrics_stream(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to open metrics stream")
+        .map(|bucket| bucket.map(|(id, row)| (id.platform, row.initiated, row.rendered)))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<MetricsResult<Vec<_>>>()
+        .expect("failed to stream metrics");
+
+    assert_eq!(
+        buckets,
+        vec![
+            (Some("web".to_string()), Some(10), Some(8)),
+            (Some("ios".to_string()), Some(4), Some(4))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_count_buckets_are_streamed_up_to_the_row_cap() {
+    let row = |platform: &str| serde_json::json!({ "platform": platform, "count": 1 });
+    let pool = MockPool {
+        max_result_rows: Some(2),
+        ..MockPool::with_rows(vec![row("android"), row("ios"), row("web")])
+    };
+
+    let buckets = SdkEventMetrics::PaymentAttempts
+        .load_metrics_stream(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to open metrics stream")
+        .map(|bucket| bucket.map(|(id, row)| (id.platform, row.truncated)))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<MetricsResult<Vec<_>>>()
+        .expect("failed to stream metrics");
+
+    // The buckets handed out before the cap was hit are already gone, so only the last one
+    // is flagged
+    assert_eq!(
+        buckets,
+        vec![
+            (Some("android".to_string()), false),
+            (Some("ios".to_string()), true)
+        ]
+    );
+    assert!(pool.last_query().ends_with("LIMIT 3"));
+}
+
+#[tokio::test]
+async fn test_streams_which_need_every_bucket_are_rejected() {
+    let pool = MockPool::default();
+    let open = |metric: SdkEventMetrics, options: SdkEventQueryOptions| {
+        let pool = &pool;
+        async move {
+            metric
+                .load_metrics_stream(
+                    &[SdkEventDimensions::Platform],
+                    "pk_test",
+                    &SdkEventFilters::default(),
+                    &Some(Granularity::OneHour),
+                    &options,
+                    &seven_day_range(),
+                    pool,
+                )
+                .await
+                .map(|_| ())
+        }
+    };
+
+    let error = open(
+        SdkEventMetrics::AveragePaymentTime,
+        SdkEventQueryOptions::default(),
+    )
+    .await
+    .expect_err("average payment time was streamed");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedMetric(_)
+    ));
+
+    let error = open(
+        SdkEventMetrics::PaymentAttempts,
+        SdkEventQueryOptions {
+            fill_gaps: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .expect_err("buckets with their gaps filled were streamed");
+    assert!(matches!(
+        error.current_context(),
+        MetricsError::UnsupportedMetric(_)
+    ));
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+}
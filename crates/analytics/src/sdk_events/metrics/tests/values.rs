@@ -0,0 +1,771 @@
+use super::*;
+
+#[tokio::test]
+async fn test_average_payment_methods_displayed_by_platform() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "count": 4, "total": 24 }),
+        serde_json::json!({ "platform": "ios", "count": 5, "total": 15 }),
+    ]);
+
+    let buckets = SdkEventMetrics::AveragePaymentMethodsDisplayed
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let averages = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+            accumulator.add_metric_bucket(&SdkEventMetrics::AveragePaymentMethodsDisplayed, row);
+            (
+                id.platform.clone(),
+                accumulator.collect().average_payment_methods_displayed,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        averages,
+        vec![
+            (Some("ios".to_string()), Some(3.0)),
+            (Some("web".to_string()), Some(6.0))
+        ]
+    );
+
+    let displayed = "JSONExtract(value, 'payment_methods_count', 'Nullable(Float64)')";
+    let query = pool.last_query();
+    assert!(query.starts_with(&format!(
+        "SELECT platform as platform, count({displayed}) as count, sum({displayed}) as total"
+    )));
+    assert!(query.contains("event_name = 'APP_RENDERED'"));
+    assert!(query.contains(&format!("{displayed} IS NOT NULL")));
+}
+
+#[tokio::test]
+async fn test_attempted_amounts_are_summed_per_currency() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "currency": "JPY", "count": 2, "total": 150000 }),
+        serde_json::json!({ "currency": "USD", "count": 3, "total": 4500 }),
+    ]);
+
+    let buckets = SdkEventMetrics::PaymentAttemptedAmount
+        .load_metrics(
+            &[SdkEventDimensions::Currency],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let amounts = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+            accumulator.add_metric_bucket(&SdkEventMetrics::PaymentAttemptedAmount, row);
+            (
+                id.currency.clone(),
+                accumulator.collect().payment_attempted_amount,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        amounts,
+        vec![
+            (Some("JPY".to_string()), Some(150000)),
+            (Some("USD".to_string()), Some(4500))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT currency, count(*) as count, sum(amount) as total"));
+    assert!(query.contains("event_name = 'PAYMENT_ATTEMPT'"));
+    assert!(query.contains("amount IS NOT NULL"));
+    assert!(query.contains("GROUP BY currency"));
+}
+
+#[tokio::test]
+async fn test_latency_breakdown_averages_the_network_and_render_time() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({
+            "platform": "web",
+            "count": 4,
+            "network_total": 2000,
+            "render_total": 600
+        }),
+        serde_json::json!({ "platform": "ios", "count": 0 }),
+    ]);
+
+    let buckets = SdkEventMetrics::LatencyBreakdown
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let averages = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+            accumulator.add_metric_bucket(&SdkEventMetrics::LatencyBreakdown, row);
+            let values = accumulator.collect();
+            (
+                id.platform.clone(),
+                values.average_network_ms,
+                values.average_render_ms,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        averages,
+        vec![
+            (Some("ios".to_string()), None, None),
+            (Some("web".to_string()), Some(500.0), Some(150.0))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.starts_with(
+        "SELECT platform as platform, count(*) as count, sum(network_latency) as \
+         network_total, sum(render_latency) as render_total"
+    ));
+    assert!(query.contains("event_name = 'PAYMENT_ATTEMPT'"));
+    assert!(query.contains("network_latency IS NOT NULL"));
+    assert!(query.contains("render_latency IS NOT NULL"));
+}
+
+#[tokio::test]
+async fn test_amounts_of_mixed_currencies_are_rejected() {
+    let pool = MockPool::default();
+    let load = |dimensions: Vec<SdkEventDimensions>,
+                filters: SdkEventFilters,
+                options: SdkEventQueryOptions| {
+        let pool = &pool;
+        async move {
+            SdkEventMetrics::PaymentAttemptedAmount
+                .load_metrics(
+                    &dimensions,
+                    "pk_test",
+                    &filters,
+                    &None,
+                    &options,
+                    &seven_day_range(),
+                    pool,
+                )
+                .await
+        }
+    };
+    let two_currencies = SdkEventFilters {
+        currency: vec!["JPY".to_string(), "USD".to_string()],
+        ..Default::default()
+    };
+    let subtotals = SdkEventQueryOptions {
+        subtotals: true,
+        ..Default::default()
+    };
+
+    for (dimensions, filters, options) in [
+        (
+            vec![SdkEventDimensions::Platform],
+            SdkEventFilters::default(),
+            SdkEventQueryOptions::default(),
+        ),
+        (vec![], two_currencies, SdkEventQueryOptions::default()),
+        (
+            vec![SdkEventDimensions::Currency],
+            SdkEventFilters::default(),
+            subtotals.clone(),
+        ),
+    ] {
+        let error = load(dimensions, filters, options)
+            .await
+            .expect_err("amounts of several currencies were summed");
+        assert!(matches!(
+            error.current_context(),
+            MetricsError::MixedCurrencies(_)
+        ));
+    }
+    assert!(pool
+        .queries
+        .lock()
+        .expect("queries lock poisoned")
+        .is_empty());
+
+    // A single currency can be summed over, even into subtotals
+    let usd = SdkEventFilters {
+        currency: vec!["USD".to_string()],
+        ..Default::default()
+    };
+    load(vec![SdkEventDimensions::Platform], usd, subtotals)
+        .await
+        .expect("failed to load the amounts of a single currency");
+    assert!(pool.last_query().contains("currency IN ('USD')"));
+}
+
+#[tokio::test]
+async fn test_payment_success_rate_is_null_without_attempts() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "payment_method": "card", "attempted": 4, "successful": 3, "rate": 0.75 }),
+        serde_json::json!({ "payment_method": "wallet", "attempted": 0, "successful": 0, "rate": null }),
+    ]);
+
+    let buckets = SdkEventMetrics::PaymentSuccessRate
+        .load_metrics(
+            &[SdkEventDimensions::PaymentMethod],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let rates = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut accumulator = SuccessRateAccumulator::default();
+            accumulator.add_metrics_bucket(row);
+            (id.payment_method.clone(), accumulator.collect())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rates,
+        vec![
+            (Some("card".to_string()), Some(0.75)),
+            (Some("wallet".to_string()), None)
+        ]
+    );
+    assert!(pool.last_query().contains(
+        "countIf(event_name = 'CONFIRM_CALL' AND log_type = 'INFO') / nullIf(countIf(event_name = 'PAYMENT_ATTEMPT'), 0) as rate"
+    ));
+}
+
+#[tokio::test]
+async fn test_three_ds_method_success_rate_is_null_without_invocations() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "browser_name": "Chrome", "attempted": 8, "successful": 6, "rate": 0.75 }),
+        serde_json::json!({ "browser_name": "Safari", "attempted": 0, "successful": 0, "rate": null }),
+    ]);
+
+    let buckets = SdkEventMetrics::ThreeDsMethodSuccessRate
+        .load_metrics(
+            &[SdkEventDimensions::BrowserName],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let rates = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+            accumulator.add_metric_bucket(&SdkEventMetrics::ThreeDsMethodSuccessRate, row);
+            (id.browser_name.clone(), accumulator.collect().success_rate)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rates,
+        vec![
+            (Some("Chrome".to_string()), Some(0.75)),
+            (Some("Safari".to_string()), None)
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.contains(
+        "countIf(event_name = 'THREE_DS_METHOD_RESULT') / \
+         nullIf(countIf(event_name = 'THREE_DS_METHOD' AND value = 'Y'), 0) as rate"
+    ));
+    assert!(query.contains("event_name IN ('THREE_DS_METHOD', 'THREE_DS_METHOD_RESULT')"));
+    assert!(query.contains("GROUP BY browser_name"));
+}
+
+#[tokio::test]
+async fn test_payment_time_min_max_within_time_bucket() {
+    let time_bucket = "2024-03-01 10:00:00";
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "time_bucket": time_bucket, "count": 3, "total": 900, "min": 120, "max": 450 }),
+        serde_json::json!({ "time_bucket": time_bucket, "count": 2, "total": 700, "min": 90, "max": 380 }),
+    ]);
+
+    let buckets = SdkEventMetrics::AveragePaymentTime
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains("min(latency) as min, max(latency) as max"));
+    assert!(query.contains("GROUP BY time_bucket"));
+
+    let mut accumulator = MinMaxAccumulator::default();
+    for (id, row) in buckets.iter() {
+        assert_eq!(id.time_bucket.as_deref(), Some(time_bucket));
+        accumulator.add_metrics_bucket(row);
+    }
+    assert_eq!(accumulator.collect(), (Some(90.0), Some(450.0)));
+}
+
+#[tokio::test]
+async fn test_whole_range_percentiles_are_not_bucketed_by_time() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "platform": "web", "count": 40, "total": 12000, "p95": 870 }),
+        serde_json::json!({ "platform": "ios", "count": 10, "total": 2500, "p95": 410 }),
+    ]);
+    let options = SdkEventQueryOptions {
+        fill_gaps: true,
+        percentile_bucketing: PercentileBucketing::WholeRange,
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::AveragePaymentTime
+        .load_metrics(
+            &[SdkEventDimensions::Platform],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &options,
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    assert!(query.contains("quantiles(0.5, 0.9, 0.95, 0.99)(latency)[3] as p95"));
+    assert!(!query.contains("time_bucket"));
+    // A single bucket for each group, the gaps of a time series aren't filled
+    assert_eq!(buckets.len(), 2);
+    assert!(buckets.iter().all(|(id, _)| id.time_bucket.is_none()));
+    let web = buckets
+        .iter()
+        .find(|(id, _)| id.platform.as_deref() == Some("web"))
+        .and_then(|(_, row)| row.p95.as_ref().and_then(ToPrimitive::to_f64));
+    assert_eq!(web, Some(870.0));
+
+    // The metrics without percentiles are still bucketed
+    SdkEventMetrics::PaymentAttempts
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneHour),
+            &SdkEventQueryOptions {
+                fill_gaps: false,
+                ..options
+            },
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+    assert!(pool.last_query().contains("GROUP BY time_bucket"));
+}
+
+#[tokio::test]
+async fn test_time_to_first_render_is_measured_per_session() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({
+        "platform": "web",
+        "browser_name": "Safari",
+        "count": 4,
+        "total": 1800,
+        "p50": 350
+    })]);
+    let filters = SdkEventFilters {
+        platform: vec!["web".to_string()],
+        browser_name: vec!["Safari".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::TimeToFirstRender
+        .load_metrics(
+            &[
+                SdkEventDimensions::Platform,
+                SdkEventDimensions::BrowserName,
+            ],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let mut accumulator = SdkEventMetricsAccumulator::default();
+    for (_, row) in buckets.iter() {
+        accumulator.time_to_first_render.add_metrics_bucket(row);
+        accumulator
+            .time_to_first_render_percentiles
+            .add_metrics_bucket(row);
+    }
+    let value = accumulator.collect();
+    assert_eq!(value.average_time_to_first_render_ms, Some(450.0));
+    assert_eq!(value.time_to_first_render_p50, Some(350.0));
+
+    let query = pool.last_query();
+    assert!(query.starts_with(
+        "SELECT platform, browser_name, count(time_to_first_render) as count, \
+         sum(time_to_first_render) as total"
+    ));
+    assert!(query.contains(
+        "dateDiff('millisecond', minIfOrNull(created_at_precise, event_name = 'ORCA_ELEMENTS_CALLED'), \
+         minIfOrNull(created_at_precise, event_name = 'APP_RENDERED')) as time_to_first_render"
+    ));
+    assert!(query.contains("event_name IN ('ORCA_ELEMENTS_CALLED', 'APP_RENDERED')"));
+    assert!(query.contains("platform IN ('web')"));
+    assert!(query.contains("browser_name IN ('Safari')"));
+    assert!(query.contains(
+        "GROUP BY payment_id, platform, browser_name) _ GROUP BY platform, browser_name"
+    ));
+}
+
+#[tokio::test]
+async fn test_payment_retries_are_counted_per_session() {
+    let pool = MockPool::with_rows(vec![serde_json::json!({
+        "payment_method": "card",
+        "count": 4,
+        "total": 6,
+        "max_retries": 3,
+        "p50": 2
+    })]);
+    let filters = SdkEventFilters {
+        payment_method: vec!["card".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::PaymentRetryCount
+        .load_metrics(
+            &[SdkEventDimensions::PaymentMethod],
+            "pk_test",
+            &filters,
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let (_, row) = buckets.first().expect("missing bucket");
+    assert_eq!(row.max_retries, Some(3));
+    let mut average = AverageAccumulator::default();
+    average.add_metrics_bucket(row);
+    assert_eq!(average.collect(), Some(1.5));
+
+    let query = pool.last_query();
+    assert!(query.starts_with(
+        "SELECT payment_method, count(*) as count, sum(retries) as total, max(retries) as max_retries"
+    ));
+    assert!(query.contains("count(*) - 1 as retries FROM sdk_events_audit"));
+    assert!(query.contains("payment_method IN ('card')"));
+    assert!(query.contains("event_name = 'PAYMENT_ATTEMPT'"));
+    assert!(!query.contains("first_event"));
+    assert!(query.contains("GROUP BY payment_id, payment_method) _ GROUP BY payment_method"));
+}
+
+#[tokio::test]
+async fn test_abandoned_sessions_are_counted_by_reason_and_filtered_per_session() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "abandonment_reason": "modal_closed", "count": 5 }),
+        serde_json::json!({ "abandonment_reason": null, "count": 2 }),
+    ]);
+    let filters = SdkEventFilters {
+        payment_method: vec!["card".to_string()],
+        platform: vec!["web".to_string()],
+        ..Default::default()
+    };
+
+    let buckets = SdkEventMetrics::AbandonedSessionsCount
+        .load_metrics(
+            &[SdkEventDimensions::AbandonmentReason],
+            "pk_test",
+            &filters,
+            &Some(Granularity::OneHour),
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut accumulator = crate::sdk_events::SdkEventMetricsAccumulator::default();
+            accumulator.add_metric_bucket(&SdkEventMetrics::AbandonedSessionsCount, row);
+            (
+                id.abandonment_reason.clone(),
+                accumulator.collect().abandoned_sessions_count,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![(Some("modal_closed".to_string()), Some(5)), (None, Some(2))]
+    );
+
+    let query = pool.last_query();
+    assert!(query.starts_with("SELECT abandonment_reason, count(*) as count, time_bucket"));
+    assert!(query.contains(
+        "any(nullIf(JSONExtractString(value, 'abandonment_reason'), '')) as abandonment_reason"
+    ));
+    // The session is bucketed by its initiation rather than split across the buckets
+    assert!(query.contains("toStartOfInterval(min(created_at), INTERVAL 60 MINUTE) as time_bucket"));
+    assert!(query.contains("platform IN ('web')"));
+    assert!(!query.contains("payment_method IN"));
+    assert!(query.contains(
+        "GROUP BY payment_id HAVING countIf(event_name = 'ORCA_ELEMENTS_CALLED') > 0 AND countIf(event_name = 'PAYMENT_ATTEMPT') < 1 AND argMaxIf(payment_method, created_at_precise, isNotNull(payment_method)) IN ('card')"
+    ));
+    assert!(query.contains(") _ GROUP BY abandonment_reason, time_bucket"));
+}
+
+#[tokio::test]
+async fn test_three_ds_flow_breakdown_by_payment_method() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({
+            "payment_method": "card",
+            "count": 10,
+            "challenge_count": 4,
+            "frictionless_count": 6,
+            "challenge_rate": 0.4
+        }),
+        serde_json::json!({
+            "payment_method": "wallet",
+            "count": 0,
+            "challenge_count": 0,
+            "frictionless_count": 0
+        }),
+    ]);
+
+    let buckets = SdkEventMetrics::ThreeDsFlowBreakdown
+        .load_metrics(
+            &[SdkEventDimensions::PaymentMethod],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let flows = buckets
+        .iter()
+        .map(|(id, row)| {
+            let mut flows = ThreeDsFlowAccumulator::default();
+            flows.add_metrics_bucket(row);
+            (id.payment_method.as_deref(), flows.collect())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        flows,
+        vec![
+            (Some("card"), (Some(4), Some(6), Some(0.4))),
+            (Some("wallet"), (Some(0), Some(0), None)),
+        ]
+    );
+    let query = pool.last_query();
+    assert!(query.contains(
+        "countIf(value = 'C') as challenge_count, countIf(value != 'C') as frictionless_count"
+    ));
+    assert!(query.contains("countIf(value = 'C') / nullIf(count(*), 0) as challenge_rate"));
+    assert!(query.contains("GROUP BY payment_method"));
+}
+
+#[tokio::test]
+async fn test_totals_are_rounded_by_the_data_source() {
+    let pool = MockPool::default();
+    for decimal_places in [Some(2), None] {
+        let options = SdkEventQueryOptions {
+            decimal_places,
+            ..Default::default()
+        };
+        SdkEventMetrics::AveragePaymentTime
+            .load_metrics(
+                &[],
+                "pk_test",
+                &SdkEventFilters::default(),
+                &None,
+                &options,
+                &seven_day_range(),
+                &pool,
+            )
+            .await
+            .expect("failed to load metrics");
+    }
+
+    let queries = pool.queries.lock().expect("queries lock poisoned").clone();
+    let mut queries = queries.iter();
+    let rounded = queries.next().expect("missing rounded query");
+    assert!(rounded.contains("round(sum(latency), 2) as total"));
+    let full_precision = queries.next().expect("missing full precision query");
+    assert!(full_precision.contains("sum(latency) as total"));
+    assert!(!full_precision.contains("round("));
+}
+
+#[tokio::test]
+async fn test_aggregate_of_zero_is_kept_apart_from_missing_value() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "time_bucket": "2024-03-01T00:00:00Z", "count": 2, "total": 0 }),
+        serde_json::json!({ "time_bucket": "2024-03-02T00:00:00Z", "count": 3, "total": null }),
+    ]);
+
+    let buckets = SdkEventMetrics::AveragePaymentTime
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &Some(Granularity::OneDay),
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query().to_lowercase();
+    assert!(!query.contains("coalesce") && !query.contains("ifnull"));
+    let totals = buckets
+        .iter()
+        .map(|(_, row)| (row.count, row.total.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(totals, vec![(Some(2), Some(0.into())), (Some(3), None)]);
+
+    let mut average = AverageAccumulator::default();
+    if let Some((_, row)) = buckets.first() {
+        average.add_metrics_bucket(row);
+    }
+    assert_eq!(average.collect(), Some(0.0));
+}
+
+#[tokio::test]
+async fn test_bucket_without_matching_events_has_no_value() {
+    let pool = MockPool::default();
+
+    let buckets = SdkEventMetrics::ThreeDsFlowBreakdown
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+    assert!(buckets.is_empty());
+
+    // Nothing was loaded, so there is no count rather than a count of zero
+    let mut flows = ThreeDsFlowAccumulator::default();
+    buckets
+        .iter()
+        .for_each(|(_, row)| flows.add_metrics_bucket(row));
+    assert_eq!(flows.collect(), (None, None, None));
+
+    // Whereas flows which were seen, but none of them challenged, count zero challenges
+    let mut flows = ThreeDsFlowAccumulator::default();
+    flows.add_metrics_bucket(&SdkEventMetricRow {
+        count: Some(4),
+        challenge_count: Some(0),
+        frictionless_count: Some(4),
+        ..Default::default()
+    });
+    assert_eq!(flows.collect(), (Some(0), Some(4), Some(0.0)));
+}
+
+#[tokio::test]
+async fn test_payment_experience_breakdown_groups_by_experience() {
+    let pool = MockPool::with_rows(vec![
+        serde_json::json!({ "payment_experience": "redirect_to_url", "payment_method": "card", "count": 6 }),
+        serde_json::json!({ "payment_experience": "invoke_sdk_client", "payment_method": "wallet", "count": 2 }),
+    ]);
+
+    let buckets = SdkEventMetrics::PaymentExperienceBreakdown
+        .load_metrics(
+            &[SdkEventDimensions::PaymentMethod],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let counts = buckets
+        .iter()
+        .map(|(id, row)| (id.payment_experience.clone(), row.count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (Some("invoke_sdk_client".to_string()), Some(2)),
+            (Some("redirect_to_url".to_string()), Some(6))
+        ]
+    );
+
+    let query = pool.last_query();
+    assert!(query.contains("event_name = 'PAYMENT_ATTEMPT'"));
+    assert!(query.contains("GROUP BY payment_experience, payment_method"));
+}
+
+#[tokio::test]
+async fn test_percentiles_are_selected_from_a_single_quantiles_aggregate() {
+    let pool = MockPool::default();
+
+    SdkEventMetrics::SessionDurationSeconds
+        .load_metrics(
+            &[],
+            "pk_test",
+            &SdkEventFilters::default(),
+            &None,
+            &SdkEventQueryOptions::default(),
+            &seven_day_range(),
+            &pool,
+        )
+        .await
+        .expect("failed to load metrics");
+
+    let query = pool.last_query();
+    let quantiles = "quantiles(0.5, 0.9, 0.95, 0.99)(session_duration)";
+    assert!(query.contains(&format!(
+        "{quantiles}[1] as p50, {quantiles}[2] as p90, {quantiles}[3] as p95, \
+         {quantiles}[4] as p99"
+    )));
+    assert!(!query.contains("quantile("));
+}
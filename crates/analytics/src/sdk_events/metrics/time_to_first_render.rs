@@ -0,0 +1,201 @@
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetricsBucketIdentifier, SdkEventNames,
+    },
+    Granularity, TimeRange,
+};
+use common_utils::errors::ReportSwitchExt;
+use error_stack::ResultExt;
+use time::PrimitiveDateTime;
+
+use super::SdkEventMetricRow;
+use crate::{
+    query::{Aggregate, GroupByClause, QueryBuilder, QueryFilter, Rounded, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Time from the first initiation of the sdk in a session to its first render, the payment id
+/// identifies the sdk session. `total` holds the summed time in milliseconds and `count` the number
+/// of sessions which were both initiated and rendered, the others have no time to first render.
+#[derive(Default)]
+pub(super) struct TimeToFirstRender;
+
+#[async_trait::async_trait]
+impl<T> super::SdkEventMetric<T> for TimeToFirstRender
+where
+    T: AnalyticsDataSource + super::SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    async fn load_metrics(
+        &self,
+        dimensions: &[SdkEventDimensions],
+        publishable_key: &str,
+        filters: &SdkEventFilters,
+        granularity: &Option<Granularity>,
+        options: &super::SdkEventQueryOptions,
+        time_range: &TimeRange,
+        pool: &T,
+    ) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+        let mut query_builder: QueryBuilder<T> = QueryBuilder::new(AnalyticsCollection::SdkEvents);
+        let dimensions = dimensions.to_vec();
+
+        // The inner query computes the time to first render of every session, the outer one
+        // aggregates the sessions of each bucket
+        for dim in dimensions.iter() {
+            query_builder
+                .add_aliased_select_column(options.dimension(dim), dim.as_ref())
+                .switch()?;
+            query_builder
+                .add_outer_select_column(dim.as_ref())
+                .switch()?;
+        }
+
+        // The first event of a kind is null for a session without one, which leaves the time of
+        // the session null and out of the aggregates
+        query_builder
+            .add_select_column(format!(
+                "dateDiff('millisecond', minIfOrNull(created_at_precise, event_name = '{}'), \
+                 minIfOrNull(created_at_precise, event_name = '{}')) as time_to_first_render",
+                SdkEventNames::OrcaElementsCalled,
+                SdkEventNames::AppRendered
+            ))
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column("count(time_to_first_render) as count")
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column(Rounded {
+                column: Aggregate::Sum {
+                    field: "time_to_first_render",
+                    alias: None,
+                },
+                alias: "total",
+                decimal_places: options.decimal_places,
+            })
+            .switch()?;
+
+        query_builder
+            .add_outer_select_column(Aggregate::Quantiles {
+                field: "time_to_first_render",
+                quantiles: super::PERCENTILES,
+            })
+            .switch()?;
+
+        // The count threshold would apply to the events of a single session here, only the time
+        // zone, the collections read, the row cap and the pagination, which pages the outer query,
+        // are taken from the options
+        if let Some(time_zone) = &options.time_zone {
+            query_builder.set_time_zone(time_zone).switch()?;
+        }
+        options.set_source_options(&mut query_builder).switch()?;
+        options
+            .set_pagination(&mut query_builder, &dimensions, granularity)
+            .switch()?;
+
+        if let Some(granularity) = granularity.as_ref() {
+            query_builder
+                .add_granularity_in_mins(granularity)
+                .switch()?;
+            query_builder
+                .add_outer_select_column("time_bucket")
+                .switch()?;
+        }
+
+        filters.set_filter_clause(&mut query_builder).switch()?;
+
+        query_builder
+            .add_filter_clause("merchant_id", publishable_key)
+            .switch()?;
+
+        query_builder
+            .add_filter_in_range_clause(
+                "event_name",
+                &[
+                    SdkEventNames::OrcaElementsCalled,
+                    SdkEventNames::AppRendered,
+                ],
+            )
+            .switch()?;
+
+        time_range
+            .set_filter_clause(&mut query_builder)
+            .attach_printable("Error filtering time range")
+            .switch()?;
+
+        query_builder
+            .add_group_by_clause("payment_id")
+            .attach_printable("Error grouping by session")
+            .switch()?;
+
+        for dim in dimensions.iter() {
+            query_builder
+                .add_group_by_clause(options.dimension(dim))
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+            query_builder
+                .add_outer_group_by_clause(dim.as_ref())
+                .attach_printable("Error grouping by dimensions")
+                .switch()?;
+        }
+
+        if let Some(_granularity) = granularity.as_ref() {
+            query_builder
+                .add_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+            query_builder
+                .add_outer_group_by_clause("time_bucket")
+                .attach_printable("Error adding granularity")
+                .switch()?;
+        }
+
+        super::check_scoped(&query_builder)?;
+
+        query_builder
+            .execute_query::<SdkEventMetricRow, _>(pool)
+            .await
+            .change_context(MetricsError::QueryBuildingError)?
+            .switch()?
+            .into_iter()
+            .map(|i| {
+                Ok((
+                    SdkEventMetricsBucketIdentifier::new(
+                        i.payment_method.clone(),
+                        i.platform.clone(),
+                        i.browser_name.clone(),
+                        i.source.clone(),
+                        i.component.clone(),
+                        i.payment_experience.clone(),
+                        i.sdk_version.clone(),
+                        i.device_type.clone(),
+                        i.os.clone(),
+                        i.country.clone(),
+                        i.connector.clone(),
+                        i.event_name.clone(),
+                        i.payment_method_type.clone(),
+                        i.day_of_week.clone(),
+                        i.hour_of_day.clone(),
+                        i.authentication_error_reason.clone(),
+                        i.field_name.clone(),
+                        i.customer_segment.clone(),
+                        i.cdn_region.clone(),
+                        i.currency.clone(),
+                        i.abandonment_reason.clone(),
+                        i.time_bucket.clone(),
+                    ),
+                    i,
+                ))
+            })
+            .collect::<error_stack::Result<
+                Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>,
+                crate::query::PostProcessingError,
+            >>()
+            .change_context(MetricsError::PostProcessingFailure)
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use api_models::analytics::{
+    sdk_events::{
+        SdkEventDimensions, SdkEventFilters, SdkEventMetrics, SdkEventMetricsBucketIdentifier,
+        SdkEventOrderBy, SdkEventPagination,
+    },
+    Granularity, SortDirection, TimeRange,
+};
+use time::PrimitiveDateTime;
+
+use super::{SdkEventMetric, SdkEventMetricAnalytics, SdkEventMetricRow, SdkEventQueryOptions};
+use crate::{
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsResult},
+};
+
+/// Value of every dimension of the group the events beyond the top groups are merged into
+pub const OTHER_GROUP: &str = "other";
+
+/// Loads `metric` for the `limit` groups with the most events, followed by the buckets of all
+/// other events merged into a single group labelled [`OTHER_GROUP`]. The top groups are ranked by
+/// a query ordered and limited by event count, then the metric is loaded once for the events of
+/// the top groups and once for the rest, so that the other group is aggregated by the database
+/// the same way as any group. Groups without a value for one of the dimensions can't be filtered
+/// on by themselves, their events are part of the other group.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn load_top_groups<T>(
+    metric: &SdkEventMetrics,
+    limit: u32,
+    dimensions: &[SdkEventDimensions],
+    publishable_key: &str,
+    filters: &SdkEventFilters,
+    granularity: &Option<Granularity>,
+    options: &SdkEventQueryOptions,
+    time_range: &TimeRange,
+    pool: &T,
+) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    let options = SdkEventQueryOptions {
+        top_groups: None,
+        pagination: None,
+        ..options.clone()
+    };
+    let ranking = SdkEventQueryOptions {
+        pagination: Some(SdkEventPagination {
+            order_by: SdkEventOrderBy::EventCount,
+            direction: SortDirection::Descending,
+            limit,
+            offset: 0,
+        }),
+        fill_gaps: false,
+        ..options.clone()
+    };
+
+    let top_groups = metric
+        .load_metrics(
+            dimensions,
+            publishable_key,
+            filters,
+            &None,
+            &ranking,
+            time_range,
+            pool,
+        )
+        .await?
+        .iter()
+        .filter_map(|(id, _)| group_filters(id, dimensions))
+        .collect::<Vec<_>>();
+
+    let mut buckets = if top_groups.is_empty() {
+        Vec::new()
+    } else {
+        let top_filters = SdkEventFilters {
+            all_of: vec![
+                filters.clone(),
+                SdkEventFilters {
+                    any_of: top_groups.clone(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        metric
+            .load_metrics(
+                dimensions,
+                publishable_key,
+                &top_filters,
+                granularity,
+                &options,
+                time_range,
+                pool,
+            )
+            .await?
+    };
+
+    let other_filters = SdkEventFilters {
+        all_of: vec![filters.clone()],
+        none_of: top_groups,
+        ..Default::default()
+    };
+    let other = metric
+        .load_metrics(
+            &[],
+            publishable_key,
+            &other_filters,
+            granularity,
+            &options,
+            time_range,
+            pool,
+        )
+        .await?;
+    buckets.extend(other.into_iter().map(|(mut id, mut row)| {
+        for dim in dimensions {
+            set_dimension(&mut id, &mut row, dim, OTHER_GROUP);
+        }
+        (id, row)
+    }));
+    if options.percent_of_total {
+        set_percent_of_total(&mut buckets);
+    }
+    Ok(buckets)
+}
+
+/// Takes the shares of the merged buckets again, the queries of the top groups and of the other
+/// group each only see their own groups
+fn set_percent_of_total(buckets: &mut [(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)]) {
+    let mut totals = HashMap::<Option<String>, i64>::new();
+    for (id, row) in buckets.iter() {
+        let total = totals.entry(id.time_bucket.clone()).or_default();
+        *total = total.saturating_add(row.count.unwrap_or_default());
+    }
+    for (id, row) in buckets.iter_mut() {
+        let total = totals.get(&id.time_bucket).copied().unwrap_or_default();
+        row.percent_of_total = row.count.filter(|_| total != 0).map(|count| {
+            bigdecimal::BigDecimal::from(count.saturating_mul(100))
+                / bigdecimal::BigDecimal::from(total)
+        });
+    }
+}
+
+/// Filters matching the events of the group `id` is the bucket of, `None` if the group has no
+/// value for one of `dimensions`
+fn group_filters(
+    id: &SdkEventMetricsBucketIdentifier,
+    dimensions: &[SdkEventDimensions],
+) -> Option<SdkEventFilters> {
+    let mut filters = SdkEventFilters::default();
+    for dim in dimensions {
+        let value = id.dimension(dim)?.to_string();
+        let values = match dim {
+            SdkEventDimensions::PaymentMethod => &mut filters.payment_method,
+            SdkEventDimensions::Platform => &mut filters.platform,
+            SdkEventDimensions::BrowserName => &mut filters.browser_name,
+            SdkEventDimensions::Source => &mut filters.source,
+            SdkEventDimensions::Component => &mut filters.component,
+            SdkEventDimensions::PaymentExperience => &mut filters.payment_experience,
+            SdkEventDimensions::SdkVersion => &mut filters.sdk_version,
+            SdkEventDimensions::DeviceType => &mut filters.device_type,
+            SdkEventDimensions::Os => &mut filters.os,
+            SdkEventDimensions::Country => &mut filters.country,
+            SdkEventDimensions::Connector => &mut filters.connector,
+            SdkEventDimensions::EventName => &mut filters.event_name,
+            SdkEventDimensions::PaymentMethodType => &mut filters.payment_method_type,
+            SdkEventDimensions::DayOfWeek => &mut filters.day_of_week,
+            SdkEventDimensions::HourOfDay => &mut filters.hour_of_day,
+            SdkEventDimensions::AuthenticationErrorReason => {
+                &mut filters.authentication_error_reason
+            }
+            SdkEventDimensions::FieldName => &mut filters.field_name,
+            SdkEventDimensions::CustomerSegment => &mut filters.customer_segment,
+            SdkEventDimensions::CdnRegion => &mut filters.cdn_region,
+            SdkEventDimensions::Currency => &mut filters.currency,
+            SdkEventDimensions::AbandonmentReason => &mut filters.abandonment_reason,
+        };
+        values.push(value);
+    }
+    Some(filters)
+}
+
+fn set_dimension(
+    id: &mut SdkEventMetricsBucketIdentifier,
+    row: &mut SdkEventMetricRow,
+    dim: &SdkEventDimensions,
+    value: &str,
+) {
+    let (id_value, row_value) = match dim {
+        SdkEventDimensions::PaymentMethod => (&mut id.payment_method, &mut row.payment_method),
+        SdkEventDimensions::Platform => (&mut id.platform, &mut row.platform),
+        SdkEventDimensions::BrowserName => (&mut id.browser_name, &mut row.browser_name),
+        SdkEventDimensions::Source => (&mut id.source, &mut row.source),
+        SdkEventDimensions::Component => (&mut id.component, &mut row.component),
+        SdkEventDimensions::PaymentExperience => {
+            (&mut id.payment_experience, &mut row.payment_experience)
+        }
+        SdkEventDimensions::SdkVersion => (&mut id.sdk_version, &mut row.sdk_version),
+        SdkEventDimensions::DeviceType => (&mut id.device_type, &mut row.device_type),
+        SdkEventDimensions::Os => (&mut id.os, &mut row.os),
+        SdkEventDimensions::Country => (&mut id.country, &mut row.country),
+        SdkEventDimensions::Connector => (&mut id.connector, &mut row.connector),
+        SdkEventDimensions::EventName => (&mut id.event_name, &mut row.event_name),
+        SdkEventDimensions::PaymentMethodType => {
+            (&mut id.payment_method_type, &mut row.payment_method_type)
+        }
+        SdkEventDimensions::DayOfWeek => (&mut id.day_of_week, &mut row.day_of_week),
+        SdkEventDimensions::HourOfDay => (&mut id.hour_of_day, &mut row.hour_of_day),
+        SdkEventDimensions::AuthenticationErrorReason => (
+            &mut id.authentication_error_reason,
+            &mut row.authentication_error_reason,
+        ),
+        SdkEventDimensions::FieldName => (&mut id.field_name, &mut row.field_name),
+        SdkEventDimensions::CustomerSegment => {
+            (&mut id.customer_segment, &mut row.customer_segment)
+        }
+        SdkEventDimensions::CdnRegion => (&mut id.cdn_region, &mut row.cdn_region),
+        SdkEventDimensions::Currency => (&mut id.currency, &mut row.currency),
+        SdkEventDimensions::AbandonmentReason => {
+            (&mut id.abandonment_reason, &mut row.abandonment_reason)
+        }
+    };
+    *id_value = Some(value.to_string());
+    *row_value = Some(value.to_string());
+}
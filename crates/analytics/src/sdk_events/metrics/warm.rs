@@ -0,0 +1,99 @@
+use api_models::analytics::{
+    sdk_events::{SdkEventDimensions, SdkEventFilters, SdkEventMetrics},
+    Granularity, TimeRange,
+};
+use error_stack::ResultExt;
+use router_env::logger;
+use time::PrimitiveDateTime;
+
+use super::{SdkEventMetric, SdkEventMetricAnalytics, SdkEventQueryOptions};
+use crate::{
+    query::{Aggregate, GroupByClause, ToSql, Window},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
+};
+
+/// Metric queries of one merchant run at once by [`warm_metrics_cache`] unless it's given a limit
+pub const DEFAULT_WARM_CONCURRENCY: usize = 4;
+
+/// A metric query whose buckets are loaded into the cache ahead of being asked for, e.g. one of
+/// the charts of a dashboard
+#[derive(Clone, Debug)]
+pub struct SdkEventWarmQuery {
+    pub metric: SdkEventMetrics,
+    pub dimensions: Vec<SdkEventDimensions>,
+    pub filters: SdkEventFilters,
+    pub granularity: Option<Granularity>,
+    pub options: SdkEventQueryOptions,
+    pub time_range: TimeRange,
+}
+
+/// Outcome of warming one query
+#[derive(Debug)]
+pub struct SdkEventWarmResult {
+    pub query: SdkEventWarmQuery,
+    /// Number of buckets the query loaded, or why it couldn't be loaded
+    pub result: MetricsResult<usize>,
+}
+
+/// Loads the buckets of every one of `queries` for `publishable_key`, so that the cache of `pool`
+/// already holds them when they're asked for. At most `max_concurrent` queries run at once, the
+/// others wait for one of them to finish rather than all reaching the data source together, and
+/// the queries which are already cached are answered from the cache.
+///
+/// A query failing doesn't stop the others, the results are returned in the order of `queries`.
+/// Without a cache there's nothing to warm, no query is run and no result is returned.
+pub async fn warm_metrics_cache<T>(
+    publishable_key: &str,
+    queries: Vec<SdkEventWarmQuery>,
+    max_concurrent: Option<usize>,
+    pool: &T,
+) -> Vec<SdkEventWarmResult>
+where
+    T: AnalyticsDataSource + SdkEventMetricAnalytics,
+    PrimitiveDateTime: ToSql<T>,
+    AnalyticsCollection: ToSql<T>,
+    Granularity: GroupByClause<T>,
+    Aggregate<&'static str>: ToSql<T>,
+    Window<&'static str>: ToSql<T>,
+{
+    if pool.metrics_cache().is_none() {
+        logger::debug!("Not warming sdk event metrics without a cache");
+        return Vec::new();
+    }
+    // At least one query has to run, or none would ever finish
+    let semaphore = tokio::sync::Semaphore::new(
+        max_concurrent
+            .unwrap_or(DEFAULT_WARM_CONCURRENCY)
+            .clamp(1, tokio::sync::Semaphore::MAX_PERMITS),
+    );
+    let semaphore = &semaphore;
+
+    futures::future::join_all(queries.into_iter().map(|query| async move {
+        let result = async {
+            let _permit = semaphore
+                .acquire()
+                .await
+                .change_context(MetricsError::QueryExecutionFailure)
+                .attach_printable("Metrics cache warmer closed")?;
+            query
+                .metric
+                .load_metrics(
+                    &query.dimensions,
+                    publishable_key,
+                    &query.filters,
+                    &query.granularity,
+                    &query.options,
+                    &query.time_range,
+                    pool,
+                )
+                .await
+                .map(|buckets| buckets.len())
+        }
+        .await;
+        if let Err(error) = &result {
+            logger::warn!(?error, metric = %query.metric, "Failed to warm sdk event metric");
+        }
+        SdkEventWarmResult { query, result }
+    }))
+    .await
+}
@@ -0,0 +1,97 @@
+use api_models::analytics::sdk_events::SdkEventMetricsBucketIdentifier;
+use error_stack::ResultExt;
+
+use super::metrics::SdkEventMetricRow;
+use crate::types::{MetricsError, MetricsResult};
+
+/// Encodes the buckets loaded by a sdk event metric as MessagePack, a more compact alternative
+/// to JSON for the internal callers passing them between services.
+///
+/// Fields are written by name rather than by position, so that the buckets are read as leniently
+/// as the rows of a query are, see [`SdkEventMetricRow`]: a service built against an older or
+/// newer version of the row reads the fields it knows. Decimals are written as strings and are
+/// read back exactly.
+pub fn to_msgpack(
+    buckets: &[(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)],
+) -> MetricsResult<Vec<u8>> {
+    rmp_serde::to_vec_named(buckets)
+        .change_context(MetricsError::PostProcessingFailure)
+        .attach_printable("Failed to encode the buckets as MessagePack")
+}
+
+/// Decodes buckets encoded by [`to_msgpack`]
+pub fn from_msgpack(
+    bytes: &[u8],
+) -> MetricsResult<Vec<(SdkEventMetricsBucketIdentifier, SdkEventMetricRow)>> {
+    rmp_serde::from_slice(bytes)
+        .change_context(MetricsError::PostProcessingFailure)
+        .attach_printable("Failed to decode MessagePack buckets")
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_decimals_round_trip_exactly() {
+        let decimal = |value: &str| bigdecimal::BigDecimal::from_str(value).expect("bad decimal");
+        let id = SdkEventMetricsBucketIdentifier {
+            payment_method: Some("card".to_string()),
+            time_bucket: Some("2024-03-01 10:00:00".to_string()),
+            ..Default::default()
+        };
+        let row = SdkEventMetricRow {
+            count: Some(3),
+            total: Some(decimal("12345678901234567890.123456789")),
+            p99: Some(decimal("0.10")),
+            stddev: Some(decimal("0.000000000000000001")),
+            median: None,
+            payment_method: Some("card".to_string()),
+            time_bucket: Some("2024-03-01 10:00:00".to_string()),
+            ..Default::default()
+        };
+        let buckets = vec![(id, row)];
+
+        let decoded = from_msgpack(&to_msgpack(&buckets).expect("failed to encode"))
+            .expect("failed to decode");
+
+        assert_eq!(decoded, buckets);
+        let (_, decoded_row) = decoded.first().expect("missing bucket");
+        assert_eq!(
+            decoded_row
+                .total
+                .as_ref()
+                .map(ToString::to_string)
+                .as_deref(),
+            Some("12345678901234567890.123456789")
+        );
+        assert_eq!(
+            decoded_row.p99.as_ref().map(ToString::to_string).as_deref(),
+            Some("0.10")
+        );
+        assert_eq!(decoded_row.median, None);
+    }
+
+    #[test]
+    fn test_msgpack_is_smaller_than_json() {
+        let buckets = (0..100)
+            .map(|count| {
+                (
+                    SdkEventMetricsBucketIdentifier::default(),
+                    SdkEventMetricRow {
+                        count: Some(count),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let msgpack = to_msgpack(&buckets).expect("failed to encode");
+        let json = serde_json::to_vec(&buckets).expect("failed to encode");
+
+        assert!(msgpack.len() < json.len());
+    }
+}
@@ -1,9 +1,18 @@
-use api_models::analytics::sdk_events::{SdkEventDimensions, SdkEventFilters};
-use error_stack::ResultExt;
+use std::{collections::BTreeSet, str::FromStr};
+
+use api_models::{
+    analytics::{
+        sdk_events::{SdkEventDimensions, SdkEventFilters},
+        HourWindow,
+    },
+    enums::{CountryAlpha2, Currency, PaymentMethod},
+};
+use error_stack::{report, ResultExt};
+use strum::IntoEnumIterator;
 
 use crate::{
-    query::{QueryBuilder, QueryFilter, QueryResult, ToSql},
-    types::{AnalyticsCollection, AnalyticsDataSource},
+    query::{LocalSdkEventDimension, QueryBuilder, QueryFilter, QueryResult, ToSql},
+    types::{AnalyticsCollection, AnalyticsDataSource, MetricsError, MetricsResult},
 };
 
 impl<T> QueryFilter<T> for SdkEventFilters
@@ -12,6 +21,15 @@ where
     AnalyticsCollection: ToSql<T>,
 {
     fn set_filter_clause(&self, builder: &mut QueryBuilder<T>) -> QueryResult<()> {
+        // The time zone and the null label have to be set first, so that the filters match the
+        // grouped values
+        let time_zone = builder.time_zone().map(ToString::to_string);
+        let null_label = builder.null_label().map(ToString::to_string);
+        let local = |dimension| LocalSdkEventDimension {
+            dimension,
+            time_zone: time_zone.as_deref(),
+            null_label: null_label.as_deref(),
+        };
         if !self.payment_method.is_empty() {
             builder
                 .add_filter_in_range_clause(SdkEventDimensions::PaymentMethod, &self.payment_method)
@@ -45,6 +63,330 @@ where
                 )
                 .attach_printable("Error adding payment experience filter")?;
         }
+        if !self.sdk_version.is_empty() {
+            builder
+                .add_filter_in_range_clause(SdkEventDimensions::SdkVersion, &self.sdk_version)
+                .attach_printable("Error adding sdk version filter")?;
+        }
+        if !self.device_type.is_empty() {
+            builder
+                .add_filter_in_range_clause(
+                    local(&SdkEventDimensions::DeviceType),
+                    &self.device_type,
+                )
+                .attach_printable("Error adding device type filter")?;
+        }
+        if !self.os.is_empty() {
+            builder
+                .add_filter_in_range_clause(local(&SdkEventDimensions::Os), &self.os)
+                .attach_printable("Error adding os filter")?;
+        }
+        if !self.country.is_empty() {
+            builder
+                .add_filter_in_range_clause(SdkEventDimensions::Country, &self.country)
+                .attach_printable("Error adding country filter")?;
+        }
+        if !self.connector.is_empty() {
+            builder
+                .add_filter_in_range_clause(local(&SdkEventDimensions::Connector), &self.connector)
+                .attach_printable("Error adding connector filter")?;
+        }
+        if !self.event_name.is_empty() {
+            builder
+                .add_filter_in_range_clause(SdkEventDimensions::EventName, &self.event_name)
+                .attach_printable("Error adding event name filter")?;
+        }
+        if !self.payment_method_type.is_empty() {
+            builder
+                .add_filter_in_range_clause(
+                    local(&SdkEventDimensions::PaymentMethodType),
+                    &self.payment_method_type,
+                )
+                .attach_printable("Error adding payment method type filter")?;
+        }
+        for (dimension, values) in [
+            (&SdkEventDimensions::DayOfWeek, &self.day_of_week),
+            (&SdkEventDimensions::HourOfDay, &self.hour_of_day),
+        ] {
+            if !values.is_empty() {
+                builder
+                    .add_filter_in_range_clause(local(dimension), values)
+                    .attach_printable_lazy(|| format!("Error adding {dimension} filter"))?;
+            }
+        }
+        if !self.hour_windows.is_empty() {
+            // Matched as the hours they span, the way the hour of day filter matches them
+            builder
+                .add_filter_in_range_clause(
+                    local(&SdkEventDimensions::HourOfDay),
+                    &window_hours(&self.hour_windows),
+                )
+                .attach_printable("Error adding hour windows filter")?;
+        }
+        if !self.authentication_error_reason.is_empty() {
+            builder
+                .add_filter_in_range_clause(
+                    local(&SdkEventDimensions::AuthenticationErrorReason),
+                    &self.authentication_error_reason,
+                )
+                .attach_printable("Error adding authentication error reason filter")?;
+        }
+        if !self.field_name.is_empty() {
+            builder
+                .add_filter_in_range_clause(SdkEventDimensions::FieldName, &self.field_name)
+                .attach_printable("Error adding field name filter")?;
+        }
+        if !self.customer_segment.is_empty() {
+            builder
+                .add_filter_in_range_clause(
+                    SdkEventDimensions::CustomerSegment,
+                    &self.customer_segment,
+                )
+                .attach_printable("Error adding customer segment filter")?;
+        }
+        if !self.cdn_region.is_empty() {
+            builder
+                .add_filter_in_range_clause(SdkEventDimensions::CdnRegion, &self.cdn_region)
+                .attach_printable("Error adding cdn region filter")?;
+        }
+        if !self.currency.is_empty() {
+            builder
+                .add_filter_in_range_clause(SdkEventDimensions::Currency, &self.currency)
+                .attach_printable("Error adding currency filter")?;
+        }
+        if !self.abandonment_reason.is_empty() {
+            builder
+                .add_filter_in_range_clause(
+                    SdkEventDimensions::AbandonmentReason,
+                    &self.abandonment_reason,
+                )
+                .attach_printable("Error adding abandonment reason filter")?;
+        }
+        if let Some(amount) = &self.amount {
+            builder
+                .add_numeric_range_filter_clause("amount", amount)
+                .attach_printable("Error adding amount filter")?;
+        }
+        if self.exclude_bots {
+            builder
+                .add_bool_filter_clause("is_bot", &false)
+                .attach_printable("Error adding bot filter")?;
+        }
+        if !self.any_of.is_empty() {
+            let groups = self
+                .any_of
+                .iter()
+                .map(|filters| {
+                    let mut group = builder.new_filter_group();
+                    filters.set_filter_clause(&mut group).map(|()| group)
+                })
+                .collect::<QueryResult<Vec<_>>>()?;
+            builder
+                .add_any_of_filter_clause(groups)
+                .attach_printable("Error adding any of filter")?;
+        }
+        for filters in self.all_of.iter() {
+            filters
+                .set_filter_clause(builder)
+                .attach_printable("Error adding all of filter")?;
+        }
+        if !self.none_of.is_empty() {
+            let groups = self
+                .none_of
+                .iter()
+                .map(|filters| {
+                    let mut group = builder.new_filter_group();
+                    filters.set_filter_clause(&mut group).map(|()| group)
+                })
+                .collect::<QueryResult<Vec<_>>>()?;
+            builder
+                .add_none_of_filter_clause(groups)
+                .attach_printable("Error adding none of filter")?;
+        }
         Ok(())
     }
 }
+
+/// Platforms the sdk is built for, the only ones its events are logged from
+const SDK_PLATFORMS: &[&str] = &["web", "ios", "android"];
+
+/// Fails with [`MetricsError::InvalidFilter`] if `filters`, or one of their groups, filter a
+/// dimension with a known set of values on a value outside of it, which no event would match. The
+/// error names the values which are valid. The dimensions the sdk reports as it finds them, like
+/// the browser or the event name, aren't checked, nor the ones whose missing values are matched
+/// by the null label, like the device type. An hour window has to start and end at different
+/// hours of the day. Filters are only checked here, [`QueryFilter::set_filter_clause`] applies
+/// them as they are.
+pub(crate) fn validate_filter_values(filters: &SdkEventFilters) -> MetricsResult<()> {
+    let one_of = |values: Vec<String>| format!("one of {}", values.join(", "));
+    check_filter_values(
+        SdkEventDimensions::Platform,
+        &filters.platform,
+        |value| SDK_PLATFORMS.contains(&value),
+        || one_of(SDK_PLATFORMS.iter().map(ToString::to_string).collect()),
+    )?;
+    check_filter_values(
+        SdkEventDimensions::PaymentMethod,
+        &filters.payment_method,
+        |value| PaymentMethod::from_str(value).is_ok(),
+        || {
+            one_of(
+                PaymentMethod::iter()
+                    .map(|method| method.to_string())
+                    .collect(),
+            )
+        },
+    )?;
+    check_filter_values(
+        SdkEventDimensions::Country,
+        &filters.country,
+        |value| CountryAlpha2::from_str(value).is_ok(),
+        || "an ISO 3166-1 alpha-2 country code, e.g. US".to_string(),
+    )?;
+    check_filter_values(
+        SdkEventDimensions::DayOfWeek,
+        &filters.day_of_week,
+        |value| (1..=7).any(|day: u8| day.to_string() == value),
+        || "one of 1 to 7, from Monday to Sunday".to_string(),
+    )?;
+    check_filter_values(
+        SdkEventDimensions::HourOfDay,
+        &filters.hour_of_day,
+        |value| (0..24).any(|hour: u8| hour.to_string() == value),
+        || "one of 0 to 23".to_string(),
+    )?;
+    if let Some(window) = filters
+        .hour_windows
+        .iter()
+        .find(|window| !is_valid_hour_window(window))
+    {
+        return Err(report!(MetricsError::InvalidFilter(format!(
+            "invalid hour window from {} to {}, expected a start of 0 to 23 and a different end \
+             of 0 to 24",
+            window.start_hour, window.end_hour
+        ))));
+    }
+    check_filter_values(
+        SdkEventDimensions::Currency,
+        &filters.currency,
+        |value| Currency::from_str(value).is_ok(),
+        || "an ISO 4217 currency code, e.g. USD".to_string(),
+    )?;
+    filters
+        .any_of
+        .iter()
+        .chain(filters.all_of.iter())
+        .chain(filters.none_of.iter())
+        .try_for_each(validate_filter_values)
+}
+
+/// Whether `window` starts at an hour of the day and ends at another one, `24` being the end of
+/// the day
+fn is_valid_hour_window(window: &HourWindow) -> bool {
+    window.start_hour < 24 && window.end_hour <= 24 && window.start_hour != window.end_hour
+}
+
+/// The hours of the day `windows` span, as the hour of day dimension has them
+fn window_hours(windows: &[HourWindow]) -> Vec<String> {
+    windows
+        .iter()
+        .flat_map(|window| {
+            if window.start_hour < window.end_hour {
+                (window.start_hour..window.end_hour).chain(0..0)
+            } else {
+                (window.start_hour..24).chain(0..window.end_hour)
+            }
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|hour| hour.to_string())
+        .collect()
+}
+
+/// Fails with [`MetricsError::InvalidFilter`] on the first of `values` which isn't valid, naming
+/// the `expected` values
+fn check_filter_values(
+    dimension: SdkEventDimensions,
+    values: &[String],
+    is_valid: impl Fn(&str) -> bool,
+    expected: impl FnOnce() -> String,
+) -> MetricsResult<()> {
+    match values.iter().find(|value| !is_valid(value)) {
+        Some(value) => Err(report!(MetricsError::InvalidFilter(format!(
+            "unknown {dimension} {value:?}, expected {}",
+            expected()
+        )))),
+        None => Ok(()),
+    }
+}
+
+/// Whether `filters` or any of their groups filter on the customer segment, which the query has
+/// to join [`crate::query::customer_segment_join`] for
+pub(crate) fn filters_on_customer_segment(filters: &SdkEventFilters) -> bool {
+    !filters.customer_segment.is_empty()
+        || filters
+            .any_of
+            .iter()
+            .chain(filters.all_of.iter())
+            .chain(filters.none_of.iter())
+            .any(filters_on_customer_segment)
+}
+
+/// Whether `filters` and every one of their groups only filter on `dimensions`, so that they can
+/// be applied to a collection which only has the columns of those dimensions
+pub(crate) fn filters_only_on(
+    filters: &SdkEventFilters,
+    dimensions: &[SdkEventDimensions],
+) -> bool {
+    let filtered = [
+        (SdkEventDimensions::PaymentMethod, &filters.payment_method),
+        (SdkEventDimensions::Platform, &filters.platform),
+        (SdkEventDimensions::BrowserName, &filters.browser_name),
+        (SdkEventDimensions::Source, &filters.source),
+        (SdkEventDimensions::Component, &filters.component),
+        (
+            SdkEventDimensions::PaymentExperience,
+            &filters.payment_experience,
+        ),
+        (SdkEventDimensions::SdkVersion, &filters.sdk_version),
+        (SdkEventDimensions::DeviceType, &filters.device_type),
+        (SdkEventDimensions::Os, &filters.os),
+        (SdkEventDimensions::Country, &filters.country),
+        (SdkEventDimensions::Connector, &filters.connector),
+        (SdkEventDimensions::EventName, &filters.event_name),
+        (
+            SdkEventDimensions::PaymentMethodType,
+            &filters.payment_method_type,
+        ),
+        (SdkEventDimensions::DayOfWeek, &filters.day_of_week),
+        (SdkEventDimensions::HourOfDay, &filters.hour_of_day),
+        (
+            SdkEventDimensions::AuthenticationErrorReason,
+            &filters.authentication_error_reason,
+        ),
+        (SdkEventDimensions::FieldName, &filters.field_name),
+        (
+            SdkEventDimensions::CustomerSegment,
+            &filters.customer_segment,
+        ),
+        (SdkEventDimensions::CdnRegion, &filters.cdn_region),
+        (SdkEventDimensions::Currency, &filters.currency),
+        (
+            SdkEventDimensions::AbandonmentReason,
+            &filters.abandonment_reason,
+        ),
+    ];
+    // Neither the amount nor the bot flag is a dimension, the hour windows are the hour of day
+    filters.amount.is_none()
+        && !filters.exclude_bots
+        && (filters.hour_windows.is_empty() || dimensions.contains(&SdkEventDimensions::HourOfDay))
+        && filtered
+            .iter()
+            .all(|(dimension, values)| values.is_empty() || dimensions.contains(dimension))
+        && filters
+            .any_of
+            .iter()
+            .chain(filters.all_of.iter())
+            .chain(filters.none_of.iter())
+            .all(|filters| filters_only_on(filters, dimensions))
+}
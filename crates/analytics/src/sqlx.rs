@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, sync::Arc, time::Duration};
 
 use api_models::{
     analytics::refunds::RefundType,
@@ -8,9 +8,11 @@ use common_utils::errors::{CustomResult, ParsingError};
 use diesel_models::enums::{
     AttemptStatus, AuthenticationType, Currency, PaymentMethod, RefundStatus,
 };
-use error_stack::ResultExt;
+use error_stack::{report, ResultExt};
+use futures::StreamExt;
 use masking::PeekInterface;
 use sqlx::{
+    pool::PoolConnection,
     postgres::{PgArgumentBuffer, PgPoolOptions, PgRow, PgTypeInfo, PgValueRef},
     Decode, Encode,
     Error::ColumnNotFound,
@@ -21,16 +23,22 @@ use time::PrimitiveDateTime;
 
 use super::{
     health_check::HealthCheck,
-    query::{Aggregate, ToSql, Window},
+    query::{Aggregate, DatePart, ToSql, Window},
     types::{
-        AnalyticsCollection, AnalyticsDataSource, DBEnumWrapper, LoadRow, QueryExecutionError,
-        TableEngine,
+        AnalyticsCollection, AnalyticsDataSource, DBEnumWrapper, Gauge, LoadRow,
+        MerchantQueryLimit, MerchantQueryLimiter, PoolStats, QueryExecutionError, QueryReport,
+        RetryConfig, RowStream, TableEngine,
     },
 };
 
 #[derive(Debug, Clone)]
 pub struct SqlxClient {
     pool: Pool<Postgres>,
+    retry: RetryConfig,
+    max_scanned_rows: Option<u64>,
+    merchant_query_limiter: Option<Arc<MerchantQueryLimiter>>,
+    /// Queries waiting for a connection of `pool`
+    waiters: Arc<Gauge>,
 }
 
 impl Default for SqlxClient {
@@ -44,6 +52,10 @@ impl Default for SqlxClient {
             pool: PgPoolOptions::new()
                 .connect_lazy(&database_url)
                 .expect("SQLX Pool Creation failed"),
+            retry: RetryConfig::default(),
+            max_scanned_rows: None,
+            merchant_query_limiter: None,
+            waiters: Arc::default(),
         }
     }
 }
@@ -55,14 +67,131 @@ impl SqlxClient {
             "postgres://{}:{}@{}:{}/{}",
             conf.username, password, conf.host, conf.port, conf.dbname
         );
-        #[allow(clippy::expect_used)]
-        let pool = PgPoolOptions::new()
+        let mut options = PgPoolOptions::new()
             .max_connections(conf.pool_size)
-            .acquire_timeout(std::time::Duration::from_secs(conf.connection_timeout))
+            .acquire_timeout(Duration::from_secs(conf.connection_timeout));
+        if let Some(min_idle) = conf.min_idle {
+            options = options.min_connections(min_idle);
+        }
+        if let Some(max_lifetime) = conf.max_lifetime {
+            options = options.max_lifetime(Duration::from_secs(max_lifetime));
+        }
+        #[allow(clippy::expect_used)]
+        let pool = options
             .connect_lazy(&database_url)
             .expect("SQLX Pool Creation failed");
-        Self { pool }
+        Self {
+            pool,
+            retry: RetryConfig::default(),
+            max_scanned_rows: None,
+            merchant_query_limiter: None,
+            waiters: Arc::default(),
+        }
+    }
+
+    pub fn with_retry_config(self, retry: RetryConfig) -> Self {
+        Self { retry, ..self }
+    }
+
+    /// Rejects queries which postgres estimates to read more than `max_scanned_rows` rows
+    pub fn with_max_scanned_rows(self, max_scanned_rows: u64) -> Self {
+        Self {
+            max_scanned_rows: Some(max_scanned_rows),
+            ..self
+        }
     }
+
+    /// Limits the metric queries each merchant runs at once to `limit`
+    pub fn with_merchant_query_limit(self, limit: &MerchantQueryLimit) -> Self {
+        Self {
+            merchant_query_limiter: Some(Arc::new(MerchantQueryLimiter::new(limit))),
+            ..self
+        }
+    }
+}
+
+/// Takes a connection of `pool`, counting the query in `waiters` until it has one
+async fn acquire(
+    pool: &Pool<Postgres>,
+    waiters: &Arc<Gauge>,
+) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+    let _waiting = waiters.track();
+    pool.acquire().await
+}
+
+/// Rows fetched ahead of the consumer of a row stream
+const STREAM_BUFFER_SIZE: usize = 1024;
+
+/// Sums the row estimates of the nodes of a text `EXPLAIN` plan which read a table. The bitmap
+/// index scans are skipped since the heap scan on top of them reads the same rows.
+fn scanned_rows(plan: &[String]) -> u64 {
+    plan.iter()
+        .filter(|node| node.contains(" Scan ") && !node.contains("Bitmap Index Scan"))
+        .filter_map(|node| {
+            node.split_once(" rows=")?
+                .1
+                .split(' ')
+                .next()?
+                .parse::<u64>()
+                .ok()
+        })
+        .fold(0, u64::saturating_add)
+}
+
+/// Sums the rows the nodes of a text `EXPLAIN ANALYZE` plan which read a table actually read,
+/// over all of their loops. The bitmap index scans are skipped like in [`scanned_rows`], and the
+/// nodes which never ran read nothing.
+fn actual_scanned_rows(plan: &[String]) -> u64 {
+    plan.iter()
+        .filter(|node| node.contains(" Scan ") && !node.contains("Bitmap Index Scan"))
+        .filter_map(|node| {
+            let actual = node.split_once("(actual ")?.1;
+            let field = |name: &str| {
+                actual
+                    .split_once(name)?
+                    .1
+                    .split([' ', ')'])
+                    .next()?
+                    .parse::<u64>()
+                    .ok()
+            };
+            Some(field(" rows=")?.saturating_mul(field(" loops=")?))
+        })
+        .fold(0, u64::saturating_add)
+}
+
+/// Lines of the text plan postgres answers the `EXPLAIN` statement `explain` with
+async fn query_plan(
+    pool: &Pool<Postgres>,
+    explain: &str,
+) -> CustomResult<Vec<String>, QueryExecutionError> {
+    sqlx::query(explain)
+        .fetch_all(pool)
+        .await
+        .map_err(query_error)?
+        .iter()
+        .map(|node| node.try_get::<String, _>(0))
+        .collect::<Result<Vec<_>, _>>()
+        .change_context(QueryExecutionError::RowExtractionFailure)
+}
+
+/// Whether the query could succeed if sent again unchanged
+fn is_transient(error: &sqlx::Error) -> bool {
+    matches!(error, sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut)
+}
+
+/// A failed query as it's reported by the data source, the ones postgres couldn't be reached for
+/// are told apart
+fn query_error(error: sqlx::Error) -> error_stack::Report<QueryExecutionError> {
+    let context = match &error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => QueryExecutionError::ConnectionFailure,
+        _ => QueryExecutionError::DatabaseError,
+    };
+    error_stack::report!(error).change_context(context)
 }
 
 pub trait DbType {
@@ -154,20 +283,178 @@ impl super::disputes::metrics::DisputeMetricAnalytics for SqlxClient {}
 impl AnalyticsDataSource for SqlxClient {
     type Row = PgRow;
 
+    fn source_name(&self) -> &'static str {
+        "Sqlx"
+    }
+
     async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
     where
         Self: LoadRow<T>,
     {
-        sqlx::query(&format!("{query};"))
-            .fetch_all(&self.pool)
+        let query_with_terminator = format!("{query};");
+        self.retry
+            .retry(is_transient, || async {
+                let mut connection = acquire(&self.pool, &self.waiters).await?;
+                sqlx::query(&query_with_terminator)
+                    .fetch_all(&mut *connection)
+                    .await
+            })
             .await
-            .change_context(QueryExecutionError::DatabaseError)
+            .map_err(query_error)
             .attach_printable_lazy(|| format!("Failed to run query {query}"))?
             .into_iter()
             .map(Self::load_row)
             .collect::<Result<Vec<_>, _>>()
             .change_context(QueryExecutionError::RowExtractionFailure)
     }
+
+    async fn load_results_stream<T>(
+        &self,
+        query: String,
+    ) -> CustomResult<RowStream<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        let waiters = Arc::clone(&self.waiters);
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER_SIZE);
+        // The row stream of sqlx borrows the query, so it's driven by a task owning both
+        tokio::spawn(async move {
+            let query = format!("{query};");
+            let mut connection = match acquire(&pool, &waiters).await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    // Nothing is left to do if the receiver is gone already
+                    sender.send(Err(error)).await.ok();
+                    return;
+                }
+            };
+            let mut rows = sqlx::query(&query).fetch(&mut *connection);
+            while let Some(row) = rows.next().await {
+                // The receiver is dropped once the caller stops reading, stop fetching then
+                if sender.send(row).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(
+            futures::stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|row| (row, receiver))
+            })
+            .map(|row| row.map_err(query_error).and_then(Self::load_row))
+            .boxed(),
+        )
+    }
+
+    async fn estimate_scanned_rows(
+        &self,
+        query: &str,
+    ) -> CustomResult<Option<u64>, QueryExecutionError> {
+        let plan = query_plan(&self.pool, &format!("EXPLAIN {query};"))
+            .await
+            .attach_printable_lazy(|| format!("Failed to estimate query {query}"))?;
+        Ok(Some(scanned_rows(&plan)))
+    }
+
+    async fn load_results_with_report<T>(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> CustomResult<(Vec<T>, QueryReport), QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        let load = async {
+            let rows = self.load_results(query).await?;
+            // Postgres only tells what a query read by running it once more under
+            // `EXPLAIN ANALYZE`, which is what the statistics cost
+            let plan = query_plan(&self.pool, &format!("EXPLAIN ANALYZE {query};"))
+                .await
+                .attach_printable_lazy(|| format!("Failed to analyze query {query}"))?;
+            Ok::<_, error_stack::Report<QueryExecutionError>>((
+                rows,
+                QueryReport {
+                    scanned_rows: Some(actual_scanned_rows(&plan)),
+                    ..Default::default()
+                },
+            ))
+        };
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, load)
+                .await
+                .map_err(|_| report!(QueryExecutionError::Timeout))
+                .attach_printable_lazy(|| format!("Query did not finish within {timeout:?}"))?,
+            None => load.await,
+        }
+    }
+
+    fn max_scanned_rows(&self) -> Option<u64> {
+        self.max_scanned_rows
+    }
+
+    fn merchant_query_limiter(&self) -> Option<&MerchantQueryLimiter> {
+        self.merchant_query_limiter.as_deref()
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        let idle = u32::try_from(self.pool.num_idle()).unwrap_or(u32::MAX);
+        Some(PoolStats {
+            in_use: self.pool.size().saturating_sub(idle),
+            idle: Some(idle),
+            waiters: self.waiters.get(),
+        })
+    }
+
+    fn get_date_part(time_column: &str, part: DatePart, time_zone: Option<&str>) -> String {
+        // The time is stored as a UTC timestamp, shift it to the wall clock of the requested zone
+        let time = time_zone
+            .map(|tz| format!("(({time_column} AT TIME ZONE 'UTC') AT TIME ZONE '{tz}')"))
+            .unwrap_or_else(|| time_column.to_string());
+        match part {
+            // isodow counts from Monday as 1 like clickhouse does, dow would start on Sunday as 0
+            DatePart::DayOfWeek => format!("extract(isodow from {time})::text"),
+            DatePart::HourOfDay => format!("extract(hour from {time})::text"),
+        }
+    }
+
+    fn get_json_string(column: &str, key: &str) -> String {
+        // Casting to jsonb would fail the whole query on the first value which isn't JSON
+        format!("substring({column} from '\"{key}\"\\s*:\\s*\"([^\"]*)\"')")
+    }
+
+    fn get_json_number(column: &str, key: &str) -> String {
+        // Only the outer group is returned, the whole number with its decimals
+        format!(
+            "substring({column} from '\"{key}\"\\s*:\\s*(-?[0-9]+(\\.[0-9]+)?)')::double precision"
+        )
+    }
+
+    fn get_sample_clause(sample_rate: f64) -> String {
+        // Bernoulli picks rows rather than whole pages, which would skew a sample of a few pages
+        format!("TABLESAMPLE BERNOULLI ({})", sample_rate * 100.0)
+    }
+
+    fn get_max_partitions_clause(_max_partitions: u32) -> Option<String> {
+        // Postgres has no bound on the partitions a query reads
+        None
+    }
+
+    fn get_round(expression: &str, decimal_places: u32) -> String {
+        // Postgres only rounds numerics to a number of decimals, not floating point values
+        format!("round(({expression})::numeric, {decimal_places})")
+    }
+
+    fn get_string_cast(expression: &str) -> String {
+        format!("({expression})::text")
+    }
+
+    fn get_count_if(condition: &str, count_column: Option<&str>) -> String {
+        format!(
+            "sum(CASE WHEN {condition} THEN {} ELSE 0 END)",
+            count_column.unwrap_or("1")
+        )
+    }
 }
 #[async_trait::async_trait]
 impl HealthCheck for SqlxClient {
@@ -512,6 +799,10 @@ impl ToSql<SqlxClient> for AnalyticsCollection {
             Self::Refund => Ok("refund".to_string()),
             Self::SdkEvents => Err(error_stack::report!(ParsingError::UnknownError)
                 .attach_printable("SdkEvents table is not implemented for Sqlx"))?,
+            Self::SdkEventsArchive => Err(error_stack::report!(ParsingError::UnknownError)
+                .attach_printable("SdkEventsArchive table is not implemented for Sqlx"))?,
+            Self::SdkEventsHourly => Err(error_stack::report!(ParsingError::UnknownError)
+                .attach_printable("SdkEventsHourly view is not implemented for Sqlx"))?,
             Self::ApiEvents => Err(error_stack::report!(ParsingError::UnknownError)
                 .attach_printable("ApiEvents table is not implemented for Sqlx"))?,
             Self::PaymentIntent => Ok("payment_intent".to_string()),
@@ -520,6 +811,8 @@ impl ToSql<SqlxClient> for AnalyticsCollection {
             Self::OutgoingWebhookEvent => Err(error_stack::report!(ParsingError::UnknownError)
                 .attach_printable("OutgoingWebhookEvents table is not implemented for Sqlx"))?,
             Self::Dispute => Ok("dispute".to_string()),
+            Self::CustomerSegments => Err(error_stack::report!(ParsingError::UnknownError)
+                .attach_printable("CustomerSegments table is not implemented for Sqlx"))?,
         }
     }
 }
@@ -563,6 +856,72 @@ where
                     alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
                 )
             }
+            Self::Percentile {
+                field,
+                alias,
+                quantile,
+            } => {
+                format!(
+                    "percentile_cont({quantile}) within group (order by {}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to percentile aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // Postgres has no cardinality estimator, the approximate count is an exact one
+            Self::CountDistinct { field, alias } | Self::CountDistinctApprox { field, alias } => {
+                format!(
+                    "count(distinct {}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to count distinct aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // Postgres has no t-digest either, the approximate median is the exact one
+            Self::MedianTDigest { field, alias } => {
+                format!(
+                    "percentile_cont(0.5) within group (order by {}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to median aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            // The percentiles of the same ordered values are computed over a single sort of them
+            Self::Quantiles { field, quantiles } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to quantiles aggregate")?;
+                quantiles
+                    .iter()
+                    .map(|(alias, quantile)| {
+                        format!(
+                            "percentile_cont({quantile}) within group (order by {field}) as {alias}"
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+            Self::StdDev { field, alias } => {
+                format!(
+                    "stddev_pop({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to standard deviation aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::Variance { field, alias } => {
+                format!(
+                    "var_pop({}){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to variance aggregate")?,
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
         })
     }
 }
@@ -622,6 +981,171 @@ where
                     alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
                 )
             }
+            Self::CumulativeSum {
+                field,
+                partition_by,
+                order_by,
+                alias,
+            } => {
+                format!(
+                    "sum({}) over ({}{} rows unbounded preceding){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to cumulative sum window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::MovingAverage {
+                field,
+                partition_by,
+                order_by,
+                preceding,
+                alias,
+            } => {
+                format!(
+                    "avg({}) over ({}{} rows between {preceding} preceding and current row){}",
+                    field
+                        .to_sql(table_engine)
+                        .attach_printable("Failed to moving average window")?,
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    order_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |(order_column, order)| format!(
+                            " order by {} {}",
+                            order_column.to_owned(),
+                            order.to_string()
+                        )
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
+            Self::PercentOfTotal {
+                field,
+                partition_by,
+                alias,
+            } => {
+                let field = field
+                    .to_sql(table_engine)
+                    .attach_printable("Failed to percent of total window")?;
+                format!(
+                    "{field} * 100 / nullif(sum({field}) over ({}), 0){}",
+                    partition_by.as_ref().map_or_else(
+                        || "".to_owned(),
+                        |partition_by| format!("partition by {}", partition_by.to_owned())
+                    ),
+                    alias.map_or_else(|| "".to_owned(), |alias| format!(" as {}", alias))
+                )
+            }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanned_rows_sums_table_reads() {
+        let plan = [
+            "Append  (cost=4.18..40.51 rows=1210 width=8)",
+            "  ->  Seq Scan on payment_attempt  (cost=0.00..18.10 rows=810 width=8)",
+            "        Filter: (merchant_id = 'merchant'::text)",
+            "  ->  Bitmap Heap Scan on refund  (cost=4.18..12.64 rows=400 width=8)",
+            "        Recheck Cond: (merchant_id = 'merchant'::text)",
+            "        ->  Bitmap Index Scan on refund_merchant_id_index  (cost=0.00..4.18 rows=400 width=0)",
+        ]
+        .map(String::from);
+        assert_eq!(scanned_rows(&plan), 1210);
+    }
+
+    #[test]
+    fn test_actual_scanned_rows_sums_table_reads_over_their_loops() {
+        let plan = [
+            "Nested Loop  (cost=0.29..48.62 rows=10 width=8) (actual time=0.031..0.102 rows=12 loops=1)",
+            "  ->  Seq Scan on payment_intent  (cost=0.00..18.10 rows=4 width=8) (actual time=0.011..0.020 rows=3 loops=1)",
+            "  ->  Index Scan using payment_attempt_index on payment_attempt  (cost=0.29..7.62 rows=3 width=8) (actual time=0.009..0.012 rows=4 loops=3)",
+            "  ->  Seq Scan on refund  (cost=0.00..18.10 rows=810 width=8) (never executed)",
+            "Planning Time: 0.120 ms",
+            "Execution Time: 0.150 ms",
+        ]
+        .map(String::from);
+        assert_eq!(actual_scanned_rows(&plan), 15);
+    }
+
+    #[test]
+    fn test_unreachable_database_is_a_connection_failure() {
+        assert!(matches!(
+            query_error(sqlx::Error::PoolTimedOut).current_context(),
+            QueryExecutionError::ConnectionFailure
+        ));
+        assert!(matches!(
+            query_error(sqlx::Error::RowNotFound).current_context(),
+            QueryExecutionError::DatabaseError
+        ));
+    }
+
+    #[test]
+    fn test_quantiles_lowering() {
+        let quantiles = Aggregate::Quantiles {
+            field: "latency",
+            quantiles: &[("p50", 0.5), ("p99", 0.99)],
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<SqlxClient>>::to_sql(
+                &quantiles,
+                &TableEngine::BasicTree
+            )
+            .ok()
+            .as_deref(),
+            Some(
+                "percentile_cont(0.5) within group (order by latency) as p50, \
+                 percentile_cont(0.99) within group (order by latency) as p99"
+            )
+        );
+    }
+
+    #[test]
+    fn test_spread_lowering() {
+        let stddev = Aggregate::StdDev {
+            field: "latency",
+            alias: Some("stddev"),
+        };
+        let variance = Aggregate::Variance {
+            field: "latency",
+            alias: Some("variance"),
+        };
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<SqlxClient>>::to_sql(
+                &stddev,
+                &TableEngine::BasicTree
+            )
+            .ok()
+            .as_deref(),
+            Some("stddev_pop(latency) as stddev")
+        );
+        assert_eq!(
+            <Aggregate<&'static str> as ToSql<SqlxClient>>::to_sql(
+                &variance,
+                &TableEngine::BasicTree
+            )
+            .ok()
+            .as_deref(),
+            Some("var_pop(latency) as variance")
+        );
+    }
+}
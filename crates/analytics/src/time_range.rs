@@ -0,0 +1,298 @@
+use api_models::analytics::{Granularity, RelativeTimeRange, TimeRange};
+use chrono::{Datelike, TimeZone};
+use common_utils::date_time;
+use error_stack::report;
+use time::PrimitiveDateTime;
+
+use crate::types::{MetricsError, MetricsResult};
+
+/// The [`TimeRange`] `range` covers right now, see [`relative_time_range_at`]
+pub fn relative_time_range(range: RelativeTimeRange, time_zone: &str) -> MetricsResult<TimeRange> {
+    relative_time_range_at(range, time_zone, date_time::now())
+}
+
+/// The [`TimeRange`] `range` covers at the UTC time `now`. The days of the range are the days of
+/// `time_zone`: the range starts at the first local midnight of the range and ends right before
+/// the local midnight following it, both given in UTC like every other time range. A day is
+/// shorter or longer than 24 hours if the clocks are changed on it, and starts at the first local
+/// time which exists if its midnight is skipped.
+pub fn relative_time_range_at(
+    range: RelativeTimeRange,
+    time_zone: &str,
+    now: PrimitiveDateTime,
+) -> MetricsResult<TimeRange> {
+    let time_zone = time_zone.parse::<chrono_tz::Tz>().map_err(|_| {
+        report!(MetricsError::InvalidTimeRange(format!(
+            "unknown time zone {time_zone}"
+        )))
+    })?;
+    let today = chrono::DateTime::from_timestamp(now.assume_utc().unix_timestamp(), 0)
+        .map(|now| time_zone.from_utc_datetime(&now.naive_utc()).date_naive())
+        .ok_or_else(|| out_of_range(now))?;
+
+    let (first_day, day_after) = match range {
+        RelativeTimeRange::Today => (Some(today), today.succ_opt()),
+        RelativeTimeRange::Yesterday => (today.pred_opt(), Some(today)),
+        RelativeTimeRange::LastSevenDays => (
+            today.checked_sub_days(chrono::Days::new(6)),
+            today.succ_opt(),
+        ),
+        RelativeTimeRange::ThisMonth => {
+            let first_day = today.with_day(1);
+            let day_after =
+                first_day.and_then(|day| day.checked_add_months(chrono::Months::new(1)));
+            (first_day, day_after)
+        }
+    };
+    let (Some(first_day), Some(day_after)) = (first_day, day_after) else {
+        return Err(out_of_range(now));
+    };
+
+    Ok(TimeRange {
+        start_time: start_of_day(first_day, time_zone)?,
+        end_time: Some(start_of_day(day_after, time_zone)? - time::Duration::SECOND),
+    })
+}
+
+fn out_of_range(now: PrimitiveDateTime) -> error_stack::Report<MetricsError> {
+    report!(MetricsError::InvalidTimeRange(format!(
+        "relative range of {now} is out of range"
+    )))
+}
+
+/// Lengths of the time ranges [`default_granularity`] suggests each granularity for
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct GranularityThresholds {
+    /// Ranges shorter than this many hours are bucketed by hour
+    pub hourly_below_hours: u32,
+    /// Longer ranges shorter than this many days are bucketed by day, the others by week
+    pub daily_below_days: u32,
+}
+
+impl Default for GranularityThresholds {
+    fn default() -> Self {
+        Self {
+            hourly_below_hours: 48,
+            daily_below_days: 60,
+        }
+    }
+}
+
+/// The granularity a time series over `time_range` is bucketed by when the caller didn't pick
+/// one, so that a long range isn't split into thousands of buckets. A range without an end ends
+/// now.
+pub fn default_granularity(
+    time_range: &TimeRange,
+    thresholds: &GranularityThresholds,
+) -> Granularity {
+    let end_time = time_range.end_time.unwrap_or_else(date_time::now);
+    let length = end_time - time_range.start_time;
+    if length < time::Duration::hours(thresholds.hourly_below_hours.into()) {
+        Granularity::OneHour
+    } else if length < time::Duration::days(thresholds.daily_below_days.into()) {
+        Granularity::OneDay
+    } else {
+        Granularity::Weekly
+    }
+}
+
+/// `granularity` if the caller picked one, the [`default_granularity`] of `time_range` otherwise
+pub fn granularity_or_default(
+    granularity: Option<Granularity>,
+    time_range: &TimeRange,
+    thresholds: &GranularityThresholds,
+) -> Granularity {
+    granularity.unwrap_or_else(|| default_granularity(time_range, thresholds))
+}
+
+/// UTC time of the first instant of `day` in `time_zone`
+fn start_of_day(
+    day: chrono::NaiveDate,
+    time_zone: chrono_tz::Tz,
+) -> MetricsResult<PrimitiveDateTime> {
+    let midnight = day.and_time(chrono::NaiveTime::MIN);
+    // Clocks are only ever changed by whole quarter hours, so the first one which exists is the
+    // start of a day whose midnight is skipped
+    let start = (0..=24 * 4)
+        .find_map(|quarters| {
+            let local = midnight + chrono::Duration::minutes(quarters * 15);
+            time_zone.from_local_datetime(&local).earliest()
+        })
+        .ok_or_else(|| {
+            report!(MetricsError::InvalidTimeRange(format!(
+                "{day} has no start in {time_zone}"
+            )))
+        })?;
+    time::OffsetDateTime::from_unix_timestamp(start.timestamp())
+        .map(|start| PrimitiveDateTime::new(start.date(), start.time()))
+        .map_err(|_| {
+            report!(MetricsError::InvalidTimeRange(format!(
+                "start of {day} is out of range"
+            )))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::parse_time_bucket;
+
+    fn at(value: &str) -> PrimitiveDateTime {
+        parse_time_bucket(value).expect("invalid time")
+    }
+
+    fn range_at(
+        range: RelativeTimeRange,
+        time_zone: &str,
+        now: PrimitiveDateTime,
+    ) -> (PrimitiveDateTime, Option<PrimitiveDateTime>) {
+        let range = relative_time_range_at(range, time_zone, now).expect("failed to build range");
+        (range.start_time, range.end_time)
+    }
+
+    #[test]
+    fn test_today_is_the_local_day() {
+        // Already the next day in India
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::Today,
+                "Asia/Kolkata",
+                at("2024-03-01 20:00:00")
+            ),
+            (at("2024-03-01 18:30:00"), Some(at("2024-03-02 18:29:59")))
+        );
+        assert_eq!(
+            range_at(RelativeTimeRange::Today, "UTC", at("2024-03-01 20:00:00")),
+            (at("2024-03-01 00:00:00"), Some(at("2024-03-01 23:59:59")))
+        );
+    }
+
+    #[test]
+    fn test_yesterday_and_last_seven_days_across_the_year_boundary() {
+        let now = at("2024-01-01 03:00:00");
+        assert_eq!(
+            range_at(RelativeTimeRange::Yesterday, "America/New_York", now),
+            (at("2023-12-30 05:00:00"), Some(at("2023-12-31 04:59:59")))
+        );
+        assert_eq!(
+            range_at(RelativeTimeRange::LastSevenDays, "Europe/Berlin", now),
+            (at("2023-12-25 23:00:00"), Some(at("2024-01-01 22:59:59")))
+        );
+    }
+
+    #[test]
+    fn test_this_month_at_month_boundaries() {
+        // Still February in New York
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::ThisMonth,
+                "America/New_York",
+                at("2024-03-01 03:00:00")
+            ),
+            (at("2024-02-01 05:00:00"), Some(at("2024-03-01 04:59:59")))
+        );
+        // Already February in Berlin
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::ThisMonth,
+                "Europe/Berlin",
+                at("2024-01-31 23:30:00")
+            ),
+            (at("2024-01-31 23:00:00"), Some(at("2024-02-29 22:59:59")))
+        );
+        // The clocks change within the month
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::ThisMonth,
+                "Europe/Berlin",
+                at("2024-03-15 12:00:00")
+            ),
+            (at("2024-02-29 23:00:00"), Some(at("2024-03-31 21:59:59")))
+        );
+    }
+
+    #[test]
+    fn test_days_of_daylight_saving_changes() {
+        // 23 hours long
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::Today,
+                "America/New_York",
+                at("2024-03-10 12:00:00")
+            ),
+            (at("2024-03-10 05:00:00"), Some(at("2024-03-11 03:59:59")))
+        );
+        // 25 hours long
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::Today,
+                "America/New_York",
+                at("2024-11-03 12:00:00")
+            ),
+            (at("2024-11-03 04:00:00"), Some(at("2024-11-04 04:59:59")))
+        );
+        // Clocks skip from midnight to 1am, so the day starts at 1am
+        assert_eq!(
+            range_at(
+                RelativeTimeRange::Today,
+                "America/Havana",
+                at("2024-03-10 12:00:00")
+            ),
+            (at("2024-03-10 05:00:00"), Some(at("2024-03-11 03:59:59")))
+        );
+    }
+
+    #[test]
+    fn test_unknown_time_zone_is_rejected() {
+        let error = relative_time_range_at(
+            RelativeTimeRange::Today,
+            "Mars/Olympus_Mons",
+            at("2024-03-01 12:00:00"),
+        )
+        .expect_err("an unknown time zone was accepted");
+        assert!(matches!(
+            error.current_context(),
+            MetricsError::InvalidTimeRange(_)
+        ));
+    }
+
+    #[test]
+    fn test_default_granularity_follows_range_length() {
+        let thresholds = GranularityThresholds::default();
+        let granularity = |start: &str, end: &str| {
+            let time_range = TimeRange {
+                start_time: at(start),
+                end_time: Some(at(end)),
+            };
+            format!("{:?}", default_granularity(&time_range, &thresholds))
+        };
+
+        assert_eq!(
+            granularity("2024-03-01 00:00:00", "2024-03-02 23:59:59"),
+            "OneHour"
+        );
+        assert_eq!(
+            granularity("2024-03-01 00:00:00", "2024-03-03 00:00:00"),
+            "OneDay"
+        );
+        assert_eq!(
+            granularity("2024-03-01 00:00:00", "2024-04-29 23:59:59"),
+            "OneDay"
+        );
+        assert_eq!(
+            granularity("2024-03-01 00:00:00", "2024-05-30 00:00:00"),
+            "Weekly"
+        );
+
+        // A granularity which was picked is kept
+        let quarter = TimeRange {
+            start_time: at("2024-01-01 00:00:00"),
+            end_time: Some(at("2024-03-31 23:59:59")),
+        };
+        assert!(matches!(
+            granularity_or_default(Some(Granularity::OneHour), &quarter, &thresholds),
+            Granularity::OneHour
+        ));
+    }
+}
@@ -1,14 +1,29 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
+use api_models::analytics::Granularity;
 use common_utils::{
-    errors::{CustomResult, ErrorSwitch, ParsingError},
+    errors::{CustomResult, ErrorSwitch, ParsingError, ReportSwitchExt},
     events::{ApiEventMetric, ApiEventsType},
     impl_misc_api_event_type,
 };
 use error_stack::{report, Report, ResultExt};
+use futures::{stream::BoxStream, StreamExt};
+use router_env::logger;
+
+use super::query::{clickhouse_date_part, clickhouse_time_bucket, DatePart, QueryBuildingError};
+use crate::{errors::AnalyticsError, health_check::HealthCheck, time_range::GranularityThresholds};
 
-use super::query::QueryBuildingError;
-use crate::errors::AnalyticsError;
+/// Default of [`AnalyticsDataSource::health_check_timeout`], short enough for a readiness probe
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(serde::Deserialize, Debug, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -20,20 +35,27 @@ pub enum AnalyticsDomain {
     Dispute,
 }
 
-#[derive(Debug, strum::AsRefStr, strum::Display, Clone, Copy)]
+#[derive(Debug, strum::AsRefStr, strum::Display, Clone, Copy, PartialEq, Eq)]
 pub enum AnalyticsCollection {
     Payment,
     Refund,
     SdkEvents,
+    /// Sdk events moved out of [`Self::SdkEvents`] once they're old, with the same columns
+    SdkEventsArchive,
+    /// Materialized view rolling [`Self::SdkEvents`] up per hour, see
+    /// [`crate::sdk_events::metrics::MaterializedView`]
+    SdkEventsHourly,
     ApiEvents,
     PaymentIntent,
     ConnectorEvents,
     OutgoingWebhookEvent,
     Dispute,
+    /// Segment of the customer of each payment, joined onto the sdk events
+    CustomerSegments,
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum TableEngine {
     CollapsingMergeTree { sign: &'static str },
     BasicTree,
@@ -63,6 +85,19 @@ where
     }
 }
 
+/// What the data source reports about a query after running it, see
+/// [`AnalyticsDataSource::load_results_with_report`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryReport {
+    /// Rows the query read, `None` if the data source can't tell
+    pub scanned_rows: Option<u64>,
+    /// Bytes the query read, `None` if the data source can't tell
+    pub scanned_bytes: Option<u64>,
+    /// Whether the secondary of a [`crate::FallbackSource`] answered the query as its primary
+    /// couldn't be reached
+    pub served_by_fallback: bool,
+}
+
 // Analytics Framework
 
 pub trait RefundAnalytics {}
@@ -74,13 +109,414 @@ where
     Self: Sized + Sync + Send,
 {
     type Row;
+
+    /// Name the queries of the data source are labelled with in the metrics of the crate
+    fn source_name(&self) -> &'static str;
+
     async fn load_results<T>(&self, query: &str) -> CustomResult<Vec<T>, QueryExecutionError>
     where
         Self: LoadRow<T>;
 
+    /// Runs the query like [`Self::load_results`] but gives up once `timeout` has elapsed. The
+    /// pending request is dropped, which cancels it, and a [`QueryExecutionError::Timeout`] is
+    /// returned.
+    async fn load_results_with_timeout<T>(
+        &self,
+        query: &str,
+        timeout: Duration,
+    ) -> CustomResult<Vec<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        tokio::time::timeout(timeout, self.load_results(query))
+            .await
+            .map_err(|_| report!(QueryExecutionError::Timeout))
+            .attach_printable_lazy(|| format!("Query did not finish within {timeout:?}"))?
+    }
+
+    /// Like [`Self::load_results`] but hands out the rows as the data source returns them instead
+    /// of collecting all of them first. Data sources without a way to stream rows load the whole
+    /// result before handing it out.
+    async fn load_results_stream<T>(
+        &self,
+        query: String,
+    ) -> CustomResult<RowStream<T>, QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+        T: Send + 'static,
+    {
+        let rows = self.load_results(&query).await?;
+        Ok(futures::stream::iter(rows.into_iter().map(Ok)).boxed())
+    }
+
+    /// Runs the query like [`Self::load_results_with_timeout`], or [`Self::load_results`]
+    /// without a timeout, and reports how much it read. Data sources which can't tell that
+    /// report nothing read.
+    async fn load_results_with_report<T>(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> CustomResult<(Vec<T>, QueryReport), QueryExecutionError>
+    where
+        Self: LoadRow<T>,
+    {
+        let rows = match timeout {
+            Some(timeout) => self.load_results_with_timeout(query, timeout).await?,
+            None => self.load_results(query).await?,
+        };
+        Ok((rows, QueryReport::default()))
+    }
+
+    /// Number of rows the data source expects to read when running the query, `None` if it
+    /// can't estimate that.
+    async fn estimate_scanned_rows(
+        &self,
+        _query: &str,
+    ) -> CustomResult<Option<u64>, QueryExecutionError> {
+        Ok(None)
+    }
+
+    /// Queries estimated to read more rows than this are rejected with
+    /// [`QueryExecutionError::TooLarge`] instead of being run, there's no limit by default.
+    fn max_scanned_rows(&self) -> Option<u64> {
+        None
+    }
+
+    /// Sdk event metric queries returning more rows are cut to this many, and their buckets are
+    /// flagged as truncated, see [`crate::query::QueryBuilder::set_max_rows`]. Results aren't
+    /// capped by default.
+    fn max_result_rows(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether the materialized views of the sdk events, see
+    /// [`crate::sdk_events::metrics::MaterializedView`], exist so that the metrics can read them
+    /// instead of the events. Data sources don't have them by default.
+    fn has_materialized_views(&self) -> bool {
+        false
+    }
+
+    /// Metric queries over a longer time range only query its end, ranges aren't limited by
+    /// default
+    fn max_time_range(&self) -> Option<time::Duration> {
+        None
+    }
+
+    /// Range lengths of the granularities [`crate::time_range::default_granularity`] suggests
+    fn granularity_thresholds(&self) -> GranularityThresholds {
+        GranularityThresholds::default()
+    }
+
+    /// Limits the metric queries each merchant runs at once, there's no limit by default
+    fn merchant_query_limiter(&self) -> Option<&MerchantQueryLimiter> {
+        None
+    }
+
+    /// Current utilization of the connections to the database, `None` if the data source doesn't
+    /// keep track of it
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    /// How long [`Self::health_check`] waits for the data source to answer
+    fn health_check_timeout(&self) -> Duration {
+        HEALTH_CHECK_TIMEOUT
+    }
+
+    /// Checks the data source is reachable by running a trivial query, a data source which
+    /// doesn't answer within [`Self::health_check_timeout`] fails with
+    /// [`MetricsError::QueryTimeout`]
+    async fn health_check(&self) -> MetricsResult<()>
+    where
+        Self: HealthCheck,
+    {
+        let timeout = self.health_check_timeout();
+        tokio::time::timeout(timeout, self.deep_health_check())
+            .await
+            .map_err(|_| report!(QueryExecutionError::Timeout))
+            .attach_printable_lazy(|| format!("Health check did not finish within {timeout:?}"))
+            .and_then(|result| result)
+            .switch()
+    }
+
     fn get_table_engine(_table: AnalyticsCollection) -> TableEngine {
         TableEngine::BasicTree
     }
+
+    /// Column holding the time of the rows of `table`, which time ranges filter on and which
+    /// granularities bucket. Defaults to `created_at`, a data source whose schema stores the time
+    /// of a table elsewhere names that column instead.
+    fn get_time_column(_table: AnalyticsCollection) -> &'static str {
+        "created_at"
+    }
+
+    /// Expression for the start of the `granularity` bucket `time_column` falls in, as selected
+    /// by [`crate::query::QueryBuilder::add_granularity_in_mins`]. Defaults to the clickhouse
+    /// functions.
+    fn get_time_bucket(
+        time_column: &str,
+        granularity: &Granularity,
+        time_zone: Option<&str>,
+    ) -> String {
+        clickhouse_time_bucket(time_column, granularity, time_zone)
+    }
+
+    /// Expression for `part` of `time_column` on the wall clock of `time_zone`, as a string.
+    /// Defaults to the clickhouse functions.
+    fn get_date_part(time_column: &str, part: DatePart, time_zone: Option<&str>) -> String {
+        clickhouse_date_part(time_column, part, time_zone)
+    }
+
+    /// Expression for the string at `key` of the JSON object stored as a string in `column`, null
+    /// if the column doesn't hold an object with that key. Defaults to the clickhouse functions.
+    fn get_json_string(column: &str, key: &str) -> String {
+        format!("nullIf(JSONExtractString({column}, '{key}'), '')")
+    }
+
+    /// Expression for the number at `key` of the JSON object stored as a string in `column`, null
+    /// if the column doesn't hold an object with a number at that key. Defaults to the clickhouse
+    /// functions.
+    fn get_json_number(column: &str, key: &str) -> String {
+        format!("JSONExtract({column}, '{key}', 'Nullable(Float64)')")
+    }
+
+    /// Clause following the table which reads a sample of `sample_rate` of its rows, as set by
+    /// [`crate::query::QueryBuilder::set_sample_rate`]. Defaults to the clickhouse `SAMPLE`,
+    /// which needs the table to have a sampling key.
+    fn get_sample_clause(sample_rate: f64) -> String {
+        format!("SAMPLE {sample_rate}")
+    }
+
+    /// Clause ending a query which makes it fail if it would read more than `max_partitions`
+    /// partitions of a table, as set by [`crate::query::QueryBuilder::set_max_partitions`].
+    /// Defaults to the clickhouse `max_partitions_to_read` setting, `None` leaves the partitions
+    /// read unbounded.
+    fn get_max_partitions_clause(max_partitions: u32) -> Option<String> {
+        Some(format!(
+            "SETTINGS max_partitions_to_read = {max_partitions}"
+        ))
+    }
+
+    /// Expression rounding the number `expression` to `decimal_places` decimals, for a
+    /// [`crate::query::Rounded`] column. Defaults to the clickhouse `round`.
+    fn get_round(expression: &str, decimal_places: u32) -> String {
+        format!("round({expression}, {decimal_places})")
+    }
+
+    /// Expression for the value of `expression` as a string, for a
+    /// [`crate::sdk_events::computed_dimension::LocalComputedDimension`]. Defaults to the
+    /// clickhouse `toString`.
+    fn get_string_cast(expression: &str) -> String {
+        format!("toString({expression})")
+    }
+
+    /// Expression counting the rows matching `condition`, or summing `count_column` over them,
+    /// for a [`crate::query::EventCountIf`]. Defaults to the clickhouse `countIf` and `sumIf`.
+    fn get_count_if(condition: &str, count_column: Option<&str>) -> String {
+        match count_column {
+            Some(count_column) => format!("sumIf({count_column}, {condition})"),
+            None => format!("countIf({condition})"),
+        }
+    }
+}
+
+pub type RowStream<T> = BoxStream<'static, CustomResult<T, QueryExecutionError>>;
+
+/// How often a data source retries a query that failed with a transient error (dropped
+/// connection, unavailable server, exhausted pool), waiting `base_delay_ms * 2^attempt` in
+/// between. Errors in the query itself are never retried.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 100,
+        }
+    }
+}
+
+impl RetryConfig {
+    fn delay(&self, attempt: u32) -> Duration {
+        Duration::from_millis(
+            self.base_delay_ms
+                .saturating_mul(2_u64.saturating_pow(attempt)),
+        )
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error `is_transient` rejects, or the
+    /// retries run out. The error of the last attempt is returned as is.
+    pub(crate) async fn retry<T, E, F, Fut>(
+        &self,
+        is_transient: impl Fn(&E) -> bool + Send,
+        mut operation: F,
+    ) -> Result<T, E>
+    where
+        E: std::fmt::Debug,
+        F: FnMut() -> Fut + Send,
+        Fut: std::future::Future<Output = Result<T, E>> + Send,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Err(error) if attempt < self.max_retries && is_transient(&error) => {
+                    let delay = self.delay(attempt);
+                    logger::warn!(?error, attempt, ?delay, "Retrying analytics query");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// How many metric queries of a single merchant run at once on a data source, and how many more
+/// wait for one of them to finish. Queries beyond that are rejected, so that a single merchant
+/// can't take up the whole connection pool.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct MerchantQueryLimit {
+    pub max_concurrent_queries: u32,
+    pub max_queued_queries: u32,
+}
+
+impl Default for MerchantQueryLimit {
+    fn default() -> Self {
+        Self {
+            max_concurrent_queries: 4,
+            max_queued_queries: 16,
+        }
+    }
+}
+
+/// Hands out the slots of [`MerchantQueryLimit`] per merchant. Waiting queries of a merchant are
+/// let through in the order they arrived, the queries of other merchants don't wait for them.
+#[derive(Debug)]
+pub struct MerchantQueryLimiter {
+    max_concurrent_queries: usize,
+    max_queued_queries: usize,
+    merchants: Mutex<HashMap<String, Arc<tokio::sync::Semaphore>>>,
+}
+
+/// Slot of a running query, which is given back when dropped
+#[derive(Debug)]
+pub struct MerchantQueryPermit {
+    _permit: tokio::sync::OwnedSemaphorePermit,
+}
+
+impl MerchantQueryLimiter {
+    pub fn new(limit: &MerchantQueryLimit) -> Self {
+        let to_usize = |value: u32| usize::try_from(value).unwrap_or(usize::MAX);
+        Self {
+            // A merchant which can't run any query would wait forever
+            max_concurrent_queries: to_usize(limit.max_concurrent_queries)
+                .clamp(1, tokio::sync::Semaphore::MAX_PERMITS),
+            max_queued_queries: to_usize(limit.max_queued_queries),
+            merchants: Mutex::default(),
+        }
+    }
+
+    /// Waits for a slot of `merchant`, fails with [`MetricsError::TooManyQueries`] if its queue
+    /// is full
+    pub(crate) async fn acquire(&self, merchant: &str) -> MetricsResult<MerchantQueryPermit> {
+        let semaphore = {
+            let mut merchants = self
+                .merchants
+                .lock()
+                .map_err(|_| report!(MetricsError::QueryExecutionFailure))
+                .attach_printable("Merchant query limiter lock poisoned")?;
+            // The map holds the only reference to the semaphore of a merchant without queries
+            merchants.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            let semaphore = merchants
+                .entry(merchant.to_string())
+                .or_insert_with(|| {
+                    Arc::new(tokio::sync::Semaphore::new(self.max_concurrent_queries))
+                })
+                .clone();
+            // Every running or waiting query holds a reference, besides the map and this one
+            let queries = Arc::strong_count(&semaphore).saturating_sub(2);
+            let running = self
+                .max_concurrent_queries
+                .saturating_sub(semaphore.available_permits());
+            if semaphore.available_permits() == 0
+                && queries.saturating_sub(running) >= self.max_queued_queries
+            {
+                return Err(report!(MetricsError::TooManyQueries)).attach_printable_lazy(|| {
+                    format!(
+                        "{queries} queries of merchant {merchant} are already running or queued"
+                    )
+                });
+            }
+            semaphore
+        };
+        semaphore
+            .acquire_owned()
+            .await
+            .map(|permit| MerchantQueryPermit { _permit: permit })
+            .change_context(MetricsError::QueryExecutionFailure)
+            .attach_printable("Merchant query limiter closed")
+    }
+}
+
+/// Waits for a slot of `merchant` if `pool` limits the queries each merchant runs at once
+pub(crate) async fn acquire_merchant_query_permit<T: AnalyticsDataSource>(
+    pool: &T,
+    merchant: &str,
+) -> MetricsResult<Option<MerchantQueryPermit>> {
+    match pool.merchant_query_limiter() {
+        Some(limiter) => limiter.acquire(merchant).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Utilization of the connections of a data source at one point in time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct PoolStats {
+    /// Connections running a query
+    pub in_use: u32,
+    /// Open connections without a query, `None` if the data source can't tell
+    pub idle: Option<u32>,
+    /// Queries waiting for a connection to free up
+    pub waiters: u32,
+}
+
+/// [`PoolStats`] of the data sources of an analytics provider, `None` for the ones it doesn't use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct AnalyticsPoolStats {
+    pub sqlx: Option<PoolStats>,
+    pub clickhouse: Option<PoolStats>,
+}
+
+/// Number of queries in some state, e.g. waiting for a connection. A query is counted for as long
+/// as it holds the guard returned by [`Gauge::track`].
+#[derive(Debug, Default)]
+pub(crate) struct Gauge(AtomicU32);
+
+/// Counts its query in a [`Gauge`] until dropped
+#[derive(Debug)]
+pub(crate) struct GaugeGuard(Arc<Gauge>);
+
+impl Gauge {
+    pub(crate) fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn track(self: &Arc<Self>) -> GaugeGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(Arc::clone(self))
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 pub trait LoadRow<T>
@@ -91,17 +527,70 @@ where
     fn load_row(row: Self::Row) -> CustomResult<T, QueryExecutionError>;
 }
 
-#[derive(thiserror::Error, Debug)]
+/// Why loading a metric failed, the report carries the details. The snake case name of a variant
+/// is the kind a failed query is counted as.
+///
+/// The errors of the data sources are switched to the variant naming their cause where there is
+/// one. The errors of the query builder are switched to [`Self::QueryBuildingError`], the sdk
+/// event metrics name their cause instead, see [`Self::BadQuery`] and [`Self::Unsupported`].
+#[derive(thiserror::Error, Debug, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum MetricsError {
     #[error("Error building query")]
     QueryBuildingError,
+    /// The data source ran the query and failed, or returned rows which couldn't be read
     #[error("Error running Query")]
     QueryExecutionFailure,
+    /// The data source couldn't be reached, the query may succeed once it's sent again
+    #[error("Failed to connect to the data source")]
+    ConnectionFailed,
     #[error("Error processing query results")]
     PostProcessingFailure,
     #[allow(dead_code)]
     #[error("Not Implemented")]
     NotImplemented,
+    #[error("Query timed out")]
+    QueryTimeout,
+    #[error("Query would read too many rows")]
+    QueryTooLarge,
+    #[error("Metric can't be grouped by the {0} dimension")]
+    UnsupportedDimension(String),
+    #[error("Too many queries of the merchant are already running")]
+    TooManyQueries,
+    #[error("Invalid time range: {0}")]
+    InvalidTimeRange(String),
+    /// A filter value which no event can have, e.g. an unknown event name
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("Metrics can't be combined: {0}")]
+    IncompatibleMetrics(String),
+    #[error("The {0} metric isn't supported by this query")]
+    UnsupportedMetric(String),
+    #[error("Invalid funnel stages: {0}")]
+    InvalidFunnelStages(String),
+    #[error("Invalid computed dimension: {0}")]
+    InvalidComputedDimension(String),
+    #[error("Subtotals can't be returned: {0}")]
+    UnsupportedSubtotals(String),
+    /// The query would read the events of every merchant or from the start of time, which a
+    /// metric query never should
+    #[error("Query isn't scoped to a merchant and time range")]
+    UnscopedQuery,
+    /// The query would add up the amounts of payments in different currencies
+    #[error("Amounts can't be summed across currencies: {0}")]
+    MixedCurrencies(String),
+    /// A metric query was run without one of the fields it can't be run without
+    #[error("Metric query is missing its {0}")]
+    IncompleteQuery(String),
+    /// The query asked for something which can't be built, e.g. a label which can't be inlined
+    #[error("Invalid query: {0}")]
+    BadQuery(String),
+    /// The query asked for something the data source doesn't support
+    #[error("Not supported: {0}")]
+    Unsupported(String),
+    /// An error none of the other variants describe
+    #[error("Unexpected error")]
+    Other,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -110,6 +599,12 @@ pub enum QueryExecutionError {
     RowExtractionFailure,
     #[error("Database error")]
     DatabaseError,
+    #[error("Query timed out")]
+    Timeout,
+    #[error("Query would read too many rows")]
+    TooLarge,
+    #[error("Failed to connect to the data source")]
+    ConnectionFailure,
 }
 
 pub type MetricsResult<T> = CustomResult<T, MetricsError>;
@@ -120,6 +615,17 @@ impl ErrorSwitch<MetricsError> for QueryBuildingError {
     }
 }
 
+impl ErrorSwitch<MetricsError> for QueryExecutionError {
+    fn switch(&self) -> MetricsError {
+        match self {
+            Self::Timeout => MetricsError::QueryTimeout,
+            Self::TooLarge => MetricsError::QueryTooLarge,
+            Self::ConnectionFailure => MetricsError::ConnectionFailed,
+            Self::RowExtractionFailure | Self::DatabaseError => MetricsError::QueryExecutionFailure,
+        }
+    }
+}
+
 pub type FiltersResult<T> = CustomResult<T, FiltersError>;
 
 #[derive(thiserror::Error, Debug)]
@@ -3,11 +3,13 @@ use api_models::analytics::{
     disputes::{DisputeDimensions, DisputeMetrics},
     payments::{PaymentDimensions, PaymentMetrics},
     refunds::{RefundDimensions, RefundMetrics},
-    sdk_events::{SdkEventDimensions, SdkEventMetrics},
+    sdk_events::{SdkEventDimensions, SdkEventMetricMetadata, SdkEventMetrics},
     NameDescription,
 };
 use strum::IntoEnumIterator;
 
+use crate::sdk_events::{accumulator, metrics};
+
 pub fn get_payment_dimensions() -> Vec<NameDescription> {
     PaymentDimensions::iter().map(Into::into).collect()
 }
@@ -36,6 +38,19 @@ pub fn get_sdk_event_metrics_info() -> Vec<NameDescription> {
     SdkEventMetrics::iter().map(Into::into).collect()
 }
 
+/// Dimensions, filters and output fields of every sdk event metric, for clients to build their
+/// queries from
+pub fn get_sdk_event_metrics_metadata() -> Vec<SdkEventMetricMetadata> {
+    SdkEventMetrics::iter()
+        .map(|metric| SdkEventMetricMetadata {
+            dimensions: metrics::supported_dimensions(&metric),
+            filters: metrics::supported_filters(&metric),
+            output_fields: accumulator::output_fields(&metric).to_vec(),
+            name: metric,
+        })
+        .collect()
+}
+
 pub fn get_api_event_metrics_info() -> Vec<NameDescription> {
     ApiEventMetrics::iter().map(Into::into).collect()
 }
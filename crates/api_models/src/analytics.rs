@@ -54,6 +54,68 @@ pub enum Granularity {
     OneHour,
     #[serde(rename = "G_ONEDAY")]
     OneDay,
+    #[serde(rename = "G_WEEKLY")]
+    Weekly,
+    #[serde(rename = "G_MONTHLY")]
+    Monthly,
+    #[serde(rename = "G_QUARTERLY")]
+    Quarterly,
+}
+
+/// A range of whole days named relative to the current day of a time zone
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelativeTimeRange {
+    Today,
+    Yesterday,
+    /// Today and the six days before it
+    LastSevenDays,
+    /// Every day of the current month, including the ones still to come
+    ThisMonth,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    Equal,
+}
+
+/// Condition on the number of events in a group, applied after aggregation
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct CountThreshold {
+    pub comparison: Comparison,
+    pub value: u64,
+}
+
+/// Bounds of a numeric value, each of them is optional so that the range can be open ended
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct NumericRange {
+    pub gte: Option<u64>,
+    pub lte: Option<u64>,
+    pub gt: Option<u64>,
+    pub lt: Option<u64>,
+}
+
+/// Hours of every day from `start_hour` up to `end_hour`, e.g. `12` to `14` for 12:00 to 13:59. A
+/// window ending before it starts wraps around midnight, `22` to `2` is 22:00 to 01:59.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct HourWindow {
+    /// `0` to `23`
+    pub start_hour: u8,
+    /// `0` to `24`, other than `start_hour`
+    pub end_hour: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Ascending,
+    #[default]
+    Descending,
 }
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
@@ -129,6 +191,8 @@ pub struct GetRefundMetricRequest {
 pub struct GetSdkEventMetricRequest {
     pub time_series: Option<TimeSeries>,
     pub time_range: TimeRange,
+    /// Buckets are returned in time order, the buckets of a time bucket are sorted by these
+    /// dimensions in the order they're listed, each ascending with missing values last
     #[serde(default)]
     pub group_by_names: Vec<SdkEventDimensions>,
     #[serde(default)]
@@ -136,11 +200,108 @@ pub struct GetSdkEventMetricRequest {
     pub metrics: HashSet<SdkEventMetrics>,
     #[serde(default)]
     pub delta: bool,
+    /// IANA time zone (e.g. `Asia/Kolkata`) the time buckets are aligned to, defaults to UTC
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    /// Only return the groups whose event count satisfies this condition
+    #[serde(default)]
+    pub count_threshold: Option<CountThreshold>,
+    /// Cancel each metric query that takes longer than this many milliseconds
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Only return this page of the groups of each metric
+    #[serde(default)]
+    pub pagination: Option<sdk_events::SdkEventPagination>,
+    /// Return a zero valued bucket for every time bucket of the range a group has no events in
+    #[serde(default)]
+    pub fill_gaps: bool,
+    /// Only return this many groups with the most events of each metric, the events of the
+    /// other groups are returned as a single group labelled `other` in every dimension
+    #[serde(default)]
+    pub top_groups: Option<u32>,
+    /// Approximate the metrics from this fraction of the events, greater than 0 and at most 1,
+    /// instead of reading every event. The counts are scaled back up to all events.
+    #[serde(default)]
+    pub sample_rate: Option<f64>,
+    /// Round the total of each bucket to this many decimals, it's returned with its full
+    /// precision otherwise
+    #[serde(default)]
+    pub decimal_places: Option<u32>,
+    /// Funnel stages of the funnel waterfall in the order they're reached, defaults to every
+    /// stage in the order of the sdk flow
+    #[serde(default)]
+    pub funnel_stages: Option<Vec<sdk_events::FunnelStage>>,
+    /// Label of the group of the events without a value of a dimension, e.g. without a device
+    /// type, defaults to `__null__`. Up to 64 ASCII letters, digits, `_`, `-` and `.`.
+    #[serde(default)]
+    pub null_label: Option<String>,
+    /// Also group the events by this expression over their dimensions and `amount`, e.g.
+    /// `CASE WHEN amount < 1000 THEN 'small' ELSE 'large' END`. Only a restricted SQL grammar of
+    /// numbers, strings, columns, operators, `CASE` and a few functions is accepted, its value is
+    /// returned as the `computed_dimension` of each bucket.
+    #[serde(default)]
+    pub computed_dimension: Option<String>,
+    /// Also return the subtotals of the groups, rolling up the grouped dimensions from the last
+    /// one to the first: e.g. the total of each browser when grouping by browser and os, and the
+    /// total of every group. A subtotal has no value for the dimensions it adds up and the
+    /// number of them as its `subtotal_level`.
+    #[serde(default)]
+    pub subtotals: bool,
+    /// Whether the percentiles of the metrics having them are computed for each time bucket of
+    /// the time series, the default, or once over the whole time range for each group
+    #[serde(default)]
+    pub percentile_bucketing: sdk_events::PercentileBucketing,
+    /// Count each event once even if it was ingested several times: the count metrics count the
+    /// distinct event ids of their events rather than every row. Exact distinct counts are
+    /// costlier than counting rows on large ranges, so it's off by default.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// Also read the archived sdk events, for a time range reaching back past the recent ones.
+    /// The events of both are aggregated together, a bucket with events in both is returned once.
+    #[serde(default)]
+    pub include_archived: bool,
+    /// Targets of the metrics, which each bucket returns along its values so that they can be
+    /// shown over or under target. The targets of metrics which aren't requested are ignored.
+    #[serde(default)]
+    pub targets: Vec<sdk_events::SdkEventMetricTarget>,
+    /// Return the statistics of every query run for the metrics, the rows it read and returned
+    /// and how long it took, along the metrics. Reading them costs the data source some work on
+    /// top of the query, so they're off by default.
+    #[serde(default)]
+    pub include_query_stats: bool,
 }
 
 #[derive(Debug, serde::Serialize)]
 pub struct AnalyticsMetadata {
     pub current_time_range: TimeRange,
+    /// Fraction of the events approximate metrics were computed from, absent if they're exact
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+    /// Whether a metric had more buckets than the data source returns, only its first ones in
+    /// the order of the query are returned then
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Statistics of the queries run for the metrics, only returned when they're requested
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub query_stats: Vec<QueryStats>,
+}
+
+/// What running a query took
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct QueryStats {
+    /// Fingerprint of the query, the same for the queries differing only in their literals
+    pub query_fingerprint: String,
+    /// Rows the data source read to answer the query, absent if it doesn't report them
+    pub scanned_rows: Option<u64>,
+    /// Bytes the data source read to answer the query, absent if it doesn't report them
+    pub scanned_bytes: Option<u64>,
+    /// Rows the query returned
+    pub result_rows: u64,
+    /// Time from sending the query to having all of its rows
+    pub elapsed_ms: u64,
+    /// Whether a secondary data source answered the query as the primary one couldn't be reached
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub served_by_fallback: bool,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -206,6 +367,8 @@ pub struct SdkEventFiltersResponse {
 pub struct SdkEventFilterValue {
     pub dimension: SdkEventDimensions,
     pub values: Vec<String>,
+    /// The dimension has more values than the ones listed
+    pub truncated: bool,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -1,9 +1,15 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fmt,
     hash::{Hash, Hasher},
+    str::FromStr,
 };
 
-use super::{NameDescription, TimeRange};
+use common_utils::errors::ParsingError;
+use error_stack::{report, ResultExt};
+use strum::IntoEnumIterator;
+
+use super::{HourWindow, NameDescription, NumericRange, SortDirection, TimeRange};
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -12,6 +18,43 @@ pub struct SdkEventsRequest {
     pub time_range: TimeRange,
 }
 
+/// Request for a sample of the raw events behind a bucket of a metric
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SdkEventSampleRequest {
+    /// Bucket whose events are sampled, only its values of `group_by_names` are matched
+    pub bucket: SdkEventMetricsBucketIdentifier,
+    /// Dimensions the metric was grouped by. An event matches the bucket's value of each of them,
+    /// or has none where the bucket has none.
+    #[serde(default)]
+    pub group_by_names: Vec<SdkEventDimensions>,
+    #[serde(default)]
+    pub filters: SdkEventFilters,
+    /// Part of the metric's time range to sample from, e.g. the span of the bucket's time bucket
+    pub time_range: TimeRange,
+    /// IANA time zone the bucket's day of week and hour of day are on, defaults to UTC
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    /// Label the metric gave the missing values of the dimensions, defaults to `__null__`
+    #[serde(default)]
+    pub null_label: Option<String>,
+    /// Most events returned, larger limits are capped
+    pub limit: u32,
+}
+
+/// Request for the metrics of the last few minutes up to now, each over the whole window, for a
+/// live view polling them. They're always loaded fresh rather than from a cache.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SdkEventRecentMetricsRequest {
+    pub metrics: std::collections::HashSet<SdkEventMetrics>,
+    #[serde(default)]
+    pub filters: SdkEventFilters,
+    /// Minutes up to now the metrics are taken over, defaults to 5. Longer windows are capped.
+    #[serde(default)]
+    pub window_mins: Option<u32>,
+}
+
 #[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct SdkEventFilters {
     #[serde(default)]
@@ -26,6 +69,77 @@ pub struct SdkEventFilters {
     pub component: Vec<String>,
     #[serde(default)]
     pub payment_experience: Vec<String>,
+    #[serde(default)]
+    pub sdk_version: Vec<String>,
+    #[serde(default)]
+    pub device_type: Vec<String>,
+    #[serde(default)]
+    pub os: Vec<String>,
+    #[serde(default)]
+    pub country: Vec<String>,
+    #[serde(default)]
+    pub connector: Vec<String>,
+    /// Matches the events with one of these names, names no event has match nothing
+    #[serde(default)]
+    pub event_name: Vec<String>,
+    /// Matches the events with one of these payment method types, events without one are
+    /// matched by the null label, `__null__` by default
+    #[serde(default)]
+    pub payment_method_type: Vec<String>,
+    /// Matches the events of these days of the week, `1` for Monday to `7` for Sunday
+    #[serde(default)]
+    pub day_of_week: Vec<String>,
+    /// Matches the events of these hours of the day, `0` to `23`
+    #[serde(default)]
+    pub hour_of_day: Vec<String>,
+    /// Matches the events within one of these windows of hours on any day of the time range, in
+    /// the time zone of the request like the hour of day
+    #[serde(default)]
+    pub hour_windows: Vec<HourWindow>,
+    /// Matches the events with one of these authentication error reasons, events without one are
+    /// matched by the null label, `__null__` by default
+    #[serde(default)]
+    pub authentication_error_reason: Vec<String>,
+    /// Matches the events of one of these payment input fields, events without a field aren't
+    /// matched
+    #[serde(default)]
+    pub field_name: Vec<String>,
+    /// Matches the events of the payments of customers in one of these segments, events of
+    /// payments without a segment aren't matched
+    #[serde(default)]
+    pub customer_segment: Vec<String>,
+    /// Matches the events served from one of these CDN regions, events without a region aren't
+    /// matched
+    #[serde(default)]
+    pub cdn_region: Vec<String>,
+    /// Matches the events of the payments in one of these currencies, e.g. `USD`
+    #[serde(default)]
+    pub currency: Vec<String>,
+    /// Matches the events carrying one of these abandonment reasons, events without one aren't
+    /// matched. The abandoned sessions count matches the sessions with one of them instead.
+    #[serde(default)]
+    pub abandonment_reason: Vec<String>,
+    /// Matches the events whose payment amount, in the minor unit of its currency, is within
+    /// this range
+    #[serde(default)]
+    pub amount: Option<NumericRange>,
+    /// Leaves out the events of the sessions flagged as automated by their `is_bot` column:
+    /// synthetic monitoring, crawlers and headless browsers, classified by the user agent the
+    /// sdk was loaded with. The events logged without the flag are left out as well. Off by
+    /// default, so that every session is counted.
+    #[serde(default)]
+    pub exclude_bots: bool,
+    /// Matches the events which match at least one of these groups, in addition to the filters
+    /// above. The filters of a group combine the same way as the ones above.
+    #[serde(default)]
+    pub any_of: Vec<SdkEventFilters>,
+    /// Matches the events which match every one of these groups
+    #[serde(default)]
+    pub all_of: Vec<SdkEventFilters>,
+    /// Matches the events which match none of these groups. An event without a value for a
+    /// dimension a group filters on doesn't match that group.
+    #[serde(default)]
+    pub none_of: Vec<SdkEventFilters>,
 }
 
 #[derive(
@@ -53,6 +167,34 @@ pub enum SdkEventDimensions {
     Source,
     Component,
     PaymentExperience,
+    SdkVersion,
+    /// One of `mobile`, `tablet` or `desktop`
+    DeviceType,
+    /// Operating system along with its version, e.g. `Android 14`
+    Os,
+    Country,
+    Connector,
+    EventName,
+    PaymentMethodType,
+    /// Day of the week of the event in the requested time zone, `1` for Monday to `7` for Sunday
+    DayOfWeek,
+    /// Hour of the day of the event in the requested time zone, `0` to `23`
+    HourOfDay,
+    /// Reason code of an unsuccessful authentication, e.g. the 3DS error of the issuer
+    AuthenticationErrorReason,
+    /// Payment input field the event is about, e.g. `card_number` or `cvv`
+    FieldName,
+    /// Segment of the customer of the event's payment, e.g. `new` or `returning`. It's joined
+    /// from the customer segments rather than stored on the event.
+    CustomerSegment,
+    /// CDN region the sdk was served from, e.g. `ap-south-1`. Only the load failures carry it.
+    CdnRegion,
+    /// ISO 4217 code of the currency of the event's payment, e.g. `USD`
+    Currency,
+    /// Reason the sdk recorded for the checkout being abandoned, e.g. `modal_closed`,
+    /// `navigated_away` or `timeout`. Only some events carry it, in the JSON object of their
+    /// value.
+    AbandonmentReason,
 }
 
 #[derive(
@@ -84,6 +226,23 @@ pub enum SdkEventMetrics {
     PaymentMethodSelectedCount,
     PaymentDataFilledCount,
     AveragePaymentTime,
+    SdkErrorEncounteredCount,
+    SessionDurationSeconds,
+    FunnelConversionRate,
+    UniqueSessionsCount,
+    PaymentSuccessRate,
+    PaymentRetryCount,
+    ThreeDsFlowBreakdown,
+    SdkBounceRate,
+    PaymentExperienceBreakdown,
+    TimeToFirstRender,
+    FunnelWaterfall,
+    SdkLoadFailureCount,
+    AveragePaymentMethodsDisplayed,
+    PaymentAttemptedAmount,
+    LatencyBreakdown,
+    ThreeDsMethodSuccessRate,
+    AbandonedSessionsCount,
 }
 
 #[derive(
@@ -119,6 +278,32 @@ pub enum SdkEventNames {
     ThreeDsMethod,
     LoaderChanged,
     DisplayThreeDsSdk,
+    /// The sdk script or one of its resources failed to load, e.g. since the CDN was unreachable
+    SdkLoadFailed,
+}
+
+/// Stage of the sdk funnel, reached with the first event of its name in a session
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Hash,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    strum::Display,
+    strum::EnumIter,
+    strum::AsRefStr,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum FunnelStage {
+    Initiated,
+    Rendered,
+    Selected,
+    Filled,
+    Attempted,
 }
 
 pub mod metric_behaviour {
@@ -136,6 +321,23 @@ pub mod metric_behaviour {
     pub struct PaymentMethodSelectedCount;
     pub struct PaymentDataFilledCount;
     pub struct AveragePaymentTime;
+    pub struct SdkErrorEncounteredCount;
+    pub struct SessionDurationSeconds;
+    pub struct FunnelConversionRate;
+    pub struct UniqueSessionsCount;
+    pub struct PaymentSuccessRate;
+    pub struct PaymentRetryCount;
+    pub struct ThreeDsFlowBreakdown;
+    pub struct SdkBounceRate;
+    pub struct PaymentExperienceBreakdown;
+    pub struct TimeToFirstRender;
+    pub struct FunnelWaterfall;
+    pub struct SdkLoadFailureCount;
+    pub struct AveragePaymentMethodsDisplayed;
+    pub struct PaymentAttemptedAmount;
+    pub struct LatencyBreakdown;
+    pub struct ThreeDsMethodSuccessRate;
+    pub struct AbandonedSessionsCount;
 }
 
 impl From<SdkEventMetrics> for NameDescription {
@@ -156,7 +358,85 @@ impl From<SdkEventDimensions> for NameDescription {
     }
 }
 
-#[derive(Debug, serde::Serialize, Eq)]
+/// What the groups of a metric are sorted by before they're paginated
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", content = "dimension", rename_all = "snake_case")]
+pub enum SdkEventOrderBy {
+    /// Number of events in the group, or of sessions for the metrics computed per session
+    EventCount,
+    /// Value of a dimension, which has to be one of the grouped by dimensions
+    Dimension(SdkEventDimensions),
+}
+
+/// Page of the groups of a metric. Groups which sort the same are ordered by their dimensions,
+/// so that pages don't overlap.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SdkEventPagination {
+    pub order_by: SdkEventOrderBy,
+    #[serde(default)]
+    pub direction: SortDirection,
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// Which events the percentiles of a metric, e.g. the P95 payment time, are computed over.
+/// Percentiles can't be merged, the percentile of the percentiles of each time bucket isn't the
+/// percentile of the whole range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PercentileBucketing {
+    /// The events of each time bucket of a time series, like every other metric
+    #[default]
+    PerBucket,
+    /// Every event of the time range, returning a single bucket without a time bucket for each
+    /// group even in a time series
+    WholeRange,
+}
+
+/// Which side of its target the value of a metric should be on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetDirection {
+    /// The values at or above the target meet it, e.g. of a success rate
+    #[default]
+    AtLeast,
+    /// The values at or below the target meet it, e.g. of a bounce rate or a payment time
+    AtMost,
+}
+
+/// Target of a metric its value in each bucket is compared to, e.g. a payment success rate of at
+/// least `0.95`. Targets are given by the caller, the data source has none.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SdkEventMetricTarget {
+    pub metric: SdkEventMetrics,
+    /// Value the metric is compared to. A metric returning several values is compared on a single
+    /// one of them: the average of the metrics timing or counting per session, and the
+    /// conversion of the whole funnel.
+    pub target: f64,
+    #[serde(default)]
+    pub direction: TargetDirection,
+    /// Values of the dimensions a bucket has to have for the target to apply, e.g.
+    /// `{"platform": "ios"}`, a target without any applies to every bucket. A bucket gets the
+    /// target of its metric matching the most of its dimensions, the first listed of them if
+    /// several match as many.
+    #[serde(default)]
+    pub dimensions: BTreeMap<SdkEventDimensions, String>,
+}
+
+/// Value of a metric in a bucket along the target it's compared to
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct SdkEventTargetResult {
+    pub metric: SdkEventMetrics,
+    pub target: f64,
+    pub direction: TargetDirection,
+    /// Value of the metric the target is compared to, absent if the bucket has none
+    pub value: Option<f64>,
+    /// Whether the value meets the target, absent without a value
+    pub meets_target: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, Eq)]
 pub struct SdkEventMetricsBucketIdentifier {
     pub payment_method: Option<String>,
     pub platform: Option<String>,
@@ -164,10 +444,31 @@ pub struct SdkEventMetricsBucketIdentifier {
     pub source: Option<String>,
     pub component: Option<String>,
     pub payment_experience: Option<String>,
+    pub sdk_version: Option<String>,
+    pub device_type: Option<String>,
+    pub os: Option<String>,
+    pub country: Option<String>,
+    pub connector: Option<String>,
+    pub event_name: Option<String>,
+    pub payment_method_type: Option<String>,
+    pub day_of_week: Option<String>,
+    pub hour_of_day: Option<String>,
+    pub authentication_error_reason: Option<String>,
+    pub field_name: Option<String>,
+    pub customer_segment: Option<String>,
+    pub cdn_region: Option<String>,
+    pub currency: Option<String>,
+    pub abandonment_reason: Option<String>,
+    /// Value of the computed dimension of the request, if it has one
+    pub computed_dimension: Option<String>,
+    /// Number of the grouped dimensions, from the last one, the bucket is a subtotal over. `None`
+    /// for the bucket of a single group.
+    pub subtotal_level: Option<u32>,
     pub time_bucket: Option<String>,
 }
 
 impl SdkEventMetricsBucketIdentifier {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         payment_method: Option<String>,
         platform: Option<String>,
@@ -175,6 +476,21 @@ impl SdkEventMetricsBucketIdentifier {
         source: Option<String>,
         component: Option<String>,
         payment_experience: Option<String>,
+        sdk_version: Option<String>,
+        device_type: Option<String>,
+        os: Option<String>,
+        country: Option<String>,
+        connector: Option<String>,
+        event_name: Option<String>,
+        payment_method_type: Option<String>,
+        day_of_week: Option<String>,
+        hour_of_day: Option<String>,
+        authentication_error_reason: Option<String>,
+        field_name: Option<String>,
+        customer_segment: Option<String>,
+        cdn_region: Option<String>,
+        currency: Option<String>,
+        abandonment_reason: Option<String>,
         time_bucket: Option<String>,
     ) -> Self {
         Self {
@@ -184,9 +500,186 @@ impl SdkEventMetricsBucketIdentifier {
             source,
             component,
             payment_experience,
+            sdk_version,
+            device_type,
+            os,
+            country,
+            connector,
+            event_name,
+            payment_method_type,
+            day_of_week,
+            hour_of_day,
+            authentication_error_reason,
+            field_name,
+            customer_segment,
+            cdn_region,
+            currency,
+            abandonment_reason,
+            computed_dimension: None,
+            subtotal_level: None,
             time_bucket,
         }
     }
+
+    /// Value of `dimension` of the bucket's group
+    pub fn dimension(&self, dimension: &SdkEventDimensions) -> Option<&str> {
+        match dimension {
+            SdkEventDimensions::PaymentMethod => &self.payment_method,
+            SdkEventDimensions::Platform => &self.platform,
+            SdkEventDimensions::BrowserName => &self.browser_name,
+            SdkEventDimensions::Source => &self.source,
+            SdkEventDimensions::Component => &self.component,
+            SdkEventDimensions::PaymentExperience => &self.payment_experience,
+            SdkEventDimensions::SdkVersion => &self.sdk_version,
+            SdkEventDimensions::DeviceType => &self.device_type,
+            SdkEventDimensions::Os => &self.os,
+            SdkEventDimensions::Country => &self.country,
+            SdkEventDimensions::Connector => &self.connector,
+            SdkEventDimensions::EventName => &self.event_name,
+            SdkEventDimensions::PaymentMethodType => &self.payment_method_type,
+            SdkEventDimensions::DayOfWeek => &self.day_of_week,
+            SdkEventDimensions::HourOfDay => &self.hour_of_day,
+            SdkEventDimensions::AuthenticationErrorReason => &self.authentication_error_reason,
+            SdkEventDimensions::FieldName => &self.field_name,
+            SdkEventDimensions::CustomerSegment => &self.customer_segment,
+            SdkEventDimensions::CdnRegion => &self.cdn_region,
+            SdkEventDimensions::Currency => &self.currency,
+            SdkEventDimensions::AbandonmentReason => &self.abandonment_reason,
+        }
+        .as_deref()
+    }
+
+    /// Value of `dimension` of the bucket's group, to be changed
+    pub fn dimension_mut(&mut self, dimension: &SdkEventDimensions) -> &mut Option<String> {
+        match dimension {
+            SdkEventDimensions::PaymentMethod => &mut self.payment_method,
+            SdkEventDimensions::Platform => &mut self.platform,
+            SdkEventDimensions::BrowserName => &mut self.browser_name,
+            SdkEventDimensions::Source => &mut self.source,
+            SdkEventDimensions::Component => &mut self.component,
+            SdkEventDimensions::PaymentExperience => &mut self.payment_experience,
+            SdkEventDimensions::SdkVersion => &mut self.sdk_version,
+            SdkEventDimensions::DeviceType => &mut self.device_type,
+            SdkEventDimensions::Os => &mut self.os,
+            SdkEventDimensions::Country => &mut self.country,
+            SdkEventDimensions::Connector => &mut self.connector,
+            SdkEventDimensions::EventName => &mut self.event_name,
+            SdkEventDimensions::PaymentMethodType => &mut self.payment_method_type,
+            SdkEventDimensions::DayOfWeek => &mut self.day_of_week,
+            SdkEventDimensions::HourOfDay => &mut self.hour_of_day,
+            SdkEventDimensions::AuthenticationErrorReason => &mut self.authentication_error_reason,
+            SdkEventDimensions::FieldName => &mut self.field_name,
+            SdkEventDimensions::CustomerSegment => &mut self.customer_segment,
+            SdkEventDimensions::CdnRegion => &mut self.cdn_region,
+            SdkEventDimensions::Currency => &mut self.currency,
+            SdkEventDimensions::AbandonmentReason => &mut self.abandonment_reason,
+        }
+    }
+}
+
+impl SdkEventMetricsBucketIdentifier {
+    /// The bucket of `time_bucket` of the group without a value for any dimension, which
+    /// [`Self::with_dimension`] gives values
+    pub fn of_time_bucket(time_bucket: Option<String>) -> Self {
+        Self {
+            time_bucket,
+            ..Default::default()
+        }
+    }
+
+    /// The bucket with `value` for `dimension`
+    pub fn with_dimension(
+        mut self,
+        dimension: SdkEventDimensions,
+        value: impl Into<String>,
+    ) -> Self {
+        *self.dimension_mut(&dimension) = Some(value.into());
+        self
+    }
+
+    /// The dimensions the bucket's group has a value for, in the order they're declared
+    pub fn dimension_values(&self) -> impl Iterator<Item = (SdkEventDimensions, &str)> + '_ {
+        SdkEventDimensions::iter()
+            .filter_map(|dimension| Some((dimension, self.dimension(&dimension)?)))
+    }
+}
+
+/// Key of a bucket, which is the same for every bucket of the same group and time bucket: the
+/// form urlencoded time bucket, values of the dimensions in the order they're declared, computed
+/// dimension and subtotal level, e.g. `time_bucket=2024-03-01+00%3A00%3A00&browser_name=Chrome`.
+/// The values a bucket doesn't have are left out, an empty value is kept. [`FromStr`] reads the
+/// bucket back from its key.
+impl fmt::Display for SdkEventMetricsBucketIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut key = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(time_bucket) = &self.time_bucket {
+            key.append_pair(TIME_BUCKET_KEY, time_bucket);
+        }
+        for (dimension, value) in self.dimension_values() {
+            key.append_pair(dimension.as_ref(), value);
+        }
+        if let Some(computed_dimension) = &self.computed_dimension {
+            key.append_pair(COMPUTED_DIMENSION_KEY, computed_dimension);
+        }
+        if let Some(subtotal_level) = self.subtotal_level {
+            key.append_pair(SUBTOTAL_LEVEL_KEY, &subtotal_level.to_string());
+        }
+        f.write_str(&key.finish())
+    }
+}
+
+const TIME_BUCKET_KEY: &str = "time_bucket";
+const COMPUTED_DIMENSION_KEY: &str = "computed_dimension";
+const SUBTOTAL_LEVEL_KEY: &str = "subtotal_level";
+
+impl FromStr for SdkEventMetricsBucketIdentifier {
+    type Err = error_stack::Report<ParsingError>;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let mut id = Self::default();
+        for (name, value) in url::form_urlencoded::parse(key.as_bytes()) {
+            let field = match name.as_ref() {
+                TIME_BUCKET_KEY => &mut id.time_bucket,
+                COMPUTED_DIMENSION_KEY => &mut id.computed_dimension,
+                SUBTOTAL_LEVEL_KEY => {
+                    if id.subtotal_level.is_some() {
+                        return Err(report!(ParsingError::StructParseFailure(
+                            "SdkEventMetricsBucketIdentifier"
+                        )))
+                        .attach_printable("Subtotal level given twice");
+                    }
+                    id.subtotal_level = Some(
+                        value
+                            .parse()
+                            .change_context(ParsingError::StructParseFailure(
+                                "SdkEventMetricsBucketIdentifier",
+                            ))
+                            .attach_printable_lazy(|| format!("Invalid subtotal level {value}"))?,
+                    );
+                    continue;
+                }
+                name => {
+                    let dimension = SdkEventDimensions::iter()
+                        .find(|dimension| dimension.as_ref() == name)
+                        .ok_or_else(|| {
+                            report!(ParsingError::StructParseFailure(
+                                "SdkEventMetricsBucketIdentifier"
+                            ))
+                        })
+                        .attach_printable_lazy(|| format!("Unknown dimension {name}"))?;
+                    id.dimension_mut(&dimension)
+                }
+            };
+            if field.is_some() {
+                return Err(report!(ParsingError::StructParseFailure(
+                    "SdkEventMetricsBucketIdentifier"
+                )))
+                .attach_printable_lazy(|| format!("{name} given twice"));
+            }
+            *field = Some(value.into_owned());
+        }
+        Ok(id)
+    }
 }
 
 impl Hash for SdkEventMetricsBucketIdentifier {
@@ -197,6 +690,23 @@ impl Hash for SdkEventMetricsBucketIdentifier {
         self.source.hash(state);
         self.component.hash(state);
         self.payment_experience.hash(state);
+        self.sdk_version.hash(state);
+        self.device_type.hash(state);
+        self.os.hash(state);
+        self.country.hash(state);
+        self.connector.hash(state);
+        self.event_name.hash(state);
+        self.payment_method_type.hash(state);
+        self.day_of_week.hash(state);
+        self.hour_of_day.hash(state);
+        self.authentication_error_reason.hash(state);
+        self.field_name.hash(state);
+        self.customer_segment.hash(state);
+        self.cdn_region.hash(state);
+        self.currency.hash(state);
+        self.abandonment_reason.hash(state);
+        self.computed_dimension.hash(state);
+        self.subtotal_level.hash(state);
         self.time_bucket.hash(state);
     }
 }
@@ -216,6 +726,15 @@ pub struct SdkEventMetricsBucketValue {
     pub payment_attempts: Option<u64>,
     pub payment_methods_call_count: Option<u64>,
     pub average_payment_time: Option<f64>,
+    pub payment_time_min: Option<f64>,
+    pub payment_time_max: Option<f64>,
+    pub payment_time_median: Option<f64>,
+    pub payment_time_stddev: Option<f64>,
+    pub payment_time_variance: Option<f64>,
+    pub payment_time_p50: Option<f64>,
+    pub payment_time_p90: Option<f64>,
+    pub payment_time_p95: Option<f64>,
+    pub payment_time_p99: Option<f64>,
     pub sdk_rendered_count: Option<u64>,
     pub sdk_initiated_count: Option<u64>,
     pub payment_method_selected_count: Option<u64>,
@@ -227,6 +746,87 @@ pub struct SdkEventMetricsBucketValue {
     pub authentication_unsuccessful_count: Option<u64>,
     pub three_ds_challenge_flow_count: Option<u64>,
     pub three_ds_frictionless_flow_count: Option<u64>,
+    pub sdk_error_encountered_count: Option<u64>,
+    pub average_session_duration_seconds: Option<f64>,
+    pub session_duration_p50: Option<f64>,
+    pub session_duration_p90: Option<f64>,
+    pub session_duration_p95: Option<f64>,
+    pub session_duration_p99: Option<f64>,
+    pub funnel_initiated: Option<u64>,
+    pub funnel_rendered: Option<u64>,
+    pub funnel_selected: Option<u64>,
+    pub funnel_filled: Option<u64>,
+    pub funnel_attempted: Option<u64>,
+    pub initiated_to_rendered_rate: Option<f64>,
+    pub rendered_to_selected_rate: Option<f64>,
+    pub selected_to_filled_rate: Option<f64>,
+    pub filled_to_attempted_rate: Option<f64>,
+    pub unique_sessions_count: Option<u64>,
+    /// Successful payments per attempt, null when there were no attempts
+    pub payment_success_rate: Option<f64>,
+    /// Attempts a session made after its first one, over the sessions with an attempt
+    pub average_payment_retries: Option<f64>,
+    pub max_payment_retries: Option<u64>,
+    pub payment_retries_p50: Option<f64>,
+    pub payment_retries_p90: Option<f64>,
+    pub payment_retries_p95: Option<f64>,
+    pub payment_retries_p99: Option<f64>,
+    pub three_ds_challenge_count: Option<u64>,
+    pub three_ds_frictionless_count: Option<u64>,
+    /// Challenged flows over all 3DS flows, null when there were no flows
+    pub three_ds_challenge_rate: Option<f64>,
+    /// Initiations which were never rendered over all initiations, null without initiations
+    pub sdk_bounce_rate: Option<f64>,
+    /// Payment attempts of the bucket's payment experience
+    pub payment_experience_attempt_count: Option<u64>,
+    /// Milliseconds from the first initiation of a session to its first render, over the
+    /// sessions which were rendered
+    pub average_time_to_first_render_ms: Option<f64>,
+    pub time_to_first_render_p50: Option<f64>,
+    pub time_to_first_render_p90: Option<f64>,
+    pub time_to_first_render_p95: Option<f64>,
+    pub time_to_first_render_p99: Option<f64>,
+    /// Every requested funnel stage in the requested order, with the drop from the stage
+    /// before it
+    pub funnel_waterfall: Option<Vec<FunnelWaterfallStage>>,
+    /// Sessions in which the sdk failed to load
+    pub sdk_load_failure_count: Option<u64>,
+    /// Payment methods displayed per render of the sdk, over the renders which reported them
+    pub average_payment_methods_displayed: Option<f64>,
+    /// Sum of the amounts of the attempted payments, in the minor unit of their currency. Only
+    /// returned for the buckets of a single currency.
+    pub payment_attempted_amount: Option<u64>,
+    /// Milliseconds of the payment time spent on the network calls, over the payment attempts
+    /// reporting both parts of their time
+    pub average_network_ms: Option<f64>,
+    /// Milliseconds of the payment time spent rendering the sdk, over the same attempts
+    pub average_render_ms: Option<f64>,
+    /// Successful 3DS method results per invoked 3DS method, null when none was invoked
+    pub success_rate: Option<f64>,
+    /// Sessions which were initiated but made no payment attempt
+    pub abandoned_sessions_count: Option<u64>,
+}
+
+/// A stage of the funnel waterfall of a bucket
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct FunnelWaterfallStage {
+    pub stage: FunnelStage,
+    pub count: Option<u64>,
+    /// Count of the previous stage minus the count of this one, null for the first stage. It's
+    /// negative if more sessions reached this stage than the previous one.
+    pub drop_from_previous: Option<i64>,
+}
+
+/// What can be asked of a metric and what it answers with
+#[derive(Debug, serde::Serialize)]
+pub struct SdkEventMetricMetadata {
+    pub name: SdkEventMetrics,
+    /// Dimensions the metric can be grouped by
+    pub dimensions: Vec<SdkEventDimensions>,
+    /// Fields of [`SdkEventFilters`] the events of the metric can be filtered on
+    pub filters: Vec<&'static str>,
+    /// Fields of [`SdkEventMetricsBucketValue`] the metric sets
+    pub output_fields: Vec<&'static str>,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -235,4 +835,59 @@ pub struct MetricsBucketResponse {
     pub values: SdkEventMetricsBucketValue,
     #[serde(flatten)]
     pub dimensions: SdkEventMetricsBucketIdentifier,
+    /// Value of each requested metric with a target along that target, ordered by metric
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<SdkEventTargetResult>,
+}
+
+#[cfg(test)]
+mod bucket_identifier_tests {
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_bucket_key_round_trips() {
+        let id = SdkEventMetricsBucketIdentifier::of_time_bucket(Some(
+            "2024-03-01 00:00:00".to_string(),
+        ))
+        .with_dimension(SdkEventDimensions::Os, "")
+        .with_dimension(SdkEventDimensions::BrowserName, "Chrome & Co=1%");
+
+        // The dimensions are written in the order they're declared, whichever was set first
+        let key = id.to_string();
+        assert_eq!(
+            key,
+            "time_bucket=2024-03-01+00%3A00%3A00&browser_name=Chrome+%26+Co%3D1%25&os="
+        );
+
+        let parsed = key
+            .parse::<SdkEventMetricsBucketIdentifier>()
+            .expect("failed to parse bucket key");
+        assert_eq!(parsed.os.as_deref(), Some(""));
+        assert_eq!(parsed.browser_name.as_deref(), Some("Chrome & Co=1%"));
+        assert_eq!(parsed.platform, None);
+        assert_eq!(parsed.to_string(), key);
+        assert_eq!(
+            "".parse::<SdkEventMetricsBucketIdentifier>()
+                .expect("failed to parse empty bucket key")
+                .to_string(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_invalid_bucket_keys_are_rejected() {
+        for key in [
+            "browser=Chrome",
+            "os=ios&os=android",
+            "subtotal_level=one",
+            "subtotal_level=1&subtotal_level=1",
+        ] {
+            assert!(
+                key.parse::<SdkEventMetricsBucketIdentifier>().is_err(),
+                "{key}"
+            );
+        }
+    }
 }